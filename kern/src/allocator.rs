@@ -13,7 +13,7 @@ use core::alloc::{GlobalAlloc, Layout};
 use core::fmt;
 
 use crate::mutex::Mutex;
-use pi::atags::{Atag, Atags};
+use pi::bootinfo::BootInfo;
 
 /// `LocalAlloc` is an analogous trait to the standard library's `GlobalAlloc`,
 /// but it takes `&mut self` in `alloc()` and `dealloc()`.
@@ -69,25 +69,52 @@ extern "C" {
     static __text_end: u8;
 }
 
+/// Returns the end address of physical memory, as described by the device
+/// tree or the `MEM` ATAG, if it can be determined.
+pub fn memory_end() -> Option<usize> {
+    match BootInfo::get().memory() {
+        Some((start, size)) if size > 0 => Some(start + size - 1),
+        _ => None
+    }
+}
+
+/// Returns the (start address, end address) of the initial ramdisk, if the
+/// firmware loaded one and described it in the device tree or with an
+/// `INITRD2` ATAG. The end address is exclusive.
+pub fn initrd() -> Option<(usize, usize)> {
+    BootInfo::get().initrd()
+}
+
 /// Returns the (start address, end address) of the available memory on this
 /// system if it can be determined. If it cannot, `None` is returned.
 ///
+/// The initial ramdisk and the device tree, if there are any, are not
+/// available: the largest region of memory around them is returned.
+///
 /// This function is expected to return `Some` under all normal cirumstances.
 pub fn memory_map() -> Option<(usize, usize)> {
     let binary_end = unsafe { (&__text_end as *const u8) as usize };
+    let info = BootInfo::get();
+
+    let mut region = (binary_end + 1, memory_end()?);
+    for reserved in info.initrd().into_iter().chain(info.dtb()) {
+        region = exclude(region, reserved);
+    }
+    Some(region)
+}
+
+/// Returns the larger of the parts of `region` below and above `reserved`,
+/// whose end address is exclusive.
+fn exclude(region: (usize, usize), reserved: (usize, usize)) -> (usize, usize) {
+    let ((start, end), (res_start, res_end)) = (region, reserved);
+    if res_start >= end || res_end <= start {
+        return region;
+    }
 
-    // this assumes only one mem atag
-    let mut mem_end = 0usize;
-    for atag in Atags::get() {
-        match atag {
-            Atag::Mem(mem) => mem_end = (mem.start + mem.size - 1) as usize,
-            _ => ()
-        };
-    } 
-    if mem_end == 0 {
-        None
-    }  else {
-        Some((binary_end + 1, mem_end))
+    if res_start.saturating_sub(start) >= end.saturating_sub(res_end) {
+        (start, res_start - 1)
+    } else {
+        (res_end, end)
     }
 }
 
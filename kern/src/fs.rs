@@ -1,16 +1,34 @@
+pub mod adapter;
+pub mod initrd;
+pub mod procfs;
 pub mod sd;
+pub mod vfs;
 
+use alloc::format;
 use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
 use core::fmt::{self, Debug};
 use shim::io;
 use shim::ioerr;
-use shim::path::Path;
+use shim::path::{Path, PathBuf};
 
 pub use fat32::traits;
-use fat32::vfat::{Dir, Entry, File, VFat, VFatHandle};
+use fat32::check::{self, Report};
+use fat32::exfat::{ExFat, ExFatHandle};
+use fat32::ext2::{Ext2, Ext2Handle};
+use fat32::partition::{self, PartitionInfo};
+use fat32::traits::BlockDevice;
+use fat32::tmpfs::{TmpFs, TmpFsHandle};
+use fat32::vfat::{CacheStats, StatFs, VFat, VFatHandle};
 
+use self::adapter::TraitFs;
+use self::initrd::Initrd;
+use self::procfs::ProcFs;
 use self::sd::Sd;
+pub use self::vfs::{Dir, Entry, File, Metadata, MountTable, Mountable};
 use crate::mutex::Mutex;
+use crate::param::{FS_CACHE_SECTORS, FS_JOURNAL_SECTORS, TMPFS_CAPACITY};
 
 #[derive(Clone)]
 pub struct PiVFatHandle(Rc<Mutex<VFat<Self>>>);
@@ -38,7 +56,134 @@ impl VFatHandle for PiVFatHandle {
         f(&mut self.0.lock())
     }
 }
-pub struct FileSystem(Mutex<Option<PiVFatHandle>>);
+
+#[derive(Clone)]
+pub struct PiExFatHandle(Rc<Mutex<ExFat<Self>>>);
+
+// See `PiVFatHandle`.
+unsafe impl Send for PiExFatHandle {}
+unsafe impl Sync for PiExFatHandle {}
+
+impl Debug for PiExFatHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "PiExFatHandle")
+    }
+}
+
+impl ExFatHandle for PiExFatHandle {
+    fn new(val: ExFat<PiExFatHandle>) -> Self {
+        PiExFatHandle(Rc::new(Mutex::new(val)))
+    }
+
+    fn lock<R>(&self, f: impl FnOnce(&mut ExFat<PiExFatHandle>) -> R) -> R {
+        f(&mut self.0.lock())
+    }
+}
+
+#[derive(Clone)]
+pub struct PiExt2Handle(Rc<Mutex<Ext2<Self>>>);
+
+// See `PiVFatHandle`.
+unsafe impl Send for PiExt2Handle {}
+unsafe impl Sync for PiExt2Handle {}
+
+impl Debug for PiExt2Handle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "PiExt2Handle")
+    }
+}
+
+impl Ext2Handle for PiExt2Handle {
+    fn new(val: Ext2<PiExt2Handle>) -> Self {
+        PiExt2Handle(Rc::new(Mutex::new(val)))
+    }
+
+    fn lock<R>(&self, f: impl FnOnce(&mut Ext2<PiExt2Handle>) -> R) -> R {
+        f(&mut self.0.lock())
+    }
+}
+
+#[derive(Clone)]
+pub struct PiTmpFsHandle(Rc<Mutex<TmpFs<Self>>>);
+
+// See `PiVFatHandle`.
+unsafe impl Send for PiTmpFsHandle {}
+unsafe impl Sync for PiTmpFsHandle {}
+
+impl Debug for PiTmpFsHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "PiTmpFsHandle")
+    }
+}
+
+impl TmpFsHandle for PiTmpFsHandle {
+    fn new(val: TmpFs<PiTmpFsHandle>) -> Self {
+        PiTmpFsHandle(Rc::new(Mutex::new(val)))
+    }
+
+    fn lock<R>(&self, f: impl FnOnce(&mut TmpFs<PiTmpFsHandle>) -> R) -> R {
+        f(&mut self.0.lock())
+    }
+}
+
+/// Returns a new, empty tmpfs holding at most `TMPFS_CAPACITY` bytes of file
+/// data in the kernel heap.
+pub fn tmpfs() -> Rc<dyn Mountable> {
+    Rc::new(TraitFs::new(TmpFs::<PiTmpFsHandle>::volume(TMPFS_CAPACITY)))
+}
+
+/// Mounts the file system in partition `part` of `device`, trying the
+/// drivers its partition type allows.
+fn mount_partition<T>(device: &T, part: &PartitionInfo) -> Option<Rc<dyn Mountable>>
+where
+    T: BlockDevice + Clone + 'static,
+{
+    if part.may_be_fat() {
+        if let Ok(vfat) = VFat::<PiVFatHandle>::from_partition(device.clone(), part) {
+            vfat.lock(|vfat| vfat.device.set_capacity(FS_CACHE_SECTORS)).ok()?;
+            return Some(Rc::new(TraitFs::new(vfat)));
+        }
+    }
+    if part.may_be_exfat() {
+        if let Ok(exfat) = ExFat::<PiExFatHandle>::from_partition(device.clone(), part) {
+            exfat.lock(|exfat| exfat.device.set_capacity(FS_CACHE_SECTORS)).ok()?;
+            return Some(Rc::new(TraitFs::new(exfat)));
+        }
+    }
+    if part.may_be_ext2() {
+        if let Ok(ext2) = Ext2::<PiExt2Handle>::from_partition(device.clone(), part) {
+            ext2.lock(|ext2| ext2.device.set_capacity(FS_CACHE_SECTORS)).ok()?;
+            return Some(Rc::new(TraitFs::new(ext2)));
+        }
+    }
+    None
+}
+
+/// Mounts the file systems of every partition of `device`, returning them
+/// with their sources: `name` followed by `pN` for partition `N`. If none
+/// can be mounted and `whole` is given, it is tried instead, with `name` as
+/// the source.
+fn mount_device<T>(device: &T, name: &str, whole: Option<PartitionInfo>) -> Vec<(String, Rc<dyn Mountable>)>
+where
+    T: BlockDevice + Clone + 'static,
+{
+    let partitions = partition::partitions(device.clone()).unwrap_or_default();
+    let mounted: Vec<(String, Rc<dyn Mountable>)> = partitions.iter().enumerate()
+        .filter_map(|(i, part)| mount_partition(device, part).map(|fs| (format!("{}p{}", name, i + 1), fs)))
+        .collect();
+    match whole {
+        Some(part) if mounted.is_empty() => {
+            mount_partition(device, &part).map(|fs| (String::from(name), fs)).into_iter().collect()
+        }
+        _ => mounted
+    }
+}
+
+// See `PiVFatHandle`: the mounted file systems are shared through `Rc`s.
+unsafe impl Send for MountTable {}
+
+/// The kernel's view of every mounted file system.
+pub struct FileSystem(Mutex<Option<MountTable>>);
 
 impl FileSystem {
     /// Returns an uninitialized `FileSystem`.
@@ -49,23 +194,134 @@ impl FileSystem {
         FileSystem(Mutex::new(None))
     }
 
-    /// Initializes the file system.
+    /// Initializes the file system: the first FAT partition of the SD card,
+    /// or the first partition with a supported file system if there is none,
+    /// is mounted at `/`, the other partitions `N` with a supported file
+    /// system at `/sd0pN`, the process file system at `/proc` and a tmpfs at
+    /// `/tmp`.
+    ///
+    /// The file systems of the initial ramdisk, if the firmware loaded one,
+    /// are mounted at `/initrdpN`, or `/initrd` for an image without a
+    /// partition table. Without an SD card, or one without a supported file
+    /// system, the first of them is mounted at `/` instead.
+    ///
     /// The caller should assure that the method is invoked only once during the
     /// kernel initialization.
     ///
     /// # Panics
     ///
-    /// Panics if the underlying disk or file sytem failed to initialize.
+    /// Panics if neither the SD card nor the initial ramdisk holds a
+    /// supported file system.
     pub unsafe fn initialize(&self) {
-        let sd = Sd::new().unwrap();
-        *self.0.lock() = Some(VFat::<PiVFatHandle>::from(sd).unwrap());
+        let mut mounted = match Sd::new() {
+            Ok(sd) => mount_device(&sd, "sd0", None),
+            Err(_) => Vec::new(),
+        };
+        let root = mounted.iter().position(|(_, fs)| fs.as_vfat().is_some()).unwrap_or(0);
+        if let Some(initrd) = Initrd::get() {
+            mounted.extend(mount_device(&initrd, "initrd", Some(initrd.as_partition())));
+        }
+        if mounted.is_empty() {
+            panic!("no supported file system on the SD card or the initial ramdisk");
+        }
+
+        let (source, fs) = mounted.remove(root);
+        let mut table = MountTable::new();
+        table.mount("/", &source, fs).unwrap();
+        for (source, fs) in mounted {
+            table.mount(format!("/{}", source), &source, fs).unwrap();
+        }
+        table.mount("/proc", "proc", Rc::new(ProcFs)).unwrap();
+        table.mount("/tmp", "tmpfs", tmpfs()).unwrap();
+
+        *self.0.lock() = Some(table);
+    }
+
+    /// Mounts `fs` at `path`. See `MountTable::mount()`.
+    pub fn mount<P: AsRef<Path>>(&self, path: P, source: &str, fs: Rc<dyn Mountable>) -> io::Result<()> {
+        self.0.lock().as_mut().unwrap().mount(path, source, fs)
+    }
+
+    /// Unmounts the file system mounted at `path`. See `MountTable::unmount()`.
+    pub fn unmount<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.0.lock().as_mut().unwrap().unmount(path).map(|_| ())
+    }
+
+    /// Returns the mount point, source and type of every mounted file system.
+    pub fn mounts(&self) -> Vec<(PathBuf, String, &'static str)> {
+        self.0.lock().as_ref().unwrap().mounts().iter()
+            .map(|mount| (mount.path.clone(), mount.source.clone(), mount.fs.fs_type()))
+            .collect()
+    }
+
+    /// Returns the file system the absolute path `path` lies in.
+    fn mounted_at<P: AsRef<Path>>(&self, path: P) -> io::Result<Rc<dyn Mountable>> {
+        let path = vfs::normalize(path)?;
+        let table = self.0.lock();
+        let (mount, _) = table.as_ref().unwrap().resolve(&path)?;
+        Ok(mount.fs.clone())
+    }
+
+    /// Returns the handle of the FAT volume `path` lies in.
+    fn vfat_at<P: AsRef<Path>>(&self, path: P) -> io::Result<PiVFatHandle> {
+        match self.mounted_at(path)?.as_vfat() {
+            Some(vfat) => Ok(vfat.clone()),
+            None => ioerr!(Other, "not a FAT file system")
+        }
+    }
+
+    /// Writes everything modified through any of the mounted file systems
+    /// back to its device.
+    pub fn sync(&self) -> io::Result<()> {
+        let mounted: Vec<Rc<dyn Mountable>> = self.0.lock().as_ref().unwrap().mounts().iter()
+            .map(|mount| mount.fs.clone())
+            .collect();
+        for fs in mounted {
+            fs.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the usage statistics of the file system `path` lies in.
+    pub fn statfs<P: AsRef<Path>>(&self, path: P) -> io::Result<StatFs> {
+        self.mounted_at(path)?.statfs()
+    }
+
+    /// Checks the consistency of the FAT file system `path` lies in,
+    /// repairing any problems found if `repair` is `true`. See
+    /// `fat32::check::check()`.
+    pub fn check<P: AsRef<Path>>(&self, path: P, repair: bool) -> io::Result<Report> {
+        check::check(&self.vfat_at(path)?, repair)
+    }
+
+    /// Turns on journaling of the metadata of the FAT file system `path` lies
+    /// in, creating a journal of `FS_JOURNAL_SECTORS` sectors if the volume
+    /// has none. See `fat32::vfat::enable_journal()`.
+    pub fn enable_journal<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fat32::vfat::enable_journal(&self.vfat_at(path)?, FS_JOURNAL_SECTORS)
+    }
+
+    /// Returns `true` if `path` lies in a FAT file system whose metadata is
+    /// journaled.
+    pub fn is_journaling<P: AsRef<Path>>(&self, path: P) -> bool {
+        match self.vfat_at(path) {
+            Ok(vfat) => vfat.lock(|vfat: &mut VFat<PiVFatHandle>| vfat.is_journaling()),
+            Err(_) => false
+        }
+    }
+
+    /// Returns the sector cache's counters along with the number of sectors
+    /// currently cached, for the file system `path` lies in if it has a
+    /// sector cache.
+    pub fn cache_stats<P: AsRef<Path>>(&self, path: P) -> Option<(CacheStats, usize)> {
+        self.mounted_at(path).ok()?.cache_stats()
     }
 }
 
 impl fat32::traits::FileSystem for &FileSystem {
-    type File = File<PiVFatHandle>;
-    type Dir = Dir<PiVFatHandle>;
-    type Entry = Entry<PiVFatHandle>;
+    type File = File;
+    type Dir = Dir;
+    type Entry = Entry;
 
     /// Opens the entry at `path`. `path` must be absolute.
     ///
@@ -80,35 +336,22 @@ impl fat32::traits::FileSystem for &FileSystem {
     ///
     /// All other error values are implementation defined.
     fn open<P: AsRef<Path>>(self, p: P) -> io::Result<Self::Entry> {
-        if !p.as_ref().is_absolute() {
-            return ioerr!(InvalidInput, "path must be absolute");
-        }
-        
         self.0.lock().as_ref().unwrap().open(p)
     }
 
-    /*
-    /// Opens the file at `path`. `path` must be absolute.
-    ///
-    /// # Errors
-    ///
-    /// In addition to the error conditions for `open()`, this method returns an
-    /// error kind of `Other` if the entry at `path` is not a regular file.
-    fn open_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self::File> {
-        self.open(path)?
-            .into_file()
-            .ok_or(io::Error::new(io::ErrorKind::Other, "not a regular file"))
+    fn create_file<P: AsRef<Path>>(self, p: P) -> io::Result<Self::File> {
+        self.0.lock().as_ref().unwrap().create_file(p)
     }
 
-    /// Opens the directory at `path`. `path` must be absolute.
-    ///
-    /// # Errors
-    ///
-    /// In addition to the error conditions for `open()`, this method returns an
-    /// error kind of `Other` if the entry at `path` is not a directory.
-    fn open_dir<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Dir> {
-        self.open(path)?
-            .into_dir()
-            .ok_or(io::Error::new(io::ErrorKind::Other, "not a directory"))
-    }*/
+    fn create_dir<P: AsRef<Path>>(self, p: P) -> io::Result<Self::Dir> {
+        self.0.lock().as_ref().unwrap().create_dir(p)
+    }
+
+    fn remove<P: AsRef<Path>>(self, p: P) -> io::Result<()> {
+        self.0.lock().as_ref().unwrap().remove(p)
+    }
+
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, from: P, to: Q) -> io::Result<()> {
+        self.0.lock().as_ref().unwrap().rename(from, to)
+    }
 }
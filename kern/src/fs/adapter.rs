@@ -0,0 +1,461 @@
+use alloc::collections::BTreeMap;
+use alloc::rc::{Rc, Weak};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cell::Cell;
+
+use shim::io::{self, Read, Seek, SeekFrom, Write};
+use shim::ioerr;
+use shim::path::{Component, Path, PathBuf};
+
+use fat32::exfat::{ExFat, ExFatFs};
+use fat32::ext2::{Ext2, Ext2Fs};
+use fat32::tmpfs::{TmpFs, TmpFsVolume};
+use fat32::traits::{self, Dir as _, Entry as _, File as _};
+use fat32::vfat::{CacheStats, StatFs, VFat, VFatHandle};
+
+use crate::fs::vfs::{Inode, Metadata, Mountable};
+use crate::fs::{PiExFatHandle, PiExt2Handle, PiTmpFsHandle, PiVFatHandle};
+use crate::mutex::Mutex;
+
+/// A file system implementing the `fat32::traits` file system traits, as
+/// used by `TraitFs`. The traits are implemented for references to the
+/// handles of mounted volumes; this trait names the handle itself.
+pub trait Backing: Clone + 'static {
+    type File: traits::File + 'static;
+    type Dir: traits::Dir<Entry = Self::Entry> + 'static;
+    type Entry: traits::Entry<File = Self::File, Dir = Self::Dir> + 'static;
+
+    /// The name of the file system's type, as shown by `mount`.
+    const FS_TYPE: &'static str;
+
+    fn open(&self, path: &Path) -> io::Result<Self::Entry>;
+    fn create_file(&self, path: &Path) -> io::Result<Self::File>;
+    fn create_dir(&self, path: &Path) -> io::Result<Self::Dir>;
+    fn remove(&self, path: &Path) -> io::Result<()>;
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    fn sync(&self) -> io::Result<()>;
+    fn statfs(&self) -> io::Result<StatFs>;
+
+    /// Returns the sector cache's counters and the number of sectors cached,
+    /// for file systems with a sector cache.
+    fn cache_stats(&self) -> Option<(CacheStats, usize)>;
+
+    /// Returns the FAT volume handle, for FAT volumes.
+    fn as_vfat(&self) -> Option<&PiVFatHandle> {
+        None
+    }
+}
+
+/// Implements the path operations of `Backing` by forwarding them to the
+/// `fat32::traits::FileSystem` implementation for references to the handle.
+macro_rules! forward_paths {
+    () => {
+        fn open(&self, path: &Path) -> io::Result<Self::Entry> {
+            traits::FileSystem::open(self, path)
+        }
+
+        fn create_file(&self, path: &Path) -> io::Result<Self::File> {
+            traits::FileSystem::create_file(self, path)
+        }
+
+        fn create_dir(&self, path: &Path) -> io::Result<Self::Dir> {
+            traits::FileSystem::create_dir(self, path)
+        }
+
+        fn remove(&self, path: &Path) -> io::Result<()> {
+            traits::FileSystem::remove(self, path)
+        }
+
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+            traits::FileSystem::rename(self, from, to)
+        }
+    };
+}
+
+impl Backing for PiVFatHandle {
+    type File = fat32::vfat::File<PiVFatHandle>;
+    type Dir = fat32::vfat::Dir<PiVFatHandle>;
+    type Entry = fat32::vfat::Entry<PiVFatHandle>;
+
+    const FS_TYPE: &'static str = "vfat";
+
+    forward_paths!();
+
+    fn sync(&self) -> io::Result<()> {
+        self.lock(|vfat: &mut VFat<PiVFatHandle>| vfat.flush())
+    }
+
+    fn statfs(&self) -> io::Result<StatFs> {
+        self.lock(|vfat: &mut VFat<PiVFatHandle>| vfat.statfs())
+    }
+
+    fn cache_stats(&self) -> Option<(CacheStats, usize)> {
+        Some(self.lock(|vfat: &mut VFat<PiVFatHandle>| (vfat.device.stats(), vfat.device.len())))
+    }
+
+    fn as_vfat(&self) -> Option<&PiVFatHandle> {
+        Some(self)
+    }
+}
+
+impl Backing for ExFatFs<PiExFatHandle> {
+    type File = fat32::exfat::File<PiExFatHandle>;
+    type Dir = fat32::exfat::Dir<PiExFatHandle>;
+    type Entry = fat32::exfat::Entry<PiExFatHandle>;
+
+    const FS_TYPE: &'static str = "exfat";
+
+    forward_paths!();
+
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn statfs(&self) -> io::Result<StatFs> {
+        self.lock(|exfat: &mut ExFat<PiExFatHandle>| exfat.statfs())
+    }
+
+    fn cache_stats(&self) -> Option<(CacheStats, usize)> {
+        Some(self.lock(|exfat: &mut ExFat<PiExFatHandle>| (exfat.device.stats(), exfat.device.len())))
+    }
+}
+
+impl Backing for Ext2Fs<PiExt2Handle> {
+    type File = fat32::ext2::File<PiExt2Handle>;
+    type Dir = fat32::ext2::Dir<PiExt2Handle>;
+    type Entry = fat32::ext2::Entry<PiExt2Handle>;
+
+    const FS_TYPE: &'static str = "ext2";
+
+    forward_paths!();
+
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn statfs(&self) -> io::Result<StatFs> {
+        Ok(self.lock(|ext2: &mut Ext2<PiExt2Handle>| ext2.statfs()))
+    }
+
+    fn cache_stats(&self) -> Option<(CacheStats, usize)> {
+        Some(self.lock(|ext2: &mut Ext2<PiExt2Handle>| (ext2.device.stats(), ext2.device.len())))
+    }
+}
+
+impl Backing for TmpFsVolume<PiTmpFsHandle> {
+    type File = fat32::tmpfs::File<PiTmpFsHandle>;
+    type Dir = fat32::tmpfs::Dir<PiTmpFsHandle>;
+    type Entry = fat32::tmpfs::Entry<PiTmpFsHandle>;
+
+    const FS_TYPE: &'static str = "tmpfs";
+
+    forward_paths!();
+
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn statfs(&self) -> io::Result<StatFs> {
+        Ok(self.lock(|tmpfs: &mut TmpFs<PiTmpFsHandle>| tmpfs.statfs()))
+    }
+
+    fn cache_stats(&self) -> Option<(CacheStats, usize)> {
+        None
+    }
+}
+
+/// Mounts a file system implementing the `fat32::traits` file system traits
+/// into the VFS.
+pub struct TraitFs<B: Backing> {
+    fs: B,
+    inodes: Rc<InodeTable<B>>,
+}
+
+impl<B: Backing> TraitFs<B> {
+    /// Returns `fs`, ready to be mounted.
+    pub fn new(fs: B) -> TraitFs<B> {
+        TraitFs { fs, inodes: Rc::new(InodeTable(Mutex::new(BTreeMap::new()))) }
+    }
+}
+
+impl<B: Backing> Mountable for TraitFs<B> {
+    fn fs_type(&self) -> &'static str {
+        B::FS_TYPE
+    }
+
+    fn root(&self) -> io::Result<Rc<dyn Inode>> {
+        let root = self.fs.open(Path::new("/"))?;
+        self.inodes.get_or_insert(&self.fs, PathBuf::from("/"), String::from("/"), root)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let from = canonical(&self.fs, from)?;
+        self.inodes.flush(&from)?;
+        self.fs.rename(&from, to)?;
+        self.inodes.invalidate(&from);
+        Ok(())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.inodes.flush(Path::new("/"))?;
+        self.fs.sync()
+    }
+
+    fn statfs(&self) -> io::Result<StatFs> {
+        self.fs.statfs()
+    }
+
+    fn cache_stats(&self) -> Option<(CacheStats, usize)> {
+        self.fs.cache_stats()
+    }
+
+    fn as_vfat(&self) -> Option<&PiVFatHandle> {
+        self.fs.as_vfat()
+    }
+}
+
+/// Returns the absolute path `path` in `fs` with every name spelled as its
+/// entry spells it, which for case insensitive file systems need not be as
+/// `path` does.
+fn canonical<B: Backing>(fs: &B, path: &Path) -> io::Result<PathBuf> {
+    let mut canonical = PathBuf::from("/");
+    for component in path.components() {
+        if let Component::Normal(name) = component {
+            let entry = fs.open(&canonical.join(name))?;
+            canonical.push(entry.name());
+        }
+    }
+    Ok(canonical)
+}
+
+/// The inodes of a `TraitFs` in use, by their canonical path. Looking up an
+/// entry returns the inode already open for it, if any, so that everyone
+/// using a file shares its size and cluster chain.
+struct InodeTable<B: Backing>(Mutex<BTreeMap<PathBuf, Weak<TraitInode<B>>>>);
+
+impl<B: Backing> InodeTable<B> {
+    /// Returns the inode open for the entry at the canonical path `path`, or
+    /// wraps `entry`, named `name`, in a new one.
+    fn get_or_insert(self: &Rc<Self>, fs: &B, path: PathBuf, name: String, entry: B::Entry) -> io::Result<Rc<dyn Inode>> {
+        let mut inodes = self.0.lock();
+        if let Some(inode) = inodes.get(&path).and_then(|inode| inode.upgrade()) {
+            return Ok(inode);
+        }
+
+        let inode = Rc::new(TraitInode::new(fs.clone(), self.clone(), path.clone(), name, entry)?);
+        inodes.insert(path, Rc::downgrade(&inode));
+        Ok(inode)
+    }
+
+    /// Writes what was written to the open files at the canonical path `path`
+    /// and below it back to their entries. See `TraitInode::flush()`.
+    fn flush(&self, path: &Path) -> io::Result<()> {
+        let open: Vec<Rc<TraitInode<B>>> = self.0.lock().iter()
+            .filter(|(p, _)| p.starts_with(path))
+            .filter_map(|(_, inode)| inode.upgrade())
+            .collect();
+        for inode in open.iter() {
+            inode.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Forgets the inodes at the canonical path `path` and below it, and
+    /// marks them stale so that using them fails.
+    fn invalidate(&self, path: &Path) {
+        let mut stale = Vec::new();
+        let mut inodes = self.0.lock();
+        let paths: Vec<PathBuf> = inodes.keys().filter(|p| p.starts_with(path)).cloned().collect();
+        for p in paths {
+            if let Some(inode) = inodes.remove(&p).and_then(|inode| inode.upgrade()) {
+                inode.stale.set(true);
+                stale.push(inode);
+            }
+        }
+
+        // dropping the inodes may take the lock
+        drop(inodes);
+    }
+}
+
+/// What a `TraitInode` holds open of its entry.
+enum Node<B: Backing> {
+    File(Mutex<B::File>),
+    Dir(B::Dir),
+}
+
+/// An entry of a `TraitFs`, which remembers its path in the file system for
+/// the operations the traits only offer by path.
+pub struct TraitInode<B: Backing> {
+    fs: B,
+    inodes: Rc<InodeTable<B>>,
+    path: PathBuf,
+    name: String,
+    metadata: Metadata,
+    node: Node<B>,
+    /// Set once the entry has been removed or moved away from `path`.
+    stale: Cell<bool>,
+}
+
+impl<B: Backing> TraitInode<B> {
+    /// Wraps `entry`, which is at `path` in `fs` and named `name`.
+    fn new(fs: B, inodes: Rc<InodeTable<B>>, path: PathBuf, name: String, entry: B::Entry) -> io::Result<TraitInode<B>> {
+        let is_dir = entry.is_dir();
+        let size = entry.as_file().map_or(0, |file| file.size());
+        let metadata = Metadata::from(entry.metadata(), is_dir, size);
+        let node = match is_dir {
+            true => entry.into_dir().map(Node::Dir),
+            false => entry.into_file().map(|file| Node::File(Mutex::new(file))),
+        };
+
+        match node {
+            Some(node) => Ok(TraitInode { fs, inodes, path, name, metadata, node, stale: Cell::new(false) }),
+            None => ioerr!(Other, "entry is neither a file nor a directory")
+        }
+    }
+
+    /// Fails with `NotFound` if the entry has been removed or moved since
+    /// `self` was opened.
+    fn check_stale(&self) -> io::Result<()> {
+        match self.stale.get() {
+            true => ioerr!(NotFound, "entry was removed or moved"),
+            false => Ok(())
+        }
+    }
+
+    /// Returns the directory `self` holds open, or `InvalidInput` if it is a
+    /// file.
+    fn dir(&self) -> io::Result<&B::Dir> {
+        self.check_stale()?;
+        match &self.node {
+            Node::Dir(dir) => Ok(dir),
+            Node::File(_) => ioerr!(InvalidInput, "not a directory")
+        }
+    }
+
+    /// Returns the file `self` holds open, or `Other` if it is a directory.
+    fn file(&self) -> io::Result<&Mutex<B::File>> {
+        self.check_stale()?;
+        match &self.node {
+            Node::File(file) => Ok(file),
+            Node::Dir(_) => ioerr!(Other, "not a regular file")
+        }
+    }
+
+    /// Returns the inode of `entry`, which is in `self`. `.` and `..` are
+    /// not shared, as their paths are not canonical.
+    fn child_inode(&self, entry: B::Entry) -> io::Result<Rc<dyn Inode>> {
+        let name = entry.name().to_string();
+        let path = self.path.join(&name);
+        match name.as_str() {
+            "." | ".." => Ok(Rc::new(TraitInode::new(self.fs.clone(), self.inodes.clone(), path, name, entry)?)),
+            _ => self.inodes.get_or_insert(&self.fs, path, name, entry)
+        }
+    }
+
+    /// Writes what was written to the file `self` holds open back to its
+    /// entry, leaving it cached. Entries removed or moved away were flushed
+    /// before, and are left alone.
+    fn flush(&self) -> io::Result<()> {
+        match &self.node {
+            Node::File(file) if !self.stale.get() => file.lock().flush(),
+            _ => Ok(())
+        }
+    }
+
+    /// Opens the entry named `name` in `self`.
+    fn child(&self, name: &str) -> io::Result<Rc<dyn Inode>> {
+        self.dir()?;
+        let entry = self.fs.open(&self.path.join(name))?;
+        self.child_inode(entry)
+    }
+}
+
+impl<B: Backing> Drop for TraitInode<B> {
+    fn drop(&mut self) {
+        // files are synced on close, but processes may exit without closing
+        let _ = self.flush();
+
+        let mut inodes = self.inodes.0.lock();
+        if inodes.get(&self.path).map_or(false, |inode| inode.upgrade().is_none()) {
+            inodes.remove(&self.path);
+        }
+    }
+}
+
+impl<B: Backing> Inode for TraitInode<B> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_dir(&self) -> bool {
+        match self.node {
+            Node::Dir(_) => true,
+            Node::File(_) => false
+        }
+    }
+
+    /// Returns the metadata of the entry as it was opened, with the current
+    /// size of files.
+    fn metadata(&self) -> Metadata {
+        let mut metadata = self.metadata.clone();
+        if let Ok(file) = self.file() {
+            metadata.size = file.lock().size();
+        }
+        metadata
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut file = self.file()?.lock();
+        if offset >= file.size() {
+            return Ok(0);
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        file.read(buf)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> io::Result<usize> {
+        let mut file = self.file()?.lock();
+        file.seek(SeekFrom::Start(offset))?;
+        file.write(buf)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        match &self.node {
+            Node::File(file) if !self.stale.get() => file.lock().sync(),
+            _ => Ok(())
+        }
+    }
+
+    fn lookup(&self, name: &str) -> io::Result<Rc<dyn Inode>> {
+        self.child(name)
+    }
+
+    fn entries(&self) -> io::Result<Vec<Rc<dyn Inode>>> {
+        let mut entries: Vec<Rc<dyn Inode>> = Vec::new();
+        for entry in self.dir()?.entries()? {
+            entries.push(self.child_inode(entry)?);
+        }
+        Ok(entries)
+    }
+
+    fn create_file(&self, name: &str) -> io::Result<Rc<dyn Inode>> {
+        self.fs.create_file(&self.dir().map(|_| self.path.join(name))?)?;
+        self.child(name)
+    }
+
+    fn create_dir(&self, name: &str) -> io::Result<Rc<dyn Inode>> {
+        self.fs.create_dir(&self.dir().map(|_| self.path.join(name))?)?;
+        self.child(name)
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        self.dir()?;
+        let path = self.path.join(self.fs.open(&self.path.join(name))?.name());
+        self.inodes.flush(&path)?;
+        self.fs.remove(&path)?;
+        self.inodes.invalidate(&path);
+        Ok(())
+    }
+}
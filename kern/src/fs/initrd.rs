@@ -0,0 +1,97 @@
+use core::cmp::min;
+use core::slice;
+
+use shim::io;
+use shim::ioerr;
+
+use fat32::partition::{PartitionInfo, PartitionKind};
+use fat32::traits::BlockDevice;
+
+use crate::allocator;
+
+/// The size of the ramdisk's sectors in bytes.
+const SECTOR_SIZE: usize = 512;
+
+/// The initial ramdisk the firmware loaded into memory, as a block device.
+/// Writes go to memory and are lost at reboot. Every clone accesses the same
+/// memory, so each of its mounted partitions can own one.
+#[derive(Debug, Clone)]
+pub struct Initrd {
+    start: usize,
+    num_sectors: u64,
+}
+
+impl Initrd {
+    /// Returns the initial ramdisk, or `None` if the firmware loaded none.
+    /// The memory it lies in is left out of the heap by
+    /// `allocator::memory_map()`.
+    pub fn get() -> Option<Initrd> {
+        let (start, end) = allocator::initrd()?;
+        Some(Initrd { start, num_sectors: ((end - start) / SECTOR_SIZE) as u64 })
+    }
+
+    /// The size of the ramdisk in sectors.
+    pub fn num_sectors(&self) -> u64 {
+        self.num_sectors
+    }
+
+    /// Returns the whole ramdisk as a FAT partition, for images that hold a
+    /// file system but no partition table.
+    pub fn as_partition(&self) -> PartitionInfo {
+        PartitionInfo { start: 0, num_sectors: self.num_sectors, kind: PartitionKind::Primary(0x0C) }
+    }
+
+    /// Returns the memory of sectors `n` up to `n + count`.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind `InvalidInput` is returned if any of the sectors lies
+    /// past the end of the ramdisk.
+    fn sectors(&self, n: u64, count: u64) -> io::Result<&'static mut [u8]> {
+        if n.checked_add(count).map_or(true, |end| end > self.num_sectors) {
+            return ioerr!(InvalidInput, "sector past the end of the ramdisk");
+        }
+        let ptr = (self.start + n as usize * SECTOR_SIZE) as *mut u8;
+        Ok(unsafe { slice::from_raw_parts_mut(ptr, count as usize * SECTOR_SIZE) })
+    }
+}
+
+impl BlockDevice for Initrd {
+    /// Copies sector `n` of the ramdisk into `buf`. On success, the number of
+    /// bytes read is returned.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind `InvalidInput` is returned if `n` lies past the end of
+    /// the ramdisk.
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let sector = self.sectors(n, 1)?;
+        let len = min(buf.len(), SECTOR_SIZE);
+        buf[..len].copy_from_slice(&sector[..len]);
+        Ok(len)
+    }
+
+    /// Copies as many whole sectors starting at sector `n` as fit in `buf` at
+    /// once.
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len() / SECTOR_SIZE * SECTOR_SIZE;
+        let sectors = self.sectors(n, (len / SECTOR_SIZE) as u64)?;
+        buf[..len].copy_from_slice(sectors);
+        Ok(len)
+    }
+
+    /// Overwrites sector `n` of the ramdisk with the first 512 bytes of `buf`.
+    /// On success, the number of bytes written is returned.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind `UnexpectedEof` is returned if `buf.len() < 512`, and
+    /// of kind `InvalidInput` if `n` lies past the end of the ramdisk.
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() < SECTOR_SIZE {
+            return ioerr!(UnexpectedEof, "buf.len() must be at least 512");
+        }
+        self.sectors(n, 1)?.copy_from_slice(&buf[..SECTOR_SIZE]);
+        Ok(SECTOR_SIZE)
+    }
+}
@@ -0,0 +1,106 @@
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::fmt::Write;
+
+use shim::io;
+use shim::ioerr;
+
+use crate::fs::vfs::{Inode, Metadata, Mountable};
+use crate::{ALLOCATOR, FILESYSTEM};
+
+/// The files of the process file system and the functions generating their
+/// contents.
+const FILES: &[(&str, fn() -> String)] = &[
+    ("meminfo", meminfo),
+    ("mounts", mounts),
+    ("uptime", uptime),
+];
+
+fn meminfo() -> String {
+    format!("{:#?}\n", ALLOCATOR)
+}
+
+fn mounts() -> String {
+    let mut contents = String::new();
+    for (path, source, fs_type) in FILESYSTEM.mounts() {
+        let _ = writeln!(contents, "{} {} {}", source, path.to_str().unwrap_or("?"), fs_type);
+    }
+    contents
+}
+
+fn uptime() -> String {
+    let now = pi::timer::current_time();
+    format!("{}.{:03}\n", now.as_secs(), now.subsec_millis())
+}
+
+/// A pseudo file system of read-only files describing the kernel's state,
+/// whose contents are generated when they are opened.
+pub struct ProcFs;
+
+impl Mountable for ProcFs {
+    fn fs_type(&self) -> &'static str {
+        "proc"
+    }
+
+    fn root(&self) -> io::Result<Rc<dyn Inode>> {
+        Ok(Rc::new(ProcDir))
+    }
+}
+
+/// The root, and only, directory of a `ProcFs`.
+struct ProcDir;
+
+impl Inode for ProcDir {
+    fn name(&self) -> &str {
+        "/"
+    }
+
+    fn is_dir(&self) -> bool {
+        true
+    }
+
+    fn metadata(&self) -> Metadata {
+        Metadata { is_dir: true, read_only: true, ..Metadata::default() }
+    }
+
+    fn lookup(&self, name: &str) -> io::Result<Rc<dyn Inode>> {
+        match FILES.iter().find(|(file, _)| *file == name) {
+            Some(&(name, generate)) => Ok(Rc::new(ProcFile { name, contents: generate().into_bytes() })),
+            None => ioerr!(NotFound, "file not found")
+        }
+    }
+
+    fn entries(&self) -> io::Result<Vec<Rc<dyn Inode>>> {
+        FILES.iter().map(|(name, _)| self.lookup(name)).collect()
+    }
+}
+
+/// A file of a `ProcFs`, holding the contents generated when it was opened.
+struct ProcFile {
+    name: &'static str,
+    contents: Vec<u8>,
+}
+
+impl Inode for ProcFile {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn is_dir(&self) -> bool {
+        false
+    }
+
+    fn metadata(&self) -> Metadata {
+        Metadata { read_only: true, size: self.contents.len() as u64, ..Metadata::default() }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let start = min(offset as usize, self.contents.len());
+        let n = min(buf.len(), self.contents.len() - start);
+        buf[..n].copy_from_slice(&self.contents[start..start + n]);
+        Ok(n)
+    }
+}
@@ -3,8 +3,11 @@ use shim::io;
 use shim::ioerr;
 
 use fat32::traits::BlockDevice;
+use pi::emmc::{self, Card, Emmc};
 use pi::timer;
 
+use crate::mutex::Mutex;
+
 extern "C" {
     /// A global representing the last SD controller error that occured.
     static sd_err: i64;
@@ -37,9 +40,17 @@ pub extern "C" fn wait_micros(micros: u32) {
     timer::spin_sleep(Duration::from_micros((micros * 100).into()));
 }
 
-/// A handle to an SD card controller.
-#[derive(Debug)]
-pub struct Sd {}
+/// The controller, set by `Sd::new()`. `libsd` drives the same registers, so
+/// it is only called with this locked too, and no two command sequences can
+/// interleave.
+static EMMC: Mutex<Option<Emmc>> = Mutex::new(None);
+
+/// A handle to an SD card controller. Every clone drives the same card, so
+/// each of its mounted partitions can own one.
+#[derive(Debug, Clone)]
+pub struct Sd {
+    card: Card,
+}
 
 impl Sd {
     /// Initializes the SD card controller and returns a handle to it.
@@ -48,16 +59,28 @@ impl Sd {
     /// with atomic memory access, but we can't use it yet since we haven't
     /// written the memory management unit (MMU).
     pub unsafe fn new() -> Result<Sd, io::Error> {
+        let mut controller = EMMC.lock();
         let err = sd_init();
         match err {
-            0 => Ok(Sd {}),
-            -1 => ioerr!(TimedOut, "SD Card Initialization Timed Out (init)"),
-            -2 => ioerr!(BrokenPipe, "Error Sending Commands to SD Card (init)"),
-            _ => ioerr!(Other, "Unknown Error Occurred (init)")
+            0 => {},
+            -1 => return ioerr!(TimedOut, "SD Card Initialization Timed Out (init)"),
+            -2 => return ioerr!(BrokenPipe, "Error Sending Commands to SD Card (init)"),
+            _ => return ioerr!(Other, "Unknown Error Occurred (init)")
         }
+
+        let mut emmc = Emmc::new();
+        let card = match emmc.card() {
+            Ok(card) => card,
+            Err(emmc::Error::TimedOut) => return ioerr!(TimedOut, "SD Card Initialization Timed Out (init)"),
+            Err(emmc::Error::Command) => return ioerr!(BrokenPipe, "Error Sending Commands to SD Card (init)"),
+        };
+        *controller = Some(emmc);
+        Ok(Sd { card })
     }
 }
 
+// `libsd` only transfers one sector per command, so `read_sectors` keeps its
+// default loop; read-ahead still saves the file system's per-sector overhead.
 impl BlockDevice for Sd {
     /// Reads sector `n` from the SD card into `buf`. On success, the number of
     /// bytes read is returned.
@@ -78,6 +101,7 @@ impl BlockDevice for Sd {
         if buf.len() < 512 {
             return ioerr!(InvalidInput, "buf.len() must be at least 512");
         }
+        let _controller = EMMC.lock();
         let buf_ptr = buf.as_mut_ptr();
         match unsafe { sd_readsector(n as i32, buf_ptr) } {
             0 => {
@@ -90,13 +114,35 @@ impl BlockDevice for Sd {
                     }
                 }
             }
-            _ => { 
-                return Ok(n as usize);
-            }
+            _ => Ok(512)
         }
     }
 
-    fn write_sector(&mut self, _n: u64, _buf: &[u8]) -> io::Result<usize> {
-        unimplemented!("SD card and file system are read only")
+    /// Writes the first 512 bytes of `buf` to sector `n` of the SD card. On
+    /// success, the number of bytes written is returned.
+    ///
+    /// # Errors
+    ///
+    /// An I/O error of kind `InvalidInput` is returned if `buf.len() < 512` or
+    /// `n > 2^31 - 1` (the maximum value for an `i32`).
+    ///
+    /// An error of kind `TimedOut` is returned if a timeout occurs while
+    /// writing to the SD card.
+    ///
+    /// An error of kind `BrokenPipe` is returned for all other errors.
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        if n > 0x7FFFFFFF {
+            return ioerr!(InvalidInput, "n is too large");
+        }
+        if buf.len() < 512 {
+            return ioerr!(InvalidInput, "buf.len() must be at least 512");
+        }
+        let mut controller = EMMC.lock();
+        let emmc = controller.as_mut().expect("SD card controller is initialized");
+        match emmc.write_block(self.card, n as u32, buf) {
+            Ok(()) => Ok(512),
+            Err(emmc::Error::TimedOut) => ioerr!(TimedOut, "SD Card Timed Out (write)"),
+            Err(emmc::Error::Command) => ioerr!(BrokenPipe, "Error Sending Commands to SD Card (write)"),
+        }
     }
 }
@@ -0,0 +1,663 @@
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use shim::io::{self, SeekFrom};
+use shim::ioerr;
+use shim::path::{Component, Path, PathBuf};
+
+use fat32::traits;
+use fat32::vfat::{CacheStats, StatFs};
+
+use crate::fs::PiVFatHandle;
+
+/// A file or directory of a mounted file system.
+///
+/// Inodes are shared as `Rc<dyn Inode>`, so everything that changes the
+/// inode or its file system takes `&self`.
+pub trait Inode {
+    /// The name of the inode in its parent directory.
+    fn name(&self) -> &str;
+
+    /// Returns `true` if the inode is a directory.
+    fn is_dir(&self) -> bool;
+
+    /// Returns the inode's current metadata.
+    fn metadata(&self) -> Metadata;
+
+    /// The size of the inode's data in bytes.
+    fn size(&self) -> u64 {
+        self.metadata().size
+    }
+
+    /// Reads from byte `offset` of a file into `buf`. Returns the number of
+    /// bytes read, which is `0` at the end of the file.
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> io::Result<usize> {
+        ioerr!(Other, "not a regular file")
+    }
+
+    /// Writes `buf` to a file at byte `offset`, growing the file as needed.
+    /// Returns the number of bytes written.
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> io::Result<usize> {
+        ioerr!(PermissionDenied, "read-only file system")
+    }
+
+    /// Writes any buffered data of a file to its device.
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Returns the entry named `name` in a directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if there is no such entry and `InvalidInput` if
+    /// `self` is not a directory.
+    fn lookup(&self, _name: &str) -> io::Result<Rc<dyn Inode>> {
+        ioerr!(InvalidInput, "not a directory")
+    }
+
+    /// Returns the entries of a directory.
+    fn entries(&self) -> io::Result<Vec<Rc<dyn Inode>>> {
+        ioerr!(InvalidInput, "not a directory")
+    }
+
+    /// Creates an empty file named `name` in a directory.
+    fn create_file(&self, _name: &str) -> io::Result<Rc<dyn Inode>> {
+        ioerr!(PermissionDenied, "read-only file system")
+    }
+
+    /// Creates an empty directory named `name` in a directory.
+    fn create_dir(&self, _name: &str) -> io::Result<Rc<dyn Inode>> {
+        ioerr!(PermissionDenied, "read-only file system")
+    }
+
+    /// Removes the file or empty directory named `name` from a directory.
+    fn remove(&self, _name: &str) -> io::Result<()> {
+        ioerr!(PermissionDenied, "read-only file system")
+    }
+}
+
+/// A file system that can be mounted into the `MountTable`.
+pub trait Mountable {
+    /// The name of the file system's type, as shown by `mount`.
+    fn fs_type(&self) -> &'static str;
+
+    /// Returns the root directory of the file system.
+    fn root(&self) -> io::Result<Rc<dyn Inode>>;
+
+    /// Moves the entry at `from` to `to`. Both paths are absolute paths
+    /// within the file system.
+    fn rename(&self, _from: &Path, _to: &Path) -> io::Result<()> {
+        ioerr!(PermissionDenied, "read-only file system")
+    }
+
+    /// Writes everything modified through the file system back to its
+    /// device.
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Returns the file system's usage statistics.
+    fn statfs(&self) -> io::Result<StatFs> {
+        ioerr!(Other, "file system has no usage statistics")
+    }
+
+    /// Returns the counters of the file system's sector cache along with the
+    /// number of sectors cached, if it has one.
+    fn cache_stats(&self) -> Option<(CacheStats, usize)> {
+        None
+    }
+
+    /// Returns the handle of the FAT volume behind the file system, for the
+    /// operations only FAT volumes support.
+    fn as_vfat(&self) -> Option<&PiVFatHandle> {
+        None
+    }
+}
+
+/// A point in time, as reported by any of the file systems.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Timestamp {
+    pub year: usize,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl Timestamp {
+    /// Converts the timestamp `ts` of a file system.
+    pub fn from<T: traits::Timestamp>(ts: T) -> Timestamp {
+        Timestamp {
+            year: ts.year(),
+            month: ts.month(),
+            day: ts.day(),
+            hour: ts.hour(),
+            minute: ts.minute(),
+            second: ts.second(),
+        }
+    }
+}
+
+impl traits::Timestamp for Timestamp {
+    fn year(&self) -> usize {
+        self.year
+    }
+
+    fn month(&self) -> u8 {
+        self.month
+    }
+
+    fn day(&self) -> u8 {
+        self.day
+    }
+
+    fn hour(&self) -> u8 {
+        self.hour
+    }
+
+    fn minute(&self) -> u8 {
+        self.minute
+    }
+
+    fn second(&self) -> u8 {
+        self.second
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}/{:02}/{:04} {:02}:{:02}:{:02}", self.month, self.day, self.year,
+               self.hour, self.minute, self.second)
+    }
+}
+
+/// Metadata of an inode, independent of its file system.
+#[derive(Default, Debug, Clone)]
+pub struct Metadata {
+    pub is_dir: bool,
+    pub read_only: bool,
+    pub hidden: bool,
+    pub size: u64,
+    pub created: Timestamp,
+    pub accessed: Timestamp,
+    pub modified: Timestamp,
+}
+
+impl Metadata {
+    /// Converts the metadata `metadata` of a file system's entry whose data
+    /// is `size` bytes long.
+    pub fn from<M: traits::Metadata>(metadata: &M, is_dir: bool, size: u64) -> Metadata {
+        Metadata {
+            is_dir,
+            read_only: metadata.read_only(),
+            hidden: metadata.hidden(),
+            size,
+            created: Timestamp::from(metadata.created()),
+            accessed: Timestamp::from(metadata.accessed()),
+            modified: Timestamp::from(metadata.modified()),
+        }
+    }
+}
+
+impl traits::Metadata for Metadata {
+    type Timestamp = Timestamp;
+
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn hidden(&self) -> bool {
+        self.hidden
+    }
+
+    fn created(&self) -> Self::Timestamp {
+        self.created
+    }
+
+    fn accessed(&self) -> Self::Timestamp {
+        self.accessed
+    }
+
+    fn modified(&self) -> Self::Timestamp {
+        self.modified
+    }
+}
+
+impl fmt::Display for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = [
+            if self.is_dir { 'd' } else { '-' },
+            if self.read_only { '-' } else { 'w' },
+            if self.hidden { 'h' } else { '-' },
+        ];
+        write!(f, "{}r{}{} {:10} {}", flags[0], flags[1], flags[2], self.size, self.modified)
+    }
+}
+
+/// A file system mounted at `path`.
+pub struct Mount {
+    /// The canonical absolute path of the mount point.
+    pub path: PathBuf,
+    /// What was mounted, such as the partition the file system lives on.
+    pub source: String,
+    pub fs: Rc<dyn Mountable>,
+}
+
+impl fmt::Debug for Mount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mount")
+            .field("path", &self.path)
+            .field("source", &self.source)
+            .field("fs_type", &self.fs.fs_type())
+            .finish()
+    }
+}
+
+/// Returns `path` without `.` and `..` components, resolving `..` lexically.
+/// `..` of the root directory is the root directory.
+///
+/// # Errors
+///
+/// Returns `InvalidInput` if `path` is not absolute.
+pub fn normalize<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
+    if !path.as_ref().is_absolute() {
+        return ioerr!(InvalidInput, "path must be absolute");
+    }
+
+    let mut normalized = PathBuf::from("/");
+    for component in path.as_ref().components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::ParentDir => {
+                normalized.pop();
+            },
+            _ => (),
+        }
+    }
+    Ok(normalized)
+}
+
+/// Returns the last component of the normalized path `path` as a `&str`.
+fn file_name(path: &Path) -> io::Result<&str> {
+    match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => Ok(name),
+        None => ioerr!(InvalidInput, "path has no valid file name")
+    }
+}
+
+/// The table of mounted file systems, which resolves paths by walking the
+/// inodes of the file system mounted deepest along them.
+#[derive(Debug, Default)]
+pub struct MountTable {
+    mounts: Vec<Mount>,
+}
+
+impl MountTable {
+    /// Returns an empty mount table.
+    pub fn new() -> MountTable {
+        MountTable { mounts: Vec::new() }
+    }
+
+    /// Returns the mounted file systems in the order they were mounted.
+    pub fn mounts(&self) -> &[Mount] {
+        &self.mounts
+    }
+
+    /// Mounts `fs` at `path`. Apart from the root file system, `path` must be
+    /// an existing directory or lie directly in one.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AlreadyExists` if a file system is already mounted at `path`
+    /// and `NotFound` if the parent of `path` is not a directory.
+    pub fn mount<P: AsRef<Path>>(&mut self, path: P, source: &str, fs: Rc<dyn Mountable>) -> io::Result<()> {
+        let path = normalize(path)?;
+        if self.mounts.iter().any(|mount| mount.path == path) {
+            return ioerr!(AlreadyExists, "a file system is already mounted there");
+        }
+        if let Some(parent) = path.parent() {
+            if !self.lookup(parent)?.is_dir() {
+                return ioerr!(NotFound, "parent of mount point is not a directory");
+            }
+        }
+
+        self.mounts.push(Mount { path, source: source.to_string(), fs });
+        Ok(())
+    }
+
+    /// Unmounts the file system mounted at `path`, after writing back what
+    /// was modified through it. Returns the file system.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if nothing is mounted at `path`, and `Other` if
+    /// other file systems are mounted below it.
+    pub fn unmount<P: AsRef<Path>>(&mut self, path: P) -> io::Result<Rc<dyn Mountable>> {
+        let path = normalize(path)?;
+        let index = match self.mounts.iter().position(|mount| mount.path == path) {
+            Some(index) => index,
+            None => return ioerr!(NotFound, "nothing is mounted there")
+        };
+        if self.mounts.iter().any(|mount| mount.path != path && mount.path.starts_with(&path)) {
+            return ioerr!(Other, "mount point is busy");
+        }
+
+        self.mounts[index].fs.sync()?;
+        Ok(self.mounts.remove(index).fs)
+    }
+
+    /// Returns the mount the normalized path `path` lies in, which is the one
+    /// with the longest mount point `path` starts with, along with the rest
+    /// of `path` as an absolute path within the mounted file system.
+    pub fn resolve(&self, path: &Path) -> io::Result<(&Mount, PathBuf)> {
+        let mount = self.mounts.iter()
+            .filter(|mount| path.starts_with(&mount.path))
+            .max_by_key(|mount| mount.path.components().count());
+        match mount {
+            Some(mount) => {
+                let rest = path.strip_prefix(&mount.path).unwrap_or(Path::new(""));
+                Ok((mount, Path::new("/").join(rest)))
+            },
+            None => ioerr!(NotFound, "no file system is mounted")
+        }
+    }
+
+    /// Returns the inode at the absolute path `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if there is no entry at `path` and `InvalidInput`
+    /// if a component but the last is not a directory.
+    pub fn lookup<P: AsRef<Path>>(&self, path: P) -> io::Result<Rc<dyn Inode>> {
+        let path = normalize(path)?;
+        let (mount, rest) = self.resolve(&path)?;
+        let mut inode = mount.fs.root()?;
+        for component in rest.components() {
+            if let Component::Normal(name) = component {
+                match name.to_str() {
+                    Some(name) => inode = inode.lookup(name)?,
+                    None => return ioerr!(InvalidInput, "name contained invalid utf8")
+                }
+            }
+        }
+        Ok(inode)
+    }
+
+    /// Returns the file systems mounted directly in the directory at the
+    /// normalized path `dir`, as `(name, root inode)` pairs.
+    fn mounted_in(&self, dir: &Path) -> io::Result<Vec<(String, Rc<dyn Inode>)>> {
+        let mut mounted = Vec::new();
+        for mount in self.mounts.iter().filter(|mount| mount.path.parent() == Some(dir)) {
+            mounted.push((file_name(&mount.path)?.to_string(), mount.fs.root()?));
+        }
+        Ok(mounted)
+    }
+
+    /// Opens the entry at `path`.
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<Entry> {
+        let path = normalize(path)?;
+        let inode = self.lookup(&path)?;
+        let name = match path.parent() {
+            Some(_) => file_name(&path)?.to_string(),
+            None => String::from("/")
+        };
+
+        match inode.is_dir() {
+            true => {
+                let mounted = self.mounted_in(&path)?;
+                Ok(Entry::Dir(Dir::new(name, inode, mounted)))
+            },
+            false => Ok(Entry::File(File::new(name, inode)))
+        }
+    }
+
+    /// Returns the directory `path` lies in and the name of its last
+    /// component. Fails with `AlreadyExists` or `Other` if a file system is
+    /// mounted at or below `path`.
+    fn parent_of(&self, path: &Path) -> io::Result<(Rc<dyn Inode>, String)> {
+        if self.mounts.iter().any(|mount| mount.path.starts_with(path)) {
+            return match path.parent() {
+                Some(_) => ioerr!(Other, "mount point is busy"),
+                None => ioerr!(AlreadyExists, "the root directory exists")
+            };
+        }
+
+        let parent = match path.parent() {
+            Some(parent) => self.lookup(parent)?,
+            None => return ioerr!(InvalidInput, "path has no parent")
+        };
+        Ok((parent, file_name(path)?.to_string()))
+    }
+
+    /// Creates an empty file at `path`.
+    pub fn create_file<P: AsRef<Path>>(&self, path: P) -> io::Result<File> {
+        let path = normalize(path)?;
+        let (parent, name) = self.parent_of(&path)?;
+        let inode = parent.create_file(&name)?;
+        Ok(File::new(name, inode))
+    }
+
+    /// Creates an empty directory at `path`.
+    pub fn create_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<Dir> {
+        let path = normalize(path)?;
+        let (parent, name) = self.parent_of(&path)?;
+        let inode = parent.create_dir(&name)?;
+        Ok(Dir::new(name, inode, Vec::new()))
+    }
+
+    /// Removes the file or empty directory at `path`.
+    pub fn remove<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = normalize(path)?;
+        let (parent, name) = self.parent_of(&path)?;
+        parent.remove(&name)
+    }
+
+    /// Moves the entry at `from` to `to`, which must lie in the same file
+    /// system.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Other` if `from` and `to` are in different file systems or
+    /// either is a mount point.
+    pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(&self, from: P, to: Q) -> io::Result<()> {
+        let (from, to) = (normalize(from)?, normalize(to)?);
+        self.parent_of(&from)?;
+        self.parent_of(&to)?;
+
+        let (from_mount, from_rest) = self.resolve(&from)?;
+        let (to_mount, to_rest) = self.resolve(&to)?;
+        if from_mount.path != to_mount.path {
+            return ioerr!(Other, "cannot move entries between file systems");
+        }
+        from_mount.fs.rename(&from_rest, &to_rest)
+    }
+}
+
+/// An open file.
+pub struct File {
+    pub name: String,
+    pub inode: Rc<dyn Inode>,
+    /// The file's metadata as of when it was opened.
+    pub metadata: Metadata,
+    pub offset: u64,
+}
+
+impl File {
+    /// Returns `inode`, named `name`, opened at its start.
+    pub fn new(name: String, inode: Rc<dyn Inode>) -> File {
+        let metadata = inode.metadata();
+        File { name, inode, metadata, offset: 0 }
+    }
+}
+
+impl fmt::Debug for File {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("File")
+            .field("name", &self.name)
+            .field("offset", &self.offset)
+            .finish()
+    }
+}
+
+impl traits::File for File {
+    fn sync(&mut self) -> io::Result<()> {
+        self.inode.sync()
+    }
+
+    fn size(&self) -> u64 {
+        self.inode.size()
+    }
+}
+
+impl io::Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inode.read_at(self.offset, buf)?;
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
+
+impl io::Write for File {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inode.write_at(self.offset, buf)?;
+        self.offset += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inode.sync()
+    }
+}
+
+impl io::Seek for File {
+    /// Seek to offset `pos` in the file.
+    ///
+    /// A seek to the end of the file is allowed. A seek _beyond_ the end of the
+    /// file returns an `InvalidInput` error.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let size = self.inode.size();
+        let new_offset = match pos {
+            SeekFrom::Start(off) => off as i64,
+            SeekFrom::Current(off) => self.offset as i64 + off,
+            SeekFrom::End(off) => size as i64 + off
+        };
+
+        if new_offset < 0 || new_offset as u64 > size {
+            return ioerr!(InvalidInput, "invalid seek");
+        }
+        self.offset = new_offset as u64;
+        Ok(self.offset)
+    }
+}
+
+/// An open directory. File systems mounted in it are listed among its
+/// entries, in place of what they are mounted over.
+pub struct Dir {
+    pub name: String,
+    pub inode: Rc<dyn Inode>,
+    pub metadata: Metadata,
+    mounted: Vec<(String, Rc<dyn Inode>)>,
+}
+
+impl Dir {
+    fn new(name: String, inode: Rc<dyn Inode>, mounted: Vec<(String, Rc<dyn Inode>)>) -> Dir {
+        let metadata = inode.metadata();
+        Dir { name, inode, metadata, mounted }
+    }
+}
+
+impl fmt::Debug for Dir {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dir")
+            .field("name", &self.name)
+            .field("mounted", &self.mounted.iter().map(|(name, _)| name).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl traits::Dir for Dir {
+    type Entry = Entry;
+    type Iter = alloc::vec::IntoIter<Entry>;
+
+    fn entries(&self) -> io::Result<Self::Iter> {
+        let mut entries: Vec<Entry> = Vec::new();
+        for inode in self.inode.entries()? {
+            let name = inode.name().to_string();
+            if self.mounted.iter().all(|(mounted, _)| *mounted != name) {
+                entries.push(Entry::from_inode(name, inode));
+            }
+        }
+        for (name, inode) in self.mounted.iter() {
+            entries.push(Entry::from_inode(name.clone(), inode.clone()));
+        }
+        Ok(entries.into_iter())
+    }
+}
+
+/// An entry of a directory, or a path opened through the mount table.
+#[derive(Debug)]
+pub enum Entry {
+    File(File),
+    Dir(Dir),
+}
+
+impl Entry {
+    fn from_inode(name: String, inode: Rc<dyn Inode>) -> Entry {
+        match inode.is_dir() {
+            true => Entry::Dir(Dir::new(name, inode, Vec::new())),
+            false => Entry::File(File::new(name, inode)),
+        }
+    }
+}
+
+impl traits::Entry for Entry {
+    type File = File;
+    type Dir = Dir;
+    type Metadata = Metadata;
+
+    fn name(&self) -> &str {
+        match self {
+            Entry::File(f) => &f.name,
+            Entry::Dir(d) => &d.name
+        }
+    }
+
+    fn metadata(&self) -> &Self::Metadata {
+        match self {
+            Entry::File(f) => &f.metadata,
+            Entry::Dir(d) => &d.metadata
+        }
+    }
+
+    fn as_file(&self) -> Option<&File> {
+        match &self {
+            Entry::File(f) => Some(&f),
+            _ => None
+        }
+    }
+
+    fn as_dir(&self) -> Option<&Dir> {
+        match &self {
+            Entry::Dir(d) => Some(&d),
+            _ => None
+        }
+    }
+
+    fn into_file(self) -> Option<File> {
+        match self {
+            Entry::File(f) => Some(f),
+            _ => None
+        }
+    }
+
+    fn into_dir(self) -> Option<Dir> {
+        match self {
+            Entry::Dir(d) => Some(d),
+            _ => None
+        }
+    }
+}
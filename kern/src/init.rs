@@ -19,11 +19,13 @@ global_asm!(include_str!("init/vectors.s"));
 // so, no debug build support!
 //
 
+/// The entry point. Newer firmware passes the address of a device tree blob
+/// in `x0`, the first argument.
 #[no_mangle]
-pub unsafe extern "C" fn _start() -> ! {
+pub unsafe extern "C" fn _start(dtb: usize) -> ! {
     if MPIDR_EL1.get_value(MPIDR_EL1::Aff0) == 0 {
         SP.set(KERN_STACK_BASE);
-        kinit()
+        kinit(dtb)
     }
     unreachable!()
 }
@@ -102,8 +104,9 @@ unsafe fn switch_to_el1() {
 }
 
 #[no_mangle]
-unsafe fn kinit() -> ! {
+unsafe fn kinit(dtb: usize) -> ! {
     zeros_bss();
+    pi::bootinfo::set_dtb_address(dtb);
     switch_to_el2();
     switch_to_el1();
     kmain();
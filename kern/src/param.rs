@@ -23,6 +23,25 @@ pub const USER_MAX_VM_SIZE: usize = 0x4000_0000;
 const_assert_eq!(USER_IMG_BASE.wrapping_add(USER_MAX_VM_SIZE), 0);
 pub const KERN_STACK_BASE: usize = 0x80_000;
 
+/// The number of sectors the file system keeps cached in the kernel heap.
+pub const FS_CACHE_SECTORS: usize = 2048;
+
+/// The size, in sectors, of the metadata journal created by `journal on`. A
+/// flush fails while more metadata sectors are dirty than it holds.
+pub const FS_JOURNAL_SECTORS: u32 = 1024;
+
+/// The most file data, in bytes, a tmpfs may hold in the kernel heap.
+pub const TMPFS_CAPACITY: u64 = 4 * 1024 * 1024;
+
+/// The longest path, in bytes, a system call accepts.
+pub const PATH_MAX: usize = 1024;
+
+/// The most bytes of arguments `exec()` accepts.
+pub const EXEC_MAX_ARG_SIZE: usize = 4096;
+
+/// The most file descriptors a process may have open at once.
+pub const PROCESS_MAX_FDS: usize = 64;
+
 /// The `tick` time.
 // FIXME: When you're ready, change this to something more reasonable.
 //pub const TICK: Duration = Duration::from_secs(2);
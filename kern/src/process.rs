@@ -1,3 +1,5 @@
+mod elf;
+pub mod fd;
 mod process;
 mod scheduler;
 mod stack;
@@ -0,0 +1,125 @@
+use alloc::vec::Vec;
+use core::mem;
+
+use shim::const_assert_size;
+use shim::io;
+
+use kernel_api::{OsError, OsResult};
+
+/// `e_ident` magic bytes every ELF image starts with.
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+/// `e_ident[EI_CLASS]` of 64-bit images.
+const ELFCLASS64: u8 = 2;
+/// `e_ident[EI_DATA]` of little endian images.
+const ELFDATA2LSB: u8 = 1;
+/// The only ELF version there is.
+const EV_CURRENT: u8 = 1;
+/// `e_type` of executable (non position independent) images.
+const ET_EXEC: u16 = 2;
+/// `e_machine` of AArch64 images.
+const EM_AARCH64: u16 = 183;
+
+/// `p_type` of a segment to be loaded into memory.
+pub const PT_LOAD: u32 = 1;
+
+/// `p_flags` bit of executable segments.
+pub const PF_X: u32 = 1 << 0;
+/// `p_flags` bit of writable segments.
+pub const PF_W: u32 = 1 << 1;
+
+/// The ELF64 file header.
+#[repr(C)]
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone)]
+pub struct Header {
+    pub ident: [u8; 16],
+    pub kind: u16,
+    pub machine: u16,
+    pub version: u32,
+    pub entry: u64,
+    pub phoff: u64,
+    pub shoff: u64,
+    pub flags: u32,
+    pub ehsize: u16,
+    pub phentsize: u16,
+    pub phnum: u16,
+    pub shentsize: u16,
+    pub shnum: u16,
+    pub shstrndx: u16,
+}
+
+const_assert_size!(Header, 64);
+
+/// An ELF64 program header, describing one segment of the image.
+#[repr(C)]
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone)]
+pub struct ProgramHeader {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub paddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+    pub align: u64,
+}
+
+const_assert_size!(ProgramHeader, 56);
+
+/// Reads exactly `buf.len()` bytes, reporting a short image as
+/// `InvalidArgument` rather than as an I/O error.
+fn read_exact<R: io::Read>(image: &mut R, buf: &mut [u8]) -> OsResult<()> {
+    match image.read_exact(buf) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(OsError::InvalidArgument),
+        Err(e) => Err(e.into()),
+    }
+}
+
+impl Header {
+    /// Reads the file header from the start of `image` and checks that it
+    /// describes a little endian AArch64 executable.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidArgument` if the image is too short or the header is
+    /// malformed or for another machine.
+    pub fn read<R: io::Read + io::Seek>(image: &mut R) -> OsResult<Header> {
+        let mut buf = [0u8; 64];
+        image.seek(io::SeekFrom::Start(0))?;
+        read_exact(image, &mut buf)?;
+        let header = unsafe { mem::transmute::<[u8; 64], Header>(buf) };
+
+        if header.ident[..4] != ELF_MAGIC
+            || header.ident[4] != ELFCLASS64
+            || header.ident[5] != ELFDATA2LSB
+            || header.ident[6] != EV_CURRENT
+            || header.kind != ET_EXEC
+            || header.machine != EM_AARCH64
+            || header.phentsize as usize != mem::size_of::<ProgramHeader>()
+        {
+            return Err(OsError::InvalidArgument);
+        }
+
+        Ok(header)
+    }
+
+    /// Reads the program headers the header points to from `image`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidArgument` if the headers lie past the end of the image.
+    pub fn program_headers<R: io::Read + io::Seek>(&self, image: &mut R) -> OsResult<Vec<ProgramHeader>> {
+        image.seek(io::SeekFrom::Start(self.phoff)).map_err(|_| OsError::InvalidArgument)?;
+
+        let mut headers = Vec::with_capacity(self.phnum as usize);
+        for _ in 0..self.phnum {
+            let mut buf = [0u8; 56];
+            read_exact(image, &mut buf)?;
+            headers.push(unsafe { mem::transmute::<[u8; 56], ProgramHeader>(buf) });
+        }
+
+        Ok(headers)
+    }
+}
@@ -0,0 +1,83 @@
+use alloc::vec::Vec;
+
+use kernel_api::{OsError, OsResult};
+
+use crate::fs::File;
+use crate::param::PROCESS_MAX_FDS;
+
+/// What a file descriptor refers to.
+#[derive(Debug)]
+pub enum Descriptor {
+    /// The console: reads come from the UART and writes go to it.
+    Console,
+    /// A file of `FILESYSTEM`, opened with the `NR_OPEN` flags `flags`.
+    File { file: File, flags: u64 },
+}
+
+/// The open file descriptors of a process, indexed by number.
+#[derive(Debug)]
+pub struct FdTable(Vec<Option<Descriptor>>);
+
+// See `MountTable`: open files refer to their file system through `Rc`s.
+unsafe impl Send for FdTable {}
+
+impl FdTable {
+    /// Returns a table with descriptors 0, 1 and 2 open on the console.
+    pub fn new() -> FdTable {
+        let mut fds = Vec::with_capacity(3);
+        for _ in 0..3 {
+            fds.push(Some(Descriptor::Console));
+        }
+        FdTable(fds)
+    }
+
+    /// Returns a copy of the table for a forked process. Its files share the
+    /// originals' inodes but start with copies of their offsets, so the two
+    /// processes seek independently.
+    pub fn duplicate(&self) -> FdTable {
+        let fds = self.0.iter().map(|desc| match desc {
+            Some(Descriptor::Console) => Some(Descriptor::Console),
+            Some(Descriptor::File { file, flags }) => {
+                let mut copy = File::new(file.name.clone(), file.inode.clone());
+                copy.offset = file.offset;
+                Some(Descriptor::File { file: copy, flags: *flags })
+            }
+            None => None,
+        });
+        FdTable(fds.collect())
+    }
+
+    /// Stores `desc` at the lowest free descriptor and returns its number.
+    ///
+    /// Returns `NoMemory` if `PROCESS_MAX_FDS` descriptors are open already.
+    pub fn insert(&mut self, desc: Descriptor) -> OsResult<u64> {
+        if let Some(fd) = self.0.iter().position(|desc| desc.is_none()) {
+            self.0[fd] = Some(desc);
+            return Ok(fd as u64);
+        }
+
+        if self.0.len() >= PROCESS_MAX_FDS {
+            return Err(OsError::NoMemory);
+        }
+        self.0.push(Some(desc));
+        Ok(self.0.len() as u64 - 1)
+    }
+
+    /// Returns what the descriptor `fd` refers to.
+    ///
+    /// Returns `InvalidArgument` if `fd` is not open.
+    pub fn get(&mut self, fd: u64) -> OsResult<&mut Descriptor> {
+        self.0.get_mut(fd as usize)
+            .and_then(|desc| desc.as_mut())
+            .ok_or(OsError::InvalidArgument)
+    }
+
+    /// Closes the descriptor `fd` and returns what it referred to.
+    ///
+    /// Returns `InvalidArgument` if `fd` is not open.
+    pub fn remove(&mut self, fd: u64) -> OsResult<Descriptor> {
+        self.0.get_mut(fd as usize)
+            .and_then(|desc| desc.take())
+            .ok_or(OsError::InvalidArgument)
+    }
+}
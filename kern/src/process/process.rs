@@ -1,16 +1,18 @@
 use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
 use shim::io;
 use shim::path::Path;
 use crate::param::*;
-use crate::process::{Stack, State};
+use crate::process::fd::FdTable;
+use crate::process::{elf, Stack, State};
 use crate::traps::TrapFrame;
 use crate::vm::*;
 use kernel_api::{OsError, OsResult};
 use core::mem;
 use crate::FILESYSTEM;
-use fat32::traits::FileSystem;
-use crate::fs::PiVFatHandle;
-use fat32::vfat::File;
+use fat32::traits::{File as _, FileSystem};
+use crate::fs::File;
 use core::cmp::min;
 use crate::allocator::util::align_down;
 
@@ -28,6 +30,13 @@ pub struct Process {
     pub vmap: Box<UserPageTable>,
     /// The scheduling state of the process.
     pub state: State,
+    /// The ID of the process that forked this one, if it is still alive.
+    pub parent: Option<Id>,
+    /// The child (or `WAIT_ANY`) whose exit status this process is blocked
+    /// in `wait()` for. Cleared by the scheduler when the status arrives.
+    pub waiting_for: Option<Id>,
+    /// The open file descriptors of the process.
+    pub fds: FdTable,
 }
 
 impl Process {
@@ -47,15 +56,95 @@ impl Process {
                 context: Box::new(TrapFrame::default()),
                 stack,
                 vmap: Box::new(UserPageTable::new()),
-                state: State::Ready
+                state: State::Ready,
+                parent: None,
+                waiting_for: None,
+                fds: FdTable::new()
             }
         )
     }
 
+    /// Returns a child of this process for `fork()`. The child resumes from
+    /// `tf`, the trap frame this process entered the kernel with, seeing a
+    /// return value of `0`, and shares this process's pages copy-on-write.
+    ///
+    /// Returns `NoMemory` if the child's stack could not be allocated.
+    pub fn fork(&mut self, tf: &TrapFrame) -> OsResult<Process> {
+        let stack = match Stack::new() {
+            Some(s) => s,
+            None => return Err(OsError::NoMemory)
+        };
+
+        let mut child = Process {
+            context: Box::new(*tf),
+            stack,
+            vmap: Box::new(self.vmap.fork()),
+            state: State::Ready,
+            parent: Some(self.context.tpidr),
+            waiting_for: None,
+            fds: self.fds.duplicate()
+        };
+
+        child.context.ttbr1 = child.vmap.get_baddr().as_u64();
+        child.context.x_regs[0] = 0;
+        child.context.x_regs[7] = OsError::Ok as u64;
+        Ok(child)
+    }
+
+    /// Replaces the image of this process with the program stored in the
+    /// given path, passing it `args`. The process keeps its ID and open file
+    /// descriptors, and its reset trap frame is restored into `tf` so that
+    /// returning to user space starts the new program.
+    ///
+    /// Returns the error of `load()` if the program cannot be loaded, in which
+    /// case this process is left untouched.
+    pub fn exec<P: AsRef<Path>>(&mut self, pn: P, args: &[&str], tf: &mut TrapFrame) -> OsResult<()> {
+        let mut image = Process::load(pn)?;
+        image.push_args(args);
+        image.context.tpidr = self.context.tpidr;
+
+        // the old page table is dropped here, freeing the pages only this
+        // process mapped
+        self.vmap = image.vmap;
+        self.context = image.context;
+        *tf = *self.context;
+        Ok(())
+    }
+
+    /// Copies `args` to the top of the stack of a freshly loaded process and
+    /// lays out a `[&str]` referring to them below. The program starts with
+    /// the number of arguments in `x0`, the address of the slice in `x1`, and
+    /// `sp` right below it.
+    ///
+    /// The caller makes sure the arguments fit in the stack page.
+    fn push_args(&mut self, args: &[&str]) {
+        let base = Self::get_stack_base().as_usize();
+        let mut sp = Self::get_stack_top().as_usize();
+        let page = self.vmap.get_page(Self::get_stack_base()).expect("stack allocated");
+
+        let mut strs = Vec::with_capacity(args.len());
+        for arg in args.iter() {
+            sp -= arg.len();
+            page[sp - base..sp - base + arg.len()].copy_from_slice(arg.as_bytes());
+            strs.push((sp as u64, arg.len() as u64));
+        }
+
+        sp = align_down(sp, 16) - 16 * args.len();
+        for (i, &(ptr, len)) in strs.iter().enumerate() {
+            let offset = sp - base + 16 * i;
+            page[offset..offset + 8].copy_from_slice(&ptr.to_le_bytes());
+            page[offset + 8..offset + 16].copy_from_slice(&len.to_le_bytes());
+        }
+
+        self.context.x_regs[0] = args.len() as u64;
+        self.context.x_regs[1] = sp as u64;
+        self.context.sp = sp as u64;
+    }
+
     /// Load a program stored in the given path by calling `do_load()` method.
     /// Set trapframe `context` corresponding to the its page table.
     /// `sp` - the address of stack top
-    /// `elr` - the address of image base.
+    /// `elr` - the entry point of the image, set by `do_load()`.
     /// `ttbr0` - the base address of kernel page table
     /// `ttbr1` - the base address of user page table
     /// `spsr` - `F`, `A`, `D` bit should be set.
@@ -69,7 +158,6 @@ impl Process {
         // set up context
         p.context.ttbr0 = VMM.get_baddr().as_u64();
         p.context.ttbr1 = p.vmap.get_baddr().as_u64();
-        p.context.elr = Self::get_image_base().as_u64();
         p.context.sp = Self::get_stack_top().as_u64();
         // set bit 4 to be in aarch64 (0)
         // set bits 0-3 to execute in EL0, correct sp (0)
@@ -79,37 +167,91 @@ impl Process {
         Ok(p)
     }
 
-    /// Creates a process and open a file with given path.
-    /// Allocates one page for stack with read/write permission, and N pages with read/write/execute
-    /// permission to load file's contents.
+    /// Creates a process and loads the ELF64 executable stored at the given
+    /// path into it. Allocates one page for stack with read/write permission,
+    /// and maps every `PT_LOAD` segment at its virtual address with the
+    /// permission its flags ask for; a page shared by two segments gets the
+    /// permissions of both. Memory past a segment's file contents (`.bss`) is
+    /// zero-filled. The entry point is stored in the `elr` of the context.
+    ///
+    /// Returns `NoEntry` if there is no file at `pn`, `NoMemory` if its pages
+    /// cannot be allocated, and `InvalidArgument` if the file is not an AArch64
+    /// executable, or if a segment is malformed or lies outside of the user
+    /// image space.
     fn do_load<P: AsRef<Path>>(pn: P) -> OsResult<Process> {
-        use io::Read;
+        use io::{Read, Seek, SeekFrom};
         // create a process struct
         let mut loaded_proc = Process::new()?;
-        loaded_proc.vmap.alloc(Self::get_stack_base(), PagePerm::RW); // allocate a page for the stack
+        loaded_proc.vmap.alloc(Self::get_stack_base(), PagePerm::RW)?; // allocate a page for the stack
 
         // open the file
-        let mut bin_file: File<PiVFatHandle> = match FILESYSTEM.open_file(pn) {
-            Ok(f) => f,
-            Err(_) => return Err(OsError::IoError)
-        };
+        let mut bin_file: File = FILESYSTEM.open_file(pn)?;
+        let bin_size = bin_file.size();
+
+        let header = elf::Header::read(&mut bin_file)?;
+        let segments: Vec<elf::ProgramHeader> = header.program_headers(&mut bin_file)?
+            .into_iter()
+            .filter(|ph| ph.kind == elf::PT_LOAD)
+            .collect();
+
+        // check the segments, and gather the flags of every page they cover
+        let mut pages: BTreeMap<usize, u32> = BTreeMap::new();
+        let mut entry_mapped = false;
+        for ph in segments.iter() {
+            let end = match (ph.vaddr.checked_add(ph.memsz), ph.offset.checked_add(ph.filesz)) {
+                (Some(end), Some(file_end)) if ph.filesz <= ph.memsz
+                    && file_end <= bin_size
+                    && ph.vaddr >= USER_IMG_BASE as u64
+                    && end <= USER_STACK_BASE as u64 => end as usize,
+                _ => return Err(OsError::InvalidArgument)
+            };
+
+            if ph.flags & elf::PF_X != 0 && ph.vaddr <= header.entry && header.entry < end as u64 {
+                entry_mapped = true;
+            }
+
+            let mut page_va = align_down(ph.vaddr as usize, PAGE_SIZE);
+            while page_va < end {
+                *pages.entry(page_va).or_insert(0) |= ph.flags;
+                page_va += PAGE_SIZE;
+            }
+        }
+
+        if !entry_mapped {
+            return Err(OsError::InvalidArgument);
+        }
+
+        // allocate the (zeroed) pages
+        for (&page_va, &flags) in pages.iter() {
+            let perm = match (flags & elf::PF_W != 0, flags & elf::PF_X != 0) {
+                (true, true) => PagePerm::RWX,
+                (true, false) => PagePerm::RW,
+                (false, true) => PagePerm::RX,
+                (false, false) => PagePerm::RO,
+            };
+            loaded_proc.vmap.alloc(page_va.into(), perm)?;
+        }
 
-        // allocate file pages, read into them
-        let bin_size = bin_file.size as usize;
-        let num_pages = (bin_size / PAGE_SIZE) + 1;
+        // read the file contents of every segment into its pages
+        for ph in segments.iter() {
+            bin_file.seek(SeekFrom::Start(ph.offset))?;
 
-        let mut remaining_bytes = bin_size;
-        for page_num in 0..num_pages {
-            let page_va = Self::get_image_base() + (PAGE_SIZE * (page_num as usize)).into();
-            let page = loaded_proc.vmap.alloc(page_va.into(), PagePerm::RWX); // allocate a page 
-            let bytes_to_read = min(PAGE_SIZE, remaining_bytes);
+            let mut va = ph.vaddr as usize;
+            let end = va + ph.filesz as usize;
+            while va < end {
+                let page_va = align_down(va, PAGE_SIZE);
+                let offset = va - page_va;
+                let bytes_to_read = min(PAGE_SIZE - offset, end - va);
+                let page = loaded_proc.vmap.get_page(page_va.into()).expect("segment page allocated");
 
-            if let Err(_) = bin_file.read_exact(&mut page[0..bytes_to_read]) {
-                return Err(OsError::IoError);
+                if let Err(_) = bin_file.read_exact(&mut page[offset..offset + bytes_to_read]) {
+                    return Err(OsError::IoError);
+                }
+                va += bytes_to_read;
             }
-            remaining_bytes -= bytes_to_read;
         }
 
+        loaded_proc.context.elr = header.entry;
         Ok(loaded_proc)
     }
 
@@ -162,6 +304,8 @@ impl Process {
             } else {
                 mem::replace(&mut self.state, State::Waiting(done));
             }
+        } else {
+            self.state = state;
         }
 
         return false;
@@ -1,16 +1,19 @@
 use alloc::boxed::Box;
 use alloc::collections::vec_deque::VecDeque;
+use alloc::vec::Vec;
 use core::fmt;
 use crate::mutex::Mutex;
 use crate::param::{TICK};
 use crate::process::{Id, Process, State};
 use crate::traps::TrapFrame;
+use crate::vm::VirtualAddr;
 use crate::IRQ;
 use crate::SCHEDULER;
 extern crate pi;
 use pi::interrupt;
 use pi::timer;
 use core::fmt::Formatter;
+use kernel_api::{OsError, OsResult, WAIT_ANY};
 
 /// Process scheduler for the entire machine.
 #[derive(Debug)]
@@ -64,11 +67,39 @@ impl GlobalScheduler {
         }
     }
 
-    /// Kills currently running process and returns that process's ID.
-    /// For more details, see the documentaion on `Scheduler::kill()`.
+    /// Kills currently running process, as if it exited with status `-1`,
+    /// and returns that process's ID.
     #[must_use]
     pub fn kill(&self, tf: &mut TrapFrame) -> Option<Id> {
-        self.critical(|scheduler| scheduler.kill(tf))
+        self.exit(-1, tf)
+    }
+
+    /// Ends currently running process with exit status `status`, switches to
+    /// the next process, and returns the ended process's ID. For more
+    /// details, see the documentation on `Scheduler::exit()`.
+    pub fn exit(&self, status: i32, tf: &mut TrapFrame) -> Option<Id> {
+        let pid = self.critical(|scheduler| scheduler.exit(status, tf))?;
+        self.switch_to(tf);
+        Some(pid)
+    }
+
+    /// Collects the exit status of a child of currently running process.
+    /// For more details, see the documentation on `Scheduler::wait()`.
+    pub fn wait(&self, pid: Id) -> OsResult<Option<(Id, i32)>> {
+        self.critical(|scheduler| scheduler.wait(pid))
+    }
+
+    /// Forks the currently running process and returns the child's ID.
+    /// For more details, see the documentation on `Scheduler::fork()`.
+    pub fn fork(&self, tf: &mut TrapFrame) -> OsResult<Id> {
+        self.critical(|scheduler| scheduler.fork(tf))
+    }
+
+    /// Resolves a write fault of the currently running process on the
+    /// copy-on-write page containing `va`. Returns `false` if the fault was
+    /// not caused by copy-on-write.
+    pub fn resolve_cow(&self, va: VirtualAddr) -> bool {
+        self.critical(|scheduler| scheduler.resolve_cow(va))
     }
 
     /// Starts executing processes in user space using timer interrupt based
@@ -107,14 +138,8 @@ impl GlobalScheduler {
     pub unsafe fn initialize(&self) {
         *self.0.lock() = Some(Scheduler::new()); 
         
-        self.add(Process::load("/fib").unwrap());
-        self.add(Process::load("/fib").unwrap());
-        self.add(Process::load("/fib").unwrap());
-        self.add(Process::load("/fib").unwrap());
-        self.add(Process::load("/syscall_test").unwrap());
-        self.add(Process::load("/syscall_test").unwrap());
-        self.add(Process::load("/syscall_test").unwrap());
-        self.add(Process::load("/syscall_test").unwrap());
+        // `/init` starts every other program with `fork()` and `exec()`
+        self.add(Process::load("/init").unwrap());
     }
 
     // The following method may be useful for testing Phase 3:
@@ -125,7 +150,7 @@ impl GlobalScheduler {
         use crate::vm::{VirtualAddr, PagePerm};
     
         let mut page = proc.vmap.alloc(
-            VirtualAddr::from(USER_IMG_BASE as u64), PagePerm::RWX);
+            VirtualAddr::from(USER_IMG_BASE as u64), PagePerm::RWX).unwrap();
     
         let text = unsafe {
             core::slice::from_raw_parts(test_user_process as *const u8, 24)
@@ -135,9 +160,19 @@ impl GlobalScheduler {
     }*/
 }
 
+/// A process that exited before its parent collected its exit status. All
+/// of its resources are freed; only its ID and status are kept.
+#[derive(Debug)]
+struct Zombie {
+    pid: Id,
+    parent: Id,
+    status: i32,
+}
+
 #[derive(Debug)]
 pub struct Scheduler {
     processes: VecDeque<Process>,
+    zombies: Vec<Zombie>,
     last_id: Option<Id>,
 }
 
@@ -146,6 +181,7 @@ impl Scheduler {
     fn new() -> Scheduler {
         Scheduler {
             processes: VecDeque::new(),
+            zombies: Vec::new(),
             last_id: None
         }
     }
@@ -158,7 +194,8 @@ impl Scheduler {
     /// It is the caller's responsibility to ensure that the first time `switch`
     /// is called, that process is executing on the CPU.
     fn add(&mut self, mut process: Process) -> Option<Id> {
-        if self.last_id == Some(u64::max_value()) {
+        // `WAIT_ANY` is never handed out as an ID
+        if self.last_id == Some(WAIT_ANY - 1) {
             return None;
         }
 
@@ -214,24 +251,113 @@ impl Scheduler {
         Some(next_id)
     }
 
-    /// Kills currently running process by scheduling out the current process
-    /// as `Dead` state. Removes the dead process from the queue, drop the
-    /// dead process's instance, and returns the dead process's process ID.
-    fn kill(&mut self, tf: &mut TrapFrame) -> Option<Id> {
+    /// Returns the currently running process, if any.
+    pub fn current(&mut self) -> Option<&mut Process> {
+        match self.processes.front_mut() {
+            Some(process) => match process.state {
+                State::Running => Some(process),
+                _ => None
+            },
+            None => None
+        }
+    }
+
+    /// Creates a child of the currently running process, whose trap frame is
+    /// `tf`, and adds it to the queue. Returns the child's ID.
+    ///
+    /// Returns `NoMemory` if the child could not be created or scheduled.
+    fn fork(&mut self, tf: &mut TrapFrame) -> OsResult<Id> {
+        let child = match self.current() {
+            Some(process) => process.fork(tf)?,
+            None => return Err(OsError::Unknown)
+        };
+
+        self.add(child).ok_or(OsError::NoMemory)
+    }
+
+    /// Resolves a copy-on-write fault at `va` of the currently running
+    /// process. See `UserPageTable::resolve_cow()`.
+    fn resolve_cow(&mut self, va: VirtualAddr) -> bool {
+        self.current().map_or(false, |process| process.vmap.resolve_cow(va))
+    }
+
+    /// Collects the exit status of the child of the currently running process
+    /// with ID `pid`, or of any of its children if `pid` is `WAIT_ANY`. If
+    /// such a child already exited, its zombie is dropped and its ID and
+    /// status are returned. Otherwise the process is marked as waiting for
+    /// it, `None` is returned, and `exit()` delivers the status later.
+    ///
+    /// Returns `NoEntry` if the process has no such child.
+    fn wait(&mut self, pid: Id) -> OsResult<Option<(Id, i32)>> {
+        let ppid = self.current().ok_or(OsError::Unknown)?.context.tpidr;
+        let zombie = self.zombies.iter().position(|zombie| {
+            zombie.parent == ppid && (pid == WAIT_ANY || zombie.pid == pid)
+        });
+        if let Some(i) = zombie {
+            let zombie = self.zombies.remove(i);
+            return Ok(Some((zombie.pid, zombie.status)));
+        }
+
+        let alive = self.processes.iter().any(|process| {
+            process.parent == Some(ppid) && (pid == WAIT_ANY || process.context.tpidr == pid)
+        });
+        if !alive {
+            return Err(OsError::NoEntry);
+        }
+
+        self.current().unwrap().waiting_for = Some(pid);
+        Ok(None)
+    }
+
+    /// Ends the currently running process with exit status `status` by
+    /// scheduling it out as `Dead`, and returns its ID. Its children are
+    /// orphaned, and those that already exited are dropped.
+    ///
+    /// If its parent is waiting for it, the status is written into the
+    /// parent's trap frame right away. If the parent is alive but not
+    /// waiting, its ID and status are kept as a `Zombie` until the parent
+    /// collects them. Either way the process itself is dropped.
+    ///
+    /// The caller switches to the next process.
+    fn exit(&mut self, status: i32, tf: &mut TrapFrame) -> Option<Id> {
         // stop current proc and set state to dead
-        if self.schedule_out(State::Dead, tf) {
-            // dead boi will be at back of queue after schedule out
-            // this method needs to be syncronized for this to work properly
-            let kill_me = self.processes.pop_back()?;
-            let pid = kill_me.context.tpidr;
-            core::mem::drop(kill_me);
-            if let None = self.switch_to(tf) {
-                aarch64::wfi();
+        if !self.schedule_out(State::Dead, tf) {
+            return None;
+        }
+
+        // dead proc will be at back of queue after schedule out
+        let dead = self.processes.pop_back()?;
+        let pid = dead.context.tpidr;
+
+        // nobody is left to collect the children that already exited
+        self.zombies.retain(|zombie| zombie.parent != pid);
+        for process in self.processes.iter_mut() {
+            if process.parent == Some(pid) {
+                process.parent = None;
+            }
+        }
+
+        let parent_idx = dead.parent.and_then(|ppid| {
+            self.processes.iter().position(|process| process.context.tpidr == ppid)
+        });
+
+        if let Some(i) = parent_idx {
+            let parent = &mut self.processes[i];
+            match parent.waiting_for {
+                Some(id) if id == pid || id == WAIT_ANY => {
+                    parent.context.x_regs[0] = pid;
+                    parent.context.x_regs[1] = status as u64;
+                    parent.context.x_regs[7] = OsError::Ok as u64;
+                    parent.waiting_for = None;
+                }
+                _ => {
+                    let parent = parent.context.tpidr;
+                    self.zombies.push(Zombie { pid, parent, status });
+                }
             }
-            Some(pid)
-        } else {
-            None
         }
+
+        Some(pid)
     }
 }
 
@@ -240,6 +366,9 @@ impl fmt::Display for Scheduler {
         for process in self.processes.iter() {
             write!(f, "{}:{:#?} -> ", process.context.tpidr, process.state)?;
         }
+        for zombie in self.zombies.iter() {
+            write!(f, "{}:Zombie({}) -> ", zombie.pid, zombie.status)?;
+        }
         write!(f, "end")
     }
 }
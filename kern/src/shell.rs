@@ -7,7 +7,8 @@ use pi::atags::Atags;
 
 use fat32::traits::FileSystem;
 use fat32::traits::{Dir, Entry};
-use crate::fs::PiVFatHandle;
+use crate::fs::procfs::ProcFs;
+use crate::fs::{self, Mountable};
 
 use crate::console::{kprint, kprintln, CONSOLE};
 use pi::gpio::Gpio;
@@ -17,7 +18,8 @@ use crate::ALLOCATOR;
 use crate::FILESYSTEM;
 use core::str;
 
-use fat32::vfat::File;
+use crate::fs::File;
+use alloc::rc::Rc;
 
 const BOOTLOADER_START_ADDR: usize = 0x4000000;
 const BOOTLOADER_START: *mut u8 = BOOTLOADER_START_ADDR as *mut u8;
@@ -140,7 +142,7 @@ fn ls<P: AsRef<Path>>(cwd: P, args: &mut StackVec<&str>) {
     kprintln!();
     for entry in entries {
         if !entry.metadata().hidden() || display_hidden {
-            kprintln!("{}", entry.metadata());
+            kprintln!("{} {}", entry.metadata(), entry.name());
         }
     }
 }
@@ -197,7 +199,7 @@ fn cat<P: AsRef<Path>>(cwd: P, args: StackVec<&str>) {
                     break;
                 }
             };
-            let mut f: File<PiVFatHandle> = match FILESYSTEM.open_file(&abs_path) {
+            let mut f: File = match FILESYSTEM.open_file(&abs_path) {
                 Ok(res) => res,
                 Err(_) => {
                     kprintln!("\ncan't open file: {}", &abs_path.to_str().unwrap());
@@ -223,6 +225,203 @@ fn cat<P: AsRef<Path>>(cwd: P, args: StackVec<&str>) {
     }
 }
 
+/// Resolves `arg` against `cwd` into a canonical absolute path.
+fn resolve<P: AsRef<Path>>(cwd: P, arg: &str) -> Result<PathBuf, ()> {
+    let mut raw_path: PathBuf = [arg].iter().collect();
+    if !raw_path.is_absolute() {
+        raw_path = cwd.as_ref().join(raw_path);
+    }
+
+    canonicalize(raw_path)
+}
+
+fn touch<P: AsRef<Path>>(cwd: P, args: StackVec<&str>) {
+    if args.len() < 2 {
+        kprintln!("\nusage: touch <file1> ... <filen>");
+        return;
+    }
+
+    for arg in args.as_slice()[1..].iter() {
+        let abs_path = match resolve(&cwd, arg) {
+            Ok(p) => p,
+            Err(_) => {
+                kprintln!("\ninvalid arg: {}", arg);
+                break;
+            }
+        };
+
+        if FILESYSTEM.open(&abs_path).is_ok() {
+            continue;
+        }
+
+        if FILESYSTEM.create_file(&abs_path).is_err() {
+            kprintln!("\ncan't create file: {}", &abs_path.to_str().unwrap());
+            break;
+        }
+    }
+}
+
+fn mkdir<P: AsRef<Path>>(cwd: P, args: StackVec<&str>) {
+    if args.len() < 2 {
+        kprintln!("\nusage: mkdir <dir1> ... <dirn>");
+        return;
+    }
+
+    for arg in args.as_slice()[1..].iter() {
+        let abs_path = match resolve(&cwd, arg) {
+            Ok(p) => p,
+            Err(_) => {
+                kprintln!("\ninvalid arg: {}", arg);
+                break;
+            }
+        };
+
+        if FILESYSTEM.create_dir(&abs_path).is_err() {
+            kprintln!("\ncan't create directory: {}", &abs_path.to_str().unwrap());
+            break;
+        }
+    }
+}
+
+fn rm<P: AsRef<Path>>(cwd: P, args: StackVec<&str>) {
+    if args.len() < 2 {
+        kprintln!("\nusage: rm <path1> ... <pathn>");
+        return;
+    }
+
+    for arg in args.as_slice()[1..].iter() {
+        let abs_path = match resolve(&cwd, arg) {
+            Ok(p) => p,
+            Err(_) => {
+                kprintln!("\ninvalid arg: {}", arg);
+                break;
+            }
+        };
+
+        if let Err(e) = FILESYSTEM.remove(&abs_path) {
+            kprintln!("\ncan't remove {}: {:?}", &abs_path.to_str().unwrap(), e.kind());
+            break;
+        }
+    }
+}
+
+fn df<P: AsRef<Path>>(cwd: P) {
+    let stats = match FILESYSTEM.statfs(cwd) {
+        Ok(stats) => stats,
+        Err(e) => {
+            kprint!("\ndf failed: {:?}", e.kind());
+            return;
+        }
+    };
+
+    let percent = match stats.total_clusters {
+        0 => 0,
+        total => stats.used_clusters() as u64 * 100 / total as u64
+    };
+    kprintln!("\n{:>12} {:>12} {:>12} {:>5}", "Size", "Used", "Avail", "Use%");
+    kprint!("{:>12} {:>12} {:>12} {:>4}%", stats.total_bytes(), stats.used_bytes(), stats.free_bytes(), percent);
+}
+
+fn fsck<P: AsRef<Path>>(cwd: P, args: StackVec<&str>) {
+    let repair = match args.as_slice() {
+        [_] => false,
+        [_, "-r"] => true,
+        _ => {
+            kprintln!("\nusage: fsck [-r]");
+            return;
+        }
+    };
+
+    match FILESYSTEM.check(cwd, repair) {
+        Ok(report) => kprint!("\n{}", report),
+        Err(e) => kprint!("\nfsck failed: {:?}", e.kind())
+    }
+}
+
+fn journal<P: AsRef<Path>>(cwd: P, args: StackVec<&str>) {
+    match args.as_slice() {
+        [_] => kprint!("\njournaling is {}", if FILESYSTEM.is_journaling(&cwd) { "on" } else { "off" }),
+        [_, "on"] => if let Err(e) = FILESYSTEM.enable_journal(&cwd) {
+            kprint!("\njournal failed: {:?}", e.kind());
+        },
+        _ => kprintln!("\nusage: journal [on]")
+    }
+}
+
+fn mv<P: AsRef<Path>>(cwd: P, args: StackVec<&str>) {
+    if args.len() != 3 {
+        kprintln!("\nusage: mv <from> <to>");
+        return;
+    }
+
+    let (from, to) = match (resolve(&cwd, args.as_slice()[1]), resolve(&cwd, args.as_slice()[2])) {
+        (Ok(from), Ok(to)) => (from, to),
+        _ => {
+            kprintln!("\ninvalid path");
+            return;
+        }
+    };
+
+    // moving onto a directory moves the entry into it
+    let to = match FILESYSTEM.open_dir(&to) {
+        Ok(_) => match from.file_name() {
+            Some(name) => to.join(name),
+            None => to
+        },
+        Err(_) => to
+    };
+
+    if let Err(e) = FILESYSTEM.rename(&from, &to) {
+        kprintln!("\ncan't move {} to {}: {:?}", from.to_str().unwrap(), to.to_str().unwrap(), e.kind());
+    }
+}
+
+fn mount<P: AsRef<Path>>(cwd: P, args: StackVec<&str>) {
+    match args.as_slice() {
+        [_] => {
+            kprintln!();
+            for (path, source, fs_type) in FILESYSTEM.mounts() {
+                kprintln!("{} on {} type {}", source, path.to_str().unwrap(), fs_type);
+            }
+        },
+        [_, fs_type @ "proc", path] | [_, fs_type @ "tmpfs", path] => {
+            let abs_path = match resolve(&cwd, path) {
+                Ok(p) => p,
+                Err(_) => {
+                    kprintln!("\ninvalid path: {}", path);
+                    return;
+                }
+            };
+            let fs: Rc<dyn Mountable> = match *fs_type {
+                "proc" => Rc::new(ProcFs),
+                _ => fs::tmpfs(),
+            };
+            if let Err(e) = FILESYSTEM.mount(&abs_path, fs_type, fs) {
+                kprintln!("\ncan't mount on {}: {:?}", abs_path.to_str().unwrap(), e.kind());
+            }
+        },
+        _ => kprintln!("\nusage: mount [proc|tmpfs <path>]")
+    }
+}
+
+fn umount<P: AsRef<Path>>(cwd: P, args: StackVec<&str>) {
+    if args.len() != 2 {
+        kprintln!("\nusage: umount <path>");
+        return;
+    }
+
+    let abs_path = match resolve(&cwd, args.as_slice()[1]) {
+        Ok(p) => p,
+        Err(_) => {
+            kprintln!("\ninvalid path: {}", args.as_slice()[1]);
+            return;
+        }
+    };
+    if let Err(e) = FILESYSTEM.unmount(&abs_path) {
+        kprintln!("\ncan't unmount {}: {:?}", abs_path.to_str().unwrap(), e.kind());
+    }
+}
+
 /// Error type for `Command` parse failures.
 #[derive(Debug)]
 enum Error {
@@ -358,6 +557,27 @@ pub fn shell(prefix: &str) {
                     "ls" => ls(&cwd, &mut command.args),
                     "cat" => cat(&cwd, command.args),
                     "cd" => cd(&mut cwd, command.args),
+                    "touch" => touch(&cwd, command.args),
+                    "mkdir" => mkdir(&cwd, command.args),
+                    "rm" | "rmdir" => rm(&cwd, command.args),
+                    "mv" => mv(&cwd, command.args),
+                    "sync" => {
+                        if FILESYSTEM.sync().is_err() {
+                            kprint!("\nfailed to write back to the SD card");
+                        }
+                    },
+                    "df" => df(&cwd),
+                    "fsck" => fsck(&cwd, command.args),
+                    "journal" => journal(&cwd, command.args),
+                    "mount" => mount(&cwd, command.args),
+                    "umount" => umount(&cwd, command.args),
+                    "cachestat" => match FILESYSTEM.cache_stats(&cwd) {
+                        Some((stats, cached)) => {
+                            kprint!("\n{} sectors cached, {} hits, {} misses, {} prefetched, {} evictions, {} writebacks",
+                                    cached, stats.hits, stats.misses, stats.prefetched, stats.evictions, stats.writebacks);
+                        },
+                        None => kprint!("\nno sector cache")
+                    },
                     "files" => {
                         let root_dir = match (&FILESYSTEM).open("/") {
                             Ok(entry) => entry.into_dir().unwrap(),
@@ -5,7 +5,7 @@ mod syscall;
 pub mod irq;
 pub use self::frame::TrapFrame;
 
-use self::syndrome::Syndrome;
+use self::syndrome::{Fault, Syndrome};
 use self::syscall::handle_syscall;
 use crate::console::kprintln;
 use crate::shell;
@@ -14,6 +14,9 @@ use pi::interrupt;
 use pi::timer;
 use core::time::Duration;
 use crate::IRQ;
+use crate::SCHEDULER;
+use crate::vm::VirtualAddr;
+use aarch64::FAR_EL1;
 
 #[repr(u16)]
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -56,6 +59,13 @@ pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
                 tf.elr += 4;
             },
             Syndrome::Svc(num) => handle_syscall(num, tf),
+            Syndrome::DataAbort { kind: Fault::Permission, .. } if source == Source::LowerAArch64 => {
+                let va = unsafe { FAR_EL1.get() };
+                if !SCHEDULER.resolve_cow(VirtualAddr::from(va)) {
+                    kprintln!("permission fault at {:#x}, killing process", va);
+                    let _ = SCHEDULER.kill(tf);
+                }
+            },
             syndrome @ _ => kprintln!("no handler: {:#?}", syndrome),
         },
         Info {kind: Kind::Irq, ..} => {
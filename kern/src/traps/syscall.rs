@@ -1,11 +1,23 @@
 use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::convert::TryInto;
 use core::time::Duration;
+use core::{mem, slice};
 
-use crate::console::{kprint};
-use crate::process::State;
+use shim::io::{self, Read, Seek, SeekFrom, Write};
+
+use fat32::traits::{File as _, FileSystem as _};
+
+use crate::console::CONSOLE;
+use crate::param::{EXEC_MAX_ARG_SIZE, PAGE_SIZE, PATH_MAX};
+use crate::process::fd::Descriptor;
+use crate::process::{Process, State};
 use crate::process::state::EventPollFn;
 use crate::traps::TrapFrame;
-use crate::SCHEDULER;
+use crate::vm::VirtualAddr;
+use crate::{FILESYSTEM, SCHEDULER};
 use kernel_api::*;
 extern crate pi;
 use pi::timer;
@@ -51,20 +63,12 @@ pub fn sys_time(tf: &mut TrapFrame) {
 
 /// Kills current process.
 ///
-/// This system call does not take paramer and does not return any value.
-pub fn sys_exit(tf: &mut TrapFrame) {
-    let _ = SCHEDULER.kill(tf);
+/// This system call takes one parameter: the exit status, which the parent
+/// collects with `wait`. It does not return any value.
+pub fn sys_exit(status: i32, tf: &mut TrapFrame) {
+    let _ = SCHEDULER.exit(status, tf);
 }
 
-/// Write to console.
-///
-/// This system call takes one parameter: a u8 character to print.
-///
-/// It only returns the usual status value.
-pub fn sys_write(b: u8, tf: &mut TrapFrame) {
-    kprint!("{}", b as char);
-    tf.x_regs[7] = OsError::Ok as u64;
-}
 
 /// Returns current process's ID.
 ///
@@ -77,13 +81,325 @@ pub fn sys_getpid(tf: &mut TrapFrame) {
     tf.x_regs[7] = OsError::Ok as u64;
 }
 
+/// Creates a copy of the current process.
+///
+/// This system call does not take parameter.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the child's process ID in the parent, and `0` in the child.
+pub fn sys_fork(tf: &mut TrapFrame) {
+    match SCHEDULER.fork(tf) {
+        Ok(id) => {
+            tf.x_regs[0] = id;
+            tf.x_regs[7] = OsError::Ok as u64;
+        }
+        Err(e) => tf.x_regs[7] = e as u64,
+    }
+}
+
+/// Waits for a child of the current process to exit.
+///
+/// This system call takes one parameter: the ID of the child to wait for, or
+/// `WAIT_ANY` for any child. It blocks until the child exits, unless it
+/// already has.
+///
+/// In addition to the usual status value, this system call returns two
+/// parameters: the child's ID and its exit status. If the process has no
+/// such child, `NoEntry` is returned.
+pub fn sys_wait(pid: u64, tf: &mut TrapFrame) {
+    match SCHEDULER.wait(pid) {
+        Ok(Some((id, status))) => {
+            tf.x_regs[0] = id;
+            tf.x_regs[1] = status as u64;
+            tf.x_regs[7] = OsError::Ok as u64;
+        }
+        Ok(None) => {
+            // `exit()` of the child fills in the return values
+            let collected: EventPollFn = Box::new(|process| process.waiting_for.is_none());
+            SCHEDULER.switch(State::Waiting(collected), tf);
+        }
+        Err(e) => tf.x_regs[7] = e as u64,
+    }
+}
+
+/// Reads the `len` bytes of UTF-8 at `va` in the user memory of `process`.
+/// Returns `InvalidArgument` if there are more than `max`.
+fn read_str(process: &mut Process, va: u64, len: u64, max: usize) -> OsResult<String> {
+    if len as usize > max {
+        return Err(OsError::InvalidArgument);
+    }
+
+    let mut buf = alloc::vec![0u8; len as usize];
+    process.vmap.read(VirtualAddr::from(va), &mut buf)?;
+    String::from_utf8(buf).map_err(|_| OsError::InvalidArgument)
+}
+
+/// Replaces the current process's program.
+///
+/// This system call takes four parameters: the address and length of the
+/// path of the program, and the address and length of a `[&str]` of
+/// arguments for it. The arguments may take up at most `EXEC_MAX_ARG_SIZE`
+/// bytes, including the slice itself.
+///
+/// On success, this system call does not return: the new program starts
+/// with the number of arguments in `x0` and the address of a copy of them in
+/// `x1`. Otherwise it returns the usual status value.
+pub fn sys_exec(path: u64, path_len: u64, argv: u64, argc: u64, tf: &mut TrapFrame) {
+    let result = SCHEDULER.critical(|scheduler| -> OsResult<()> {
+        let process = scheduler.current().ok_or(OsError::Unknown)?;
+        let path = read_str(process, path, path_len, PATH_MAX)?;
+
+        if argc as usize > EXEC_MAX_ARG_SIZE / 16 {
+            return Err(OsError::InvalidArgument);
+        }
+        let mut size = 16 * argc as usize;
+        let mut args = Vec::with_capacity(argc as usize);
+        for i in 0..argc {
+            // a `&str` is laid out as its address followed by its length
+            let mut arg = [0u8; 16];
+            process.vmap.read(VirtualAddr::from(argv.wrapping_add(16 * i)), &mut arg)?;
+            let ptr = u64::from_le_bytes(arg[..8].try_into().unwrap());
+            let len = u64::from_le_bytes(arg[8..].try_into().unwrap());
+
+            size = match size.checked_add(len as usize) {
+                Some(size) if size <= EXEC_MAX_ARG_SIZE => size,
+                _ => return Err(OsError::InvalidArgument),
+            };
+            args.push(read_str(process, ptr, len, EXEC_MAX_ARG_SIZE)?);
+        }
+
+        let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+        process.exec(&path, &args, tf)
+    });
+
+    if let Err(e) = result {
+        tf.x_regs[7] = e as u64;
+    }
+}
+
+/// Runs `f` on the currently running process, and returns the value it
+/// produces along with the usual status value.
+fn with_current<F>(tf: &mut TrapFrame, f: F)
+where
+    F: FnOnce(&mut Process) -> OsResult<u64>,
+{
+    let result = SCHEDULER.critical(|scheduler| match scheduler.current() {
+        Some(process) => f(process),
+        None => Err(OsError::Unknown),
+    });
+
+    match result {
+        Ok(value) => {
+            tf.x_regs[0] = value;
+            tf.x_regs[7] = OsError::Ok as u64;
+        }
+        Err(e) => tf.x_regs[7] = e as u64,
+    }
+}
+
+/// Opens a file of the file system.
+///
+/// This system call takes three parameters: the address and length of the
+/// file's path, and the `O_*` flags to open it with. With `O_CREAT`, a file
+/// that does not exist is created.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the new file descriptor.
+pub fn sys_open(path: u64, path_len: u64, flags: u64, tf: &mut TrapFrame) {
+    with_current(tf, |process| {
+        if flags & O_ACCMODE > O_RDWR {
+            return Err(OsError::InvalidArgument);
+        }
+
+        let path = read_str(process, path, path_len, PATH_MAX)?;
+        let file = match FILESYSTEM.open_file(path.as_str()) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound && flags & O_CREAT != 0 => {
+                FILESYSTEM.create_file(path.as_str())?
+            }
+            result => result?,
+        };
+        process.fds.insert(Descriptor::File { file, flags })
+    });
+}
+
+/// Reads the bytes waiting at the console, up to `len`, into the user memory
+/// of `process` at `buf`. Returns `None` if there are none.
+fn read_console(process: &mut Process, buf: u64, len: usize) -> OsResult<Option<u64>> {
+    let mut data = Vec::new();
+    {
+        let mut console = CONSOLE.lock();
+        while data.len() < len && console.has_byte() {
+            data.push(console.read_byte());
+        }
+    }
+
+    if data.is_empty() && len > 0 {
+        return Ok(None);
+    }
+    process.vmap.write(VirtualAddr::from(buf), &data)?;
+    Ok(Some(data.len() as u64))
+}
+
+/// Reads from a file descriptor.
+///
+/// This system call takes three parameters: the file descriptor, and the
+/// address and length of the buffer to read into. At most `PAGE_SIZE` bytes
+/// are read at once. Reading the console blocks until a byte arrives.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes read, which is `0` at the end of a file.
+pub fn sys_read(fd: u64, buf: u64, len: u64, tf: &mut TrapFrame) {
+    let len = min(len as usize, PAGE_SIZE);
+    let result = SCHEDULER.critical(|scheduler| -> OsResult<Option<u64>> {
+        let process = scheduler.current().ok_or(OsError::Unknown)?;
+        let file = match process.fds.get(fd)? {
+            Descriptor::Console => return read_console(process, buf, len),
+            Descriptor::File { flags, .. } if *flags & O_ACCMODE == O_WRONLY => {
+                return Err(OsError::NoAccess);
+            }
+            Descriptor::File { file, .. } => file,
+        };
+
+        let mut data = alloc::vec![0u8; len];
+        let n = file.read(&mut data)?;
+        process.vmap.write(VirtualAddr::from(buf), &data[..n])?;
+        Ok(Some(n as u64))
+    });
+
+    match result {
+        Ok(Some(n)) => {
+            tf.x_regs[0] = n;
+            tf.x_regs[7] = OsError::Ok as u64;
+        }
+        Ok(None) => {
+            let has_input: EventPollFn = Box::new(move |process| {
+                match read_console(process, buf, len) {
+                    Ok(None) => return false,
+                    Ok(Some(n)) => {
+                        process.context.x_regs[0] = n;
+                        process.context.x_regs[7] = OsError::Ok as u64;
+                    }
+                    Err(e) => process.context.x_regs[7] = e as u64,
+                }
+                true
+            });
+            SCHEDULER.switch(State::Waiting(has_input), tf);
+        }
+        Err(e) => tf.x_regs[7] = e as u64,
+    }
+}
+
+/// Writes to a file descriptor.
+///
+/// This system call takes three parameters: the file descriptor, and the
+/// address and length of the bytes to write. At most `PAGE_SIZE` bytes are
+/// written at once.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes written.
+pub fn sys_write(fd: u64, buf: u64, len: u64, tf: &mut TrapFrame) {
+    let len = min(len as usize, PAGE_SIZE);
+    with_current(tf, |process| {
+        let mut data = alloc::vec![0u8; len];
+        process.vmap.read(VirtualAddr::from(buf), &mut data)?;
+
+        match process.fds.get(fd)? {
+            Descriptor::Console => {
+                let mut console = CONSOLE.lock();
+                for &byte in data.iter() {
+                    if byte == b'\n' {
+                        console.write_byte(b'\r');
+                    }
+                    console.write_byte(byte);
+                }
+                Ok(len as u64)
+            }
+            Descriptor::File { flags, .. } if *flags & O_ACCMODE == O_RDONLY => Err(OsError::NoAccess),
+            Descriptor::File { file, .. } => Ok(file.write(&data)? as u64),
+        }
+    });
+}
+
+/// Closes a file descriptor, writing back what was written through it.
+///
+/// This system call takes one parameter: the file descriptor.
+///
+/// It only returns the usual status value.
+pub fn sys_close(fd: u64, tf: &mut TrapFrame) {
+    with_current(tf, |process| {
+        if let Descriptor::File { mut file, flags } = process.fds.remove(fd)? {
+            if flags & O_ACCMODE != O_RDONLY {
+                file.sync()?;
+            }
+        }
+        Ok(0)
+    });
+}
+
+/// Moves the offset of a file descriptor.
+///
+/// This system call takes three parameters: the file descriptor, the new
+/// offset, and one of `SEEK_SET`, `SEEK_CUR` or `SEEK_END` telling what the
+/// offset is relative to. The console cannot seek.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the new offset from the start of the file.
+pub fn sys_lseek(fd: u64, offset: i64, whence: u64, tf: &mut TrapFrame) {
+    with_current(tf, |process| {
+        let pos = match whence {
+            SEEK_SET => SeekFrom::Start(offset as u64),
+            SEEK_CUR => SeekFrom::Current(offset),
+            SEEK_END => SeekFrom::End(offset),
+            _ => return Err(OsError::InvalidArgument),
+        };
+
+        match process.fds.get(fd)? {
+            Descriptor::Console => Err(OsError::InvalidArgument),
+            Descriptor::File { file, .. } => Ok(file.seek(pos)?),
+        }
+    });
+}
+
+/// Describes what a file descriptor refers to.
+///
+/// This system call takes two parameters: the file descriptor, and the
+/// address of a `Stat` to fill in.
+///
+/// It only returns the usual status value.
+pub fn sys_fstat(fd: u64, stat: u64, tf: &mut TrapFrame) {
+    with_current(tf, |process| {
+        let value = match process.fds.get(fd)? {
+            Descriptor::Console => Stat { kind: S_IFCHR, ..Stat::default() },
+            Descriptor::File { file, .. } => Stat {
+                kind: S_IFREG,
+                size: file.size(),
+                read_only: file.metadata.read_only as u64,
+            },
+        };
+
+        let bytes = unsafe {
+            slice::from_raw_parts(&value as *const Stat as *const u8, mem::size_of::<Stat>())
+        };
+        process.vmap.write(VirtualAddr::from(stat), bytes)?;
+        Ok(0)
+    });
+}
+
 pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
     match num as usize {
         NR_SLEEP => sys_sleep(tf.x_regs[0] as u32, tf),
-        NR_WRITE => sys_write(tf.x_regs[0] as u8, tf),
+        NR_WRITE => sys_write(tf.x_regs[0], tf.x_regs[1], tf.x_regs[2], tf),
         NR_TIME => sys_time(tf),
-        NR_EXIT => sys_exit(tf),
+        NR_EXIT => sys_exit(tf.x_regs[0] as i32, tf),
         NR_GETPID => sys_getpid(tf),
+        NR_FORK => sys_fork(tf),
+        NR_EXEC => sys_exec(tf.x_regs[0], tf.x_regs[1], tf.x_regs[2], tf.x_regs[3], tf),
+        NR_WAIT => sys_wait(tf.x_regs[0], tf),
+        NR_OPEN => sys_open(tf.x_regs[0], tf.x_regs[1], tf.x_regs[2], tf),
+        NR_READ => sys_read(tf.x_regs[0], tf.x_regs[1], tf.x_regs[2], tf),
+        NR_CLOSE => sys_close(tf.x_regs[0], tf),
+        NR_LSEEK => sys_lseek(tf.x_regs[0], tf.x_regs[1] as i64, tf.x_regs[2], tf),
+        NR_FSTAT => sys_fstat(tf.x_regs[0], tf.x_regs[1], tf),
         _ => unimplemented!("unimplemented syscall!!")
     };
 }
@@ -4,6 +4,7 @@ use aarch64::*;
 
 mod address;
 mod pagetable;
+mod refs;
 
 pub use self::address::{PhysicalAddr, VirtualAddr};
 pub use self::pagetable::*;
@@ -3,15 +3,19 @@ use core::ops::{Deref, DerefMut};
 use alloc::boxed::Box;
 use alloc::fmt;
 use core::alloc::{GlobalAlloc, Layout};
+use core::cmp::min;
 
 use crate::allocator;
+use crate::allocator::util::align_down;
 use crate::param::*;
-use crate::vm::{PhysicalAddr, VirtualAddr};
+use crate::vm::{refs, PhysicalAddr, VirtualAddr};
 use crate::ALLOCATOR;
 
 use aarch64::vmsa::*;
 use shim::const_assert_size;
 
+use kernel_api::{OsError, OsResult};
+
 extern crate pi;
 use pi::common::{IO_BASE, IO_BASE_END};
 
@@ -203,7 +207,7 @@ impl KernPageTable {
         let mut pt = PageTable::new(EntryPerm::KERN_RW);
 
         // if this unwrap panics, we have massive problems
-        let (_, end_addr) = allocator::memory_map().unwrap();
+        let end_addr = allocator::memory_end().unwrap();
         let mut i = 0x0000_0000;
         // create a page table entry for every possible page in phyiscal memory 
         while i < end_addr {
@@ -247,9 +251,13 @@ impl KernPageTable {
     }
 }
 
+/// The access a user process has to one of its pages. The kernel never
+/// executes user pages.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum PagePerm {
     RW,
     RO,
+    RX,
     RWX,
 }
 
@@ -262,22 +270,28 @@ impl UserPageTable {
         UserPageTable(PageTable::new(EntryPerm::USER_RW))
     }
 
-    /// Allocates a page and set an L3 entry translates given virtual address to the
-    /// physical address of the allocated page. Returns the allocated page.
+    /// Translates the user virtual address `va` into this table's index space.
     ///
     /// # Panics
     /// Panics if the virtual address is lower than `USER_IMG_BASE`.
-    /// Panics if the virtual address has already been allocated.
-    /// Panics if allocator fails to allocate a page.
-    ///
-    /// TODO. use Result<T> and make it failurable
-    /// TODO. use perm properly
-    pub fn alloc(&mut self, va: VirtualAddr, _perm: PagePerm) -> &mut [u8] {
+    fn internal_va(va: VirtualAddr) -> VirtualAddr {
         if va.as_usize() < USER_IMG_BASE {
             panic!("attempted to allocate memory starting at kernel space address!!");
         }
 
-        let internal_va: VirtualAddr = (va.as_usize() - USER_IMG_BASE).into(); // translate address into this proc's space
+        (va.as_usize() - USER_IMG_BASE).into()
+    }
+
+    /// Allocates a zeroed page and set an L3 entry translates given virtual
+    /// address to the physical address of the allocated page with the access
+    /// `perm`. Returns the allocated page, or `NoMemory` if the allocator fails
+    /// to allocate one.
+    ///
+    /// # Panics
+    /// Panics if the virtual address is lower than `USER_IMG_BASE`.
+    /// Panics if the virtual address has already been allocated.
+    pub fn alloc(&mut self, va: VirtualAddr, perm: PagePerm) -> OsResult<&mut [u8]> {
+        let internal_va = Self::internal_va(va); // translate address into this proc's space
 
         if self.0.is_valid(internal_va) {
             panic!("attempted to allocate a previously allocated page");
@@ -286,21 +300,174 @@ impl UserPageTable {
         let pa = unsafe { ALLOCATOR.alloc(Page::layout()) };// allocate a physical page
 
         if pa == core::ptr::null_mut() {
-            panic!("failed to allocate requested physical page");
+            return Err(OsError::NoMemory);
         }
 
+        let (ap, uxn) = match perm {
+            PagePerm::RW => (EntryPerm::USER_RW, 1),
+            PagePerm::RO => (EntryPerm::USER_RO, 1),
+            PagePerm::RX => (EntryPerm::USER_RO, 0),
+            PagePerm::RWX => (EntryPerm::USER_RW, 0),
+        };
+
         let mut entry = RawL3Entry::new(0);
         entry.set_value((pa as u64) >> 16, RawL3Entry::ADDR);
         entry.set_value(EntryValid::Valid, RawL3Entry::VALID);
         entry.set_value(PageType::Page, RawL3Entry::TYPE);
         entry.set_value(EntryAttr::Mem, RawL3Entry::ATTR);
-        entry.set_value(EntryPerm::USER_RW, RawL3Entry::AP);
+        entry.set_value(ap, RawL3Entry::AP);
         entry.set_value(EntrySh::ISh, RawL3Entry::SH);
         entry.set_value(1, RawL3Entry::AF);
+        entry.set_value(uxn, RawL3Entry::UXN);
+        entry.set_value(1, RawL3Entry::PXN);
 
         self.0.set_entry(internal_va, entry);
 
-        unsafe { core::slice::from_raw_parts_mut(pa, PAGE_SIZE) }
+        let page = unsafe { core::slice::from_raw_parts_mut(pa, PAGE_SIZE) };
+        page.iter_mut().for_each(|b| *b = 0);
+        Ok(page)
+    }
+
+    /// Returns the page the given virtual address is mapped to, or `None` if
+    /// it was never allocated. The kernel can write the page whatever the
+    /// user's access to it.
+    ///
+    /// # Panics
+    /// Panics if the virtual address is lower than `USER_IMG_BASE`.
+    pub fn get_page(&mut self, va: VirtualAddr) -> Option<&mut [u8]> {
+        let (l2_index, l3_index) = PageTable::locate(Self::internal_va(va));
+        let pa = self.0.l3[l2_index].entries[l3_index].get_page_addr()?;
+        Some(unsafe { core::slice::from_raw_parts_mut(pa.as_ptr() as *mut u8, PAGE_SIZE) })
+    }
+
+    /// Copies the user memory starting at `va` into `buf`. The memory is read
+    /// through this table, whichever process is running.
+    ///
+    /// Returns `BadAddress` if any of the memory is not mapped.
+    pub fn read(&mut self, va: VirtualAddr, buf: &mut [u8]) -> OsResult<()> {
+        let mut va = va.as_usize();
+        let mut copied = 0;
+        while copied < buf.len() {
+            if va < USER_IMG_BASE {
+                return Err(OsError::BadAddress);
+            }
+
+            let page_va = align_down(va, PAGE_SIZE);
+            let offset = va - page_va;
+            let n = min(PAGE_SIZE - offset, buf.len() - copied);
+            let page = self.get_page(page_va.into()).ok_or(OsError::BadAddress)?;
+            buf[copied..copied + n].copy_from_slice(&page[offset..offset + n]);
+
+            copied += n;
+            va = va.wrapping_add(n);
+        }
+
+        Ok(())
+    }
+
+    /// Copies `buf` into the user memory starting at `va`, through this
+    /// table. Copy-on-write pages are copied first, as a write by the process
+    /// itself would.
+    ///
+    /// Returns `BadAddress` if any of the memory is not mapped or is read-only
+    /// to the process, and `NoMemory` if a copy-on-write page could not be
+    /// copied. The memory before the offending page may have been written.
+    pub fn write(&mut self, va: VirtualAddr, buf: &[u8]) -> OsResult<()> {
+        let mut va = va.as_usize();
+        let mut copied = 0;
+        while copied < buf.len() {
+            if va < USER_IMG_BASE {
+                return Err(OsError::BadAddress);
+            }
+
+            let page_va = align_down(va, PAGE_SIZE);
+            let (l2_index, l3_index) = PageTable::locate(Self::internal_va(page_va.into()));
+            let entry = self.0.l3[l2_index].entries[l3_index];
+            if !entry.is_valid() {
+                return Err(OsError::BadAddress);
+            } else if entry.0.get_value(RawL3Entry::COW) == 1 {
+                if !self.resolve_cow(page_va.into()) {
+                    return Err(OsError::NoMemory);
+                }
+            } else if entry.0.get_value(RawL3Entry::AP) != EntryPerm::USER_RW {
+                return Err(OsError::BadAddress);
+            }
+
+            let offset = va - page_va;
+            let n = min(PAGE_SIZE - offset, buf.len() - copied);
+            let page = self.get_page(page_va.into()).ok_or(OsError::BadAddress)?;
+            page[offset..offset + n].copy_from_slice(&buf[copied..copied + n]);
+
+            copied += n;
+            va = va.wrapping_add(n);
+        }
+
+        Ok(())
+    }
+
+    /// Returns a new `UserPageTable` mapping the same pages as `self`. Writable
+    /// pages are turned into read-only copy-on-write pages in both tables, so
+    /// that the first write to one faults and is resolved by `resolve_cow()`.
+    ///
+    /// The TLB entries of the old permissions are flushed when returning to
+    /// user space.
+    pub fn fork(&mut self) -> UserPageTable {
+        let mut child = UserPageTable::new();
+
+        for (parent_l3, child_l3) in self.0.l3.iter_mut().zip(child.0.l3.iter_mut()) {
+            for (entry, child_entry) in parent_l3.entries.iter_mut().zip(child_l3.entries.iter_mut()) {
+                if let Some(pa) = entry.get_page_addr() {
+                    if entry.0.get_value(RawL3Entry::AP) == EntryPerm::USER_RW {
+                        entry.0.set_value(EntryPerm::USER_RO, RawL3Entry::AP);
+                        entry.0.set_value(1, RawL3Entry::COW);
+                    }
+                    refs::share(pa);
+                    *child_entry = *entry;
+                }
+            }
+        }
+
+        child
+    }
+
+    /// Resolves a write fault on the copy-on-write page containing `va` by
+    /// making it writable again, copying it first if another page table still
+    /// maps it.
+    ///
+    /// Returns `false` if `va` does not lie in a copy-on-write page, or if no
+    /// page could be allocated for the copy.
+    pub fn resolve_cow(&mut self, va: VirtualAddr) -> bool {
+        if va.as_usize() < USER_IMG_BASE {
+            return false;
+        }
+
+        let page_va = align_down(va.as_usize(), PAGE_SIZE);
+        let (l2_index, l3_index) = PageTable::locate(Self::internal_va(page_va.into()));
+        let entry = &mut self.0.l3[l2_index].entries[l3_index];
+
+        let pa = match entry.get_page_addr() {
+            Some(pa) if entry.0.get_value(RawL3Entry::COW) == 1 => pa,
+            _ => return false,
+        };
+
+        if refs::is_shared(pa) {
+            let new_pa = unsafe { ALLOCATOR.alloc(Page::layout()) };
+            if new_pa == core::ptr::null_mut() {
+                return false;
+            }
+
+            unsafe {
+                core::ptr::copy_nonoverlapping(pa.as_ptr(), new_pa, PAGE_SIZE);
+                if refs::release(pa) {
+                    ALLOCATOR.dealloc(pa.as_ptr() as *mut u8, Page::layout());
+                }
+            }
+            entry.0.set_value((new_pa as u64) >> 16, RawL3Entry::ADDR);
+        }
+
+        entry.0.set_value(EntryPerm::USER_RW, RawL3Entry::AP);
+        entry.0.set_value(0, RawL3Entry::COW);
+        true
     }
 }
 
@@ -334,11 +501,14 @@ impl DerefMut for UserPageTable {
 
 impl Drop for UserPageTable {
     fn drop(&mut self) {
-        // iterate over the L3 entries and deallocate the mapped ones
+        // iterate over the L3 entries and deallocate the mapped ones nobody
+        // else maps
         for entry in self.into_iter() {
             if let Some(pa) = entry.get_page_addr() {
-                unsafe { 
-                    ALLOCATOR.dealloc(pa.as_ptr() as *mut u8, Page::layout());
+                if refs::release(pa) {
+                    unsafe {
+                        ALLOCATOR.dealloc(pa.as_ptr() as *mut u8, Page::layout());
+                    }
                 }
             }
         }
@@ -0,0 +1,41 @@
+use alloc::collections::BTreeMap;
+
+use crate::mutex::Mutex;
+use crate::vm::PhysicalAddr;
+
+/// The number of user page tables mapping each physical page that is mapped
+/// by more than one. Pages missing from the map have a single owner.
+static SHARED: Mutex<Option<BTreeMap<u64, usize>>> = Mutex::new(None);
+
+/// Records one more user page table mapping the page at `pa`.
+pub fn share(pa: PhysicalAddr) {
+    let mut shared = SHARED.lock();
+    *shared.get_or_insert_with(BTreeMap::new).entry(pa.as_u64()).or_insert(1) += 1;
+}
+
+/// Returns `true` if more than one user page table maps the page at `pa`.
+pub fn is_shared(pa: PhysicalAddr) -> bool {
+    SHARED.lock().as_ref().map_or(false, |shared| shared.contains_key(&pa.as_u64()))
+}
+
+/// Drops one user page table's reference to the page at `pa`. Returns `true`
+/// if that was the last reference, in which case the caller frees the page.
+pub fn release(pa: PhysicalAddr) -> bool {
+    let mut shared = SHARED.lock();
+    let shared = match shared.as_mut() {
+        Some(shared) => shared,
+        None => return true,
+    };
+
+    match shared.get_mut(&pa.as_u64()) {
+        Some(count) if *count > 2 => {
+            *count -= 1;
+            false
+        }
+        Some(_) => {
+            shared.remove(&pa.as_u64());
+            false
+        }
+        None => true,
+    }
+}
@@ -0,0 +1,108 @@
+//! Creates a disk image holding a FAT32 file system, optionally populated
+//! with the contents of a host directory.
+//!
+//! usage: mkfs [-c sectors-per-cluster] [-n label] [-i volume-id] <image> <size> [dir]
+//!
+//! `size` is in bytes and may carry a `K`, `M` or `G` suffix.
+
+use std::env;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use fat32::mkfs::{self, Options};
+use fat32::vfat::{VFat, VFatHandle};
+
+#[derive(Clone)]
+struct Handle(Arc<Mutex<VFat<Self>>>);
+
+impl fmt::Debug for Handle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Handle")
+    }
+}
+
+impl VFatHandle for Handle {
+    fn new(val: VFat<Handle>) -> Self {
+        Handle(Arc::new(Mutex::new(val)))
+    }
+
+    fn lock<R>(&self, f: impl FnOnce(&mut VFat<Handle>) -> R) -> R {
+        f(&mut self.0.lock().expect("lock poisoned"))
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("usage: mkfs [-c sectors-per-cluster] [-n label] [-i volume-id] <image> <size> [dir]");
+    process::exit(2);
+}
+
+fn fail<E: fmt::Debug>(what: &str, err: E) -> ! {
+    eprintln!("mkfs: {}: {:?}", what, err);
+    process::exit(1);
+}
+
+/// Parses a size in bytes with an optional binary `K`, `M` or `G` suffix.
+fn parse_size(arg: &str) -> Option<u64> {
+    let (digits, multiplier) = match arg.chars().last()? {
+        'K' | 'k' => (&arg[..arg.len() - 1], 1 << 10),
+        'M' | 'm' => (&arg[..arg.len() - 1], 1 << 20),
+        'G' | 'g' => (&arg[..arg.len() - 1], 1 << 30),
+        _ => (arg, 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Parses a volume label into its space padded, upper-case on-disk form.
+fn parse_label(arg: &str) -> Option<[u8; 11]> {
+    if arg.len() > 11 || !arg.is_ascii() {
+        return None;
+    }
+    let mut label = [b' '; 11];
+    label[..arg.len()].copy_from_slice(arg.to_ascii_uppercase().as_bytes());
+    Some(label)
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut sectors_per_cluster = 0;
+    let mut label = None;
+    let mut volume_id = 0;
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "-c" => sectors_per_cluster = value().parse().unwrap_or_else(|_| usage()),
+            "-n" => label = Some(parse_label(&value()).unwrap_or_else(|| usage())),
+            "-i" => volume_id = u32::from_str_radix(&value(), 16).unwrap_or_else(|_| usage()),
+            _ if arg.starts_with('-') => usage(),
+            _ => positional.push(arg),
+        }
+    }
+
+    let (image, size, source) = match positional.as_slice() {
+        [image, size] => (image, size, None),
+        [image, size, source] => (image, size, Some(Path::new(source))),
+        _ => usage(),
+    };
+    let size = parse_size(size).unwrap_or_else(|| usage());
+
+    let mut options = Options::new(size / 512);
+    options.sectors_per_cluster = sectors_per_cluster;
+    options.volume_id = volume_id;
+    if let Some(label) = label {
+        options.volume_label = label;
+    }
+
+    let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(image)
+        .unwrap_or_else(|e| fail(image, e));
+    file.set_len(options.num_sectors * 512).unwrap_or_else(|e| fail(image, e));
+    mkfs::format(&mut file, &options).unwrap_or_else(|e| fail("format", e));
+
+    if let Some(source) = source {
+        let vfat = VFat::<Handle>::from(file).unwrap_or_else(|e| fail("mount", e));
+        mkfs::populate(&vfat, source).unwrap_or_else(|e| fail(&source.to_string_lossy(), e));
+    }
+}
@@ -0,0 +1,503 @@
+//! A consistency checker for FAT volumes in the spirit of `fsck.fat`.
+//!
+//! `check()` walks every directory reachable from the root and cross checks
+//! the directory entries against the FAT. When asked to, it also repairs what
+//! it finds so that the volume can be trusted again, e.g. after a crash in the
+//! middle of a write.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use shim::io;
+
+use crate::vfat::dir::{VFatDirEntry, ATTR_DIRECTORY, ATTR_LFN, DELETED_MARKER};
+use crate::vfat::name::{lfn_checksum, LFN_CHARS_PER_ENTRY};
+use crate::vfat::{Cluster, EntryLocation, Status, VFat, VFatHandle};
+
+/// Attribute bit marking an entry as the volume label.
+const ATTR_VOLUME_ID: u8 = 0x08;
+
+/// The value written to a FAT entry to end a chain. It is truncated to the
+/// width of FAT12 and FAT16 entries.
+const END_OF_CHAIN: u32 = 0x0FFFFFFF;
+
+/// A single inconsistency found by `check()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The entry at `path` starts at `cluster`, which is not a data cluster
+    /// of the volume. Such files are emptied and such directories removed.
+    BadStartCluster { path: String, cluster: u32 },
+    /// The FAT entry of `cluster` in the chain of `path` is free, reserved,
+    /// bad or points outside of the volume. The chain is ended at `cluster`.
+    BrokenChain { path: String, cluster: u32 },
+    /// The chain of `path` leads back to `cluster`, which it already
+    /// contains. The chain is ended before the loop.
+    ChainLoop { path: String, cluster: u32 },
+    /// `cluster` is part of the chains of both `other` and `path`. The chain
+    /// of `path`, found second, is ended before `cluster`.
+    CrossLinked { path: String, other: String, cluster: u32 },
+    /// The file at `path` is `size` bytes long but its chain has `clusters`
+    /// clusters. The chain is trimmed, or the size reduced, to match.
+    SizeMismatch { path: String, size: u32, clusters: usize },
+    /// The long file name records starting at `index` in the directory `dir`
+    /// are out of sequence or don't match the checksum of the short name that
+    /// follows them. The records are deleted, leaving the short name.
+    BadLongName { dir: String, index: usize },
+    /// `count` clusters are marked used in the FAT but belong to no entry.
+    /// They are marked free.
+    LostClusters { count: u32 },
+    /// `entries` entries of FAT copy `copy` differ from the first FAT. The
+    /// first FAT is copied over it.
+    FatMismatch { copy: u8, entries: u32 },
+    /// The FSInfo structure records `recorded` free clusters but the FAT has
+    /// `actual`. The FSInfo structure is corrected.
+    FreeCountMismatch { recorded: u32, actual: u32 },
+}
+
+/// The outcome of a `check()`.
+#[derive(Debug, Default, Clone)]
+pub struct Report {
+    /// Every problem found, in the order it was found.
+    pub problems: Vec<Problem>,
+    /// The number of files visited.
+    pub files: usize,
+    /// The number of directories visited, including the root.
+    pub dirs: usize,
+    /// The number of clusters in use by reachable files and directories.
+    pub used_clusters: u32,
+    /// Whether the problems were repaired.
+    pub repaired: bool,
+}
+
+impl Report {
+    /// Returns `true` if no problems were found.
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Checks the consistency of the volume behind `vfat`. If `repair` is `true`,
+/// every problem found is fixed as described on its `Problem` variant and the
+/// changes are flushed to the device before returning.
+///
+/// # Errors
+///
+/// Returns an error if reading from or writing to the device fails. Problems
+/// with the file system itself are reported in the returned `Report`.
+pub fn check<HANDLE: VFatHandle>(vfat: &HANDLE, repair: bool) -> io::Result<Report> {
+    vfat.lock(|vfat| {
+        let report = Checker::new(vfat, repair).run()?;
+        if repair {
+            vfat.flush()?;
+        }
+        Ok(report)
+    })
+}
+
+/// State of a long file name whose records are being collected.
+struct LongName {
+    first_index: usize,
+    checksum: u8,
+    seq_num: u8,
+    chunks: Vec<[u16; LFN_CHARS_PER_ENTRY]>,
+}
+
+impl LongName {
+    /// Decodes the collected name. The chunks are stored last to first.
+    fn decode(&self) -> String {
+        let chars: Vec<u16> = self.chunks.iter().rev()
+            .flat_map(|chunk| chunk.iter().cloned())
+            .take_while(|&c| c != 0x0000 && c != 0xFFFF)
+            .collect();
+        String::from_utf16_lossy(&chars)
+    }
+}
+
+struct Checker<'a, HANDLE: VFatHandle> {
+    vfat: &'a mut VFat<HANDLE>,
+    repair: bool,
+    /// For every cluster, 1 + the index into `paths` of the entry whose chain
+    /// contains it, or 0 if no chain seen so far contains it.
+    owners: Vec<u32>,
+    paths: Vec<String>,
+    report: Report,
+}
+
+impl<'a, HANDLE: VFatHandle> Checker<'a, HANDLE> {
+    fn new(vfat: &'a mut VFat<HANDLE>, repair: bool) -> Checker<'a, HANDLE> {
+        let num_entries = vfat.total_clusters as usize + 2;
+        Checker {
+            vfat,
+            repair,
+            owners: vec![0; num_entries],
+            paths: Vec::new(),
+            report: Report { repaired: repair, ..Report::default() },
+        }
+    }
+
+    fn run(mut self) -> io::Result<Report> {
+        // Repairs update every FAT copy, so bring the copies in line first.
+        self.compare_fats()?;
+
+        let root = self.vfat.rootdir_cluster;
+        let root_records = match self.vfat.is_fixed_root(root) {
+            true => self.vfat.root_dir_entries,
+            false => self.claim_chain(root, "/")?.len() * self.vfat.bytes_per_cluster() / 32,
+        };
+        let mut pending = vec![(root, String::from("/"), root_records)];
+        while let Some((cluster, path, num_records)) = pending.pop() {
+            self.report.dirs += 1;
+            self.check_dir(cluster, &path, num_records, &mut pending)?;
+        }
+
+        self.find_lost_clusters()?;
+        self.report.used_clusters = self.owners.iter().filter(|&&owner| owner != 0).count() as u32;
+        Ok(self.report)
+    }
+
+    fn is_data_cluster(&self, raw: u32) -> bool {
+        raw >= 2 && (raw as usize) < self.owners.len()
+    }
+
+    /// Compares every FAT copy against the first, over the entries that
+    /// describe clusters of the volume.
+    fn compare_fats(&mut self) -> io::Result<()> {
+        for copy in 1..self.vfat.num_fats {
+            let mut differing = 0;
+            for raw in 0..self.owners.len() as u32 {
+                let value = self.vfat.raw_fat_entry(0, Cluster(raw))?;
+                if self.vfat.raw_fat_entry(copy, Cluster(raw))? != value {
+                    differing += 1;
+                    if self.repair {
+                        self.vfat.set_raw_fat_entry(copy, Cluster(raw), value)?;
+                    }
+                }
+            }
+
+            if differing > 0 {
+                self.report.problems.push(Problem::FatMismatch { copy, entries: differing });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Follows the chain starting at `start` on behalf of the entry at `path`
+    /// and claims its clusters. Returns the clusters that belong to the entry;
+    /// this is empty if not even `start` does.
+    fn claim_chain(&mut self, start: Cluster, path: &str) -> io::Result<Vec<Cluster>> {
+        let mut chain = Vec::new();
+        if !self.is_data_cluster(start.0) {
+            self.report.problems.push(Problem::BadStartCluster { path: String::from(path), cluster: start.0 });
+            return Ok(chain);
+        }
+
+        self.paths.push(String::from(path));
+        let id = self.paths.len() as u32;
+        let mut cluster = start;
+        loop {
+            let owner = self.owners[cluster.0 as usize];
+            if owner != 0 {
+                let problem = if owner == id {
+                    Problem::ChainLoop { path: String::from(path), cluster: cluster.0 }
+                } else {
+                    Problem::CrossLinked {
+                        path: String::from(path),
+                        other: self.paths[owner as usize - 1].clone(),
+                        cluster: cluster.0,
+                    }
+                };
+                self.report.problems.push(problem);
+                if let (true, Some(&last)) = (self.repair, chain.last()) {
+                    self.vfat.set_fat_entry(last, END_OF_CHAIN)?;
+                }
+                break;
+            }
+
+            self.owners[cluster.0 as usize] = id;
+            chain.push(cluster);
+            match self.vfat.fat_entry(cluster)?.status() {
+                Status::Eoc(_) => break,
+                Status::Data(next) if self.is_data_cluster(next.0) => cluster = next,
+                _ => {
+                    self.report.problems.push(Problem::BrokenChain { path: String::from(path), cluster: cluster.0 });
+                    if self.repair {
+                        self.vfat.set_fat_entry(cluster, END_OF_CHAIN)?;
+                    }
+                    break;
+                }
+            }
+        }
+
+        Ok(chain)
+    }
+
+    /// Marks `clusters` free in the FAT and releases their claims.
+    fn release(&mut self, clusters: &[Cluster]) -> io::Result<()> {
+        for &cluster in clusters {
+            self.vfat.set_fat_entry(cluster, 0)?;
+            self.owners[cluster.0 as usize] = 0;
+        }
+        Ok(())
+    }
+
+    /// Marks the records `first..=last` of the directory `dir` deleted.
+    fn delete_records(&mut self, dir: Cluster, first: usize, last: usize) -> io::Result<()> {
+        for index in first..=last {
+            let mut raw = self.vfat.read_dir_entry(dir, index)?;
+            raw[0] = DELETED_MARKER;
+            self.vfat.write_dir_entry(dir, index, &raw)?;
+        }
+        Ok(())
+    }
+
+    /// Reports the long file name records `first..=last` of `dir` as bad and
+    /// deletes them if repairing.
+    fn bad_long_name(&mut self, dir: Cluster, path: &str, first: usize, last: usize) -> io::Result<()> {
+        self.report.problems.push(Problem::BadLongName { dir: String::from(path), index: first });
+        if self.repair {
+            self.delete_records(dir, first, last)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the first `num_records` records of the directory at `path`,
+    /// which starts at `dir`. Subdirectories are claimed and pushed onto
+    /// `pending`.
+    fn check_dir(&mut self, dir: Cluster, path: &str, num_records: usize,
+                 pending: &mut Vec<(Cluster, String, usize)>) -> io::Result<()> {
+        let bytes_per_cluster = self.vfat.bytes_per_cluster();
+        let mut long_name: Option<LongName> = None;
+        let mut end = num_records;
+
+        for index in 0..num_records {
+            let raw = self.vfat.read_dir_entry(dir, index)?;
+            let record = VFatDirEntry::from_bytes(raw);
+            let unknown = unsafe { record.unknown };
+            if unknown.status == 0x00 {
+                end = index;
+                break;
+            }
+
+            if unknown.status == DELETED_MARKER {
+                if let Some(name) = long_name.take() {
+                    self.bad_long_name(dir, path, name.first_index, index - 1)?;
+                }
+                continue;
+            }
+
+            if unknown.attributes.0 == ATTR_LFN {
+                let lfn = unsafe { record.long_filename };
+                let seq_num = lfn.seq_num & 0x1F;
+                let mut chunk = [0u16; LFN_CHARS_PER_ENTRY];
+                chunk[..5].copy_from_slice(&{ lfn.name_chars_1 });
+                chunk[5..11].copy_from_slice(&{ lfn.name_chars_2 });
+                chunk[11..].copy_from_slice(&{ lfn.name_chars_3 });
+
+                if lfn.seq_num & 0x40 != 0 {
+                    if let Some(name) = long_name.take() {
+                        self.bad_long_name(dir, path, name.first_index, index - 1)?;
+                    }
+                    if seq_num == 0 {
+                        self.bad_long_name(dir, path, index, index)?;
+                    } else {
+                        long_name = Some(LongName {
+                            first_index: index,
+                            checksum: lfn.name_checksum,
+                            seq_num,
+                            chunks: vec![chunk],
+                        });
+                    }
+                    continue;
+                }
+
+                match long_name.as_mut() {
+                    Some(name) if seq_num + 1 == name.seq_num && lfn.name_checksum == name.checksum => {
+                        name.seq_num = seq_num;
+                        name.chunks.push(chunk);
+                    },
+                    _ => {
+                        let first = long_name.take().map_or(index, |name| name.first_index);
+                        self.bad_long_name(dir, path, first, index)?;
+                    }
+                }
+                continue;
+            }
+
+            let mut entry = unsafe { record.regular };
+            let mut short_name = [0u8; 11];
+            short_name[..8].copy_from_slice(&entry.filename);
+            short_name[8..].copy_from_slice(&entry.extension);
+
+            let (name, first_index) = match long_name.take() {
+                Some(ref name) if name.seq_num == 1 && name.checksum == lfn_checksum(&short_name) => {
+                    (name.decode(), name.first_index)
+                },
+                other => {
+                    if let Some(name) = other {
+                        self.bad_long_name(dir, path, name.first_index, index - 1)?;
+                    }
+                    (display_short_name(&short_name), index)
+                }
+            };
+
+            if entry.attributes.0 & ATTR_VOLUME_ID != 0 || name == "." || name == ".." {
+                continue;
+            }
+
+            let entry_path = if path == "/" { format!("/{}", name) } else { format!("{}/{}", path, name) };
+            let location = EntryLocation { dir_cluster: dir, first_index, index };
+            let start = entry.cluster();
+            if entry.attributes.0 & ATTR_DIRECTORY != 0 {
+                let chain = self.claim_chain(start, &entry_path)?;
+                if chain.is_empty() {
+                    if self.repair {
+                        self.delete_records(dir, first_index, index)?;
+                    }
+                } else {
+                    pending.push((start, entry_path, chain.len() * bytes_per_cluster / 32));
+                }
+                continue;
+            }
+
+            self.report.files += 1;
+            let chain = match start.0 {
+                0 => Vec::new(),
+                _ => self.claim_chain(start, &entry_path)?,
+            };
+            if start.0 != 0 && chain.is_empty() {
+                if self.repair {
+                    entry.set_cluster(Cluster(0));
+                    entry.size = 0;
+                    entry.write(self.vfat, location)?;
+                }
+                continue;
+            }
+
+            let needed = (entry.size as usize + bytes_per_cluster - 1) / bytes_per_cluster;
+            if chain.len() == needed {
+                continue;
+            }
+
+            self.report.problems.push(Problem::SizeMismatch {
+                path: entry_path,
+                size: entry.size,
+                clusters: chain.len(),
+            });
+            if self.repair {
+                if chain.len() < needed {
+                    entry.size = (chain.len() * bytes_per_cluster) as u32;
+                } else if needed == 0 {
+                    self.release(&chain)?;
+                    entry.set_cluster(Cluster(0));
+                } else {
+                    self.vfat.set_fat_entry(chain[needed - 1], END_OF_CHAIN)?;
+                    self.release(&chain[needed..])?;
+                }
+                entry.write(self.vfat, location)?;
+            }
+        }
+
+        if let Some(name) = long_name {
+            self.bad_long_name(dir, path, name.first_index, end - 1)?;
+        }
+
+        Ok(())
+    }
+
+    /// Finds clusters that are in use according to the FAT but that weren't
+    /// claimed by any entry, then checks the free cluster count recorded in
+    /// the FSInfo structure against the FAT.
+    fn find_lost_clusters(&mut self) -> io::Result<()> {
+        let mut count = 0;
+        let mut free = 0;
+        for raw in 2..self.owners.len() as u32 {
+            if self.owners[raw as usize] != 0 {
+                continue;
+            }
+
+            match self.vfat.fat_entry(Cluster(raw))?.status() {
+                Status::Free => free += 1,
+                Status::Data(_) | Status::Eoc(_) => {
+                    count += 1;
+                    if self.repair {
+                        self.vfat.set_fat_entry(Cluster(raw), 0)?;
+                        free += 1;
+                    }
+                },
+                _ => ()
+            }
+        }
+
+        if count > 0 {
+            self.report.problems.push(Problem::LostClusters { count });
+        }
+
+        match self.vfat.recorded_free_clusters() {
+            Some(recorded) if recorded != free => {
+                self.report.problems.push(Problem::FreeCountMismatch { recorded, actual: free });
+                if self.repair {
+                    self.vfat.set_free_clusters(free);
+                }
+            },
+            _ => ()
+        }
+        Ok(())
+    }
+}
+
+/// Formats a space padded 8.3 name as `NAME.EXT`.
+fn display_short_name(short_name: &[u8; 11]) -> String {
+    let base = String::from_utf8_lossy(&short_name[..8]);
+    let extension = String::from_utf8_lossy(&short_name[8..]);
+    match extension.trim_end() {
+        "" => String::from(base.trim_end()),
+        extension => format!("{}.{}", base.trim_end(), extension),
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::BadStartCluster { path, cluster } => {
+                write!(f, "{}: starts at invalid cluster {}", path, cluster)
+            },
+            Problem::BrokenChain { path, cluster } => {
+                write!(f, "{}: chain broken at cluster {}", path, cluster)
+            },
+            Problem::ChainLoop { path, cluster } => {
+                write!(f, "{}: chain loops back to cluster {}", path, cluster)
+            },
+            Problem::CrossLinked { path, other, cluster } => {
+                write!(f, "{}: cross-linked with {} at cluster {}", path, other, cluster)
+            },
+            Problem::SizeMismatch { path, size, clusters } => {
+                write!(f, "{}: size is {} bytes but chain has {} clusters", path, size, clusters)
+            },
+            Problem::BadLongName { dir, index } => {
+                write!(f, "{}: bad long file name at entry {}", dir, index)
+            },
+            Problem::LostClusters { count } => {
+                write!(f, "{} lost clusters", count)
+            },
+            Problem::FatMismatch { copy, entries } => {
+                write!(f, "FAT copy {} differs from FAT 0 in {} entries", copy, entries)
+            },
+            Problem::FreeCountMismatch { recorded, actual } => {
+                write!(f, "FSInfo records {} free clusters but there are {}", recorded, actual)
+            },
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for problem in self.problems.iter() {
+            writeln!(f, "{}", problem)?;
+        }
+        write!(f, "{} files, {} directories, {} clusters used, {} problems{}",
+               self.files, self.dirs, self.used_clusters, self.problems.len(),
+               if self.repaired && !self.is_clean() { " (repaired)" } else { "" })
+    }
+}
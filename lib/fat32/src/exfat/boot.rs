@@ -0,0 +1,139 @@
+use core::fmt;
+use core::mem;
+use shim::const_assert_size;
+
+use crate::traits::BlockDevice;
+use crate::vfat::Error;
+
+/// The exFAT main boot sector.
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct BootSector {
+    _jump_boot: [u8; 3],
+    pub file_system_name: [u8; 8],
+    _must_be_zero: [u8; 53],
+    /// The first sector of the volume on the device, or `0` if unknown.
+    pub partition_offset: u64,
+    /// The size of the volume in sectors.
+    pub volume_length: u64,
+    /// The first sector of the first FAT, relative to the volume.
+    pub fat_offset: u32,
+    /// The size of each FAT in sectors.
+    pub fat_length: u32,
+    /// The first sector of the cluster heap, relative to the volume.
+    pub cluster_heap_offset: u32,
+    /// The number of clusters in the cluster heap.
+    pub cluster_count: u32,
+    pub first_cluster_of_root_directory: u32,
+    pub volume_serial_number: u32,
+    pub file_system_revision: u16,
+    /// Bit 0 selects the active FAT and allocation bitmap, bit 1 marks the
+    /// volume dirty.
+    pub volume_flags: u16,
+    pub bytes_per_sector_shift: u8,
+    pub sectors_per_cluster_shift: u8,
+    pub number_of_fats: u8,
+    _drive_select: u8,
+    pub percent_in_use: u8,
+    _reserved: [u8; 7],
+    _boot_code: [u8; 390],
+    pub boot_signature: u16,
+}
+
+const_assert_size!(BootSector, 512);
+
+/// The file system name of an exFAT boot sector.
+const EXFAT_NAME: [u8; 8] = *b"EXFAT   ";
+
+/// The number of sectors covered by the boot checksum: the boot sector, the
+/// extended boot sectors, the OEM parameters and a reserved sector.
+const CHECKSUMMED_SECTORS: usize = 11;
+
+impl BootSector {
+    /// Reads and validates the exFAT boot region starting at sector `sector`
+    /// of `device`: the boot sector's signatures and sizes, and the checksum
+    /// of the main boot region.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadSignature` if the boot sector is not that of an exFAT
+    /// volume or its checksum does not match. Returns `Io(err)` if reading
+    /// from the device fails.
+    pub fn from<T: BlockDevice>(mut device: T, sector: u64) -> Result<BootSector, Error> {
+        let device_sector_size = device.sector_size() as usize;
+        let mut first = vec![0u8; device_sector_size];
+        device.read_sector(sector, &mut first)?;
+
+        let mut raw = [0u8; 512];
+        raw.copy_from_slice(&first[..512]);
+        let boot = unsafe { mem::transmute::<[u8; 512], BootSector>(raw) };
+        if boot.file_system_name != EXFAT_NAME || boot.boot_signature != 0xAA55
+            || boot.bytes_per_sector_shift < 9 || boot.bytes_per_sector_shift > 12
+            || boot.bytes_per_sector_shift + boot.sectors_per_cluster_shift > 25
+            || (boot.number_of_fats != 1 && boot.number_of_fats != 2)
+            || (1usize << boot.bytes_per_sector_shift) < device_sector_size {
+            return Err(Error::BadSignature);
+        }
+
+        // the checksum sector repeats the checksum of the sectors before it
+        let bytes_per_sector = boot.bytes_per_sector();
+        let mut region = vec![0u8; (CHECKSUMMED_SECTORS + 1) * bytes_per_sector];
+        for (i, chunk) in region.chunks_mut(device_sector_size).enumerate() {
+            device.read_sector(sector + i as u64, chunk)?;
+        }
+        let checksum = boot_checksum(&region[..CHECKSUMMED_SECTORS * bytes_per_sector]);
+        let valid = region[CHECKSUMMED_SECTORS * bytes_per_sector..].chunks(4)
+            .all(|stored| stored == checksum.to_le_bytes());
+        if !valid {
+            return Err(Error::BadSignature);
+        }
+
+        Ok(boot)
+    }
+
+    /// The size of a sector in bytes.
+    pub fn bytes_per_sector(&self) -> usize {
+        1 << self.bytes_per_sector_shift
+    }
+
+    /// The number of sectors in a cluster.
+    pub fn sectors_per_cluster(&self) -> u32 {
+        1 << self.sectors_per_cluster_shift
+    }
+
+    /// The index of the FAT and allocation bitmap in use, `0` or `1`.
+    pub fn active_fat(&self) -> u8 {
+        match self.number_of_fats {
+            2 => (self.volume_flags & 1) as u8,
+            _ => 0
+        }
+    }
+}
+
+/// Computes the checksum of the main boot region `region`, which skips the
+/// volume flags and percent in use fields of the boot sector as they change
+/// while the volume is in use.
+pub fn boot_checksum(region: &[u8]) -> u32 {
+    region.iter().enumerate()
+        .filter(|&(i, _)| i != 106 && i != 107 && i != 112)
+        .fold(0u32, |checksum, (_, &byte)| checksum.rotate_right(1).wrapping_add(byte as u32))
+}
+
+impl fmt::Debug for BootSector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BootSector")
+            .field("partition_offset", &{ self.partition_offset })
+            .field("volume_length", &{ self.volume_length })
+            .field("fat_offset", &{ self.fat_offset })
+            .field("fat_length", &{ self.fat_length })
+            .field("cluster_heap_offset", &{ self.cluster_heap_offset })
+            .field("cluster_count", &{ self.cluster_count })
+            .field("first_cluster_of_root_directory", &{ self.first_cluster_of_root_directory })
+            .field("volume_serial_number", &{ self.volume_serial_number })
+            .field("volume_flags", &{ self.volume_flags })
+            .field("bytes_per_sector_shift", &self.bytes_per_sector_shift)
+            .field("sectors_per_cluster_shift", &self.sectors_per_cluster_shift)
+            .field("number_of_fats", &self.number_of_fats)
+            .finish()
+    }
+}
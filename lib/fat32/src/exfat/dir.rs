@@ -0,0 +1,257 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use shim::ffi::OsStr;
+use shim::io;
+use shim::ioerr;
+
+use crate::exfat::exfat::root_metadata;
+use crate::exfat::metadata::ATTR_DIRECTORY;
+use crate::exfat::{Entry, ExFat, ExFatHandle, File, Metadata, Stream, Timestamp};
+use crate::traits;
+use crate::vfat::Cluster;
+
+/// Type of the record marking the end of a directory.
+const ENTRY_END: u8 = 0x00;
+/// Bit set in the type of every record in use.
+const ENTRY_IN_USE: u8 = 0x80;
+const ENTRY_BITMAP: u8 = 0x81;
+const ENTRY_UPCASE: u8 = 0x82;
+const ENTRY_LABEL: u8 = 0x83;
+const ENTRY_FILE: u8 = 0x85;
+const ENTRY_STREAM: u8 = 0xC0;
+const ENTRY_NAME: u8 = 0xC1;
+
+/// Stream extension flag: the entry has clusters allocated.
+const FLAG_ALLOCATION_POSSIBLE: u8 = 0x01;
+/// Stream extension flag: the clusters are contiguous and not in the FAT.
+const FLAG_NO_FAT_CHAIN: u8 = 0x02;
+
+/// The number of UTF-16 code units of the name in a file name record.
+const NAME_CHARS_PER_ENTRY: usize = 15;
+
+/// The records of a directory the driver makes use of. Records of other
+/// types, deleted ones and entry sets with a bad checksum are skipped.
+#[derive(Debug, Clone)]
+pub(crate) enum DirRecord {
+    /// The allocation bitmap of FAT `index`.
+    Bitmap { index: u8, stream: Stream },
+    /// The up-case table and its checksum.
+    Upcase { checksum: u32, stream: Stream },
+    /// The volume label.
+    Label(String),
+    /// A file or directory's entry set.
+    File(FileSet),
+}
+
+/// A file entry together with its stream extension and file name entries.
+#[derive(Debug, Clone)]
+pub(crate) struct FileSet {
+    pub name: Vec<u16>,
+    pub name_hash: u16,
+    pub metadata: Metadata,
+    pub stream: Stream,
+}
+
+fn read_u16(raw: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([raw[at], raw[at + 1]])
+}
+
+fn read_u32(raw: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([raw[at], raw[at + 1], raw[at + 2], raw[at + 3]])
+}
+
+fn read_u64(raw: &[u8], at: usize) -> u64 {
+    read_u32(raw, at) as u64 | (read_u32(raw, at + 4) as u64) << 32
+}
+
+/// Reads the first cluster and data length of a record describing a FAT
+/// chained system structure.
+fn system_stream(raw: &[u8]) -> Stream {
+    let size = read_u64(raw, 24);
+    Stream { first_cluster: Cluster(read_u32(raw, 20)), size, valid_size: size, contiguous: false }
+}
+
+/// Computes the checksum of an entry set, which skips the checksum field of
+/// its first record.
+pub fn set_checksum(set: &[u8]) -> u16 {
+    set.iter().enumerate()
+        .filter(|&(i, _)| i != 2 && i != 3)
+        .fold(0u16, |checksum, (_, &byte)| checksum.rotate_right(1).wrapping_add(byte as u16))
+}
+
+/// Parses the records of the directory whose data is `data`.
+pub(crate) fn records(data: &[u8]) -> Vec<DirRecord> {
+    let num_records = data.len() / 32;
+    let record = |i: usize| &data[i * 32..(i + 1) * 32];
+
+    let mut records = Vec::new();
+    let mut i = 0;
+    while i < num_records {
+        let raw = record(i);
+        i += 1;
+        match raw[0] {
+            ENTRY_END => break,
+            // deleted records
+            kind if kind & ENTRY_IN_USE == 0 => continue,
+            ENTRY_BITMAP => records.push(DirRecord::Bitmap { index: raw[1] & 1, stream: system_stream(raw) }),
+            ENTRY_UPCASE => records.push(DirRecord::Upcase { checksum: read_u32(raw, 4), stream: system_stream(raw) }),
+            ENTRY_LABEL => {
+                let len = core::cmp::min(raw[1] as usize, 11);
+                let label: Vec<u16> = (0..len).map(|c| read_u16(raw, 2 + c * 2)).collect();
+                records.push(DirRecord::Label(String::from_utf16_lossy(&label)));
+            },
+            ENTRY_FILE => {
+                let secondary_count = raw[1] as usize;
+                if i + secondary_count > num_records {
+                    break;
+                }
+                let set = &data[(i - 1) * 32..(i + secondary_count) * 32];
+                i += secondary_count;
+                if let Some(file) = parse_file_set(set) {
+                    records.push(DirRecord::File(file));
+                }
+            },
+            // records of types this driver doesn't know
+            _ => continue,
+        }
+    }
+
+    records
+}
+
+/// Parses the entry set `set` of a file or directory. Returns `None` if its
+/// checksum doesn't match or it lacks a stream extension or name records.
+fn parse_file_set(set: &[u8]) -> Option<FileSet> {
+    if set.len() < 3 * 32 || set_checksum(set) != read_u16(set, 2) {
+        return None;
+    }
+
+    let stream = &set[32..64];
+    if stream[0] != ENTRY_STREAM {
+        return None;
+    }
+    let name_len = stream[3] as usize;
+    let mut name = Vec::with_capacity(name_len);
+    for record in set[64..].chunks_exact(32).take_while(|record| record[0] == ENTRY_NAME) {
+        for c in 0..NAME_CHARS_PER_ENTRY {
+            if name.len() < name_len {
+                name.push(read_u16(record, 2 + c * 2));
+            }
+        }
+    }
+    if name_len == 0 || name.len() < name_len {
+        return None;
+    }
+
+    let flags = stream[1];
+    let size = read_u64(stream, 24);
+    let valid_size = core::cmp::min(read_u64(stream, 8), size);
+    let data = match flags & FLAG_ALLOCATION_POSSIBLE != 0 && size > 0 {
+        true => Stream {
+            first_cluster: Cluster(read_u32(stream, 20)),
+            size,
+            valid_size,
+            contiguous: flags & FLAG_NO_FAT_CHAIN != 0,
+        },
+        false => Stream::default(),
+    };
+
+    let file = &set[..32];
+    Some(FileSet {
+        name,
+        name_hash: read_u16(stream, 4),
+        metadata: Metadata {
+            attributes: read_u16(file, 4),
+            created: Timestamp { packed: read_u32(file, 8), increment_10ms: file[20] },
+            modified: Timestamp { packed: read_u32(file, 12), increment_10ms: file[21] },
+            accessed: Timestamp { packed: read_u32(file, 16), increment_10ms: 0 },
+            size,
+            valid_size,
+        },
+        stream: data,
+    })
+}
+
+/// A directory of an exFAT volume.
+#[derive(Debug)]
+pub struct Dir<HANDLE: ExFatHandle> {
+    pub exfat: HANDLE,
+    pub name: String,
+    pub metadata: Metadata,
+    pub stream: Stream,
+}
+
+impl<HANDLE: ExFatHandle> Dir<HANDLE> {
+    /// Returns the root directory of the volume behind `exfat`.
+    pub fn root(exfat: HANDLE) -> Dir<HANDLE> {
+        let root = exfat.lock(|exfat: &mut ExFat<HANDLE>| exfat.root);
+        Dir { exfat, name: String::from("root"), metadata: root_metadata(&root), stream: root }
+    }
+
+    /// Returns the entry sets of the files and directories in `self`.
+    fn file_sets(&self) -> io::Result<Vec<FileSet>> {
+        let stream = self.stream;
+        let data = self.exfat.lock(|exfat: &mut ExFat<HANDLE>| exfat.read_all(stream))?;
+        Ok(records(&data).into_iter()
+            .filter_map(|record| match record {
+                DirRecord::File(file) => Some(file),
+                _ => None
+            })
+            .collect())
+    }
+
+    /// Turns the entry set `file` of an entry in `self` into an `Entry`.
+    fn entry(&self, file: FileSet) -> Entry<HANDLE> {
+        let name = String::from_utf16_lossy(&file.name);
+        match file.metadata.attributes & ATTR_DIRECTORY {
+            0 => Entry::File(File::new(self.exfat.clone(), name, file.metadata, file.stream)),
+            _ => Entry::Dir(Dir { exfat: self.exfat.clone(), name, metadata: file.metadata, stream: file.stream }),
+        }
+    }
+
+    /// Finds the entry named `name` in `self` and returns it. Names are
+    /// compared after up-casing them with the volume's up-case table.
+    ///
+    /// # Errors
+    ///
+    /// If no entry with name `name` exists in `self`, an error of `NotFound` is
+    /// returned.
+    ///
+    /// If `name` contains invalid UTF-8 characters, an error of `InvalidInput`
+    /// is returned.
+    pub fn find<P: AsRef<OsStr>>(&self, name: P) -> io::Result<Entry<HANDLE>> {
+        let name: Vec<u16> = match name.as_ref().to_str() {
+            Some(s) => s.encode_utf16().collect(),
+            None => return ioerr!(InvalidInput, "name contained invalid utf8")
+        };
+
+        let files = self.file_sets()?;
+        let found = self.exfat.lock(|exfat: &mut ExFat<HANDLE>| {
+            let hash = exfat.upcase.name_hash(&name);
+            files.into_iter()
+                .find(|file| file.name_hash == hash && exfat.upcase.eq_names(&file.name, &name))
+        });
+
+        match found {
+            Some(file) => Ok(self.entry(file)),
+            None => ioerr!(NotFound, "Entry not found in directory")
+        }
+    }
+}
+
+impl<HANDLE: ExFatHandle> traits::Dir for Dir<HANDLE> {
+    /// The type of entry stored in this directory.
+    type Entry = Entry<HANDLE>;
+
+    /// An type that is an iterator over the entries in this directory.
+    type Iter = alloc::vec::IntoIter<Entry<HANDLE>>;
+
+    /// Returns an interator over the entries in this directory.
+    fn entries(&self) -> io::Result<Self::Iter> {
+        let entries: Vec<Entry<HANDLE>> = self.file_sets()?.into_iter()
+            .map(|file| self.entry(file))
+            .collect();
+        Ok(entries.into_iter())
+    }
+}
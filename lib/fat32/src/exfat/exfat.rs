@@ -0,0 +1,360 @@
+use core::cmp::min;
+use core::fmt::Debug;
+use core::marker::PhantomData;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use shim::io;
+use shim::ioerr;
+use shim::path::{Component, Path};
+
+use crate::exfat::dir::{self, DirRecord};
+use crate::exfat::{BootSector, Dir, Entry, File, Metadata, UpcaseTable};
+use crate::partition::{self, PartitionInfo};
+use crate::traits::{BlockDevice, FileSystem};
+use crate::vfat::{CachedPartition, Cluster, Error, Partition, StatFs};
+
+/// A generic trait that handles a critical section as a closure
+pub trait ExFatHandle: Clone + Debug + Send + Sync {
+    fn new(val: ExFat<Self>) -> Self;
+    fn lock<R>(&self, f: impl FnOnce(&mut ExFat<Self>) -> R) -> R;
+}
+
+/// A mounted exFAT volume, as returned by `ExFat::from()`.
+#[derive(Clone, Debug)]
+pub struct ExFatFs<HANDLE: ExFatHandle>(pub HANDLE);
+
+impl<HANDLE: ExFatHandle> ExFatFs<HANDLE> {
+    /// Runs `f` with the volume locked.
+    pub fn lock<R>(&self, f: impl FnOnce(&mut ExFat<HANDLE>) -> R) -> R {
+        self.0.lock(f)
+    }
+}
+
+/// Where the data of a file, a directory or a system structure lives.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Stream {
+    /// The first cluster of the data, `Cluster(0)` if there is none.
+    pub first_cluster: Cluster,
+    /// The size of the data in bytes.
+    pub size: u64,
+    /// How much of the data has been written; bytes past it read as zeroes.
+    pub valid_size: u64,
+    /// Whether the data's clusters are contiguous and not recorded in the
+    /// FAT, as flagged by `NoFatChain`.
+    pub contiguous: bool,
+}
+
+/// A mounted exFAT volume. Read only.
+#[derive(Debug)]
+pub struct ExFat<HANDLE: ExFatHandle> {
+    phantom: PhantomData<HANDLE>,
+    pub device: CachedPartition,
+    pub bytes_per_sector: u32,
+    pub sectors_per_cluster: u32,
+    /// The first sector of the active FAT.
+    pub fat_start_sector: u64,
+    /// The sector of cluster 2, the first of the cluster heap.
+    pub cluster_heap_start_sector: u64,
+    /// The number of clusters in the cluster heap.
+    pub cluster_count: u32,
+    /// The root directory, which is always recorded in the FAT.
+    pub root: Stream,
+    pub volume_serial: u32,
+    pub volume_label: String,
+    /// The allocation bitmap of the active FAT.
+    pub bitmap: Stream,
+    pub upcase: UpcaseTable,
+}
+
+impl<HANDLE: ExFatHandle> ExFat<HANDLE> {
+    /// Mounts the first exFAT file system on `device`. Partitions that may
+    /// hold an exFAT file system are tried in partition table order.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if there are no candidate partitions, or the error
+    /// encountered while reading the first candidate if none of them holds an
+    /// exFAT file system.
+    pub fn from<T>(mut device: T) -> Result<ExFatFs<HANDLE>, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        let partitions = partition::partitions(&mut device)?;
+        let mut error = Error::NotFound;
+        for part in partitions.iter().filter(|part| part.may_be_exfat()) {
+            match BootSector::from(&mut device, part.start) {
+                Ok(boot) => return ExFat::mount(device, part, boot),
+                Err(e) => if let Error::NotFound = error {
+                    error = e;
+                }
+            }
+        }
+
+        Err(error)
+    }
+
+    /// Mounts the exFAT file system in `partition` of `device`, as returned
+    /// by `partition::partitions()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadSignature` if the partition doesn't hold an exFAT file
+    /// system.
+    pub fn from_partition<T>(mut device: T, partition: &PartitionInfo) -> Result<ExFatFs<HANDLE>, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        let boot = BootSector::from(&mut device, partition.start)?;
+        ExFat::mount(device, partition, boot)
+    }
+
+    fn mount<T>(device: T, part: &PartitionInfo, boot: BootSector) -> Result<ExFatFs<HANDLE>, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        let bytes_per_sector = boot.bytes_per_sector() as u64;
+        let factor = bytes_per_sector / device.sector_size();
+        let cached_partition = CachedPartition::new(device, Partition {
+            start: part.start,
+            num_sectors: part.num_sectors / factor,
+            sector_size: bytes_per_sector,
+        });
+
+        let start = part.start;
+        let mut exfat = ExFat::<HANDLE> {
+            phantom: PhantomData,
+            device: cached_partition,
+            bytes_per_sector: bytes_per_sector as u32,
+            sectors_per_cluster: boot.sectors_per_cluster(),
+            fat_start_sector: start + boot.fat_offset as u64 + boot.active_fat() as u64 * boot.fat_length as u64,
+            cluster_heap_start_sector: start + boot.cluster_heap_offset as u64,
+            cluster_count: boot.cluster_count,
+            root: Stream::default(),
+            volume_serial: boot.volume_serial_number,
+            volume_label: String::new(),
+            bitmap: Stream::default(),
+            upcase: UpcaseTable::ascii(),
+        };
+
+        let root = Cluster(boot.first_cluster_of_root_directory);
+        let root_size = exfat.chain_length(root)? as u64 * exfat.bytes_per_cluster() as u64;
+        exfat.root = Stream { first_cluster: root, size: root_size, valid_size: root_size, contiguous: false };
+
+        let (mut bitmap, mut upcase) = (None, None);
+        let root = exfat.root;
+        for record in dir::records(&exfat.read_all(root)?) {
+            match record {
+                DirRecord::Bitmap { index, stream } if index == boot.active_fat() => bitmap = Some(stream),
+                DirRecord::Upcase { checksum, stream } => upcase = Some((checksum, stream)),
+                DirRecord::Label(label) => exfat.volume_label = label,
+                _ => ()
+            }
+        }
+
+        exfat.bitmap = match bitmap {
+            Some(stream) => stream,
+            None => return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidData, "no allocation bitmap")))
+        };
+        exfat.upcase = match upcase {
+            Some((checksum, stream)) => UpcaseTable::from(&exfat.read_all(stream)?, checksum)?,
+            None => return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidData, "no up-case table")))
+        };
+
+        Ok(ExFatFs(HANDLE::new(exfat)))
+    }
+
+    /// Returns the number of bytes in a single cluster.
+    pub fn bytes_per_cluster(&self) -> usize {
+        self.bytes_per_sector as usize * self.sectors_per_cluster as usize
+    }
+
+    /// Returns the first sector of the cluster heap backing `cluster`.
+    fn cluster_start_sector(&self, cluster: Cluster) -> u64 {
+        self.cluster_heap_start_sector + (cluster.0 - 2) as u64 * self.sectors_per_cluster as u64
+    }
+
+    /// Returns `true` if `cluster` is in the cluster heap.
+    fn is_valid(&self, cluster: Cluster) -> bool {
+        cluster.0 >= 2 && cluster.0 - 2 < self.cluster_count
+    }
+
+    /// Returns the cluster following `cluster` in the FAT, or `None` if
+    /// `cluster` is the last cluster of its chain.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidData` if the entry is not a cluster of the heap or the
+    /// end of chain marker.
+    pub fn next_cluster(&mut self, cluster: Cluster) -> io::Result<Option<Cluster>> {
+        let bytes_per_sector = self.bytes_per_sector as u64;
+        let offset = cluster.0 as u64 * 4;
+        let sector = self.device.get(self.fat_start_sector + offset / bytes_per_sector)?;
+        let at = (offset % bytes_per_sector) as usize;
+        let next = u32::from_le_bytes([sector[at], sector[at + 1], sector[at + 2], sector[at + 3]]);
+        match next {
+            0xFFFFFFFF => Ok(None),
+            next if self.is_valid(Cluster(next)) => Ok(Some(Cluster(next))),
+            _ => ioerr!(InvalidData, "invalid cluster chain")
+        }
+    }
+
+    /// Returns the number of clusters in the FAT chain beginning at `start`.
+    fn chain_length(&mut self, start: Cluster) -> io::Result<u32> {
+        if !self.is_valid(start) {
+            return ioerr!(InvalidData, "invalid first cluster");
+        }
+
+        let mut cluster = start;
+        for length in 1..=self.cluster_count {
+            cluster = match self.next_cluster(cluster)? {
+                Some(next) => next,
+                None => return Ok(length)
+            };
+        }
+        ioerr!(InvalidData, "cluster chain loops")
+    }
+
+    /// Returns the cluster at position `index` of `stream`'s data. The walk
+    /// along the FAT starts at `from`, a known `(index, cluster)` pair of the
+    /// stream, if it does not lie past `index`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidData` if the stream has no cluster at `index`.
+    pub fn cluster_at(&mut self, stream: &Stream, from: Option<(u32, Cluster)>, index: u32) -> io::Result<Cluster> {
+        let num_clusters = (stream.size + self.bytes_per_cluster() as u64 - 1) / self.bytes_per_cluster() as u64;
+        if index as u64 >= num_clusters || !self.is_valid(stream.first_cluster) {
+            return ioerr!(InvalidData, "stream has no cluster at index");
+        }
+
+        if stream.contiguous {
+            let cluster = Cluster(stream.first_cluster.0 + index);
+            return match self.is_valid(cluster) {
+                true => Ok(cluster),
+                false => ioerr!(InvalidData, "contiguous stream runs past the cluster heap")
+            };
+        }
+
+        let (mut i, mut cluster) = match from {
+            Some((i, cluster)) if i <= index => (i, cluster),
+            _ => (0, stream.first_cluster)
+        };
+        while i < index {
+            cluster = match self.next_cluster(cluster)? {
+                Some(next) => next,
+                None => return ioerr!(InvalidData, "cluster chain is shorter than the stream")
+            };
+            i += 1;
+        }
+        Ok(cluster)
+    }
+
+    /// Reads from byte `offset` of `cluster` into `buf`, at most up to the end
+    /// of the cluster. Returns the number of bytes read.
+    pub fn read_cluster(&mut self, cluster: Cluster, offset: usize, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_per_sector = self.bytes_per_sector as usize;
+        let num_bytes = min(buf.len(), self.bytes_per_cluster() - offset);
+        let start_sector = self.cluster_start_sector(cluster);
+        let mut read = 0;
+        while read < num_bytes {
+            let pos = offset + read;
+            let sector_offset = pos % bytes_per_sector;
+            let n = min(num_bytes - read, bytes_per_sector - sector_offset);
+            let sector = self.device.get(start_sector + (pos / bytes_per_sector) as u64)?;
+            buf[read..read + n].copy_from_slice(&sector[sector_offset..sector_offset + n]);
+            read += n;
+        }
+        Ok(num_bytes)
+    }
+
+    /// Reads all of `stream`'s data. Bytes past its valid size are zeroes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidData`, before allocating anything, if
+    /// the stream is larger than the volume or its valid size exceeds its
+    /// size.
+    pub fn read_all(&mut self, stream: Stream) -> io::Result<Vec<u8>> {
+        let volume_size = self.cluster_count as u64 * self.bytes_per_cluster() as u64;
+        if stream.size > volume_size || stream.valid_size > stream.size {
+            return ioerr!(InvalidData, "stream size exceeds the volume");
+        }
+
+        let mut data = vec![0u8; stream.size as usize];
+        let valid = stream.valid_size as usize;
+        let bytes_per_cluster = self.bytes_per_cluster();
+        let mut cursor = None;
+        for (index, chunk) in data[..valid].chunks_mut(bytes_per_cluster).enumerate() {
+            let cluster = self.cluster_at(&stream, cursor, index as u32)?;
+            self.read_cluster(cluster, 0, chunk)?;
+            cursor = Some((index as u32, cluster));
+        }
+        Ok(data)
+    }
+
+    /// Returns the number of clusters the allocation bitmap marks as free.
+    pub fn free_clusters(&mut self) -> io::Result<u32> {
+        let bitmap = self.read_all(self.bitmap)?;
+        let used: u32 = (0..self.cluster_count as usize)
+            .filter(|&i| bitmap.get(i / 8).map_or(false, |byte| byte & (1 << (i % 8)) != 0))
+            .count() as u32;
+        Ok(self.cluster_count - used)
+    }
+
+    /// Returns usage statistics for the volume.
+    pub fn statfs(&mut self) -> io::Result<StatFs> {
+        Ok(StatFs {
+            bytes_per_cluster: self.bytes_per_cluster() as u64,
+            total_clusters: self.cluster_count,
+            free_clusters: self.free_clusters()?,
+        })
+    }
+}
+
+impl<'a, HANDLE: ExFatHandle> FileSystem for &'a ExFatFs<HANDLE> {
+    type File = File<HANDLE>;
+    type Dir = Dir<HANDLE>;
+    type Entry = Entry<HANDLE>;
+
+    fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
+        let mut entries: Vec<Entry<HANDLE>> = Vec::new();
+        for component in path.as_ref().components() {
+            match component {
+                Component::RootDir => {
+                    entries.truncate(0);
+                    entries.push(Entry::Dir(Dir::root(self.0.clone())));
+                },
+                Component::Normal(name) => {
+                    let next = match entries.last() {
+                        Some(Entry::Dir(dir)) => dir.find(name)?,
+                        _ => return ioerr!(NotFound, "file not found")
+                    };
+                    entries.push(next);
+                },
+                Component::ParentDir => {
+                    if entries.pop().is_none() {
+                        return ioerr!(NotFound, "file not found");
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        match entries.pop() {
+            Some(entry) => Ok(entry),
+            None => ioerr!(NotFound, "file not found")
+        }
+    }
+}
+
+/// Returns the metadata of the root directory, which has no file entry.
+pub(crate) fn root_metadata(root: &Stream) -> Metadata {
+    Metadata {
+        attributes: crate::exfat::metadata::ATTR_DIRECTORY,
+        size: root.size,
+        valid_size: root.valid_size,
+        ..Metadata::default()
+    }
+}
@@ -0,0 +1,108 @@
+use alloc::string::String;
+use core::cmp::min;
+
+use shim::io::{self, SeekFrom};
+use shim::ioerr;
+
+use crate::exfat::{ExFat, ExFatHandle, Metadata, Stream};
+use crate::traits;
+use crate::vfat::Cluster;
+
+/// A regular file of an exFAT volume. Read only.
+#[derive(Debug)]
+pub struct File<HANDLE: ExFatHandle> {
+    pub exfat: HANDLE,
+    pub name: String,
+    pub metadata: Metadata,
+    pub stream: Stream,
+    pub current_offset: u64,
+    /// The position within the file's chain and the cluster of the last
+    /// cluster accessed, which FAT walks resume from.
+    cursor: Option<(u32, Cluster)>,
+    bytes_per_cluster: u64,
+}
+
+impl<HANDLE: ExFatHandle> File<HANDLE> {
+    /// Returns the file named `name` whose data is `stream`.
+    pub fn new(exfat: HANDLE, name: String, metadata: Metadata, stream: Stream) -> File<HANDLE> {
+        let bytes_per_cluster = exfat.lock(|exfat: &mut ExFat<HANDLE>| exfat.bytes_per_cluster() as u64);
+        File { exfat, name, metadata, stream, current_offset: 0, cursor: None, bytes_per_cluster }
+    }
+}
+
+impl<HANDLE: ExFatHandle> traits::File for File<HANDLE> {
+    /// Files are read only; there is nothing to write back.
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Returns the size of the file in bytes.
+    fn size(&self) -> u64 {
+        self.stream.size
+    }
+}
+
+impl<HANDLE: ExFatHandle> io::Write for File<HANDLE> {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        ioerr!(PermissionDenied, "read-only file system")
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<HANDLE: ExFatHandle> io::Read for File<HANDLE> {
+    /// Reads from the current offset into `buf`. Bytes between the file's
+    /// valid data length and its size read as zeroes.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_size = min(buf.len() as u64, self.stream.size.saturating_sub(self.current_offset)) as usize;
+        let valid_size = min(read_size as u64, self.stream.valid_size.saturating_sub(self.current_offset)) as usize;
+
+        let mut read = 0;
+        while read < valid_size {
+            let offset = self.current_offset + read as u64;
+            let index = (offset / self.bytes_per_cluster) as u32;
+            let (stream, cursor) = (self.stream, self.cursor);
+            let chunk = &mut buf[read..valid_size];
+            let (cluster, n) = self.exfat.lock(|exfat: &mut ExFat<HANDLE>| -> io::Result<_> {
+                let cluster = exfat.cluster_at(&stream, cursor, index)?;
+                let n = exfat.read_cluster(cluster, (offset % exfat.bytes_per_cluster() as u64) as usize, chunk)?;
+                Ok((cluster, n))
+            })?;
+            self.cursor = Some((index, cluster));
+            read += n;
+        }
+
+        for byte in buf[valid_size..read_size].iter_mut() {
+            *byte = 0;
+        }
+        self.current_offset += read_size as u64;
+        Ok(read_size)
+    }
+}
+
+impl<HANDLE: ExFatHandle> io::Seek for File<HANDLE> {
+    /// Seek to offset `pos` in the file.
+    ///
+    /// A seek to the end of the file is allowed. A seek _beyond_ the end of the
+    /// file returns an `InvalidInput` error.
+    ///
+    /// # Errors
+    ///
+    /// Seeking before the start of a file or beyond the end of the file results
+    /// in an `InvalidInput` error.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_offset = match pos {
+            SeekFrom::Start(off) => off as i64,
+            SeekFrom::Current(off) => self.current_offset as i64 + off,
+            SeekFrom::End(off) => self.stream.size as i64 + off
+        };
+
+        if new_offset < 0 || new_offset as u64 > self.stream.size {
+            return ioerr!(InvalidInput, "invalid seek");
+        }
+        self.current_offset = new_offset as u64;
+        Ok(self.current_offset)
+    }
+}
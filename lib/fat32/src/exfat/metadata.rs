@@ -0,0 +1,94 @@
+use core::fmt;
+
+use crate::traits;
+
+/// Attribute bit marking an entry as read only.
+pub(crate) const ATTR_READ_ONLY: u16 = 0x01;
+/// Attribute bit marking an entry as hidden.
+pub(crate) const ATTR_HIDDEN: u16 = 0x02;
+/// Attribute bit marking an entry as a directory.
+pub(crate) const ATTR_DIRECTORY: u16 = 0x10;
+
+/// A timestamp as represented in exFAT file entries: a FAT date and time
+/// packed into 32 bits, with the odd seconds in a separate 10ms increment.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Timestamp {
+    /// Two-second count in bits 0-4, then minute, hour, day, month and the
+    /// year since 1980.
+    pub packed: u32,
+    /// Hundredths of a second to add, in the range [0, 199].
+    pub increment_10ms: u8,
+}
+
+impl traits::Timestamp for Timestamp {
+    fn year(&self) -> usize {
+        (self.packed >> 25) as usize + 1980
+    }
+
+    fn month(&self) -> u8 {
+        ((self.packed >> 21) & 0xF) as u8
+    }
+
+    fn day(&self) -> u8 {
+        ((self.packed >> 16) & 0x1F) as u8
+    }
+
+    fn hour(&self) -> u8 {
+        ((self.packed >> 11) & 0x1F) as u8
+    }
+
+    fn minute(&self) -> u8 {
+        ((self.packed >> 5) & 0x3F) as u8
+    }
+
+    fn second(&self) -> u8 {
+        let second = (self.packed & 0x1F) as u8 * 2 + self.increment_10ms / 100;
+        core::cmp::min(second, 59)
+    }
+}
+
+/// Metadata of an exFAT file or directory, from its file and stream
+/// extension entries.
+#[derive(Default, Debug, Clone)]
+pub struct Metadata {
+    pub attributes: u16,
+    pub created: Timestamp,
+    pub modified: Timestamp,
+    pub accessed: Timestamp,
+    /// The size of the entry's data in bytes.
+    pub size: u64,
+    /// How much of the data has been written; bytes past it read as zeroes.
+    pub valid_size: u64,
+}
+
+impl traits::Metadata for Metadata {
+    type Timestamp = Timestamp;
+
+    fn read_only(&self) -> bool {
+        self.attributes & ATTR_READ_ONLY != 0
+    }
+
+    fn hidden(&self) -> bool {
+        self.attributes & ATTR_HIDDEN != 0
+    }
+
+    fn created(&self) -> Self::Timestamp {
+        self.created
+    }
+
+    fn accessed(&self) -> Self::Timestamp {
+        self.accessed
+    }
+
+    fn modified(&self) -> Self::Timestamp {
+        self.modified
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use traits::Timestamp;
+        write!(f, "{:02}/{:02}/{:04} {:02}:{:02}:{:02}", self.month(), self.day(), self.year(),
+               self.hour(), self.minute(), self.second())
+    }
+}
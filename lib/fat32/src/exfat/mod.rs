@@ -0,0 +1,18 @@
+//! A read-only exFAT driver implementing the file system traits, for cards
+//! of 64GB and larger, which ship formatted as exFAT.
+
+pub(crate) mod boot;
+pub(crate) mod dir;
+pub(crate) mod entry;
+pub(crate) mod exfat;
+pub(crate) mod file;
+pub(crate) mod metadata;
+pub(crate) mod upcase;
+
+pub use self::boot::BootSector;
+pub use self::dir::Dir;
+pub use self::entry::Entry;
+pub use self::exfat::{ExFat, ExFatFs, ExFatHandle, Stream};
+pub use self::file::File;
+pub use self::metadata::{Metadata, Timestamp};
+pub use self::upcase::UpcaseTable;
@@ -0,0 +1,93 @@
+use alloc::vec::Vec;
+
+use shim::io;
+use shim::ioerr;
+
+/// The up-case table of an exFAT volume, which defines how file names are
+/// compared without regard to case.
+///
+/// Only the characters the table does not map to themselves are kept, so an
+/// unusual table costs memory in proportion to how much of it is unusual.
+#[derive(Debug, Clone, Default)]
+pub struct UpcaseTable {
+    /// `(character, up-cased character)` pairs, sorted by character.
+    mappings: Vec<(u16, u16)>,
+}
+
+/// Marks a run of characters that map to themselves in a compressed table.
+const IDENTITY_RUN: u16 = 0xFFFF;
+
+impl UpcaseTable {
+    /// Parses the up-case table `raw`, which may be compressed, and verifies
+    /// it against `checksum`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidData` if the checksum does not match or the table
+    /// maps more than the 65536 UTF-16 code units.
+    pub fn from(raw: &[u8], checksum: u32) -> io::Result<UpcaseTable> {
+        if table_checksum(raw) != checksum {
+            return ioerr!(InvalidData, "up-case table checksum mismatch");
+        }
+
+        let mut mappings = Vec::new();
+        let mut units = raw.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]]));
+        let mut character: u32 = 0;
+        while let Some(unit) = units.next() {
+            if character > 0xFFFF {
+                return ioerr!(InvalidData, "up-case table is too long");
+            }
+
+            match unit {
+                IDENTITY_RUN => match units.next() {
+                    Some(run) => character += run as u32,
+                    None => return ioerr!(InvalidData, "up-case table ends in a run marker")
+                },
+                upcased => {
+                    if upcased as u32 != character {
+                        mappings.push((character as u16, upcased));
+                    }
+                    character += 1;
+                }
+            }
+        }
+
+        Ok(UpcaseTable { mappings })
+    }
+
+    /// The table used when a volume has none: ASCII letters are up-cased and
+    /// every other character maps to itself.
+    pub fn ascii() -> UpcaseTable {
+        UpcaseTable { mappings: (b'a'..=b'z').map(|c| (c as u16, (c - b'a' + b'A') as u16)).collect() }
+    }
+
+    /// Returns the up-cased form of the UTF-16 code unit `unit`.
+    pub fn upcase(&self, unit: u16) -> u16 {
+        match self.mappings.binary_search_by_key(&unit, |&(character, _)| character) {
+            Ok(i) => self.mappings[i].1,
+            Err(_) => unit
+        }
+    }
+
+    /// Returns `true` if the UTF-16 names `a` and `b` are equal after
+    /// up-casing.
+    pub fn eq_names(&self, a: &[u16], b: &[u16]) -> bool {
+        a.len() == b.len() && a.iter().zip(b.iter()).all(|(&x, &y)| self.upcase(x) == self.upcase(y))
+    }
+
+    /// Computes the hash of the up-cased UTF-16 name `name`, as stored in
+    /// stream extension entries to speed up lookups.
+    pub fn name_hash(&self, name: &[u16]) -> u16 {
+        name.iter().fold(0u16, |hash, &unit| {
+            let upcased = self.upcase(unit).to_le_bytes();
+            let hash = hash.rotate_right(1).wrapping_add(upcased[0] as u16);
+            hash.rotate_right(1).wrapping_add(upcased[1] as u16)
+        })
+    }
+}
+
+/// Computes the checksum of an up-case table, as stored in its directory
+/// entry.
+pub fn table_checksum(raw: &[u8]) -> u32 {
+    raw.iter().fold(0u32, |checksum, &byte| checksum.rotate_right(1).wrapping_add(byte as u32))
+}
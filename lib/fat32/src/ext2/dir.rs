@@ -0,0 +1,141 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use shim::ffi::OsStr;
+use shim::io;
+use shim::ioerr;
+
+use crate::ext2::ext2::ROOT_INODE;
+use crate::ext2::{Entry, Ext2, Ext2Handle, File, Inode, Metadata};
+use crate::traits;
+
+/// The size of the fixed part of a directory record, before the name.
+const RECORD_HEADER_SIZE: usize = 8;
+
+/// A record of a directory's linked list of entries.
+#[derive(Debug, Clone)]
+pub(crate) struct DirRecord {
+    pub inode: u32,
+    pub name: String,
+}
+
+/// Parses the records of the directory whose data is `data`. Each record
+/// holds the distance to the next one; unused records have inode `0`.
+/// `filetype` tells whether the upper byte of the name length holds the
+/// file type instead.
+pub(crate) fn records(data: &[u8], filetype: bool) -> Vec<DirRecord> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset + RECORD_HEADER_SIZE <= data.len() {
+        let raw = &data[offset..];
+        let inode = u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]);
+        let rec_len = u16::from_le_bytes([raw[4], raw[5]]) as usize;
+        let name_len = match filetype {
+            true => raw[6] as usize,
+            false => u16::from_le_bytes([raw[6], raw[7]]) as usize,
+        };
+        if rec_len < RECORD_HEADER_SIZE || offset + rec_len > data.len()
+            || RECORD_HEADER_SIZE + name_len > rec_len {
+            break;
+        }
+
+        if inode != 0 {
+            let name = &raw[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + name_len];
+            records.push(DirRecord { inode, name: String::from_utf8_lossy(name).into() });
+        }
+        offset += rec_len;
+    }
+
+    records
+}
+
+/// A directory of an ext2 volume.
+#[derive(Debug)]
+pub struct Dir<HANDLE: Ext2Handle> {
+    pub ext2: HANDLE,
+    pub name: String,
+    pub metadata: Metadata,
+    pub inode: Inode,
+}
+
+impl<HANDLE: Ext2Handle> Dir<HANDLE> {
+    /// Returns the root directory of the volume behind `ext2`.
+    pub fn root(ext2: HANDLE) -> io::Result<Dir<HANDLE>> {
+        let (inode, metadata) = ext2.lock(|ext2: &mut Ext2<HANDLE>| -> io::Result<_> {
+            let inode = ext2.inode(ROOT_INODE)?;
+            Ok((inode, ext2.metadata(ROOT_INODE, &inode, "")))
+        })?;
+        Ok(Dir { ext2, name: String::from("root"), metadata, inode })
+    }
+
+    /// Returns the records of the entries in `self`, `.` and `..` included.
+    fn records(&self) -> io::Result<Vec<DirRecord>> {
+        let inode = self.inode;
+        self.ext2.lock(|ext2: &mut Ext2<HANDLE>| -> io::Result<_> {
+            let data = ext2.read_all(&inode)?;
+            Ok(records(&data, ext2.filetype))
+        })
+    }
+
+    /// Turns the record `record` of an entry in `self` into an `Entry`.
+    /// Returns `None` for entries that are neither regular files nor
+    /// directories, such as symbolic links and device nodes.
+    fn entry(&self, record: DirRecord) -> io::Result<Option<Entry<HANDLE>>> {
+        let (inode, metadata) = self.ext2.lock(|ext2: &mut Ext2<HANDLE>| -> io::Result<_> {
+            let inode = ext2.inode(record.inode)?;
+            Ok((inode, ext2.metadata(record.inode, &inode, &record.name)))
+        })?;
+
+        let ext2 = self.ext2.clone();
+        Ok(match (inode.is_dir(), inode.is_regular()) {
+            (true, _) => Some(Entry::Dir(Dir { ext2, name: record.name, metadata, inode })),
+            (_, true) => Some(Entry::File(File::new(ext2, record.name, metadata, inode))),
+            _ => None
+        })
+    }
+
+    /// Finds the entry named `name` in `self` and returns it. Names are
+    /// compared case sensitively.
+    ///
+    /// # Errors
+    ///
+    /// If no entry with name `name` exists in `self`, or it is neither a
+    /// regular file nor a directory, an error of `NotFound` is returned.
+    ///
+    /// If `name` contains invalid UTF-8 characters, an error of `InvalidInput`
+    /// is returned.
+    pub fn find<P: AsRef<OsStr>>(&self, name: P) -> io::Result<Entry<HANDLE>> {
+        let name = match name.as_ref().to_str() {
+            Some(s) => s,
+            None => return ioerr!(InvalidInput, "name contained invalid utf8")
+        };
+
+        let found = self.records()?.into_iter().find(|record| record.name == name);
+        match found {
+            Some(record) => match self.entry(record)? {
+                Some(entry) => Ok(entry),
+                None => ioerr!(NotFound, "Entry not found in directory")
+            },
+            None => ioerr!(NotFound, "Entry not found in directory")
+        }
+    }
+}
+
+impl<HANDLE: Ext2Handle> traits::Dir for Dir<HANDLE> {
+    /// The type of entry stored in this directory.
+    type Entry = Entry<HANDLE>;
+
+    /// An type that is an iterator over the entries in this directory.
+    type Iter = alloc::vec::IntoIter<Entry<HANDLE>>;
+
+    /// Returns an interator over the entries in this directory.
+    fn entries(&self) -> io::Result<Self::Iter> {
+        let mut entries = Vec::new();
+        for record in self.records()? {
+            if let Some(entry) = self.entry(record)? {
+                entries.push(entry);
+            }
+        }
+        Ok(entries.into_iter())
+    }
+}
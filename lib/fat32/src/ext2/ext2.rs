@@ -0,0 +1,325 @@
+use core::cmp::min;
+use core::fmt::Debug;
+use core::marker::PhantomData;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use shim::io;
+use shim::ioerr;
+use shim::path::{Component, Path};
+
+use crate::ext2::inode::NUM_DIRECT_BLOCKS;
+use crate::ext2::superblock::RO_COMPAT_LARGE_FILE;
+use crate::ext2::{BlockGroupDescriptor, Dir, Entry, File, Inode, Metadata, Superblock, Timestamp};
+use crate::partition::{self, PartitionInfo};
+use crate::traits::{BlockDevice, FileSystem};
+use crate::vfat::{CachedPartition, Error, Partition, StatFs};
+
+/// The inode of the root directory.
+pub const ROOT_INODE: u32 = 2;
+
+/// A generic trait that handles a critical section as a closure
+pub trait Ext2Handle: Clone + Debug + Send + Sync {
+    fn new(val: Ext2<Self>) -> Self;
+    fn lock<R>(&self, f: impl FnOnce(&mut Ext2<Self>) -> R) -> R;
+}
+
+/// A mounted ext2 volume, as returned by `Ext2::from()`.
+#[derive(Clone, Debug)]
+pub struct Ext2Fs<HANDLE: Ext2Handle>(pub HANDLE);
+
+impl<HANDLE: Ext2Handle> Ext2Fs<HANDLE> {
+    /// Runs `f` with the volume locked.
+    pub fn lock<R>(&self, f: impl FnOnce(&mut Ext2<HANDLE>) -> R) -> R {
+        self.0.lock(f)
+    }
+}
+
+/// A mounted ext2 volume. Read only.
+///
+/// The volume is cached in blocks: sector `n` of `device` past the start of
+/// the partition is block `n` of the volume.
+#[derive(Debug)]
+pub struct Ext2<HANDLE: Ext2Handle> {
+    phantom: PhantomData<HANDLE>,
+    pub device: CachedPartition,
+    /// The first sector of the volume, which is also its block 0.
+    pub start: u64,
+    pub block_size: u32,
+    pub blocks_count: u32,
+    pub first_data_block: u32,
+    pub inodes_count: u32,
+    pub inodes_per_group: u32,
+    pub inode_size: u16,
+    /// Whether regular files' sizes have an upper 32 bits.
+    pub large_files: bool,
+    /// Whether directory records hold the file type in the upper byte of the
+    /// name length.
+    pub filetype: bool,
+    pub groups: Vec<BlockGroupDescriptor>,
+    pub volume_name: String,
+}
+
+impl<HANDLE: Ext2Handle> Ext2<HANDLE> {
+    /// Mounts the first ext2 file system on `device`. Partitions that may
+    /// hold an ext2 file system are tried in partition table order.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if there are no candidate partitions, or the error
+    /// encountered while reading the first candidate if none of them holds an
+    /// ext2 file system.
+    pub fn from<T>(mut device: T) -> Result<Ext2Fs<HANDLE>, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        let partitions = partition::partitions(&mut device)?;
+        let mut error = Error::NotFound;
+        for part in partitions.iter().filter(|part| part.may_be_ext2()) {
+            match Superblock::from(&mut device, part.start) {
+                Ok(superblock) => return Ext2::mount(device, part, superblock),
+                Err(e) => if let Error::NotFound = error {
+                    error = e;
+                }
+            }
+        }
+
+        Err(error)
+    }
+
+    /// Mounts the ext2 file system in `partition` of `device`, as returned by
+    /// `partition::partitions()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadSignature` if the partition doesn't hold an ext2 file
+    /// system.
+    pub fn from_partition<T>(mut device: T, partition: &PartitionInfo) -> Result<Ext2Fs<HANDLE>, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        let superblock = Superblock::from(&mut device, partition.start)?;
+        Ext2::mount(device, partition, superblock)
+    }
+
+    fn mount<T>(device: T, part: &PartitionInfo, superblock: Superblock) -> Result<Ext2Fs<HANDLE>, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        let block_size = superblock.block_size() as u64;
+        let factor = block_size / device.sector_size();
+        if (superblock.blocks_count as u64) > part.num_sectors / factor {
+            return Err(Error::BadSignature);
+        }
+        let cached_partition = CachedPartition::new(device, Partition {
+            start: part.start,
+            num_sectors: part.num_sectors / factor,
+            sector_size: block_size,
+        });
+
+        let name_len = superblock.volume_name.iter().position(|&c| c == 0).unwrap_or(16);
+        let mut ext2 = Ext2::<HANDLE> {
+            phantom: PhantomData,
+            device: cached_partition,
+            start: part.start,
+            block_size: block_size as u32,
+            blocks_count: superblock.blocks_count,
+            first_data_block: superblock.first_data_block,
+            inodes_count: superblock.inodes_count,
+            inodes_per_group: superblock.inodes_per_group,
+            inode_size: superblock.inode_size(),
+            large_files: superblock.has_ro_compat(RO_COMPAT_LARGE_FILE),
+            filetype: superblock.has_filetype(),
+            groups: Vec::new(),
+            volume_name: String::from_utf8_lossy(&superblock.volume_name[..name_len]).into(),
+        };
+
+        // the group descriptor table starts in the block after the superblock
+        let num_groups = superblock.num_groups() as usize;
+        let mut table = vec![0u8; num_groups * 32];
+        ext2.read(superblock.first_data_block + 1, 0, &mut table)?;
+        ext2.groups = table.chunks_exact(32).map(BlockGroupDescriptor::from_bytes).collect();
+
+        if !ext2.inode(ROOT_INODE)?.is_dir() {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidData, "root inode is not a directory")));
+        }
+
+        Ok(Ext2Fs(HANDLE::new(ext2)))
+    }
+
+    /// Returns the number of block pointers in an indirect block.
+    fn pointers_per_block(&self) -> u32 {
+        self.block_size / 4
+    }
+
+    /// Reads from byte `offset` of block `block` into `buf`, continuing into
+    /// the blocks that follow it until `buf` is full.
+    fn read(&mut self, block: u32, offset: usize, buf: &mut [u8]) -> io::Result<()> {
+        let block_size = self.block_size as usize;
+        let mut read = 0;
+        while read < buf.len() {
+            let pos = offset + read;
+            let block_offset = pos % block_size;
+            let n = min(buf.len() - read, block_size - block_offset);
+            let data = self.device.get(self.start + block as u64 + (pos / block_size) as u64)?;
+            buf[read..read + n].copy_from_slice(&data[block_offset..block_offset + n]);
+            read += n;
+        }
+        Ok(())
+    }
+
+    /// Reads from byte `offset` of block `block` into `buf`, at most up to
+    /// the end of the block. Block `0` is a hole and reads as zeroes. Returns
+    /// the number of bytes read.
+    pub fn read_block(&mut self, block: u32, offset: usize, buf: &mut [u8]) -> io::Result<usize> {
+        let num_bytes = min(buf.len(), self.block_size as usize - offset);
+        match block {
+            0 => buf[..num_bytes].iter_mut().for_each(|byte| *byte = 0),
+            _ => self.read(block, offset, &mut buf[..num_bytes])?,
+        }
+        Ok(num_bytes)
+    }
+
+    /// Returns the inode numbered `number`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidData` if there is no such inode on the volume.
+    pub fn inode(&mut self, number: u32) -> io::Result<Inode> {
+        if number == 0 || number > self.inodes_count {
+            return ioerr!(InvalidData, "invalid inode number");
+        }
+
+        let group = ((number - 1) / self.inodes_per_group) as usize;
+        let index = ((number - 1) % self.inodes_per_group) as usize;
+        let table = match self.groups.get(group) {
+            Some(descriptor) => descriptor.inode_table,
+            None => return ioerr!(InvalidData, "inode lies past the last block group")
+        };
+
+        let mut raw = [0u8; 128];
+        self.read(table, index * self.inode_size as usize, &mut raw)?;
+        Ok(Inode::from_bytes(&raw))
+    }
+
+    /// Returns the block holding block `index` of `inode`'s data, following
+    /// indirect blocks as needed, or `0` if it is a hole.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidData` if `index` lies beyond what triply indirect
+    /// blocks can address.
+    pub fn block_of(&mut self, inode: &Inode, index: u32) -> io::Result<u32> {
+        let block = inode.block;
+        if (index as usize) < NUM_DIRECT_BLOCKS {
+            return Ok(block[index as usize]);
+        }
+
+        let per_block = self.pointers_per_block() as u64;
+        let mut index = (index as usize - NUM_DIRECT_BLOCKS) as u64;
+        let mut span = 1;
+        for level in 1..=3 {
+            span *= per_block;
+            if index >= span {
+                index -= span;
+                continue;
+            }
+
+            // walk down from the level's indirect block, one span at a time
+            let mut current = block[NUM_DIRECT_BLOCKS + level - 1];
+            while current != 0 && span > 1 {
+                span /= per_block;
+                let mut raw = [0u8; 4];
+                self.read(current, (index / span) as usize * 4, &mut raw)?;
+                current = u32::from_le_bytes(raw);
+                index %= span;
+            }
+            return Ok(current);
+        }
+
+        ioerr!(InvalidData, "block index lies beyond triply indirect blocks")
+    }
+
+    /// Reads all of `inode`'s data. Holes read as zeroes.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidData` if `inode` is larger than the volume.
+    pub fn read_all(&mut self, inode: &Inode) -> io::Result<Vec<u8>> {
+        let size = inode.size(self.large_files);
+        if size > self.blocks_count as u64 * self.block_size as u64 {
+            return ioerr!(InvalidData, "inode size exceeds the volume");
+        }
+
+        let mut data = vec![0u8; size as usize];
+        let block_size = self.block_size as usize;
+        for (index, chunk) in data.chunks_mut(block_size).enumerate() {
+            let block = self.block_of(inode, index as u32)?;
+            self.read_block(block, 0, chunk)?;
+        }
+        Ok(data)
+    }
+
+    /// Returns the metadata of inode `number`, which is `inode` and is named
+    /// `name`.
+    pub fn metadata(&self, number: u32, inode: &Inode, name: &str) -> Metadata {
+        Metadata {
+            inode: number,
+            mode: inode.mode,
+            uid: inode.uid,
+            gid: inode.gid,
+            links_count: inode.links_count,
+            size: inode.size(self.large_files),
+            accessed: Timestamp(inode.atime),
+            changed: Timestamp(inode.ctime),
+            modified: Timestamp(inode.mtime),
+            dot_file: name.starts_with('.'),
+        }
+    }
+
+    /// Returns usage statistics for the volume, in blocks.
+    pub fn statfs(&self) -> StatFs {
+        StatFs {
+            bytes_per_cluster: self.block_size as u64,
+            total_clusters: self.blocks_count - self.first_data_block,
+            free_clusters: self.groups.iter().map(|group| group.free_blocks_count as u32).sum(),
+        }
+    }
+}
+
+impl<'a, HANDLE: Ext2Handle> FileSystem for &'a Ext2Fs<HANDLE> {
+    type File = File<HANDLE>;
+    type Dir = Dir<HANDLE>;
+    type Entry = Entry<HANDLE>;
+
+    fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
+        let mut entries: Vec<Entry<HANDLE>> = Vec::new();
+        for component in path.as_ref().components() {
+            match component {
+                Component::RootDir => {
+                    entries.truncate(0);
+                    entries.push(Entry::Dir(Dir::root(self.0.clone())?));
+                },
+                Component::Normal(name) => {
+                    let next = match entries.last() {
+                        Some(Entry::Dir(dir)) => dir.find(name)?,
+                        _ => return ioerr!(NotFound, "file not found")
+                    };
+                    entries.push(next);
+                },
+                Component::ParentDir => {
+                    if entries.pop().is_none() {
+                        return ioerr!(NotFound, "file not found");
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        match entries.pop() {
+            Some(entry) => Ok(entry),
+            None => ioerr!(NotFound, "file not found")
+        }
+    }
+}
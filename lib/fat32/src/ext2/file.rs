@@ -0,0 +1,97 @@
+use alloc::string::String;
+use core::cmp::min;
+
+use shim::io::{self, SeekFrom};
+use shim::ioerr;
+
+use crate::ext2::{Ext2, Ext2Handle, Inode, Metadata};
+use crate::traits;
+
+/// A regular file of an ext2 volume. Read only.
+#[derive(Debug)]
+pub struct File<HANDLE: Ext2Handle> {
+    pub ext2: HANDLE,
+    pub name: String,
+    pub metadata: Metadata,
+    pub inode: Inode,
+    pub current_offset: u64,
+    block_size: u64,
+}
+
+impl<HANDLE: Ext2Handle> File<HANDLE> {
+    /// Returns the file named `name` whose inode is `inode`.
+    pub fn new(ext2: HANDLE, name: String, metadata: Metadata, inode: Inode) -> File<HANDLE> {
+        let block_size = ext2.lock(|ext2: &mut Ext2<HANDLE>| ext2.block_size as u64);
+        File { ext2, name, metadata, inode, current_offset: 0, block_size }
+    }
+}
+
+impl<HANDLE: Ext2Handle> traits::File for File<HANDLE> {
+    /// Files are read only; there is nothing to write back.
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Returns the size of the file in bytes.
+    fn size(&self) -> u64 {
+        self.metadata.size
+    }
+}
+
+impl<HANDLE: Ext2Handle> io::Write for File<HANDLE> {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        ioerr!(PermissionDenied, "read-only file system")
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<HANDLE: Ext2Handle> io::Read for File<HANDLE> {
+    /// Reads from the current offset into `buf`. Holes read as zeroes.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_size = min(buf.len() as u64, self.metadata.size.saturating_sub(self.current_offset)) as usize;
+
+        let mut read = 0;
+        while read < read_size {
+            let offset = self.current_offset + read as u64;
+            let index = (offset / self.block_size) as u32;
+            let block_offset = (offset % self.block_size) as usize;
+            let inode = self.inode;
+            let chunk = &mut buf[read..read_size];
+            read += self.ext2.lock(|ext2: &mut Ext2<HANDLE>| -> io::Result<usize> {
+                let block = ext2.block_of(&inode, index)?;
+                ext2.read_block(block, block_offset, chunk)
+            })?;
+        }
+
+        self.current_offset += read_size as u64;
+        Ok(read_size)
+    }
+}
+
+impl<HANDLE: Ext2Handle> io::Seek for File<HANDLE> {
+    /// Seek to offset `pos` in the file.
+    ///
+    /// A seek to the end of the file is allowed. A seek _beyond_ the end of the
+    /// file returns an `InvalidInput` error.
+    ///
+    /// # Errors
+    ///
+    /// Seeking before the start of a file or beyond the end of the file results
+    /// in an `InvalidInput` error.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_offset = match pos {
+            SeekFrom::Start(off) => off as i64,
+            SeekFrom::Current(off) => self.current_offset as i64 + off,
+            SeekFrom::End(off) => self.metadata.size as i64 + off
+        };
+
+        if new_offset < 0 || new_offset as u64 > self.metadata.size {
+            return ioerr!(InvalidInput, "invalid seek");
+        }
+        self.current_offset = new_offset as u64;
+        Ok(self.current_offset)
+    }
+}
@@ -0,0 +1,100 @@
+use core::mem;
+use shim::const_assert_size;
+
+/// The number of direct block pointers in an inode.
+pub const NUM_DIRECT_BLOCKS: usize = 12;
+
+/// Mask of the file type bits of `Inode::mode`.
+pub const MODE_TYPE_MASK: u16 = 0xF000;
+/// File type of directories.
+pub const MODE_DIRECTORY: u16 = 0x4000;
+/// File type of regular files.
+pub const MODE_REGULAR: u16 = 0x8000;
+
+/// An entry of the block group descriptor table.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct BlockGroupDescriptor {
+    pub block_bitmap: u32,
+    pub inode_bitmap: u32,
+    /// The first block of the group's inode table.
+    pub inode_table: u32,
+    pub free_blocks_count: u16,
+    pub free_inodes_count: u16,
+    pub used_dirs_count: u16,
+    _pad: u16,
+    _reserved: [u8; 12],
+}
+
+const_assert_size!(BlockGroupDescriptor, 32);
+
+impl BlockGroupDescriptor {
+    /// Parses the 32 byte descriptor `raw`.
+    pub fn from_bytes(raw: &[u8]) -> BlockGroupDescriptor {
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(&raw[..32]);
+        unsafe { mem::transmute::<[u8; 32], BlockGroupDescriptor>(bytes) }
+    }
+}
+
+/// The first 128 bytes of an on-disk inode, which is all revision 0 inodes
+/// hold and all this driver reads of larger ones.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Inode {
+    /// The file type in the upper four bits, then the permission bits.
+    pub mode: u16,
+    pub uid: u16,
+    /// The lower 32 bits of the size in bytes.
+    pub size: u32,
+    pub atime: u32,
+    pub ctime: u32,
+    pub mtime: u32,
+    pub dtime: u32,
+    pub gid: u16,
+    pub links_count: u16,
+    /// The number of 512 byte sectors allocated to the inode.
+    pub blocks: u32,
+    pub flags: u32,
+    _osd1: u32,
+    /// Twelve direct block pointers, then the single, double and triple
+    /// indirect block pointers. `0` marks a hole.
+    pub block: [u32; 15],
+    pub generation: u32,
+    pub file_acl: u32,
+    /// The upper 32 bits of the size of a regular file on volumes with large
+    /// files.
+    pub size_high: u32,
+    pub faddr: u32,
+    _osd2: [u8; 12],
+}
+
+const_assert_size!(Inode, 128);
+
+impl Inode {
+    /// Parses the inode at the start of `raw`.
+    pub fn from_bytes(raw: &[u8]) -> Inode {
+        let mut bytes = [0u8; 128];
+        bytes.copy_from_slice(&raw[..128]);
+        unsafe { mem::transmute::<[u8; 128], Inode>(bytes) }
+    }
+
+    /// Returns `true` if the inode is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.mode & MODE_TYPE_MASK == MODE_DIRECTORY
+    }
+
+    /// Returns `true` if the inode is a regular file.
+    pub fn is_regular(&self) -> bool {
+        self.mode & MODE_TYPE_MASK == MODE_REGULAR
+    }
+
+    /// The size of the inode's data in bytes. `large_files` tells whether the
+    /// volume stores the upper half of regular files' sizes.
+    pub fn size(&self, large_files: bool) -> u64 {
+        match large_files && self.is_regular() {
+            true => (self.size_high as u64) << 32 | self.size as u64,
+            false => self.size as u64
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use core::fmt;
+
+use crate::traits;
+
+/// A point in time as stored in ext2 inodes: seconds since the Unix epoch,
+/// in UTC.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Timestamp(pub u32);
+
+impl Timestamp {
+    /// Returns the `(year, month, day)` of the timestamp in the proleptic
+    /// Gregorian calendar.
+    fn date(&self) -> (usize, u8, u8) {
+        // Howard Hinnant's days-to-civil algorithm
+        let z = (self.0 / 86400) as i64 + 719468;
+        let era = z / 146097;
+        let day_of_era = z - era * 146097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u8;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+        (year as usize, month, day)
+    }
+}
+
+impl traits::Timestamp for Timestamp {
+    fn year(&self) -> usize {
+        self.date().0
+    }
+
+    fn month(&self) -> u8 {
+        self.date().1
+    }
+
+    fn day(&self) -> u8 {
+        self.date().2
+    }
+
+    fn hour(&self) -> u8 {
+        (self.0 % 86400 / 3600) as u8
+    }
+
+    fn minute(&self) -> u8 {
+        (self.0 % 3600 / 60) as u8
+    }
+
+    fn second(&self) -> u8 {
+        (self.0 % 60) as u8
+    }
+}
+
+/// Metadata of an ext2 file or directory, from its inode.
+#[derive(Default, Debug, Clone)]
+pub struct Metadata {
+    /// The inode number.
+    pub inode: u32,
+    /// The file type and permission bits.
+    pub mode: u16,
+    pub uid: u16,
+    pub gid: u16,
+    pub links_count: u16,
+    /// The size of the entry's data in bytes.
+    pub size: u64,
+    pub accessed: Timestamp,
+    /// When the inode last changed, which ext2 records instead of when it was
+    /// created.
+    pub changed: Timestamp,
+    pub modified: Timestamp,
+    /// Whether the entry's name begins with a dot.
+    pub dot_file: bool,
+}
+
+impl traits::Metadata for Metadata {
+    type Timestamp = Timestamp;
+
+    /// Whether no one may write to the entry.
+    fn read_only(&self) -> bool {
+        self.mode & 0o222 == 0
+    }
+
+    /// Whether the entry's name begins with a dot, the Unix convention for
+    /// hidden files.
+    fn hidden(&self) -> bool {
+        self.dot_file
+    }
+
+    fn created(&self) -> Self::Timestamp {
+        self.changed
+    }
+
+    fn accessed(&self) -> Self::Timestamp {
+        self.accessed
+    }
+
+    fn modified(&self) -> Self::Timestamp {
+        self.modified
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use traits::Timestamp;
+        write!(f, "{:02}/{:02}/{:04} {:02}:{:02}:{:02}", self.month(), self.day(), self.year(),
+               self.hour(), self.minute(), self.second())
+    }
+}
@@ -0,0 +1,18 @@
+//! A read-only ext2 driver implementing the file system traits, for images
+//! built with the usual Linux tools.
+
+pub(crate) mod dir;
+pub(crate) mod entry;
+pub(crate) mod ext2;
+pub(crate) mod file;
+pub(crate) mod inode;
+pub(crate) mod metadata;
+pub(crate) mod superblock;
+
+pub use self::dir::Dir;
+pub use self::entry::Entry;
+pub use self::ext2::{Ext2, Ext2Fs, Ext2Handle, ROOT_INODE};
+pub use self::file::File;
+pub use self::inode::{BlockGroupDescriptor, Inode};
+pub use self::metadata::{Metadata, Timestamp};
+pub use self::superblock::Superblock;
@@ -0,0 +1,151 @@
+use core::fmt;
+use core::mem;
+use shim::const_assert_size;
+
+use crate::traits::BlockDevice;
+use crate::vfat::Error;
+
+/// The ext2 superblock, which lives 1024 bytes into the volume.
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct Superblock {
+    pub inodes_count: u32,
+    pub blocks_count: u32,
+    pub r_blocks_count: u32,
+    pub free_blocks_count: u32,
+    pub free_inodes_count: u32,
+    /// The block holding the superblock: 1 for 1KiB blocks, 0 otherwise.
+    pub first_data_block: u32,
+    /// The block size is `1024 << log_block_size`.
+    pub log_block_size: u32,
+    pub log_frag_size: u32,
+    pub blocks_per_group: u32,
+    pub frags_per_group: u32,
+    pub inodes_per_group: u32,
+    pub mtime: u32,
+    pub wtime: u32,
+    pub mnt_count: u16,
+    pub max_mnt_count: u16,
+    pub magic: u16,
+    pub state: u16,
+    pub errors: u16,
+    pub minor_rev_level: u16,
+    pub lastcheck: u32,
+    pub checkinterval: u32,
+    pub creator_os: u32,
+    pub rev_level: u32,
+    pub def_resuid: u16,
+    pub def_resgid: u16,
+    // the following fields are only valid from revision 1 on
+    pub first_ino: u32,
+    pub inode_size: u16,
+    pub block_group_nr: u16,
+    pub feature_compat: u32,
+    pub feature_incompat: u32,
+    pub feature_ro_compat: u32,
+    pub uuid: [u8; 16],
+    pub volume_name: [u8; 16],
+    pub last_mounted: [u8; 64],
+    pub algo_bitmap: u32,
+    _reserved: [u8; 820],
+}
+
+const_assert_size!(Superblock, 1024);
+
+/// The superblock's magic number.
+const EXT2_MAGIC: u16 = 0xEF53;
+/// The byte offset of the superblock within the volume.
+const SUPERBLOCK_OFFSET: u64 = 1024;
+
+/// Incompatible feature: directory entries record the file type.
+pub const INCOMPAT_FILETYPE: u32 = 0x0002;
+/// Read-only compatible feature: regular files may exceed 4GiB.
+pub const RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+
+impl Superblock {
+    /// Reads and validates the superblock of the volume starting at sector
+    /// `sector` of `device`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadSignature` if the magic number is wrong, the volume's
+    /// geometry is invalid or it uses incompatible features other than file
+    /// types in directory entries, such as ext4's extents. Returns `Io(err)`
+    /// if reading from the device fails.
+    pub fn from<T: BlockDevice>(mut device: T, sector: u64) -> Result<Superblock, Error> {
+        let sector_size = device.sector_size();
+        let mut raw = [0u8; 1024];
+        let first = sector + SUPERBLOCK_OFFSET / sector_size;
+        let mut buf = vec![0u8; sector_size as usize];
+        let mut done = 0;
+        let mut n = 0;
+        while done < raw.len() {
+            device.read_sector(first + n, &mut buf)?;
+            let skip = if n == 0 { (SUPERBLOCK_OFFSET % sector_size) as usize } else { 0 };
+            let len = core::cmp::min(raw.len() - done, buf.len() - skip);
+            raw[done..done + len].copy_from_slice(&buf[skip..skip + len]);
+            done += len;
+            n += 1;
+        }
+
+        let superblock = unsafe { mem::transmute::<[u8; 1024], Superblock>(raw) };
+        if superblock.magic != EXT2_MAGIC
+            || superblock.log_block_size > 6
+            || superblock.blocks_per_group == 0
+            || superblock.inodes_per_group == 0
+            || superblock.inode_size() < 128
+            || (superblock.block_size() as u64) < sector_size
+            || superblock.feature_incompat & !INCOMPAT_FILETYPE != 0 {
+            return Err(Error::BadSignature);
+        }
+
+        Ok(superblock)
+    }
+
+    /// The size of a block in bytes.
+    pub fn block_size(&self) -> u32 {
+        1024 << self.log_block_size
+    }
+
+    /// The size of an on-disk inode in bytes.
+    pub fn inode_size(&self) -> u16 {
+        match self.rev_level {
+            0 => 128,
+            _ => self.inode_size
+        }
+    }
+
+    /// The number of block groups.
+    pub fn num_groups(&self) -> u32 {
+        let data_blocks = self.blocks_count - self.first_data_block;
+        (data_blocks + self.blocks_per_group - 1) / self.blocks_per_group
+    }
+
+    /// Returns `true` if the volume has all of the read-only compatible
+    /// features `features`.
+    pub fn has_ro_compat(&self, features: u32) -> bool {
+        self.rev_level > 0 && self.feature_ro_compat & features == features
+    }
+
+    /// Returns `true` if directory entries record the type of their inode.
+    pub fn has_filetype(&self) -> bool {
+        self.rev_level > 0 && self.feature_incompat & INCOMPAT_FILETYPE != 0
+    }
+}
+
+impl fmt::Debug for Superblock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Superblock")
+            .field("inodes_count", &{ self.inodes_count })
+            .field("blocks_count", &{ self.blocks_count })
+            .field("free_blocks_count", &{ self.free_blocks_count })
+            .field("first_data_block", &{ self.first_data_block })
+            .field("block_size", &self.block_size())
+            .field("blocks_per_group", &{ self.blocks_per_group })
+            .field("inodes_per_group", &{ self.inodes_per_group })
+            .field("rev_level", &{ self.rev_level })
+            .field("inode_size", &self.inode_size())
+            .field("feature_incompat", &{ self.feature_incompat })
+            .finish()
+    }
+}
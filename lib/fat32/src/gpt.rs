@@ -0,0 +1,233 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::mem;
+use shim::const_assert_size;
+use shim::io;
+
+use crate::traits::BlockDevice;
+
+/// A globally unique identifier as stored in GPT structures: the first three
+/// fields are little endian, the last two big endian.
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq, Default, Hash)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    /// The unused entry type.
+    pub const UNUSED: Guid = Guid([0; 16]);
+    /// Microsoft basic data partition, used for FAT and NTFS volumes.
+    pub const BASIC_DATA: Guid = Guid([0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44,
+                                       0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7]);
+    /// EFI system partition, which holds a FAT file system.
+    pub const EFI_SYSTEM: Guid = Guid([0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11,
+                                       0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B]);
+    /// Linux file system data, used for ext2 volumes.
+    pub const LINUX_FILESYSTEM: Guid = Guid([0xAF, 0x3D, 0xC6, 0x0F, 0x83, 0x84, 0x72, 0x47,
+                                             0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4]);
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.0;
+        write!(f, "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-",
+               b[3], b[2], b[1], b[0], b[5], b[4], b[7], b[6], b[8], b[9])?;
+        for byte in b[10..].iter() {
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+const_assert_size!(Guid, 16);
+
+/// The GUID partition table header.
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct GptHeader {
+    pub signature: u64,
+    pub revision: u32,
+    pub header_size: u32,
+    pub header_crc32: u32,
+    _reserved: u32,
+    pub current_lba: u64,
+    pub backup_lba: u64,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub disk_guid: Guid,
+    pub entries_lba: u64,
+    pub num_entries: u32,
+    pub entry_size: u32,
+    pub entries_crc32: u32,
+}
+
+const_assert_size!(GptHeader, 92);
+
+impl fmt::Debug for GptHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GptHeader")
+            .field("current_lba", &{ self.current_lba })
+            .field("backup_lba", &{ self.backup_lba })
+            .field("first_usable_lba", &{ self.first_usable_lba })
+            .field("last_usable_lba", &{ self.last_usable_lba })
+            .field("disk_guid", &{ self.disk_guid })
+            .field("entries_lba", &{ self.entries_lba })
+            .field("num_entries", &{ self.num_entries })
+            .field("entry_size", &{ self.entry_size })
+            .finish()
+    }
+}
+
+/// A GUID partition table entry. Entries may be larger than this structure;
+/// the remaining bytes are ignored.
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct GptEntry {
+    pub type_guid: Guid,
+    pub unique_guid: Guid,
+    pub first_lba: u64,
+    /// The last sector of the partition, inclusive.
+    pub last_lba: u64,
+    pub attributes: u64,
+    pub name: [u16; 36],
+}
+
+const_assert_size!(GptEntry, 128);
+
+impl GptEntry {
+    /// Returns `true` if the entry does not describe a partition.
+    pub fn is_unused(&self) -> bool {
+        self.type_guid == Guid::UNUSED
+    }
+
+    /// The partition's name, decoded from UTF-16.
+    pub fn name(&self) -> String {
+        let name = { self.name };
+        let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+        String::from_utf16_lossy(&name[..len])
+    }
+}
+
+impl fmt::Debug for GptEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GptEntry")
+            .field("type_guid", &{ self.type_guid })
+            .field("unique_guid", &{ self.unique_guid })
+            .field("first_lba", &{ self.first_lba })
+            .field("last_lba", &{ self.last_lba })
+            .field("name", &self.name())
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// There was an I/O error while reading the partition table.
+    Io(io::Error),
+    /// The GPT header signature was invalid.
+    BadSignature,
+    /// The header's size or entry size is out of range.
+    BadHeader,
+    /// The CRC32 of the header does not match its contents.
+    BadHeaderChecksum,
+    /// The CRC32 of the partition entries does not match their contents.
+    BadEntriesChecksum,
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+/// "EFI PART" in little endian.
+const GPT_SIGNATURE: u64 = 0x5452415020494645;
+
+/// The largest partition entry array this parser accepts, in bytes.
+const MAX_ENTRIES_SIZE: usize = 1 << 20;
+
+/// Computes the CRC32 (IEEE 802.3) of `data`, as used by GPT.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Reads `num_bytes` bytes starting at sector `sector` of `device`.
+fn read_bytes<T: BlockDevice>(device: &mut T, sector: u64, num_bytes: usize) -> io::Result<Vec<u8>> {
+    let sector_size = device.sector_size() as usize;
+    let num_sectors = (num_bytes + sector_size - 1) / sector_size;
+    let mut buf = vec![0u8; num_sectors * sector_size];
+    for (i, chunk) in buf.chunks_mut(sector_size).enumerate() {
+        device.read_sector(sector + i as u64, chunk)?;
+    }
+    buf.truncate(num_bytes);
+    Ok(buf)
+}
+
+impl GptHeader {
+    /// Reads and validates the GPT header at sector `lba` of `device`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadSignature` if the header's signature is invalid,
+    /// `BadHeader` if its size fields are out of range and
+    /// `BadHeaderChecksum` if its CRC32 does not match. Returns `Io(err)` if
+    /// reading the header fails.
+    pub fn from<T: BlockDevice>(mut device: T, lba: u64) -> Result<GptHeader, Error> {
+        let sector_size = device.sector_size() as usize;
+        let mut sector = read_bytes(&mut device, lba, sector_size)?;
+        let mut raw = [0u8; 92];
+        raw.copy_from_slice(&sector[..92]);
+        let header = unsafe { mem::transmute::<[u8; 92], GptHeader>(raw) };
+
+        if header.signature != GPT_SIGNATURE {
+            return Err(Error::BadSignature);
+        }
+
+        let header_size = header.header_size as usize;
+        if header_size < 92 || header_size > sector.len()
+            || (header.entry_size as usize) < mem::size_of::<GptEntry>()
+            || header.entry_size % 8 != 0
+            || header.num_entries as usize * header.entry_size as usize > MAX_ENTRIES_SIZE {
+            return Err(Error::BadHeader);
+        }
+
+        // the CRC is computed with the CRC field itself zeroed
+        sector[16..20].copy_from_slice(&[0; 4]);
+        if crc32(&sector[..header_size]) != header.header_crc32 {
+            return Err(Error::BadHeaderChecksum);
+        }
+
+        Ok(header)
+    }
+
+    /// Reads the partition entries described by this header from `device`,
+    /// including unused ones.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadEntriesChecksum` if the CRC32 of the entry array does not
+    /// match the header. Returns `Io(err)` if reading the entries fails.
+    pub fn entries<T: BlockDevice>(&self, mut device: T) -> Result<Vec<GptEntry>, Error> {
+        let entry_size = self.entry_size as usize;
+        let raw = read_bytes(&mut device, self.entries_lba, self.num_entries as usize * entry_size)?;
+        if crc32(&raw) != self.entries_crc32 {
+            return Err(Error::BadEntriesChecksum);
+        }
+
+        Ok(raw.chunks(entry_size)
+            .map(|chunk| {
+                let mut entry = [0u8; 128];
+                entry.copy_from_slice(&chunk[..128]);
+                unsafe { mem::transmute::<[u8; 128], GptEntry>(entry) }
+            })
+            .collect())
+    }
+}
@@ -15,6 +15,13 @@ mod mbr;
 mod tests;
 mod util;
 
+pub mod check;
+pub mod exfat;
+pub mod ext2;
+pub mod gpt;
+pub mod mkfs;
+pub mod partition;
+pub mod tmpfs;
 pub mod traits;
 pub mod vfat;
 
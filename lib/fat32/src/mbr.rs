@@ -2,6 +2,7 @@ use core::fmt;
 use shim::const_assert_size;
 use shim::io;
 use core::mem;
+use core::slice;
 
 use crate::traits::BlockDevice;
 
@@ -25,6 +26,7 @@ impl fmt::Debug for CHS {
 const_assert_size!(CHS, 3);
 
 #[repr(C, packed)]
+#[derive(Copy, Clone)]
 pub struct PartitionEntry {
     pub boot_indicator: u8, // 0x80 for bootable
     _start_chs: CHS,
@@ -50,6 +52,37 @@ impl fmt::Debug for PartitionEntry {
 
 const_assert_size!(PartitionEntry, 16);
 
+impl PartitionEntry {
+    /// Returns a non-bootable entry for a partition of type `partition_type`
+    /// spanning `total_sectors` sectors from `relative_sector`. The CHS fields
+    /// are set to the "use LBA" marker.
+    pub fn new(partition_type: u8, relative_sector: u32, total_sectors: u32) -> PartitionEntry {
+        let lba = CHS { head: 0xFE, cs_lower_byte: 0xFF, cs_upper_byte: 0xFF };
+        PartitionEntry {
+            boot_indicator: 0,
+            _start_chs: lba,
+            partition_type,
+            _end_chs: lba,
+            relative_sector,
+            total_sectors,
+        }
+    }
+
+    /// Returns `true` if the entry is unused.
+    pub fn is_empty(&self) -> bool {
+        self.partition_type == 0 || self.total_sectors == 0
+    }
+
+    /// Returns `true` if the entry describes an extended partition, which
+    /// holds a chain of extended boot records with logical partitions.
+    pub fn is_extended(&self) -> bool {
+        match self.partition_type {
+            0x05 | 0x0F | 0x85 => true,
+            _ => false
+        }
+    }
+}
+
 /// The master boot record (MBR).
 #[repr(C, packed)]
 pub struct MasterBootRecord {
@@ -89,6 +122,20 @@ pub enum Error {
 }
 
 impl MasterBootRecord {
+    /// Returns an MBR with the given disk ID, an empty partition table and a
+    /// valid signature.
+    pub fn new(disk_id: [u8; 10]) -> MasterBootRecord {
+        let mut mbr = unsafe { mem::transmute::<[u8; 512], MasterBootRecord>([0u8; 512]) };
+        mbr.disk_id = disk_id;
+        mbr.signature = 0xAA55;
+        mbr
+    }
+
+    /// Returns the on-disk representation of the MBR.
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self as *const MasterBootRecord as *const u8, 512) }
+    }
+
     /// Reads and returns the master boot record (MBR) from `device`.
     ///
     /// # Errors
@@ -97,11 +144,21 @@ impl MasterBootRecord {
     /// Returns `UnknownBootIndicator(n)` if partition `n` contains an invalid
     /// boot indicator. Returns `Io(err)` if the I/O error `err` occured while
     /// reading the MBR.
-    pub fn from<T: BlockDevice>(mut device: T) -> Result<MasterBootRecord, Error> {
-        // read the MBR from sector 0 of the block device, 
+    pub fn from<T: BlockDevice>(device: T) -> Result<MasterBootRecord, Error> {
+        MasterBootRecord::from_sector(device, 0)
+    }
+
+    /// Reads and returns a boot record with the MBR layout from sector
+    /// `sector` of `device`, such as an extended boot record (EBR).
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as `from()`.
+    pub fn from_sector<T: BlockDevice>(mut device: T, sector: u64) -> Result<MasterBootRecord, Error> {
+        // read the MBR from the block device, 
         // check for IO errors
         let mut sector_0_buf = [0u8; 512];
-        match device.read_sector(0, &mut sector_0_buf) {
+        match device.read_sector(sector, &mut sector_0_buf) {
              Err(err) => return Err(Error::Io(err)),
             _ => ()
         };
@@ -0,0 +1,225 @@
+//! Creates FAT32 file systems, the equivalent of `mkfs.fat -F 32`.
+//!
+//! `format()` writes an MBR with a single FAT32 partition to a block device
+//! and lays out an empty file system inside of it. On the host, `populate()`
+//! then copies a directory tree into the new file system. Both are fully
+//! deterministic, so the same inputs always produce the same image.
+
+use shim::io;
+use shim::ioerr;
+
+use crate::mbr::{MasterBootRecord, PartitionEntry};
+use crate::traits::BlockDevice;
+use crate::vfat::{BiosParameterBlock, FsInfo};
+
+/// The only sector size `format()` supports.
+const SECTOR_SIZE: usize = 512;
+/// The number of sectors reserved before the first FAT.
+const RESERVED_SECTORS: u16 = 32;
+/// The sector of the FSInfo structure, relative to the partition.
+const FS_INFO_SECTOR: u16 = 1;
+/// The sector of the backup boot sector, relative to the partition.
+const BACKUP_BOOT_SECTOR: u16 = 6;
+/// The cluster holding the root directory.
+const ROOT_CLUSTER: u32 = 2;
+/// The partition type of a FAT32 partition addressed by LBA.
+const PARTITION_TYPE_FAT32_LBA: u8 = 0x0C;
+/// The smallest number of clusters a FAT32 volume may have.
+const MIN_CLUSTERS: u64 = 65525;
+/// The largest number of clusters a FAT32 volume may have.
+const MAX_CLUSTERS: u64 = 0x0FFFFFF5;
+
+/// Parameters of the file system created by `format()`.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Size of the whole device in 512 byte sectors, including the MBR and
+    /// the gap before the partition.
+    pub num_sectors: u64,
+    /// First sector of the partition.
+    pub partition_start: u32,
+    /// The number of sectors per cluster, a power of two. `0` picks the size
+    /// Windows uses for a partition of this size.
+    pub sectors_per_cluster: u8,
+    /// The number of FAT copies.
+    pub num_fats: u8,
+    /// Serial number of the volume.
+    pub volume_id: u32,
+    /// Space padded volume label.
+    pub volume_label: [u8; 11],
+}
+
+impl Options {
+    /// Returns the default options for a device of `num_sectors` sectors: a
+    /// partition starting at 1MiB, two FATs and an automatic cluster size.
+    pub fn new(num_sectors: u64) -> Options {
+        Options {
+            num_sectors,
+            partition_start: 2048,
+            sectors_per_cluster: 0,
+            num_fats: 2,
+            volume_id: 0,
+            volume_label: *b"NO NAME    ",
+        }
+    }
+}
+
+/// Picks the cluster size Windows uses for a FAT32 partition of
+/// `partition_sectors` sectors.
+fn default_sectors_per_cluster(partition_sectors: u64) -> u8 {
+    match partition_sectors {
+        0..=532_480 => 1,
+        532_481..=16_777_216 => 8,
+        16_777_217..=33_554_432 => 16,
+        33_554_433..=67_108_864 => 32,
+        _ => 64,
+    }
+}
+
+/// Formats `device` as described by `options`: writes an MBR with a single
+/// FAT32 partition, the partition's boot sector and its backup, the FSInfo
+/// structure, every FAT copy and an empty root directory.
+///
+/// Only the metadata regions are written; `device` must already be
+/// `options.num_sectors` sectors long.
+///
+/// # Errors
+///
+/// Returns `InvalidInput` if the device doesn't use 512 byte sectors, if the
+/// cluster size is not a power of two or if the partition is too small or too
+/// large to hold a FAT32 file system. I/O errors are returned as is.
+pub fn format<T: BlockDevice>(mut device: T, options: &Options) -> io::Result<()> {
+    if device.sector_size() != SECTOR_SIZE as u64 {
+        return ioerr!(InvalidInput, "only 512 byte sectors are supported");
+    }
+
+    let partition_sectors = match options.num_sectors.checked_sub(options.partition_start as u64) {
+        Some(n) if n <= core::u32::MAX as u64 => n,
+        _ => return ioerr!(InvalidInput, "invalid partition size"),
+    };
+    let sectors_per_cluster = match options.sectors_per_cluster {
+        0 => default_sectors_per_cluster(partition_sectors),
+        n if n.is_power_of_two() => n,
+        _ => return ioerr!(InvalidInput, "sectors per cluster must be a power of two"),
+    };
+    if options.num_fats == 0 {
+        return ioerr!(InvalidInput, "at least one FAT is required");
+    }
+
+    // The FAT size calculation from Microsoft's FAT specification. It may
+    // overestimate slightly, which wastes at most a few sectors.
+    let num_fats = options.num_fats as u64;
+    let usable_sectors = partition_sectors.saturating_sub(RESERVED_SECTORS as u64);
+    let sectors_per_fat = {
+        let divisor = (256 * sectors_per_cluster as u64 + num_fats) / 2;
+        (usable_sectors + divisor - 1) / divisor
+    };
+    let data_sectors = usable_sectors.saturating_sub(num_fats * sectors_per_fat);
+    let num_clusters = data_sectors / sectors_per_cluster as u64;
+    if num_clusters < MIN_CLUSTERS {
+        return ioerr!(InvalidInput, "partition is too small for FAT32");
+    }
+    if num_clusters > MAX_CLUSTERS {
+        return ioerr!(InvalidInput, "partition is too large for FAT32");
+    }
+
+    let start = options.partition_start as u64;
+    let mut disk_id = [0u8; 10];
+    disk_id[4..8].copy_from_slice(&options.volume_id.to_le_bytes());
+    let mut mbr = MasterBootRecord::new(disk_id);
+    mbr.partition_table[0] = PartitionEntry::new(PARTITION_TYPE_FAT32_LBA, options.partition_start,
+                                                 partition_sectors as u32);
+    device.write_sector(0, mbr.as_bytes())?;
+
+    let zeroes = [0u8; SECTOR_SIZE];
+    let fat_start = start + RESERVED_SECTORS as u64;
+    let data_start = fat_start + num_fats * sectors_per_fat;
+    for sector in start..data_start + sectors_per_cluster as u64 {
+        device.write_sector(sector, &zeroes)?;
+    }
+
+    let mut ebpb = BiosParameterBlock::blank();
+    ebpb.bytes_per_sector = SECTOR_SIZE as u16;
+    ebpb.sectors_per_cluster = sectors_per_cluster;
+    ebpb.num_reserved_sectors = RESERVED_SECTORS;
+    ebpb.num_fats = options.num_fats;
+    ebpb.num_hidden_sectors = options.partition_start;
+    ebpb.total_logical_sectors = partition_sectors as u32;
+    ebpb.sectors_per_fat = sectors_per_fat as u32;
+    ebpb.root_cluster = ROOT_CLUSTER;
+    ebpb.fs_info_sector = FS_INFO_SECTOR;
+    ebpb.backup_boot_sector = BACKUP_BOOT_SECTOR;
+    ebpb.volume_id = options.volume_id;
+    ebpb.volume_label = options.volume_label;
+    device.write_sector(start, ebpb.as_bytes())?;
+    device.write_sector(start + BACKUP_BOOT_SECTOR as u64, ebpb.as_bytes())?;
+
+    // only the root directory's cluster is in use
+    let fs_info = FsInfo::new(num_clusters as u32 - 1, ROOT_CLUSTER + 1);
+    device.write_sector(start + FS_INFO_SECTOR as u64, fs_info.as_bytes())?;
+    device.write_sector(start + (BACKUP_BOOT_SECTOR + FS_INFO_SECTOR) as u64, fs_info.as_bytes())?;
+
+    // entry 0 holds the media descriptor, entry 1 the end of chain marker
+    let mut first_fat_sector = [0u8; SECTOR_SIZE];
+    first_fat_sector[0..4].copy_from_slice(&0x0FFFFFF8u32.to_le_bytes());
+    first_fat_sector[4..8].copy_from_slice(&0x0FFFFFFFu32.to_le_bytes());
+    first_fat_sector[8..12].copy_from_slice(&0x0FFFFFFFu32.to_le_bytes());
+    for i in 0..num_fats {
+        device.write_sector(fat_start + i * sectors_per_fat, &first_fat_sector)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "no_std"))]
+pub use self::host::populate;
+
+#[cfg(not(feature = "no_std"))]
+mod host {
+    use std::fs;
+    use std::io::Write;
+    use std::path::Path;
+
+    use shim::io;
+
+    use crate::traits::FileSystem;
+    use crate::vfat::{Dir, VFatHandle};
+
+    /// Copies the files and directories inside of the host directory `source`
+    /// into the root directory of `vfat`, recursively, then writes every
+    /// change back to the device.
+    ///
+    /// Entries are copied in order of their names and every timestamp is
+    /// taken from `vfat`'s time source, so the result only depends on the
+    /// names and contents of the copied files.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading from `source` or writing to `vfat` fails,
+    /// e.g. because an entry's name isn't a valid FAT file name or the volume
+    /// is full.
+    pub fn populate<HANDLE: VFatHandle>(vfat: &HANDLE, source: &Path) -> io::Result<()> {
+        let root = vfat.open_dir("/")?;
+        copy_dir(&root, source)?;
+        vfat.lock(|vfat| vfat.flush())
+    }
+
+    fn copy_dir<HANDLE: VFatHandle>(dir: &Dir<HANDLE>, source: &Path) -> io::Result<()> {
+        let mut entries = fs::read_dir(source)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let path = entry.path();
+            let metadata = fs::metadata(&path)?;
+            if metadata.is_dir() {
+                let subdir = dir.create_dir(entry.file_name())?;
+                copy_dir(&subdir, &path)?;
+            } else if metadata.is_file() {
+                let mut file = dir.create_file(entry.file_name())?;
+                file.write_all(&fs::read(&path)?)?;
+                file.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::gpt::{self, GptHeader, Guid};
+use crate::mbr::{self, MasterBootRecord};
+use crate::traits::BlockDevice;
+
+/// The MBR partition type of a GPT protective MBR entry.
+const PROTECTIVE_MBR_TYPE: u8 = 0xEE;
+
+/// The most logical partitions followed in an extended partition. Guards
+/// against loops in corrupt EBR chains.
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+
+/// What kind of partition table entry a partition came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartitionKind {
+    /// A primary MBR partition of the given type.
+    Primary(u8),
+    /// A logical partition of the given type inside an extended partition.
+    Logical(u8),
+    /// A GPT partition.
+    Gpt { type_guid: Guid, unique_guid: Guid, name: String },
+}
+
+/// A partition found by `partitions()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionInfo {
+    /// The first sector of the partition, counted from the start of the
+    /// device.
+    pub start: u64,
+    /// The length of the partition in sectors.
+    pub num_sectors: u64,
+    pub kind: PartitionKind,
+}
+
+impl PartitionInfo {
+    /// Returns `true` if the partition's type says it may hold a FAT file
+    /// system. GPT basic data partitions may also hold other file systems.
+    pub fn may_be_fat(&self) -> bool {
+        match self.kind {
+            PartitionKind::Primary(t) | PartitionKind::Logical(t) => match t {
+                0x01 | 0x04 | 0x06 | 0x0B | 0x0C | 0x0E => true,
+                _ => false
+            },
+            PartitionKind::Gpt { type_guid, .. } => {
+                type_guid == Guid::BASIC_DATA || type_guid == Guid::EFI_SYSTEM
+            }
+        }
+    }
+
+    /// Returns `true` if the partition's type says it may hold an exFAT file
+    /// system. The MBR type is shared with NTFS, as is the GPT basic data
+    /// type.
+    pub fn may_be_exfat(&self) -> bool {
+        match self.kind {
+            PartitionKind::Primary(t) | PartitionKind::Logical(t) => t == 0x07,
+            PartitionKind::Gpt { type_guid, .. } => type_guid == Guid::BASIC_DATA,
+        }
+    }
+
+    /// Returns `true` if the partition's type says it may hold an ext2 file
+    /// system: the Linux native MBR type or the GPT Linux file system type.
+    pub fn may_be_ext2(&self) -> bool {
+        match self.kind {
+            PartitionKind::Primary(t) | PartitionKind::Logical(t) => t == 0x83,
+            PartitionKind::Gpt { type_guid, .. } => type_guid == Guid::LINUX_FILESYSTEM,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The MBR, or an extended boot record, is invalid.
+    Mbr(mbr::Error),
+    /// Both the primary and the backup GPT are invalid. Holds the error of
+    /// the primary.
+    Gpt(gpt::Error),
+}
+
+impl From<mbr::Error> for Error {
+    fn from(error: mbr::Error) -> Error {
+        Error::Mbr(error)
+    }
+}
+
+impl From<gpt::Error> for Error {
+    fn from(error: gpt::Error) -> Error {
+        Error::Gpt(error)
+    }
+}
+
+/// Returns every partition on `device`, in partition table order.
+///
+/// If the MBR is a GPT protective MBR, the partitions of the GPT are returned,
+/// falling back to the backup GPT at the end of the disk if the primary is
+/// corrupt. Otherwise the MBR's primary partitions are returned, each extended
+/// partition followed by the logical partitions inside of it.
+///
+/// # Errors
+///
+/// Returns `Mbr(err)` if the MBR or an EBR can't be read or is invalid, and
+/// `Gpt(err)` if neither GPT can be read or has valid checksums.
+pub fn partitions<T: BlockDevice>(mut device: T) -> Result<Vec<PartitionInfo>, Error> {
+    let mbr = MasterBootRecord::from(&mut device)?;
+    if let Some(protective) = mbr.partition_table.iter().find(|e| e.partition_type == PROTECTIVE_MBR_TYPE) {
+        let backup_lba = (protective.relative_sector as u64 + protective.total_sectors as u64).checked_sub(1);
+        return gpt_partitions(&mut device, backup_lba);
+    }
+
+    let mut partitions = Vec::new();
+    for entry in mbr.partition_table.iter().filter(|e| !e.is_empty()) {
+        partitions.push(PartitionInfo {
+            start: entry.relative_sector as u64,
+            num_sectors: entry.total_sectors as u64,
+            kind: PartitionKind::Primary(entry.partition_type),
+        });
+
+        if entry.is_extended() {
+            logical_partitions(&mut device, entry.relative_sector as u64, &mut partitions)?;
+        }
+    }
+
+    Ok(partitions)
+}
+
+/// Follows the chain of EBRs of the extended partition starting at sector
+/// `extended_start`, appending its logical partitions to `partitions`.
+fn logical_partitions<T: BlockDevice>(device: &mut T, extended_start: u64, partitions: &mut Vec<PartitionInfo>)
+    -> Result<(), Error>
+{
+    let mut ebr_sector = extended_start;
+    for _ in 0..MAX_LOGICAL_PARTITIONS {
+        let ebr = MasterBootRecord::from_sector(&mut *device, ebr_sector)?;
+
+        // the first entry is relative to this EBR, the second to the start of
+        // the extended partition
+        let logical = &ebr.partition_table[0];
+        if !logical.is_empty() {
+            partitions.push(PartitionInfo {
+                start: ebr_sector + logical.relative_sector as u64,
+                num_sectors: logical.total_sectors as u64,
+                kind: PartitionKind::Logical(logical.partition_type),
+            });
+        }
+
+        let next = &ebr.partition_table[1];
+        if next.is_empty() || !next.is_extended() {
+            break;
+        }
+        ebr_sector = extended_start + next.relative_sector as u64;
+    }
+
+    Ok(())
+}
+
+/// Reads the partitions of the GPT, trying the primary header at LBA 1 first
+/// and the backup at `backup_lba` if the primary is invalid.
+fn gpt_partitions<T: BlockDevice>(device: &mut T, backup_lba: Option<u64>) -> Result<Vec<PartitionInfo>, Error> {
+    let primary = GptHeader::from(&mut *device, 1).and_then(|header| header.entries(&mut *device));
+    let entries = match (primary, backup_lba) {
+        (Ok(entries), _) => entries,
+        (Err(e), Some(lba)) => GptHeader::from(&mut *device, lba)
+            .and_then(|header| header.entries(&mut *device))
+            .map_err(|_| e)?,
+        (Err(e), None) => return Err(e.into()),
+    };
+
+    Ok(entries.iter()
+        .filter(|entry| !entry.is_unused())
+        .map(|entry| PartitionInfo {
+            start: entry.first_lba,
+            num_sectors: (entry.last_lba + 1).saturating_sub(entry.first_lba),
+            kind: PartitionKind::Gpt {
+                type_guid: entry.type_guid,
+                unique_guid: entry.unique_guid,
+                name: entry.name(),
+            },
+        })
+        .collect())
+}
@@ -3,7 +3,7 @@ extern crate rand;
 use std::fmt::{self, Debug};
 use std::io;
 use std::io::prelude::*;
-use std::io::Cursor;
+use std::io::{Cursor, SeekFrom};
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 
@@ -91,6 +91,12 @@ macro vfat_from_resource($name:expr) {
     VFat::<StdVFatHandle>::from(resource!($name)).expect("failed to initialize VFAT from image")
 }
 
+macro vfat_from_resource_copy($name:expr) {{
+    let mut data = Vec::new();
+    resource!($name).read_to_end(&mut data).expect("read resource data");
+    VFat::<StdVFatHandle>::from(Cursor::new(data)).expect("failed to initialize VFAT from image")
+}}
+
 #[test]
 fn check_mbr_size() {
     check_size!(MasterBootRecord, 512);
@@ -473,3 +479,1811 @@ fn shuffle_test() {
     let hash = hash_files_recursive_from(vfat, "/");
     assert_hash_eq!("mock 1 file hashes", hash, hash_for!("files-1"));
 }
+
+fn read_all(vfat: &StdVFatHandle, path: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    vfat.open_file(path)
+        .expect("file exists")
+        .read_to_end(&mut data)
+        .expect("read file");
+    data
+}
+
+#[test]
+fn test_create_write_read() {
+    let vfat = vfat_from_resource_copy!("mock1.fat32.img");
+    let root = vfat.open_dir("/").expect("root directory");
+    let mut file = root.create_file("written.txt").expect("create file");
+    assert_eq!(file.size(), 0);
+
+    let data: Vec<u8> = (0..20000u32).map(|i| (i % 251) as u8).collect();
+    file.write_all(&data[..777]).expect("write");
+    file.write_all(&data[777..]).expect("write");
+    assert_eq!(file.size(), data.len() as u64);
+    assert_eq!(read_all(&vfat, "/WRITTEN.TXT"), b"", "the entry should be written on flush");
+    file.flush().expect("flush");
+    assert_eq!(read_all(&vfat, "/WRITTEN.TXT"), data);
+
+    file.seek(SeekFrom::Start(4096)).expect("seek");
+    file.write_all(b"overwrite").expect("write");
+    assert_eq!(file.size(), data.len() as u64);
+    assert_eq!(&read_all(&vfat, "/written.txt")[4096..4105], b"overwrite");
+
+    let hash = hash_dir_from(vfat.clone(), "/");
+    assert!(hash.contains("\twritten.txt"));
+    expect_variant!(root.create_file("WRITTEN.TXT").map_err(|e| e.kind()),
+                    Err(io::ErrorKind::AlreadyExists));
+    expect_variant!(root.create_file("what?.txt").map_err(|e| e.kind()),
+                    Err(io::ErrorKind::InvalidInput));
+}
+
+#[test]
+fn test_truncate() {
+    let vfat = vfat_from_resource_copy!("mock1.fat32.img");
+    let root = vfat.open_dir("/").expect("root directory");
+    let mut file = root.create_file("trunc").expect("create file");
+    file.write_all(&[0xAB; 10000]).expect("write");
+
+    file.truncate(100).expect("shrink");
+    assert_eq!(read_all(&vfat, "/trunc"), vec![0xAB; 100]);
+
+    file.truncate(300).expect("extend");
+    let data = read_all(&vfat, "/trunc");
+    assert_eq!(&data[..100], &[0xAB; 100][..]);
+    assert!(data[100..].iter().all(|&b| b == 0));
+
+    file.truncate(0).expect("empty");
+    assert_eq!(file.start_cluster.0, 0);
+    assert_eq!(read_all(&vfat, "/trunc").len(), 0);
+}
+
+#[test]
+fn test_unlink() {
+    let vfat = vfat_from_resource_copy!("mock1.fat32.img");
+    let before = hash_dir_from(vfat.clone(), "/");
+    let free = vfat.lock(|v| v.free_clusters()).expect("free clusters");
+    let root = vfat.open_dir("/").expect("root directory");
+    let mut file = root.create_file("doomed").expect("create file");
+    file.write_all(&[1; 5000]).expect("write");
+    file.flush().expect("flush");
+    let cluster = file.start_cluster;
+
+    root.unlink("DOOMED").expect("unlink");
+    expect_variant!(vfat.open("/doomed").map_err(|e| e.kind()), Err(io::ErrorKind::NotFound));
+    assert_eq!(hash_dir_from(vfat.clone(), "/"), before);
+
+    // the freed chain is free again
+    assert_eq!(vfat.lock(|v| v.free_clusters()).expect("free clusters"), free);
+    assert_eq!(vfat.lock(|v| v.fat_entry(cluster).map(|e| e.status())).expect("fat"), vfat::Status::Free);
+
+    expect_variant!(root.unlink("NOTES").map_err(|e| e.kind()), Err(io::ErrorKind::Other));
+}
+
+/// A block device whose backing image outlives the `VFat` it is mounted by.
+#[derive(Clone)]
+struct SharedDevice(Arc<Mutex<Cursor<Vec<u8>>>>);
+
+impl SharedDevice {
+    fn from_resource(mut file: ::std::fs::File) -> SharedDevice {
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).expect("read resource data");
+        SharedDevice(Arc::new(Mutex::new(Cursor::new(data))))
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        self.0.lock().unwrap().get_ref().clone()
+    }
+}
+
+impl BlockDevice for SharedDevice {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().read_sector(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write_sector(n, buf)
+    }
+}
+
+#[test]
+fn test_sync_persists() {
+    let device = SharedDevice::from_resource(resource!("mock1.fat32.img"));
+    let original = device.snapshot();
+    let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("mount");
+
+    let mut file = vfat.open_dir("/").expect("root").create_file("saved.txt").expect("create");
+    file.write_all(b"persist me").expect("write");
+    assert_eq!(device.snapshot(), original, "writes should stay cached until synced");
+
+    file.sync().expect("sync");
+    let remounted = VFat::<StdVFatHandle>::from(Cursor::new(device.snapshot())).expect("remount");
+    assert_eq!(read_all(&remounted, "/SAVED.TXT"), b"persist me");
+}
+
+#[test]
+fn test_short_name_generation() {
+    use vfat::name::{basis_name, exact_short_name, lfn_checksum, with_numeric_tail};
+
+    assert_eq!(exact_short_name("README.TXT"), Some(*b"README  TXT"));
+    assert_eq!(exact_short_name("readme.txt"), None);
+    assert_eq!(exact_short_name("TOOLONGNAME"), None);
+
+    assert_eq!(basis_name("readme.txt"), (*b"README  TXT", false));
+    assert_eq!(basis_name("The quick brown.fox"), (*b"THEQUICKFOX", true));
+    assert_eq!(basis_name(".bashrc"), (*b"BASHRC     ", true));
+    assert_eq!(basis_name("a+b.tar.gz"), (*b"A_BTAR  GZ ", true));
+
+    assert_eq!(with_numeric_tail(b"THEQUICKFOX", 1), *b"THEQUI~1FOX");
+    assert_eq!(with_numeric_tail(b"THEQUICKFOX", 12), *b"THEQU~12FOX");
+    assert_eq!(with_numeric_tail(b"AB      TXT", 3), *b"AB~3    TXT");
+
+    assert_eq!(lfn_checksum(b"THEQUI~1FOX"), 0x07);
+}
+
+#[test]
+fn test_create_long_names() {
+    let vfat = vfat_from_resource_copy!("mock1.fat32.img");
+    let dir = vfat.open_dir("/NOTES").expect("directory");
+    let names = ["A Rather Long File Name.txt", "A Rather Long File Name 2.txt",
+                 "lowercase.md", "\u{3b1}\u{3b2}\u{3b3}.txt", "exactly-13-ch"];
+    for (i, name) in names.iter().enumerate() {
+        let mut file = dir.create_file(name).expect("create file");
+        write!(file, "file {}", i).expect("write");
+        file.flush().expect("flush");
+    }
+
+    let listed: Vec<String> = dir.entries().expect("entries").map(|e| e.name().to_string()).collect();
+    for (i, name) in names.iter().enumerate() {
+        assert!(listed.iter().any(|n| n == name), "missing {} in {:?}", name, listed);
+        let path = format!("/NOTES/{}", name);
+        assert_eq!(read_all(&vfat, &path), format!("file {}", i).as_bytes());
+    }
+
+    expect_variant!(dir.create_file("a rather long file name.TXT").map_err(|e| e.kind()),
+                    Err(io::ErrorKind::AlreadyExists));
+    expect_variant!(dir.create_file("bad:name").map_err(|e| e.kind()),
+                    Err(io::ErrorKind::InvalidInput));
+
+    // unlinking removes the LFN records along with the regular record
+    dir.unlink("A Rather Long File Name.txt").expect("unlink");
+    let mut file = dir.create_file("A Rather Long File Name.txt").expect("recreate");
+    file.write_all(b"again").expect("write");
+    file.flush().expect("flush");
+    assert_eq!(read_all(&vfat, "/NOTES/A Rather Long File Name.txt"), b"again");
+}
+
+#[test]
+fn test_create_and_remove_dirs() {
+    let vfat = vfat_from_resource_copy!("mock1.fat32.img");
+    let scratch = (&vfat).create_dir("/scratch").expect("mkdir");
+    let nested = (&vfat).create_dir("/scratch/nested dir").expect("mkdir nested");
+    let mut file = (&vfat).create_file("/scratch/nested dir/file.txt").expect("create file");
+    file.write_all(b"nested").expect("write");
+    file.flush().expect("flush");
+
+    let names: Vec<String> = scratch.entries().expect("entries").map(|e| e.name().to_string()).collect();
+    assert_eq!(names, vec![".", "..", "nested dir"]);
+    assert_eq!(read_all(&vfat, "/scratch/nested dir/file.txt"), b"nested");
+
+    // `.` and `..` point back at the directory and its parent
+    let dotdot = nested.find("..").expect("..").into_dir().expect("dir");
+    assert_eq!(dotdot.start_cluster, scratch.start_cluster);
+    let dot = nested.find(".").expect(".").into_dir().expect("dir");
+    assert_eq!(dot.start_cluster, nested.start_cluster);
+    let root = scratch.find("..").expect("..").into_dir().expect("dir");
+    assert_eq!(hash_dir_from(vfat.clone(), "/"), {
+        let mut hash = String::new();
+        hash_dir(&mut hash, root).unwrap();
+        hash
+    });
+
+    expect_variant!((&vfat).remove("/scratch").map_err(|e| e.kind()), Err(io::ErrorKind::Other));
+    expect_variant!((&vfat).create_dir("/scratch").map_err(|e| e.kind()), Err(io::ErrorKind::AlreadyExists));
+    expect_variant!((&vfat).create_dir("/missing/dir").map_err(|e| e.kind()), Err(io::ErrorKind::NotFound));
+    expect_variant!(nested.remove("..").map_err(|e| e.kind()), Err(io::ErrorKind::InvalidInput));
+
+    (&vfat).remove("/scratch/nested dir/file.txt").expect("rm file");
+    (&vfat).remove("/scratch/nested dir").expect("rmdir nested");
+    (&vfat).remove("/scratch").expect("rmdir");
+    expect_variant!(vfat.open("/scratch").map_err(|e| e.kind()), Err(io::ErrorKind::NotFound));
+}
+
+#[test]
+fn test_directory_grows() {
+    let vfat = vfat_from_resource_copy!("mock1.fat32.img");
+    let dir = (&vfat).create_dir("/many").expect("mkdir");
+    let records_per_cluster = vfat.lock(|v| v.bytes_per_cluster()) / 32;
+
+    // long names take several records each, so this spans multiple clusters
+    let count = records_per_cluster;
+    for i in 0..count {
+        let name = format!("a file with a long name {}", i);
+        let mut file = (&vfat).create_file(format!("/many/{}", name)).expect("create");
+        file.write_all(name.as_bytes()).expect("write");
+        file.flush().expect("flush");
+    }
+
+    let chain_len = vfat.lock(|v| {
+        let mut len = 1;
+        let mut cluster = dir.start_cluster;
+        while let Some(next) = v.next_cluster(cluster).expect("chain") {
+            cluster = next;
+            len += 1;
+        }
+        len
+    });
+    assert!(chain_len > 1);
+    assert_eq!(dir.entries().expect("entries").count(), count + 2);
+    for i in 0..count {
+        let name = format!("a file with a long name {}", i);
+        assert_eq!(read_all(&vfat, &format!("/many/{}", name)), name.as_bytes());
+    }
+}
+
+#[test]
+fn test_rename() {
+    let vfat = vfat_from_resource_copy!("mock1.fat32.img");
+    (&vfat).create_dir("/src").expect("mkdir");
+    (&vfat).create_dir("/dst").expect("mkdir");
+    let mut file = (&vfat).create_file("/src/Some Long Name.txt").expect("create");
+    file.write_all(&[7; 3000]).expect("write");
+    file.flush().expect("flush");
+    let cluster = file.start_cluster;
+
+    // rename in place, then move across directories
+    (&vfat).rename("/src/Some Long Name.txt", "/src/renamed.txt").expect("rename");
+    expect_variant!(vfat.open("/src/Some Long Name.txt").map_err(|e| e.kind()), Err(io::ErrorKind::NotFound));
+    (&vfat).rename("/src/renamed.txt", "/dst/moved.txt").expect("move");
+    let moved = vfat.open_file("/dst/moved.txt").expect("moved file");
+    assert_eq!(moved.start_cluster, cluster);
+    assert_eq!(read_all(&vfat, "/dst/moved.txt"), vec![7; 3000]);
+
+    // case-only renames replace the entry's own name
+    (&vfat).rename("/dst/moved.txt", "/dst/MOVED.TXT").expect("case rename");
+    let names: Vec<String> = vfat.open_dir("/dst").unwrap().entries().unwrap().map(|e| e.name().to_string()).collect();
+    assert_eq!(names, vec![".", "..", "MOVED.TXT"]);
+
+    // moving a directory rewrites its `..` entry
+    (&vfat).create_file("/src/inner.txt").expect("create");
+    (&vfat).rename("/src", "/dst/sub").expect("move dir");
+    let sub = vfat.open_dir("/dst/sub").expect("moved dir");
+    let parent = sub.find("..").expect("..").into_dir().expect("dir");
+    assert_eq!(parent.start_cluster, vfat.open_dir("/dst").unwrap().start_cluster);
+    vfat.open_file("/dst/sub/inner.txt").expect("file in moved dir");
+
+    (&vfat).create_file("/taken").expect("create");
+    expect_variant!((&vfat).rename("/dst/MOVED.TXT", "/taken").map_err(|e| e.kind()),
+                    Err(io::ErrorKind::AlreadyExists));
+    expect_variant!((&vfat).rename("/dst", "/dst/sub/dst").map_err(|e| e.kind()),
+                    Err(io::ErrorKind::InvalidInput));
+    expect_variant!((&vfat).rename("/nope", "/dst/nope").map_err(|e| e.kind()),
+                    Err(io::ErrorKind::NotFound));
+}
+
+#[test]
+fn test_cache_eviction() {
+    use vfat::{CacheStats, CachedPartition, Partition};
+
+    let device = SharedDevice(Arc::new(Mutex::new(Cursor::new(vec![0u8; 512 * 16]))));
+    let partition = Partition { start: 0, num_sectors: 16, sector_size: 512 };
+    let mut cache = CachedPartition::with_capacity(device.clone(), partition, 4);
+
+    for sector in 0..4u64 {
+        cache.get_mut(sector).expect("sector")[0] = sector as u8 + 1;
+    }
+    cache.get(0).expect("sector");
+    assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 4, evictions: 0, writebacks: 0, prefetched: 0 });
+    assert!(device.snapshot().iter().all(|&b| b == 0), "nothing is written before eviction");
+
+    // Sector 1 is now the least recently used and dirty, so it's written back.
+    cache.get(8).expect("sector");
+    assert_eq!(cache.len(), 4);
+    assert_eq!(cache.stats().evictions, 1);
+    assert_eq!(cache.stats().writebacks, 1);
+    assert_eq!(device.snapshot()[512], 2);
+    assert_eq!(device.snapshot()[0], 0);
+
+    // Re-reading an evicted sector returns the written back contents.
+    assert_eq!(cache.get(1).expect("sector")[0], 2);
+
+    cache.flush().expect("flush");
+    assert!(!cache.is_dirty());
+    let data = device.snapshot();
+    assert_eq!((data[0], data[1024], data[1536]), (1, 3, 4));
+
+    cache.set_capacity(1).expect("shrink");
+    assert_eq!(cache.len(), 1);
+    cache.reset_stats();
+    assert_eq!(cache.stats(), CacheStats::default());
+}
+
+#[test]
+fn test_small_cache_round_trip() {
+    let device = SharedDevice::from_resource(resource!("mock1.fat32.img"));
+    let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("mount");
+    vfat.lock(|v| v.device.set_capacity(2)).expect("capacity");
+
+    let data: Vec<u8> = (0..20_000u32).map(|i| (i % 253) as u8).collect();
+    let mut file = vfat.open_dir("/").expect("root").create_file("SPILL.BIN").expect("create");
+    file.write_all(&data).expect("write");
+    file.flush().expect("flush");
+    assert_eq!(read_all(&vfat, "/SPILL.BIN"), data);
+
+    let stats = vfat.lock(|v| v.device.stats());
+    assert!(stats.evictions > 0 && stats.writebacks > 0, "{:?}", stats);
+    assert!(vfat.lock(|v| v.device.len()) <= 2);
+
+    file.sync().expect("sync");
+    let remounted = VFat::<StdVFatHandle>::from(Cursor::new(device.snapshot())).expect("remount");
+    assert_eq!(read_all(&remounted, "/SPILL.BIN"), data);
+}
+
+#[test]
+fn test_check_clean() {
+    use crate::check::check;
+
+    let vfat = vfat_from_resource_copy!("mock1.fat32.img");
+    let report = check(&vfat, false).expect("check");
+    assert!(report.is_clean(), "{}", report);
+    assert!(report.dirs >= 1 && report.used_clusters >= 1);
+
+    let root = vfat.open_dir("/").expect("root directory");
+    let dir = root.create_dir("Checked Directory").expect("mkdir");
+    for i in 0..40 {
+        let mut file = dir.create_file(format!("file number {}", i)).expect("create");
+        file.write_all(&vec![i as u8; i * 300]).expect("write");
+        file.flush().expect("flush");
+    }
+    dir.remove("file number 7").expect("remove");
+    root.rename("Checked Directory", &root, "renamed").expect("rename");
+
+    let report = check(&vfat, false).expect("check");
+    assert!(report.is_clean(), "{}", report);
+}
+
+#[test]
+fn test_check_repair() {
+    use crate::check::{check, Problem};
+    use vfat::dir::VFatRegularDirEntry;
+
+    let vfat = vfat_from_resource_copy!("mock1.fat32.img");
+    let cluster_size = vfat.lock(|v| v.bytes_per_cluster());
+    let root = vfat.open_dir("/").expect("root directory");
+    let mut a = root.create_file("a.bin").expect("create");
+    a.write_all(&vec![0xA; cluster_size * 3]).expect("write");
+    a.flush().expect("flush");
+    let mut b = root.create_file("b.bin").expect("create");
+    b.write_all(&vec![0xB; cluster_size * 2]).expect("write");
+    b.flush().expect("flush");
+    let mut c = root.create_file("c.bin").expect("create");
+    c.write_all(b"0123456789").expect("write");
+    c.flush().expect("flush");
+    let long = root.create_file("long name.txt").expect("create");
+    assert!(check(&vfat, false).expect("check").is_clean());
+
+    let a_second = vfat.lock(|v| v.next_cluster(a.start_cluster)).expect("chain").expect("next");
+    let b_last = vfat.lock(|v| v.last_cluster(b.start_cluster)).expect("chain");
+    let lost = vfat.lock(|v| -> io::Result<_> {
+        // cross-link the end of b.bin into a.bin
+        v.set_fat_entry(b_last, a_second.0)?;
+
+        let mut entry = VFatRegularDirEntry::read(v, c.location)?;
+        entry.size = (cluster_size * 5) as u32;
+        entry.write(v, c.location)?;
+
+        let mut raw = v.read_dir_entry(long.location.dir_cluster, long.location.first_index)?;
+        raw[13] ^= 0xFF;
+        v.write_dir_entry(long.location.dir_cluster, long.location.first_index, &raw)?;
+
+        // make the last FAT entry of the second copy disagree with the first
+        let last = v.total_clusters as u64 + 1;
+        let sector = v.fat_start_sector + v.sectors_per_fat as u64 + last * 4 / v.bytes_per_sector as u64;
+        let offset = (last * 4 % v.bytes_per_sector as u64) as usize;
+        v.device.get_mut(sector)?[offset] ^= 0x01;
+
+        v.alloc_cluster(None)
+    }).expect("corrupt");
+
+    let report = check(&vfat, false).expect("check");
+    let expected = [
+        Problem::FatMismatch { copy: 1, entries: 1 },
+        Problem::CrossLinked { path: "/b.bin".into(), other: "/a.bin".into(), cluster: a_second.0 },
+        Problem::SizeMismatch { path: "/c.bin".into(), size: (cluster_size * 5) as u32, clusters: 1 },
+        Problem::BadLongName { dir: "/".into(), index: long.location.first_index },
+        Problem::LostClusters { count: 1 },
+    ];
+    assert_eq!(report.problems, expected, "{}", report);
+    assert_eq!(check(&vfat, false).expect("check").problems, expected, "checking alone repairs nothing");
+
+    let report = check(&vfat, true).expect("repair");
+    assert_eq!(report.problems, expected);
+    let report = check(&vfat, false).expect("check");
+    assert!(report.is_clean(), "{}", report);
+
+    assert_eq!(read_all(&vfat, "/a.bin"), vec![0xA; cluster_size * 3]);
+    assert_eq!(read_all(&vfat, "/b.bin"), vec![0xB; cluster_size * 2]);
+    assert_eq!(&read_all(&vfat, "/c.bin")[..10], b"0123456789");
+    assert_eq!(read_all(&vfat, "/c.bin").len(), cluster_size);
+    expect_variant!(vfat.open("/long name.txt").map_err(|e| e.kind()), Err(io::ErrorKind::NotFound));
+    vfat.open_file("/LONGNA~1.TXT").expect("short name remains");
+    assert_eq!(vfat.lock(|v| v.fat_entry(lost).map(|e| e.status())).expect("fat"), vfat::Status::Free);
+}
+
+/// Returns a freshly formatted 40MiB image, the smallest round size that
+/// holds a FAT32 volume with one sector per cluster.
+fn formatted_device() -> SharedDevice {
+    use crate::mkfs::{format, Options};
+
+    let mut options = Options::new(40 << 11);
+    options.volume_id = 0xC0FFEE;
+    let device = SharedDevice(Arc::new(Mutex::new(Cursor::new(vec![0u8; 40 << 20]))));
+    format(device.clone(), &options).expect("format");
+    device
+}
+
+#[test]
+fn test_mkfs_format() {
+    use crate::mkfs::{format, Options};
+
+    let device = formatted_device();
+    let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("mount");
+    assert_eq!(vfat.open_dir("/").expect("root").entries().expect("entries").count(), 0);
+    let report = crate::check::check(&vfat, false).expect("check");
+    assert!(report.is_clean(), "{}", report);
+    assert_eq!(report.used_clusters, 1);
+
+    let data = device.snapshot();
+    let ebpb = BiosParameterBlock::from(Cursor::new(data.clone()), 2048).expect("EBPB");
+    assert_eq!({ ebpb.volume_id }, 0xC0FFEE);
+    assert_eq!({ ebpb.sectors_per_cluster }, 1);
+    assert_eq!(&data[(2048 + 6) * 512..(2048 + 7) * 512], &data[2048 * 512..2049 * 512]);
+    assert_eq!(&data[(2048 + 1) * 512..(2048 + 1) * 512 + 4], b"RRaA");
+
+    let mut small = Cursor::new(vec![0u8; 16 << 20]);
+    expect_variant!(format(&mut small, &Options::new(16 << 11)).map_err(|e| e.kind()),
+                    Err(io::ErrorKind::InvalidInput));
+}
+
+#[test]
+fn test_mkfs_populate() {
+    use crate::mkfs::populate;
+
+    let source = std::env::temp_dir().join(format!("fat32-populate-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&source);
+    std::fs::create_dir_all(source.join("bin/nested dir")).expect("mkdir");
+    std::fs::write(source.join("README"), b"read me").expect("write");
+    std::fs::write(source.join("bin/fib"), vec![0x5A; 5000]).expect("write");
+    std::fs::write(source.join("bin/nested dir/Long Name.txt"), b"nested").expect("write");
+
+    let mut images = Vec::new();
+    for _ in 0..2 {
+        let device = formatted_device();
+        let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("mount");
+        populate(&vfat, &source).expect("populate");
+        images.push(device.snapshot());
+    }
+    std::fs::remove_dir_all(&source).expect("cleanup");
+    assert!(images[0] == images[1], "populating is reproducible");
+
+    let vfat = VFat::<StdVFatHandle>::from(Cursor::new(images.pop().unwrap())).expect("remount");
+    assert_eq!(read_all(&vfat, "/README"), b"read me");
+    assert_eq!(read_all(&vfat, "/bin/fib"), vec![0x5A; 5000]);
+    assert_eq!(read_all(&vfat, "/bin/nested dir/Long Name.txt"), b"nested");
+    let report = crate::check::check(&vfat, false).expect("check");
+    assert!(report.is_clean(), "{}", report);
+    assert_eq!((report.files, report.dirs), (3, 3));
+}
+
+#[test]
+fn test_statfs() {
+    let device = formatted_device();
+    let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("mount");
+    let empty = vfat.lock(|v| v.statfs()).expect("statfs");
+    assert_eq!(empty.bytes_per_cluster, 512);
+    assert_eq!(empty.used_clusters(), 1);
+    assert_eq!(empty.free_bytes() + 512, empty.total_bytes());
+
+    let root = vfat.open_dir("/").expect("root directory");
+    let mut file = root.create_file("ten").expect("create");
+    file.write_all(&[7; 5120]).expect("write");
+    file.flush().expect("flush");
+    let mut other = root.create_file("eleventh").expect("create");
+    other.write_all(&[8; 10]).expect("write");
+    other.flush().expect("flush");
+
+    // allocation continues after the last allocated cluster
+    let last = vfat.lock(|v| v.last_cluster(file.start_cluster)).expect("chain");
+    assert_eq!(other.start_cluster.0, last.0 + 1);
+
+    let stats = vfat.lock(|v| v.statfs()).expect("statfs");
+    assert_eq!(stats.used_clusters(), 12);
+    assert_eq!(stats.used_bytes(), 12 * 512);
+    root.unlink("ten").expect("unlink");
+    assert_eq!(vfat.lock(|v| v.statfs()).expect("statfs").used_clusters(), 2);
+
+    // the count survives a remount through the FSInfo sector
+    vfat.lock(|v| v.flush()).expect("flush");
+    let remounted = VFat::<StdVFatHandle>::from(Cursor::new(device.snapshot())).expect("remount");
+    assert_eq!(remounted.lock(|v| v.statfs()).expect("statfs").used_clusters(), 2);
+}
+
+#[test]
+fn test_check_free_count() {
+    use crate::check::{check, Problem};
+
+    let device = formatted_device();
+    let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("mount");
+    let actual = vfat.lock(|v| v.free_clusters()).expect("free clusters");
+    vfat.lock(|v| v.set_free_clusters(actual - 100));
+
+    let report = check(&vfat, true).expect("repair");
+    assert_eq!(report.problems, vec![Problem::FreeCountMismatch { recorded: actual - 100, actual }]);
+    let remounted = VFat::<StdVFatHandle>::from(Cursor::new(device.snapshot())).expect("remount");
+    assert_eq!(remounted.lock(|v| v.free_clusters()).expect("free clusters"), actual);
+}
+
+#[test]
+fn test_gpt_crc32() {
+    assert_eq!(crate::gpt::crc32(b""), 0);
+    assert_eq!(crate::gpt::crc32(b"123456789"), 0xCBF43926);
+}
+
+/// Writes a GPT header at `lba` whose entry array at `entries_lba` holds
+/// `entries`, and the entry array itself.
+fn write_gpt(disk: &mut [u8], lba: u64, backup_lba: u64, entries_lba: u64, entries: &[u8]) {
+    use crate::gpt::crc32;
+
+    let num_sectors = (disk.len() / 512) as u64;
+    let mut header = [0u8; 92];
+    header[0..8].copy_from_slice(b"EFI PART");
+    header[8..12].copy_from_slice(&0x00010000u32.to_le_bytes());
+    header[12..16].copy_from_slice(&92u32.to_le_bytes());
+    header[24..32].copy_from_slice(&lba.to_le_bytes());
+    header[32..40].copy_from_slice(&backup_lba.to_le_bytes());
+    header[40..48].copy_from_slice(&34u64.to_le_bytes());
+    header[48..56].copy_from_slice(&(num_sectors - 34).to_le_bytes());
+    header[56..72].copy_from_slice(&[0x42; 16]);
+    header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+    header[80..84].copy_from_slice(&((entries.len() / 128) as u32).to_le_bytes());
+    header[84..88].copy_from_slice(&128u32.to_le_bytes());
+    header[88..92].copy_from_slice(&crc32(entries).to_le_bytes());
+    let crc = crc32(&header);
+    header[16..20].copy_from_slice(&crc.to_le_bytes());
+
+    let start = lba as usize * 512;
+    disk[start..start + 92].copy_from_slice(&header);
+    let start = entries_lba as usize * 512;
+    disk[start..start + entries.len()].copy_from_slice(entries);
+}
+
+/// Returns a GPT disk holding a Linux partition followed by the FAT32 volume
+/// of `formatted_device()` as a basic data partition named "DATA".
+fn gpt_disk() -> Vec<u8> {
+    use crate::gpt::Guid;
+
+    let mut disk = formatted_device().snapshot();
+    disk.resize(disk.len() + 64 * 512, 0);
+    let num_sectors = (disk.len() / 512) as u64;
+
+    let mut mbr = MasterBootRecord::new([0; 10]);
+    mbr.partition_table[0] = PartitionEntry::new(0xEE, 1, num_sectors as u32 - 1);
+    disk[..512].copy_from_slice(mbr.as_bytes());
+
+    let mut entries = vec![0u8; 128 * 128];
+    let linux = [0xAF, 0x3D, 0xC6, 0x0F, 0x83, 0x84, 0x72, 0x47,
+                 0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4];
+    entries[0..16].copy_from_slice(&linux);
+    entries[16..32].copy_from_slice(&[1; 16]);
+    entries[32..40].copy_from_slice(&34u64.to_le_bytes());
+    entries[40..48].copy_from_slice(&2047u64.to_le_bytes());
+    entries[128..144].copy_from_slice(&Guid::BASIC_DATA.0);
+    entries[144..160].copy_from_slice(&[2; 16]);
+    entries[160..168].copy_from_slice(&2048u64.to_le_bytes());
+    entries[168..176].copy_from_slice(&((40u64 << 11) - 1).to_le_bytes());
+    for (i, c) in "DATA".encode_utf16().enumerate() {
+        entries[184 + 2 * i..186 + 2 * i].copy_from_slice(&c.to_le_bytes());
+    }
+
+    write_gpt(&mut disk, 1, num_sectors - 1, 2, &entries);
+    write_gpt(&mut disk, num_sectors - 1, 1, num_sectors - 33, &entries);
+    disk
+}
+
+#[test]
+fn test_gpt_partitions() {
+    use crate::gpt::Guid;
+    use crate::partition::{partitions, PartitionKind};
+
+    let disk = gpt_disk();
+    let parts = partitions(Cursor::new(disk.clone())).expect("partitions");
+    assert_eq!(parts.len(), 2);
+    assert_eq!((parts[0].start, parts[0].num_sectors), (34, 2014));
+    assert!(!parts[0].may_be_fat());
+    assert_eq!((parts[1].start, parts[1].num_sectors), (2048, (40 << 11) - 2048));
+    assert_eq!(parts[1].kind, PartitionKind::Gpt {
+        type_guid: Guid::BASIC_DATA,
+        unique_guid: Guid([2; 16]),
+        name: "DATA".to_string(),
+    });
+    assert_eq!(format!("{:?}", Guid::BASIC_DATA), "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7");
+
+    let vfat = VFat::<StdVFatHandle>::from(Cursor::new(disk.clone())).expect("mount first FAT");
+    let report = crate::check::check(&vfat, false).expect("check");
+    assert!(report.is_clean(), "{}", report);
+    VFat::<StdVFatHandle>::from_partition(Cursor::new(disk.clone()), &parts[1]).expect("mount partition");
+    expect_variant!(VFat::<StdVFatHandle>::from_partition(Cursor::new(disk), &parts[0]),
+                    Err(vfat::Error::BadSignature));
+}
+
+#[test]
+fn test_gpt_backup() {
+    use crate::partition::{self, partitions};
+    use crate::gpt;
+
+    // a corrupt primary header falls back to the backup
+    let mut disk = gpt_disk();
+    disk[512 + 40] ^= 1;
+    assert_eq!(partitions(Cursor::new(disk.clone())).expect("partitions").len(), 2);
+
+    // so do corrupt primary entries
+    let mut disk = gpt_disk();
+    disk[2 * 512 + 160] ^= 1;
+    assert_eq!(partitions(Cursor::new(disk.clone())).expect("partitions").len(), 2);
+
+    // if both are corrupt, the primary's error is returned
+    let backup = disk.len() - 512;
+    disk[backup..backup + 8].copy_from_slice(&[0; 8]);
+    expect_variant!(partitions(Cursor::new(disk)),
+                    Err(partition::Error::Gpt(gpt::Error::BadEntriesChecksum)));
+}
+
+#[test]
+fn test_mbr_logical_partitions() {
+    use crate::partition::{partitions, PartitionKind};
+
+    // a Linux primary partition and an extended partition holding the FAT32
+    // volume and another Linux partition
+    let mut disk = formatted_device().snapshot();
+    let total = (disk.len() / 512) as u32;
+    let mut mbr = MasterBootRecord::new([0; 10]);
+    mbr.partition_table[0] = PartitionEntry::new(0x83, 63, 100);
+    mbr.partition_table[1] = PartitionEntry::new(0x0F, 1000, total - 1000);
+    disk[..512].copy_from_slice(mbr.as_bytes());
+
+    let mut ebr = MasterBootRecord::new([0; 10]);
+    ebr.partition_table[0] = PartitionEntry::new(0x0C, 1048, total - 2048);
+    ebr.partition_table[1] = PartitionEntry::new(0x05, 500, 30);
+    disk[1000 * 512..1001 * 512].copy_from_slice(ebr.as_bytes());
+
+    let mut ebr = MasterBootRecord::new([0; 10]);
+    ebr.partition_table[0] = PartitionEntry::new(0x83, 10, 20);
+    disk[1500 * 512..1501 * 512].copy_from_slice(ebr.as_bytes());
+
+    let parts = partitions(Cursor::new(disk.clone())).expect("partitions");
+    let summary: Vec<_> = parts.iter().map(|p| (p.start, p.num_sectors, p.kind.clone())).collect();
+    assert_eq!(summary, vec![
+        (63, 100, PartitionKind::Primary(0x83)),
+        (1000, total as u64 - 1000, PartitionKind::Primary(0x0F)),
+        (2048, total as u64 - 2048, PartitionKind::Logical(0x0C)),
+        (1510, 20, PartitionKind::Logical(0x83)),
+    ]);
+
+    let vfat = VFat::<StdVFatHandle>::from(Cursor::new(disk)).expect("mount logical");
+    assert_eq!(vfat.lock(|v| v.statfs()).expect("statfs").used_clusters(), 1);
+}
+
+#[test]
+fn test_fat_type_detection() {
+    use vfat::{FatType, Status};
+
+    assert_eq!(FatType::detect(false, 4084), FatType::Fat12);
+    assert_eq!(FatType::detect(false, 4085), FatType::Fat16);
+    assert_eq!(FatType::detect(false, 65525), FatType::Fat16);
+    assert_eq!(FatType::detect(true, 100), FatType::Fat32);
+
+    assert_eq!(FatType::Fat12.widen(0xFF7).status(), Status::Bad);
+    assert_eq!(FatType::Fat12.widen(0xFF8).status(), Status::Eoc(0x0FFFFFF8));
+    assert_eq!(FatType::Fat16.widen(0xFFFF).status(), Status::Eoc(0x0FFFFFFF));
+    assert_eq!(FatType::Fat16.widen(0xFFF0).status(), Status::Reserved);
+    assert_eq!(FatType::Fat16.widen(0x1234).status(), Status::Data(vfat::Cluster(0x1234)));
+    assert_eq!(FatType::Fat32.widen(0xF0000005).status(), Status::Data(vfat::Cluster(5)));
+}
+
+/// Returns an image with an MBR and a single, empty FAT12 or FAT16 partition
+/// of `partition_sectors` sectors with one sector per cluster and a fixed root
+/// directory of `root_entries` records.
+fn legacy_fat_device(partition_sectors: u32, root_entries: u16, fat16: bool) -> SharedDevice {
+    const START: u32 = 64;
+    let num_clusters = partition_sectors;
+    let sectors_per_fat = match fat16 {
+        true => (num_clusters * 2 + 511) / 512,
+        false => (num_clusters * 3 / 2 + 511) / 512,
+    };
+
+    let mut disk = vec![0u8; (START + partition_sectors) as usize * 512];
+    let mut mbr = MasterBootRecord::new([0; 10]);
+    mbr.partition_table[0] = PartitionEntry::new(if fat16 { 0x06 } else { 0x01 }, START, partition_sectors);
+    disk[..512].copy_from_slice(mbr.as_bytes());
+
+    let boot = &mut disk[START as usize * 512..(START + 1) as usize * 512];
+    boot[..11].copy_from_slice(b"\xEB\x3C\x90MSDOS5.0");
+    boot[11..13].copy_from_slice(&512u16.to_le_bytes());
+    boot[13] = 1;
+    boot[14..16].copy_from_slice(&1u16.to_le_bytes());
+    boot[16] = 2;
+    boot[17..19].copy_from_slice(&root_entries.to_le_bytes());
+    boot[19..21].copy_from_slice(&(partition_sectors as u16).to_le_bytes());
+    boot[21] = 0xF8;
+    boot[22..24].copy_from_slice(&(sectors_per_fat as u16).to_le_bytes());
+    boot[28..32].copy_from_slice(&START.to_le_bytes());
+    boot[36] = 0x80;
+    boot[38] = 0x29;
+    boot[39..43].copy_from_slice(&0x1234ABCDu32.to_le_bytes());
+    boot[43..54].copy_from_slice(b"LEGACY     ");
+    boot[54..62].copy_from_slice(if fat16 { b"FAT16   " } else { b"FAT12   " });
+    boot[510..].copy_from_slice(&[0x55, 0xAA]);
+
+    // entry 0 holds the media descriptor, entry 1 the end of chain marker
+    let reserved: &[u8] = if fat16 { &[0xF8, 0xFF, 0xFF, 0xFF] } else { &[0xF8, 0xFF, 0xFF] };
+    for copy in 0..2 {
+        let fat = (START + 1 + copy * sectors_per_fat) as usize * 512;
+        disk[fat..fat + reserved.len()].copy_from_slice(reserved);
+    }
+
+    SharedDevice(Arc::new(Mutex::new(Cursor::new(disk))))
+}
+
+/// Fills a FAT12 or FAT16 volume through the usual API, checks it and reads
+/// everything back after a remount.
+fn exercise_legacy_fat(device: SharedDevice, fat_type: vfat::FatType) {
+    let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("mount");
+    assert_eq!(vfat.lock(|v| v.fat_type), fat_type);
+    let ebpb = BiosParameterBlock::from(Cursor::new(device.snapshot()), 64).expect("EBPB");
+    assert_eq!(&{ ebpb.volume_label }, b"LEGACY     ");
+    assert_eq!({ ebpb.volume_id }, 0x1234ABCD);
+
+    // long enough that FAT12 entries straddle sector boundaries
+    let big: Vec<u8> = (0..400 * 512).map(|i| (i % 251) as u8).collect();
+    let root = vfat.open_dir("/").expect("root");
+    assert_eq!(root.entries().expect("entries").count(), 0);
+    let mut file = root.create_file("big.bin").expect("create");
+    file.write_all(&big).expect("write");
+    file.flush().expect("flush");
+    let dir = root.create_dir("A Directory").expect("mkdir");
+    let mut file = dir.create_file("inner.txt").expect("create");
+    file.write_all(b"inside").expect("write");
+    file.flush().expect("flush");
+    dir.create_dir("sub").expect("mkdir");
+    vfat.rename("/A Directory/inner.txt", "/moved.txt").expect("rename");
+    vfat.lock(|v| v.flush()).expect("flush");
+
+    let used = 400 + 2 + 1;
+    assert_eq!(vfat.lock(|v| v.statfs()).expect("statfs").used_clusters(), used);
+    let report = crate::check::check(&vfat, false).expect("check");
+    assert!(report.is_clean(), "{}", report);
+    assert_eq!((report.files, report.dirs, report.used_clusters), (2, 3, used));
+
+    let remounted = VFat::<StdVFatHandle>::from(Cursor::new(device.snapshot())).expect("remount");
+    assert_eq!(read_all(&remounted, "/big.bin"), big);
+    assert_eq!(read_all(&remounted, "/moved.txt"), b"inside");
+    let names: Vec<_> = remounted.open_dir("/A Directory/sub/..").expect("parent")
+        .entries().expect("entries").map(|e| e.name().to_string()).collect();
+    assert_eq!(names, vec![".", "..", "sub"]);
+
+    vfat.remove("/big.bin").expect("remove");
+    assert_eq!(vfat.lock(|v| v.statfs()).expect("statfs").used_clusters(), used - 400);
+}
+
+#[test]
+fn test_fat16() {
+    // 16MiB: 32768 clusters
+    exercise_legacy_fat(legacy_fat_device(32768, 512, true), vfat::FatType::Fat16);
+}
+
+#[test]
+fn test_fat12() {
+    // 2MiB: fewer than 4085 clusters
+    exercise_legacy_fat(legacy_fat_device(4096, 224, false), vfat::FatType::Fat12);
+}
+
+#[test]
+fn test_fixed_root_full() {
+    let vfat = VFat::<StdVFatHandle>::from(legacy_fat_device(4096, 16, false)).expect("mount");
+    let root = vfat.open_dir("/").expect("root");
+    for i in 0..16 {
+        root.create_file(format!("F{}", i)).expect("create");
+    }
+    expect_variant!(root.create_file("F16").map_err(|e| e.kind()), Err(io::ErrorKind::Other));
+    assert_eq!(root.entries().expect("entries").count(), 16);
+}
+
+#[test]
+fn test_extent_map() {
+    use vfat::{Extent, File};
+
+    let vfat = VFat::<StdVFatHandle>::from(formatted_device()).expect("mount");
+    let root = vfat.open_dir("/").expect("root");
+    let mut a = root.create_file("a").expect("create");
+    let mut b = root.create_file("b").expect("create");
+
+    // interleave the chains: `a` gets runs of three clusters
+    let data: Vec<u8> = (0..12 * 512).map(|i| (i / 7) as u8).collect();
+    for run in data.chunks(3 * 512) {
+        a.write_all(run).expect("write");
+        b.write_all(&[0xBB; 512]).expect("write");
+    }
+    a.flush().expect("flush");
+    b.flush().expect("flush");
+
+    let open = |path| -> File<StdVFatHandle> { vfat.open_file(path).expect("open") };
+    let mut file = open("/a");
+    assert!(file.extents.extents().is_empty(), "the map is filled lazily");
+    for &offset in [11 * 512 + 100, 100, 6 * 512, 3 * 512 - 1, 9 * 512 + 511, 5000].iter() {
+        file.seek(SeekFrom::Start(offset as u64)).expect("seek");
+        let mut buf = [0u8; 700];
+        let n = file.read(&mut buf).expect("read");
+        assert_eq!(&buf[..n], &data[offset..offset + n], "at offset {}", offset);
+    }
+
+    let start = file.start_cluster.0;
+    let extents = file.extents.extents().to_vec();
+    assert_eq!(extents.len(), 4);
+    for (i, extent) in extents.iter().enumerate() {
+        assert_eq!(*extent, Extent { index: 3 * i as u32, start: vfat::Cluster(start + 4 * i as u32), len: 3 });
+    }
+
+    // shortening a chain elsewhere drops the map, growing one doesn't
+    let generation = vfat.lock(|v| v.chain_generation());
+    b.write_all(&[0xBB; 512]).expect("write");
+    assert_eq!(vfat.lock(|v| v.chain_generation()), generation);
+    b.truncate(512).expect("truncate");
+    assert!(vfat.lock(|v| v.chain_generation()) > generation);
+
+    file.seek(SeekFrom::Start(512)).expect("seek");
+    assert_eq!(file.extents.extents(), &[Extent { index: 0, start: vfat::Cluster(start), len: 2 }]);
+    assert_eq!(read_all(&vfat, "/a"), data);
+
+    // appending through the map continues after the last extent
+    file.seek(SeekFrom::End(0)).expect("seek");
+    file.write_all(&[0xAA; 600]).expect("append");
+    file.flush().expect("flush");
+    let mut expected = data.clone();
+    expected.extend_from_slice(&[0xAA; 600]);
+    assert_eq!(read_all(&vfat, "/a"), expected);
+    let report = crate::check::check(&vfat, false).expect("check");
+    assert!(report.is_clean(), "{}", report);
+}
+
+/// A device that logs the first sector and the number of sectors of every
+/// read it serves.
+#[derive(Clone)]
+struct LoggingDevice {
+    data: SharedDevice,
+    reads: Arc<Mutex<Vec<(u64, usize)>>>,
+}
+
+impl LoggingDevice {
+    fn new(data: SharedDevice) -> LoggingDevice {
+        LoggingDevice { data, reads: Arc::new(Mutex::new(Vec::new())) }
+    }
+
+    fn take_reads(&self) -> Vec<(u64, usize)> {
+        std::mem::replace(&mut *self.reads.lock().unwrap(), Vec::new())
+    }
+}
+
+impl BlockDevice for LoggingDevice {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.reads.lock().unwrap().push((n, 1));
+        self.data.read_sector(n, buf)
+    }
+
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.reads.lock().unwrap().push((n, buf.len() / 512));
+        self.data.0.lock().unwrap().read_sectors(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        self.data.write_sector(n, buf)
+    }
+}
+
+#[test]
+fn test_read_sectors() {
+    let data: Vec<u8> = (0..8 * 512).map(|i| (i / 512) as u8).collect();
+
+    // `SharedDevice` only implements `read_sector`, `Cursor` overrides both
+    let mut buf = vec![0u8; 3 * 512 + 100];
+    let mut device = SharedDevice(Arc::new(Mutex::new(Cursor::new(data.clone()))));
+    assert_eq!(device.read_sectors(2, &mut buf).expect("read"), 3 * 512);
+    assert_eq!(&buf[..3 * 512], &data[2 * 512..5 * 512]);
+    assert!(buf[3 * 512..].iter().all(|&b| b == 0), "partial sectors are not read");
+
+    let mut buf = vec![0u8; 3 * 512];
+    assert_eq!(Cursor::new(data.clone()).read_sectors(5, &mut buf).expect("read"), 3 * 512);
+    assert_eq!(&buf[..], &data[5 * 512..]);
+}
+
+#[test]
+fn test_readahead() {
+    let device = formatted_device();
+    let contents: Vec<u8> = (0..300 * 1024).map(|i| (i % 253) as u8).collect();
+    let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("mount");
+    let mut file = vfat.create_file("/big").expect("create");
+    file.write_all(&contents).expect("write");
+    file.flush().expect("flush");
+    vfat.lock(|v| v.flush()).expect("flush");
+
+    let logged = LoggingDevice::new(SharedDevice(Arc::new(Mutex::new(Cursor::new(device.snapshot())))));
+    let vfat = VFat::<StdVFatHandle>::from(logged.clone()).expect("remount");
+    let mut file = vfat.open_file("/big").expect("open");
+    logged.take_reads();
+
+    // sequential reads are served from multi-sector reads that grow with
+    // the window
+    let mut read = Vec::new();
+    let mut buf = [0u8; 1000];
+    loop {
+        match file.read(&mut buf).expect("read") {
+            0 => break,
+            n => read.extend_from_slice(&buf[..n]),
+        }
+    }
+    assert!(read == contents);
+    let reads = logged.take_reads();
+    let data_reads: Vec<usize> = reads.iter().filter(|&&(_, n)| n > 1).map(|&(_, n)| n).collect();
+    // the first read ahead covers 32 sectors past the two the read needs
+    assert_eq!(data_reads.first(), Some(&34));
+    assert!(data_reads.iter().any(|&n| n > 128), "{:?}", data_reads);
+    assert_eq!(data_reads.iter().sum::<usize>(), 600);
+    let stats = vfat.lock(|v| v.device.stats());
+    assert_eq!(stats.prefetched, 600);
+    assert!(stats.misses < 10, "{:?}", stats);
+
+    // random reads are not read ahead
+    let mut file = vfat.open_file("/big").expect("open");
+    for &offset in [200 * 1024, 10 * 1024, 290 * 1024, 100].iter() {
+        file.seek(SeekFrom::Start(offset)).expect("seek");
+        assert_eq!(file.read(&mut buf[..10]).expect("read"), 10);
+        assert_eq!(&buf[..10], &contents[offset as usize..offset as usize + 10]);
+    }
+    assert!(logged.take_reads().iter().all(|&(_, n)| n == 1));
+}
+
+/// A device whose writes fail once a set number of them have succeeded,
+/// simulating a crash in the middle of a flush.
+#[derive(Clone)]
+struct FailingDevice {
+    data: SharedDevice,
+    writes_left: Arc<Mutex<usize>>,
+}
+
+impl FailingDevice {
+    fn new(data: SharedDevice, writes: usize) -> FailingDevice {
+        FailingDevice { data, writes_left: Arc::new(Mutex::new(writes)) }
+    }
+
+    fn crashed(&self) -> bool {
+        *self.writes_left.lock().unwrap() == 0
+    }
+}
+
+impl BlockDevice for FailingDevice {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.data.read_sector(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let mut writes_left = self.writes_left.lock().unwrap();
+        if *writes_left == 0 {
+            return Err(io::Error::new(io::ErrorKind::Other, "simulated crash"));
+        }
+        *writes_left -= 1;
+        self.data.write_sector(n, buf)
+    }
+}
+
+/// Returns a formatted device with a 16 sector journal holding `/a.txt`.
+fn journaled_device() -> SharedDevice {
+    let device = formatted_device();
+    let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("mount");
+    vfat::enable_journal(&vfat, 16).expect("enable journal");
+    let mut file = vfat.create_file("/a.txt").expect("create");
+    file.write_all(b"old contents").expect("write");
+    file.flush().expect("flush");
+    vfat.lock(|v| v.flush()).expect("flush");
+    device
+}
+
+#[test]
+fn test_journal_enable() {
+    let device = formatted_device();
+    let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("mount");
+    assert!(!vfat.lock(|v| v.is_journaling()));
+    assert!(vfat::enable_journal(&vfat, 1).is_err());
+    vfat::enable_journal(&vfat, 16).expect("enable journal");
+    assert!(vfat.lock(|v| v.is_journaling()));
+    vfat::enable_journal(&vfat, 16).expect("enabling twice is a no-op");
+
+    let journal = vfat.open(&format!("/{}", vfat::JOURNAL_NAME)).expect("journal file");
+    assert!(journal.metadata().hidden());
+    assert_eq!(journal.metadata().size, 16 * 512);
+
+    // the journal is found again on the next mount
+    let vfat = VFat::<StdVFatHandle>::from(device).expect("remount");
+    assert!(vfat.lock(|v| v.is_journaling()));
+    assert!(crate::check::check(&vfat, false).expect("check").is_clean());
+}
+
+#[test]
+fn test_journal_protected() {
+    let vfat = VFat::<StdVFatHandle>::from(journaled_device()).expect("mount");
+    let path = format!("/{}", vfat::JOURNAL_NAME);
+
+    let denied = |result: io::Result<()>| result.map_err(|e| e.kind()) == Err(io::ErrorKind::PermissionDenied);
+    assert!(denied(vfat.remove(&path)));
+    assert!(denied(vfat.rename(&path, "/moved")));
+    assert!(denied(vfat.open_file(&path).expect("open").truncate(0)));
+    assert!(denied(vfat.open_dir("/").expect("root").unlink(vfat::JOURNAL_NAME)));
+
+    let journal = vfat.open(&path).expect("journal is still there");
+    assert_eq!(journal.metadata().size, 16 * 512);
+    assert!(vfat.lock(|v| v.is_journaling()));
+}
+
+#[test]
+fn test_journal_crash_recovery() {
+    let base = journaled_device().snapshot();
+    let contents: Vec<u8> = (0..5000).map(|i| (i % 251) as u8).collect();
+
+    let (mut old, mut new, mut replayed) = (0, 0, 0);
+    for writes in 0.. {
+        let data = SharedDevice(Arc::new(Mutex::new(Cursor::new(base.clone()))));
+        let device = FailingDevice::new(data.clone(), writes);
+        let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("mount");
+        let _ = (|| -> io::Result<()> {
+            vfat.create_dir("/dir")?;
+            let mut file = vfat.create_file("/dir/b")?;
+            file.write_all(&contents)?;
+            file.flush()?;
+            vfat.remove("/a.txt")?;
+            vfat.lock(|v| v.flush())
+        })();
+        let crashed = device.crashed();
+
+        let before = data.snapshot();
+        let vfat = VFat::<StdVFatHandle>::from(data.clone()).expect("remount after crash");
+        if data.snapshot() != before {
+            replayed += 1;
+        }
+
+        let report = crate::check::check(&vfat, false).expect("check");
+        assert!(report.is_clean(), "after {} writes: {:?}", writes, report);
+        if vfat.open("/a.txt").is_ok() {
+            assert!(vfat.open("/dir").is_err(), "after {} writes", writes);
+            assert_eq!(read_all(&vfat, "/a.txt"), b"old contents");
+            old += 1;
+        } else {
+            assert_eq!(read_all(&vfat, "/dir/b"), contents, "after {} writes", writes);
+            new += 1;
+        }
+
+        if !crashed {
+            break;
+        }
+    }
+
+    assert!(old > 0 && new > 0);
+    assert!(replayed > 0, "no crash left a committed transaction behind");
+}
+
+#[test]
+fn test_journal_small_cache() {
+    let device = journaled_device();
+    let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("mount");
+    vfat.lock(|v| v.device.set_capacity(8)).expect("shrink cache");
+
+    // dirty metadata is kept past the small cache's capacity instead of
+    // being written in place outside of a transaction
+    for i in 0..40 {
+        let name = format!("/file{}.txt", i);
+        let mut file = vfat.create_file(&name).expect("create");
+        file.write_all(name.as_bytes()).expect("write");
+        file.flush().expect("flush");
+    }
+    assert!(vfat.lock(|v| v.device.len()) > 8);
+    vfat.lock(|v| v.flush()).expect("flush");
+    assert!(vfat.lock(|v| v.device.len()) <= 8);
+
+    let vfat = VFat::<StdVFatHandle>::from(device).expect("remount");
+    assert!(crate::check::check(&vfat, false).expect("check").is_clean());
+    for i in 0..40 {
+        let name = format!("/file{}.txt", i);
+        assert_eq!(read_all(&vfat, &name), name.as_bytes());
+    }
+}
+
+#[test]
+fn test_journal_crash_auto_checkpoint() {
+    let base = journaled_device().snapshot();
+    let capacity = VFat::<StdVFatHandle>::from(SharedDevice(Arc::new(Mutex::new(Cursor::new(base.clone())))))
+        .expect("mount")
+        .lock(|v| v.device.journal().map(|journal| journal.capacity()))
+        .expect("journaling");
+
+    // checking the volume is slow, so only every third crash point is tried
+    let mut checkpoints = 0;
+    for writes in (0..).step_by(3) {
+        let data = SharedDevice(Arc::new(Mutex::new(Cursor::new(base.clone()))));
+        let device = FailingDevice::new(data.clone(), writes);
+        let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("mount");
+
+        // every new directory dirties a metadata sector of its own, so the
+        // journal fills up and is checkpointed before the final flush
+        let result = (|| -> io::Result<()> {
+            for i in 0..capacity + 4 {
+                vfat.create_dir(&format!("/dir{}", i))?;
+            }
+            vfat.lock(|v| v.flush())
+        })();
+        let crashed = device.crashed();
+        assert!(result.is_ok() || crashed, "after {} writes: {:?}", writes, result);
+
+        // a checkpoint taken in the middle of creating a directory may leave
+        // problems a repair fixes, but never a volume that doesn't mount
+        let vfat = VFat::<StdVFatHandle>::from(data.clone()).expect("remount after crash");
+        if !crate::check::check(&vfat, false).expect("check").is_clean() {
+            crate::check::check(&vfat, true).expect("repair");
+            let report = crate::check::check(&vfat, false).expect("check");
+            assert!(report.is_clean(), "after {} writes: {:?}", writes, report);
+        }
+        assert_eq!(read_all(&vfat, "/a.txt"), b"old contents");
+
+        let created = (0..capacity + 4).take_while(|i| vfat.open(&format!("/dir{}", i)).is_ok()).count();
+        for i in created..capacity + 4 {
+            assert!(vfat.open(&format!("/dir{}", i)).is_err(), "after {} writes", writes);
+        }
+        if created > 0 && crashed {
+            checkpoints += 1;
+        }
+
+        if !crashed {
+            assert_eq!(created, capacity + 4);
+            break;
+        }
+    }
+
+    assert!(checkpoints > 0, "no crash came after a checkpoint");
+}
+
+#[test]
+fn test_journal_large_transaction() {
+    let device = formatted_device();
+    let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("mount");
+    vfat::enable_journal(&vfat, 100).expect("enable journal");
+
+    // the header continues into a second sector to list them all
+    let capacity = vfat.lock(|v| v.device.journal().map(|journal| journal.capacity())).expect("journaling");
+    assert_eq!(capacity, 98);
+
+    for i in 0..80 {
+        vfat.create_dir(&format!("/dir{}", i)).expect("create");
+    }
+    vfat.lock(|v| v.flush()).expect("flush");
+
+    let vfat = VFat::<StdVFatHandle>::from(device).expect("remount");
+    assert!(crate::check::check(&vfat, false).expect("check").is_clean());
+    for i in 0..80 {
+        assert!(vfat.open(&format!("/dir{}", i)).expect("open").is_dir());
+    }
+}
+
+#[derive(Clone)]
+struct StdExFatHandle(Arc<Mutex<crate::exfat::ExFat<Self>>>);
+
+impl Debug for StdExFatHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "StdExFatHandle")
+    }
+}
+
+impl crate::exfat::ExFatHandle for StdExFatHandle {
+    fn new(val: crate::exfat::ExFat<StdExFatHandle>) -> Self {
+        StdExFatHandle(Arc::new(Mutex::new(val)))
+    }
+
+    fn lock<R>(&self, f: impl FnOnce(&mut crate::exfat::ExFat<StdExFatHandle>) -> R) -> R {
+        f(&mut self.0.lock().expect("all okay"))
+    }
+}
+
+/// Contents of the files of `exfat_device()`.
+fn exfat_hello() -> Vec<u8> {
+    (0..700).map(|i| (i % 97) as u8).collect()
+}
+
+fn exfat_fragmented() -> Vec<u8> {
+    (0..1000).map(|i| (i % 89) as u8 + 1).collect()
+}
+
+/// Builds an exFAT entry set for `name` with the given attributes and data
+/// stream `(first cluster, size, valid size, NoFatChain)`.
+fn exfat_entry_set(name: &str, attributes: u16, stream: (u32, u64, u64, bool)) -> Vec<u8> {
+    let name: Vec<u16> = name.encode_utf16().collect();
+    let name_records = (name.len() + 14) / 15;
+    let mut set = vec![0u8; (2 + name_records) * 32];
+
+    set[0] = 0x85;
+    set[1] = (1 + name_records) as u8;
+    set[4..6].copy_from_slice(&attributes.to_le_bytes());
+    // created 2019-06-15 12:30:20.50, modified a second later
+    let packed: u32 = (39 << 25) | (6 << 21) | (15 << 16) | (12 << 11) | (30 << 5) | 10;
+    set[8..12].copy_from_slice(&packed.to_le_bytes());
+    set[12..16].copy_from_slice(&packed.to_le_bytes());
+    set[16..20].copy_from_slice(&packed.to_le_bytes());
+    set[20] = 50;
+    set[21] = 150;
+
+    let (first_cluster, size, valid_size, contiguous) = stream;
+    set[32] = 0xC0;
+    set[33] = if size > 0 { 0x01 } else { 0 } | if contiguous { 0x02 } else { 0 };
+    set[35] = name.len() as u8;
+    let hash = crate::exfat::UpcaseTable::ascii().name_hash(&name);
+    set[36..38].copy_from_slice(&hash.to_le_bytes());
+    set[40..48].copy_from_slice(&valid_size.to_le_bytes());
+    set[52..56].copy_from_slice(&first_cluster.to_le_bytes());
+    set[56..64].copy_from_slice(&size.to_le_bytes());
+
+    for (i, chunk) in name.chunks(15).enumerate() {
+        let record = &mut set[(2 + i) * 32..(3 + i) * 32];
+        record[0] = 0xC1;
+        for (j, unit) in chunk.iter().enumerate() {
+            record[2 + j * 2..4 + j * 2].copy_from_slice(&unit.to_le_bytes());
+        }
+    }
+
+    let checksum = crate::exfat::dir::set_checksum(&set);
+    set[2..4].copy_from_slice(&checksum.to_le_bytes());
+    set
+}
+
+/// Returns an MBR partitioned device with a small exFAT volume of 512 byte
+/// clusters at sector 64:
+///
+///  * cluster 2 holds the allocation bitmap, 3 the up-case table and 4 and 9
+///    the root directory
+///  * `/hello.txt` is contiguous (NoFatChain) in clusters 5 and 6
+///  * `/Sub Dir` is in cluster 7 and holds `Fragmented.bin`, which is chained
+///    through clusters 10, 12 and 11 and only has 1000 of its 1400 bytes
+///    written
+///  * `/a rather long file name.txt` is empty, and a deleted entry set and
+///    one with a bad checksum precede it
+fn exfat_device() -> SharedDevice {
+    const START: usize = 64;
+    const SECTORS: usize = 96;
+    let mut disk = vec![0u8; (START + SECTORS) * 512];
+    let mut mbr = MasterBootRecord::new([0; 10]);
+    mbr.partition_table[0] = PartitionEntry::new(0x07, START as u32, SECTORS as u32);
+    disk[..512].copy_from_slice(mbr.as_bytes());
+
+    let volume = &mut disk[START * 512..];
+    let boot = &mut volume[..512];
+    boot[..3].copy_from_slice(&[0xEB, 0x76, 0x90]);
+    boot[3..11].copy_from_slice(b"EXFAT   ");
+    boot[64..72].copy_from_slice(&(START as u64).to_le_bytes());
+    boot[72..80].copy_from_slice(&(SECTORS as u64).to_le_bytes());
+    boot[80..84].copy_from_slice(&24u32.to_le_bytes());
+    boot[84..88].copy_from_slice(&1u32.to_le_bytes());
+    boot[88..92].copy_from_slice(&32u32.to_le_bytes());
+    boot[92..96].copy_from_slice(&64u32.to_le_bytes());
+    boot[96..100].copy_from_slice(&4u32.to_le_bytes());
+    boot[100..104].copy_from_slice(&0xDEADBEEFu32.to_le_bytes());
+    boot[104..106].copy_from_slice(&0x0100u16.to_le_bytes());
+    boot[108] = 9;
+    boot[109] = 0;
+    boot[110] = 1;
+    boot[510..512].copy_from_slice(&[0x55, 0xAA]);
+    let checksum = crate::exfat::boot::boot_checksum(&volume[..11 * 512]);
+    for stored in volume[11 * 512..12 * 512].chunks_mut(4) {
+        stored.copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    let cluster = |n: usize| (32 + n - 2) * 512;
+    let mut fat = |volume: &mut [u8], cluster: u32, next: u32| {
+        let at = 24 * 512 + cluster as usize * 4;
+        volume[at..at + 4].copy_from_slice(&next.to_le_bytes());
+    };
+    fat(volume, 0, 0xFFFFFFF8);
+    fat(volume, 1, 0xFFFFFFFF);
+    for &(c, next) in [(2, !0), (3, !0), (4, 9), (9, !0), (7, !0), (10, 12), (12, 11), (11, !0)].iter() {
+        fat(volume, c, next);
+    }
+
+    // clusters 2 to 12 are in use
+    volume[cluster(2)] = 0xFF;
+    volume[cluster(2) + 1] = 0x07;
+
+    // a compressed up-case table mapping a-z to A-Z
+    let mut upcase: Vec<u16> = vec![0xFFFF, 0x61];
+    upcase.extend((b'A'..=b'Z').map(|c| c as u16));
+    upcase.extend_from_slice(&[0xFFFF, (0x10000 - 0x7B) as u16]);
+    let upcase: Vec<u8> = upcase.iter().flat_map(|unit| unit.to_le_bytes().to_vec()).collect();
+    volume[cluster(3)..cluster(3) + upcase.len()].copy_from_slice(&upcase);
+
+    let mut root = Vec::new();
+    let mut record = [0u8; 32];
+    record[0] = 0x83;
+    record[1] = 5;
+    for (i, unit) in "CARDS".encode_utf16().enumerate() {
+        record[2 + i * 2..4 + i * 2].copy_from_slice(&unit.to_le_bytes());
+    }
+    root.extend_from_slice(&record);
+    let mut record = [0u8; 32];
+    record[0] = 0x81;
+    record[20..24].copy_from_slice(&2u32.to_le_bytes());
+    record[24..32].copy_from_slice(&8u64.to_le_bytes());
+    root.extend_from_slice(&record);
+    let mut record = [0u8; 32];
+    record[0] = 0x82;
+    record[4..8].copy_from_slice(&crate::exfat::upcase::table_checksum(&upcase).to_le_bytes());
+    record[20..24].copy_from_slice(&3u32.to_le_bytes());
+    record[24..32].copy_from_slice(&(upcase.len() as u64).to_le_bytes());
+    root.extend_from_slice(&record);
+    root.extend(exfat_entry_set("hello.txt", 0x20, (5, 700, 700, true)));
+    root.extend(exfat_entry_set("Sub Dir", 0x10, (7, 512, 512, false)));
+    let mut deleted = exfat_entry_set("gone.txt", 0x20, (0, 0, 0, false));
+    for record in deleted.chunks_mut(32) {
+        record[0] &= 0x7F;
+    }
+    root.extend(deleted);
+    let mut corrupt = exfat_entry_set("corrupt.txt", 0x20, (0, 0, 0, false));
+    corrupt[70] ^= 1;
+    root.extend(corrupt);
+    root.extend(exfat_entry_set("a rather long file name.txt", 0x21, (0, 0, 0, false)));
+    assert!(root.len() > 512 && root.len() <= 1024);
+    volume[cluster(4)..cluster(4) + 512].copy_from_slice(&root[..512]);
+    volume[cluster(9)..cluster(9) + root.len() - 512].copy_from_slice(&root[512..]);
+
+    let sub = exfat_entry_set("Fragmented.bin", 0x20, (10, 1400, 1000, false));
+    volume[cluster(7)..cluster(7) + sub.len()].copy_from_slice(&sub);
+
+    let hello = exfat_hello();
+    volume[cluster(5)..cluster(5) + hello.len()].copy_from_slice(&hello);
+    let fragmented = exfat_fragmented();
+    for (i, &c) in [10, 12, 11].iter().enumerate() {
+        let part = &fragmented[std::cmp::min(i * 512, 1000)..std::cmp::min((i + 1) * 512, 1000)];
+        volume[cluster(c)..cluster(c) + part.len()].copy_from_slice(part);
+        // bytes past the valid data length must not be read
+        for byte in volume[cluster(c) + part.len()..cluster(c) + 512].iter_mut() {
+            *byte = 0xEE;
+        }
+    }
+
+    SharedDevice(Arc::new(Mutex::new(Cursor::new(disk))))
+}
+
+#[test]
+fn test_exfat_mount() {
+    let fs = crate::exfat::ExFat::<StdExFatHandle>::from(exfat_device()).expect("mount");
+    fs.lock(|exfat| {
+        assert_eq!(exfat.volume_label, "CARDS");
+        assert_eq!(exfat.volume_serial, 0xDEADBEEF);
+        assert_eq!(exfat.bytes_per_cluster(), 512);
+        assert_eq!(exfat.upcase.upcase('q' as u16), 'Q' as u16);
+        assert_eq!(exfat.upcase.upcase('{' as u16), '{' as u16);
+        let stats = exfat.statfs().expect("statfs");
+        assert_eq!((stats.total_clusters, stats.free_clusters), (64, 53));
+    });
+
+    let names: Vec<String> = fs.open_dir("/").expect("root").entries().expect("entries")
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert_eq!(names, ["hello.txt", "Sub Dir", "a rather long file name.txt"]);
+
+    let entry = fs.open("/a rather long file name.txt").expect("open");
+    assert!(entry.is_file() && entry.metadata().read_only());
+    let modified = entry.metadata().modified();
+    assert_eq!((modified.year(), modified.month(), modified.day()), (2019, 6, 15));
+    assert_eq!((modified.hour(), modified.minute(), modified.second()), (12, 30, 21));
+    assert_eq!(entry.metadata().created().second(), 20);
+
+    // a corrupt boot region is rejected
+    let device = exfat_device();
+    device.0.lock().unwrap().get_mut()[64 * 512 + 200] ^= 1;
+    expect_variant!(crate::exfat::ExFat::<StdExFatHandle>::from(device), Err(vfat::Error::BadSignature));
+}
+
+#[test]
+fn test_exfat_read() {
+    let fs = crate::exfat::ExFat::<StdExFatHandle>::from(exfat_device()).expect("mount");
+
+    let mut data = Vec::new();
+    fs.open_file("/hello.txt").expect("open").read_to_end(&mut data).expect("read");
+    assert_eq!(data, exfat_hello());
+
+    // names are compared through the up-case table
+    let mut file = fs.open_file("/SUB DIR/fragmented.BIN").expect("open");
+    assert_eq!(file.size(), 1400);
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).expect("read");
+    assert_eq!(&data[..1000], &exfat_fragmented()[..]);
+    assert!(data[1000..].iter().all(|&b| b == 0));
+
+    file.seek(SeekFrom::Start(990)).expect("seek");
+    let mut buf = [0xFFu8; 20];
+    assert_eq!(file.read(&mut buf).expect("read"), 20);
+    assert_eq!(&buf[..10], &exfat_fragmented()[990..]);
+    assert_eq!(&buf[10..], &[0; 10]);
+    file.seek(SeekFrom::Start(600)).expect("seek back");
+    assert_eq!(file.read(&mut buf).expect("read"), 20);
+    assert_eq!(&buf[..], &exfat_fragmented()[600..620]);
+    assert!(file.seek(SeekFrom::End(1)).is_err());
+
+    // a directory claiming more data than the volume holds is rejected
+    // before anything is allocated for it
+    let device = exfat_device();
+    {
+        let mut disk = device.0.lock().unwrap();
+        let set = &mut disk.get_mut()[(64 + 34) * 512 + 192..][..96];
+        set[56..64].copy_from_slice(&u64::max_value().to_le_bytes());
+        let checksum = crate::exfat::dir::set_checksum(set);
+        set[2..4].copy_from_slice(&checksum.to_le_bytes());
+    }
+    let corrupt = crate::exfat::ExFat::<StdExFatHandle>::from(device).expect("mount");
+    assert_eq!(corrupt.open("/Sub Dir/Fragmented.bin").map(|_| ()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+    assert_eq!(fs.open("/gone.txt").map(|_| ()).unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(fs.open("/corrupt.txt").map(|_| ()).unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(file.write(b"x").unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    assert_eq!(fs.create_file("/new").map(|_| ()).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+}
+
+#[derive(Clone)]
+struct StdExt2Handle(Arc<Mutex<crate::ext2::Ext2<Self>>>);
+
+impl Debug for StdExt2Handle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "StdExt2Handle")
+    }
+}
+
+impl crate::ext2::Ext2Handle for StdExt2Handle {
+    fn new(val: crate::ext2::Ext2<StdExt2Handle>) -> Self {
+        StdExt2Handle(Arc::new(Mutex::new(val)))
+    }
+
+    fn lock<R>(&self, f: impl FnOnce(&mut crate::ext2::Ext2<StdExt2Handle>) -> R) -> R {
+        f(&mut self.0.lock().expect("all okay"))
+    }
+}
+
+/// The number of data blocks of `/sub/big.bin` of `ext2_device()`: twelve
+/// direct, 256 singly indirect and two doubly indirect ones.
+const EXT2_BIG_BLOCKS: usize = 12 + 256 + 2;
+
+/// Returns `true` if block `index` of `/sub/big.bin` is a hole.
+fn ext2_big_hole(index: usize) -> bool {
+    index > 12 && index < 12 + 256
+}
+
+/// Contents of `/sub/big.bin` of `ext2_device()`.
+fn ext2_big() -> Vec<u8> {
+    (0..EXT2_BIG_BLOCKS * 1024)
+        .map(|i| if ext2_big_hole(i / 1024) { 0 } else { (i % 251) as u8 + 1 })
+        .collect()
+}
+
+/// Appends a directory record for `name` to the directory block `block` at
+/// `offset`, spanning `rec_len` bytes. Returns the offset of the next record.
+fn ext2_dir_record(block: &mut [u8], offset: usize, inode: u32, name: &str, file_type: u8, rec_len: usize) -> usize {
+    let record = &mut block[offset..offset + rec_len];
+    record[0..4].copy_from_slice(&inode.to_le_bytes());
+    record[4..6].copy_from_slice(&(rec_len as u16).to_le_bytes());
+    record[6] = name.len() as u8;
+    record[7] = file_type;
+    record[8..8 + name.len()].copy_from_slice(name.as_bytes());
+    offset + rec_len
+}
+
+/// Returns an MBR partitioned device with a small ext2 volume of 1024 byte
+/// blocks at sector 64, in a single block group:
+///
+///  * block 1 holds the superblock, 2 the group descriptors, 3 and 4 the
+///    bitmaps and 5 and 6 the inode table
+///  * the root directory (inode 2) is in block 7 and holds `hello.txt` (inode
+///    12, block 8), `sub` (inode 13, block 9), a symbolic link and a deleted
+///    record
+///  * `/sub` holds the empty, read-only `.hidden` and `big.bin` (inode 15),
+///    whose data spans direct blocks 10-21, singly indirect block 22, which
+///    maps block 23 then holes, and doubly indirect block 24, which maps
+///    block 25, which maps 26 and 27
+fn ext2_device() -> SharedDevice {
+    const START: usize = 64;
+    const BLOCKS: usize = 128;
+    let mut disk = vec![0u8; START * 512 + BLOCKS * 1024];
+    let mut mbr = MasterBootRecord::new([0; 10]);
+    mbr.partition_table[0] = PartitionEntry::new(0x83, START as u32, (BLOCKS * 2) as u32);
+    disk[..512].copy_from_slice(mbr.as_bytes());
+
+    let volume = &mut disk[START * 512..];
+    let put32 = |volume: &mut [u8], at: usize, value: u32| volume[at..at + 4].copy_from_slice(&value.to_le_bytes());
+    let put16 = |volume: &mut [u8], at: usize, value: u16| volume[at..at + 2].copy_from_slice(&value.to_le_bytes());
+
+    let sb = 1024;
+    put32(volume, sb, 16);
+    put32(volume, sb + 4, BLOCKS as u32);
+    put32(volume, sb + 12, 100);
+    put32(volume, sb + 16, 3);
+    put32(volume, sb + 20, 1);
+    put32(volume, sb + 32, 8192);
+    put32(volume, sb + 36, 8192);
+    put32(volume, sb + 40, 16);
+    put16(volume, sb + 56, 0xEF53);
+    put32(volume, sb + 76, 1);
+    put32(volume, sb + 84, 11);
+    put16(volume, sb + 88, 128);
+    put32(volume, sb + 96, 0x0002);
+    put32(volume, sb + 100, 0x0002);
+    volume[sb + 120..sb + 126].copy_from_slice(b"rootfs");
+
+    let gd = 2 * 1024;
+    put32(volume, gd, 3);
+    put32(volume, gd + 4, 4);
+    put32(volume, gd + 8, 5);
+    put16(volume, gd + 12, 100);
+    put16(volume, gd + 14, 3);
+    put16(volume, gd + 16, 2);
+
+    // 2019-06-15 12:30:20 UTC
+    let mtime = 1560601820;
+    let inode = |volume: &mut [u8], number: usize, mode: u16, size: u32, blocks: &[(usize, u32)]| {
+        let at = 5 * 1024 + (number - 1) * 128;
+        put16(volume, at, mode);
+        put32(volume, at + 4, size);
+        put32(volume, at + 16, mtime);
+        put16(volume, at + 26, 1);
+        for &(i, block) in blocks {
+            put32(volume, at + 40 + i * 4, block);
+        }
+    };
+    inode(volume, 2, 0x41ED, 1024, &[(0, 7)]);
+    inode(volume, 12, 0x81A4, 700, &[(0, 8)]);
+    inode(volume, 13, 0x41ED, 1024, &[(0, 9)]);
+    inode(volume, 14, 0xA1FF, 9, &[]);
+    inode(volume, 15, 0x81A4, (EXT2_BIG_BLOCKS * 1024) as u32, &[(12, 22), (13, 24)]);
+    inode(volume, 16, 0x8124, 0, &[]);
+    for i in 0..12 {
+        put32(volume, 5 * 1024 + 14 * 128 + 40 + i * 4, 10 + i as u32);
+    }
+    put32(volume, 22 * 1024, 23);
+    put32(volume, 24 * 1024, 25);
+    put32(volume, 25 * 1024, 26);
+    put32(volume, 25 * 1024 + 4, 27);
+
+    let root = &mut volume[7 * 1024..8 * 1024];
+    let offset = ext2_dir_record(root, 0, 2, ".", 2, 12);
+    let offset = ext2_dir_record(root, offset, 2, "..", 2, 12);
+    let offset = ext2_dir_record(root, offset, 12, "hello.txt", 1, 20);
+    let offset = ext2_dir_record(root, offset, 0, "gone", 1, 12);
+    let offset = ext2_dir_record(root, offset, 14, "link", 7, 12);
+    ext2_dir_record(root, offset, 13, "sub", 2, 1024 - offset);
+
+    let sub = &mut volume[9 * 1024..10 * 1024];
+    let offset = ext2_dir_record(sub, 0, 13, ".", 2, 12);
+    let offset = ext2_dir_record(sub, offset, 2, "..", 2, 12);
+    let offset = ext2_dir_record(sub, offset, 16, ".hidden", 1, 16);
+    ext2_dir_record(sub, offset, 15, "big.bin", 1, 1024 - offset);
+
+    volume[8 * 1024..8 * 1024 + 700].copy_from_slice(&exfat_hello());
+    let big = ext2_big();
+    let data_blocks = (10..22).chain(vec![23, 26, 27]);
+    let file_blocks = (0..12).chain(vec![12, 268, 269]);
+    for (block, index) in data_blocks.zip(file_blocks) {
+        volume[block * 1024..(block + 1) * 1024].copy_from_slice(&big[index * 1024..(index + 1) * 1024]);
+    }
+
+    SharedDevice(Arc::new(Mutex::new(Cursor::new(disk))))
+}
+
+#[test]
+fn test_ext2_mount() {
+    let fs = crate::ext2::Ext2::<StdExt2Handle>::from(ext2_device()).expect("mount");
+    fs.lock(|ext2| {
+        assert_eq!(ext2.volume_name, "rootfs");
+        assert_eq!(ext2.block_size, 1024);
+        assert_eq!(ext2.groups.len(), 1);
+        let stats = ext2.statfs();
+        assert_eq!((stats.total_clusters, stats.free_clusters), (127, 100));
+    });
+
+    // symbolic links and deleted records are skipped
+    let names: Vec<String> = fs.open_dir("/").expect("root").entries().expect("entries")
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert_eq!(names, [".", "..", "hello.txt", "sub"]);
+
+    let entry = fs.open("/sub/.hidden").expect("open");
+    assert!(entry.is_file() && entry.metadata().read_only() && entry.metadata().hidden());
+    let entry = fs.open("/hello.txt").expect("open");
+    assert!(!entry.metadata().read_only() && !entry.metadata().hidden());
+    let modified = entry.metadata().modified();
+    assert_eq!((modified.year(), modified.month(), modified.day()), (2019, 6, 15));
+    assert_eq!((modified.hour(), modified.minute(), modified.second()), (12, 30, 20));
+    assert!(fs.open("/sub/..").expect("open").is_dir());
+
+    // a bad magic number is rejected
+    let device = ext2_device();
+    device.0.lock().unwrap().get_mut()[64 * 512 + 1024 + 56] ^= 1;
+    expect_variant!(crate::ext2::Ext2::<StdExt2Handle>::from(device), Err(vfat::Error::BadSignature));
+}
+
+#[test]
+fn test_ext2_read() {
+    let fs = crate::ext2::Ext2::<StdExt2Handle>::from(ext2_device()).expect("mount");
+
+    let mut data = Vec::new();
+    fs.open_file("/hello.txt").expect("open").read_to_end(&mut data).expect("read");
+    assert_eq!(data, exfat_hello());
+
+    // direct, indirect and doubly indirect blocks, and holes between them
+    let mut file = fs.open_file("/sub/big.bin").expect("open");
+    assert_eq!(file.size(), (EXT2_BIG_BLOCKS * 1024) as u64);
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).expect("read");
+    assert!(data == ext2_big());
+
+    file.seek(SeekFrom::Start(268 * 1024 - 10)).expect("seek");
+    let mut buf = [0xFFu8; 20];
+    assert_eq!(file.read(&mut buf).expect("read"), 20);
+    assert_eq!(&buf[..10], &[0; 10]);
+    assert_eq!(&buf[10..], &ext2_big()[268 * 1024..268 * 1024 + 10]);
+    file.seek(SeekFrom::End(-5)).expect("seek to the end");
+    assert_eq!(file.read(&mut buf).expect("read"), 5);
+    assert!(file.seek(SeekFrom::End(1)).is_err());
+
+    // names are case sensitive
+    assert_eq!(fs.open("/SUB").map(|_| ()).unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(fs.open("/link").map(|_| ()).unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(fs.open("/gone").map(|_| ()).unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(file.write(b"x").unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    assert_eq!(fs.create_file("/new").map(|_| ()).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+
+    // a directory larger than the volume is rejected before it is read
+    let device = ext2_device();
+    let size = 64 * 512 + 5 * 1024 + 12 * 128 + 4;
+    device.0.lock().unwrap().get_mut()[size..size + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    let fs = crate::ext2::Ext2::<StdExt2Handle>::from(device).expect("mount");
+    assert_eq!(fs.open("/sub/.hidden").map(|_| ()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+}
+
+#[derive(Clone)]
+struct StdTmpFsHandle(Arc<Mutex<crate::tmpfs::TmpFs<Self>>>);
+
+impl Debug for StdTmpFsHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "StdTmpFsHandle")
+    }
+}
+
+impl crate::tmpfs::TmpFsHandle for StdTmpFsHandle {
+    fn new(val: crate::tmpfs::TmpFs<StdTmpFsHandle>) -> Self {
+        StdTmpFsHandle(Arc::new(Mutex::new(val)))
+    }
+
+    fn lock<R>(&self, f: impl FnOnce(&mut crate::tmpfs::TmpFs<StdTmpFsHandle>) -> R) -> R {
+        f(&mut self.0.lock().expect("all okay"))
+    }
+}
+
+#[test]
+fn test_tmpfs_create_read_write() {
+    let fs = crate::tmpfs::TmpFs::<StdTmpFsHandle>::volume(64 * 1024);
+    fs.lock(|tmpfs| tmpfs.set_time_source(|| vfat::Timestamp::new(2020, 2, 29, 23, 59, 58)));
+
+    fs.create_dir("/sub").expect("create dir");
+    let mut file = fs.create_file("/sub/hello.txt").expect("create file");
+    file.write_all(&exfat_hello()).expect("write");
+    assert_eq!(file.size(), 700);
+    assert_eq!(fs.create_file("/sub/hello.txt").map(|_| ()).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(fs.create_file("/none/x").map(|_| ()).unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(fs.create_file("/sub/hello.txt/x").map(|_| ()).unwrap_err().kind(), io::ErrorKind::Other);
+    assert_eq!(fs.open("sub").map(|_| ()).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+    // a second handle sees the data, and overwrites past the end extend it
+    let mut other = fs.open_file("/sub/../sub/./hello.txt").expect("open");
+    let mut data = Vec::new();
+    other.read_to_end(&mut data).expect("read");
+    assert_eq!(data, exfat_hello());
+    other.write_all(b"tail").expect("write");
+    file.seek(SeekFrom::Start(698)).expect("seek");
+    let mut buf = [0u8; 8];
+    assert_eq!(file.read(&mut buf).expect("read"), 6);
+    assert_eq!(&buf[..6], &[exfat_hello()[698], exfat_hello()[699], b't', b'a', b'i', b'l']);
+    assert!(file.seek(SeekFrom::End(1)).is_err());
+
+    let names: Vec<String> = fs.open_dir("/").expect("root").entries().expect("entries")
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert_eq!(names, ["sub"]);
+    fs.create_file("/sub/.b").expect("create file");
+    fs.create_dir("/sub/a").expect("create dir");
+    let entries: Vec<_> = fs.open_dir("/sub").expect("open").entries().expect("entries").collect();
+    let names: Vec<&str> = entries.iter().map(|entry| entry.name()).collect();
+    assert_eq!(names, [".b", "a", "hello.txt"]);
+    assert!(entries[0].metadata().hidden() && !entries[0].metadata().read_only());
+    assert!(entries[1].is_dir() && entries[2].is_file());
+    let modified = entries[2].metadata().modified();
+    assert_eq!((modified.year(), modified.month(), modified.day()), (2020, 2, 29));
+
+    // names are case sensitive
+    assert_eq!(fs.open("/SUB").map(|_| ()).unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(fs.create_dir("/sub/..").map(|_| ()).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_tmpfs_truncate_and_capacity() {
+    let fs = crate::tmpfs::TmpFs::<StdTmpFsHandle>::volume(4 * 512);
+    let mut file = fs.create_file("/a").expect("create");
+    file.write_all(&[1; 1000]).expect("write");
+    assert_eq!(fs.lock(|tmpfs| tmpfs.statfs().free_clusters), 2);
+
+    file.truncate(10).expect("shrink");
+    assert_eq!((file.size(), file.seek(SeekFrom::Current(0)).expect("tell")), (10, 10));
+    file.truncate(20).expect("extend");
+    let mut data = Vec::new();
+    file.seek(SeekFrom::Start(0)).expect("seek");
+    file.read_to_end(&mut data).expect("read");
+    assert_eq!(data, [&[1u8; 10][..], &[0u8; 10][..]].concat());
+
+    // writes beyond the capacity fail and leave the file as it was
+    assert_eq!(file.write(&[2; 4096]).unwrap_err().kind(), io::ErrorKind::Other);
+    assert_eq!(file.size(), 20);
+    assert_eq!(file.truncate(4 * 512 + 1).unwrap_err().kind(), io::ErrorKind::Other);
+    file.truncate(4 * 512).expect("fill");
+
+    // removing the file frees its space
+    fs.remove("/a").expect("remove");
+    assert_eq!(fs.lock(|tmpfs| tmpfs.statfs().free_clusters), 4);
+    assert_eq!(fs.lock(|tmpfs| tmpfs.node_count()), 1);
+}
+
+#[test]
+fn test_tmpfs_unlink_and_rename() {
+    let fs = crate::tmpfs::TmpFs::<StdTmpFsHandle>::volume(64 * 1024);
+    fs.create_dir("/a").expect("create dir");
+    fs.create_dir("/a/b").expect("create dir");
+    fs.create_file("/a/b/f").expect("create file").write_all(b"data").expect("write");
+
+    // removal requires empty directories, and open files fail afterwards
+    let mut open = fs.open_file("/a/b/f").expect("open");
+    assert_eq!(fs.remove("/a/b").unwrap_err().kind(), io::ErrorKind::Other);
+    assert_eq!(fs.open_dir("/a").expect("open").unlink("b").unwrap_err().kind(), io::ErrorKind::Other);
+    fs.open_dir("/a/b").expect("open").unlink("f").expect("unlink");
+    assert_eq!(open.read(&mut [0; 4]).unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(open.size(), 0);
+    fs.remove("/a/b").expect("remove");
+    assert_eq!(fs.remove("/a/b").unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(fs.remove("/").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+    // renamed entries keep their data, and open files follow them
+    fs.create_dir("/a/c").expect("create dir");
+    let mut file = fs.create_file("/a/c/f").expect("create file");
+    file.write_all(b"moved").expect("write");
+    fs.rename("/a/c", "/d").expect("rename dir");
+    fs.rename("/d/f", "/d/g").expect("rename file");
+    file.write_all(b"!").expect("write");
+    let mut data = String::new();
+    fs.open_file("/d/g").expect("open").read_to_string(&mut data).expect("read");
+    assert_eq!(data, "moved!");
+    assert_eq!(fs.open("/a/c").map(|_| ()).unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert!(fs.open("/d/..").expect("open").is_dir());
+
+    fs.create_file("/h").expect("create file");
+    assert_eq!(fs.rename("/d/g", "/h").unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(fs.rename("/a", "/a/x").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    fs.create_dir("/a/x").expect("create dir");
+    assert_eq!(fs.rename("/a", "/a/x/y").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(fs.rename("/missing", "/z").unwrap_err().kind(), io::ErrorKind::NotFound);
+    fs.rename("/a/x", "/d/x").expect("rename");
+    assert!(fs.open("/d/x/..").expect("open").name() == "d");
+}
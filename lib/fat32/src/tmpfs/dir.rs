@@ -0,0 +1,145 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use shim::ffi::OsStr;
+use shim::io;
+use shim::ioerr;
+
+use crate::tmpfs::tmpfs::{entry, Data};
+use crate::tmpfs::{Entry, File, Metadata, TmpFs, TmpFsHandle};
+use crate::traits;
+
+/// A directory of a tmpfs volume.
+#[derive(Debug)]
+pub struct Dir<HANDLE: TmpFsHandle> {
+    pub tmpfs: HANDLE,
+    pub name: String,
+    pub metadata: Metadata,
+    pub inode: u64,
+}
+
+/// Returns `name` as a `str`, or `InvalidInput` if it isn't valid UTF-8.
+fn to_str(name: &OsStr) -> io::Result<&str> {
+    match name.to_str() {
+        Some(name) => Ok(name),
+        None => ioerr!(InvalidInput, "name contained invalid utf8")
+    }
+}
+
+impl<HANDLE: TmpFsHandle> Dir<HANDLE> {
+    /// Finds the entry named `name` in `self` and returns it. Names are
+    /// compared case sensitively.
+    ///
+    /// # Errors
+    ///
+    /// If no entry with name `name` exists in `self`, an error of `NotFound`
+    /// is returned.
+    ///
+    /// If `name` contains invalid UTF-8 characters, an error of `InvalidInput`
+    /// is returned.
+    pub fn find<P: AsRef<OsStr>>(&self, name: P) -> io::Result<Entry<HANDLE>> {
+        let name = to_str(name.as_ref())?;
+        let inode = self.tmpfs.lock(|tmpfs: &mut TmpFs<HANDLE>| tmpfs.lookup(self.inode, name))?;
+        entry(&self.tmpfs, inode, name)
+    }
+
+    /// Creates an empty file named `name` in `self` and returns it.
+    ///
+    /// # Errors
+    ///
+    /// If an entry named `name` already exists, an error of `AlreadyExists`
+    /// is returned.
+    ///
+    /// If `name` is not a valid file name, an error of `InvalidInput` is
+    /// returned.
+    pub fn create_file<P: AsRef<OsStr>>(&self, name: P) -> io::Result<File<HANDLE>> {
+        let name = to_str(name.as_ref())?;
+        self.tmpfs.lock(|tmpfs: &mut TmpFs<HANDLE>| tmpfs.insert(self.inode, name, Data::File(Vec::new())))?;
+        match self.find(name)? {
+            Entry::File(file) => Ok(file),
+            Entry::Dir(_) => ioerr!(Other, "not a regular file")
+        }
+    }
+
+    /// Creates an empty directory named `name` in `self` and returns it.
+    ///
+    /// # Errors
+    ///
+    /// Fails for the same reasons as `create_file()`.
+    pub fn create_dir<P: AsRef<OsStr>>(&self, name: P) -> io::Result<Dir<HANDLE>> {
+        let name = to_str(name.as_ref())?;
+        self.tmpfs.lock(|tmpfs: &mut TmpFs<HANDLE>| tmpfs.insert(self.inode, name, Data::Dir(Default::default())))?;
+        match self.find(name)? {
+            Entry::Dir(dir) => Ok(dir),
+            Entry::File(_) => ioerr!(Other, "not a directory")
+        }
+    }
+
+    /// Removes the file named `name` from `self`, freeing its data. Files
+    /// that are open fail with `NotFound` from then on.
+    ///
+    /// # Errors
+    ///
+    /// If no entry with name `name` exists in `self`, an error of `NotFound`
+    /// is returned.
+    ///
+    /// If the entry is a directory, an error of `Other` is returned.
+    pub fn unlink<P: AsRef<OsStr>>(&self, name: P) -> io::Result<()> {
+        match self.find(name.as_ref())? {
+            Entry::File(_) => self.remove(name),
+            Entry::Dir(_) => ioerr!(Other, "entry is a directory")
+        }
+    }
+
+    /// Removes the file or empty directory named `name` from `self`.
+    ///
+    /// # Errors
+    ///
+    /// If no entry with name `name` exists in `self`, an error of `NotFound`
+    /// is returned.
+    ///
+    /// If the entry is a directory that is not empty, an error of `Other` is
+    /// returned.
+    pub fn remove<P: AsRef<OsStr>>(&self, name: P) -> io::Result<()> {
+        let name = to_str(name.as_ref())?;
+        self.tmpfs.lock(|tmpfs: &mut TmpFs<HANDLE>| tmpfs.remove(self.inode, name))
+    }
+
+    /// Moves the entry named `name` in `self` into `to`, naming it
+    /// `new_name`. Open files and directories follow the entry.
+    ///
+    /// # Errors
+    ///
+    /// If no entry with name `name` exists in `self`, an error of `NotFound`
+    /// is returned.
+    ///
+    /// If an entry named `new_name` already exists in `to`, an error of
+    /// `AlreadyExists` is returned.
+    ///
+    /// If `new_name` is not a valid file name, or a directory would be moved
+    /// into itself or one of its subdirectories, an error of `InvalidInput`
+    /// is returned.
+    pub fn rename<P: AsRef<OsStr>, Q: AsRef<OsStr>>(&self, name: P, to: &Dir<HANDLE>, new_name: Q) -> io::Result<()> {
+        let (name, new_name) = (to_str(name.as_ref())?, to_str(new_name.as_ref())?);
+        self.tmpfs.lock(|tmpfs: &mut TmpFs<HANDLE>| tmpfs.rename(self.inode, name, to.inode, new_name))
+    }
+}
+
+impl<HANDLE: TmpFsHandle> traits::Dir for Dir<HANDLE> {
+    /// The type of entry stored in this directory.
+    type Entry = Entry<HANDLE>;
+
+    /// An type that is an iterator over the entries in this directory.
+    type Iter = alloc::vec::IntoIter<Entry<HANDLE>>;
+
+    /// Returns an interator over the entries in this directory, sorted by
+    /// name. There are no `.` and `..` entries.
+    fn entries(&self) -> io::Result<Self::Iter> {
+        let list = self.tmpfs.lock(|tmpfs: &mut TmpFs<HANDLE>| tmpfs.list(self.inode))?;
+        let mut entries = Vec::new();
+        for (name, inode) in list {
+            entries.push(entry(&self.tmpfs, inode, &name)?);
+        }
+        Ok(entries.into_iter())
+    }
+}
@@ -0,0 +1,99 @@
+use alloc::string::String;
+
+use shim::io::{self, SeekFrom};
+use shim::ioerr;
+
+use crate::tmpfs::{Metadata, TmpFs, TmpFsHandle};
+use crate::traits;
+
+/// A regular file of a tmpfs volume.
+#[derive(Debug)]
+pub struct File<HANDLE: TmpFsHandle> {
+    pub tmpfs: HANDLE,
+    pub name: String,
+    pub metadata: Metadata,
+    pub inode: u64,
+    pub current_offset: u64,
+}
+
+impl<HANDLE: TmpFsHandle> File<HANDLE> {
+    /// Truncates or extends the file to `size` bytes. Extended space is
+    /// filled with zeroes.
+    ///
+    /// The current offset is clamped to the new size.
+    ///
+    /// # Errors
+    ///
+    /// If the volume cannot hold the extended file, an error of `Other` is
+    /// returned.
+    pub fn truncate(&mut self, size: u64) -> io::Result<()> {
+        let inode = self.inode;
+        self.tmpfs.lock(|tmpfs: &mut TmpFs<HANDLE>| tmpfs.truncate(inode, size))?;
+        self.current_offset = core::cmp::min(self.current_offset, size);
+        Ok(())
+    }
+}
+
+impl<HANDLE: TmpFsHandle> traits::File for File<HANDLE> {
+    /// Files live in memory; there is nothing to write back.
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Returns the size of the file in bytes, or `0` if it was removed.
+    fn size(&self) -> u64 {
+        let inode = self.inode;
+        self.tmpfs.lock(|tmpfs: &mut TmpFs<HANDLE>| tmpfs.size(inode)).unwrap_or(0)
+    }
+}
+
+impl<HANDLE: TmpFsHandle> io::Write for File<HANDLE> {
+    /// Writes `buf` at the current offset, extending the file if needed.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let (inode, offset) = (self.inode, self.current_offset);
+        let n = self.tmpfs.lock(|tmpfs: &mut TmpFs<HANDLE>| tmpfs.write(inode, offset, buf))?;
+        self.current_offset += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<HANDLE: TmpFsHandle> io::Read for File<HANDLE> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (inode, offset) = (self.inode, self.current_offset);
+        let n = self.tmpfs.lock(|tmpfs: &mut TmpFs<HANDLE>| tmpfs.read(inode, offset, buf))?;
+        self.current_offset += n as u64;
+        Ok(n)
+    }
+}
+
+impl<HANDLE: TmpFsHandle> io::Seek for File<HANDLE> {
+    /// Seek to offset `pos` in the file.
+    ///
+    /// A seek to the end of the file is allowed. A seek _beyond_ the end of the
+    /// file returns an `InvalidInput` error.
+    ///
+    /// # Errors
+    ///
+    /// Seeking before the start of a file or beyond the end of the file results
+    /// in an `InvalidInput` error.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        use traits::File;
+
+        let size = self.size();
+        let new_offset = match pos {
+            SeekFrom::Start(off) => off as i64,
+            SeekFrom::Current(off) => self.current_offset as i64 + off,
+            SeekFrom::End(off) => size as i64 + off
+        };
+
+        if new_offset < 0 || new_offset as u64 > size {
+            return ioerr!(InvalidInput, "invalid seek");
+        }
+        self.current_offset = new_offset as u64;
+        Ok(self.current_offset)
+    }
+}
@@ -0,0 +1,43 @@
+use crate::traits;
+use crate::vfat::Timestamp;
+
+/// Metadata of a tmpfs file or directory.
+#[derive(Default, Debug, Clone)]
+pub struct Metadata {
+    /// The inode number.
+    pub inode: u64,
+    /// The size of the entry's data in bytes.
+    pub size: u64,
+    pub created: Timestamp,
+    pub accessed: Timestamp,
+    pub modified: Timestamp,
+    /// Whether the entry's name begins with a dot.
+    pub dot_file: bool,
+}
+
+impl traits::Metadata for Metadata {
+    type Timestamp = Timestamp;
+
+    /// Entries are always writable.
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    /// Whether the entry's name begins with a dot, the Unix convention for
+    /// hidden files.
+    fn hidden(&self) -> bool {
+        self.dot_file
+    }
+
+    fn created(&self) -> Self::Timestamp {
+        self.created
+    }
+
+    fn accessed(&self) -> Self::Timestamp {
+        self.accessed
+    }
+
+    fn modified(&self) -> Self::Timestamp {
+        self.modified
+    }
+}
@@ -0,0 +1,14 @@
+//! An in-memory file system implementing the file system traits, for
+//! scratch files that should not touch the SD card.
+
+pub(crate) mod dir;
+pub(crate) mod entry;
+pub(crate) mod file;
+pub(crate) mod metadata;
+pub(crate) mod tmpfs;
+
+pub use self::dir::Dir;
+pub use self::entry::Entry;
+pub use self::file::File;
+pub use self::metadata::Metadata;
+pub use self::tmpfs::{TmpFs, TmpFsHandle, TmpFsVolume, BLOCK_SIZE, ROOT_INODE};
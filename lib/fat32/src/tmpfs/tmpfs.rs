@@ -0,0 +1,401 @@
+use core::cmp::min;
+use core::fmt::Debug;
+use core::marker::PhantomData;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use shim::io;
+use shim::ioerr;
+use shim::path::{Component, Path};
+
+use crate::tmpfs::{Dir, Entry, File, Metadata};
+use crate::traits::FileSystem;
+use crate::vfat::vfat::split_parent;
+use crate::vfat::{StatFs, Timestamp};
+
+/// The inode number of the root directory.
+pub const ROOT_INODE: u64 = 1;
+
+/// The unit in which `TmpFs::statfs()` reports usage.
+pub const BLOCK_SIZE: u64 = 512;
+
+/// A generic trait that handles a critical section as a closure
+pub trait TmpFsHandle: Clone + Debug + Send + Sync {
+    fn new(val: TmpFs<Self>) -> Self;
+    fn lock<R>(&self, f: impl FnOnce(&mut TmpFs<Self>) -> R) -> R;
+}
+
+/// A tmpfs volume, as returned by `TmpFs::volume()`.
+#[derive(Clone, Debug)]
+pub struct TmpFsVolume<HANDLE: TmpFsHandle>(pub HANDLE);
+
+impl<HANDLE: TmpFsHandle> TmpFsVolume<HANDLE> {
+    /// Runs `f` with the volume locked.
+    pub fn lock<R>(&self, f: impl FnOnce(&mut TmpFs<HANDLE>) -> R) -> R {
+        self.0.lock(f)
+    }
+}
+
+/// What a node of the tree holds.
+#[derive(Debug)]
+pub(crate) enum Data {
+    File(Vec<u8>),
+    /// The inode numbers of the entries of a directory, by name.
+    Dir(BTreeMap<String, u64>),
+}
+
+/// A file or directory of the tree.
+#[derive(Debug)]
+pub(crate) struct Node {
+    /// The directory holding the node; the root is its own parent.
+    pub parent: u64,
+    pub data: Data,
+    pub created: Timestamp,
+    pub accessed: Timestamp,
+    pub modified: Timestamp,
+}
+
+/// A file system held entirely in memory as a tree of nodes, which is lost
+/// when it is dropped.
+///
+/// Nodes are numbered in creation order and numbers are never reused, so
+/// files and directories that are open while they are removed fail with
+/// `NotFound` instead of referring to a new node.
+#[derive(Debug)]
+pub struct TmpFs<HANDLE: TmpFsHandle> {
+    phantom: PhantomData<HANDLE>,
+    nodes: BTreeMap<u64, Node>,
+    next_inode: u64,
+    /// The most file data the volume may hold, in bytes.
+    capacity: u64,
+    /// The file data the volume holds, in bytes.
+    used: u64,
+    time_source: fn() -> Timestamp,
+}
+
+impl<HANDLE: TmpFsHandle> TmpFs<HANDLE> {
+    /// Returns an empty volume holding at most `capacity` bytes of file
+    /// data.
+    pub fn volume(capacity: u64) -> TmpFsVolume<HANDLE> {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(ROOT_INODE, Node {
+            parent: ROOT_INODE,
+            data: Data::Dir(BTreeMap::new()),
+            created: Timestamp::EPOCH,
+            accessed: Timestamp::EPOCH,
+            modified: Timestamp::EPOCH,
+        });
+
+        TmpFsVolume(HANDLE::new(TmpFs {
+            phantom: PhantomData,
+            nodes,
+            next_inode: ROOT_INODE + 1,
+            capacity,
+            used: 0,
+            time_source: || Timestamp::EPOCH,
+        }))
+    }
+
+    /// Sets the function used to timestamp created and modified entries. By
+    /// default `Timestamp::EPOCH` is used.
+    pub fn set_time_source(&mut self, time_source: fn() -> Timestamp) {
+        self.time_source = time_source;
+    }
+
+    /// Returns the current time according to the configured time source.
+    pub fn now(&self) -> Timestamp {
+        (self.time_source)()
+    }
+
+    /// Returns usage statistics for the volume, in units of `BLOCK_SIZE`.
+    pub fn statfs(&self) -> StatFs {
+        let blocks = |bytes: u64| min(bytes / BLOCK_SIZE, u32::max_value() as u64) as u32;
+        StatFs {
+            bytes_per_cluster: BLOCK_SIZE,
+            total_clusters: blocks(self.capacity),
+            free_clusters: blocks(self.capacity - self.used),
+        }
+    }
+
+    /// Returns the number of files and directories on the volume, the root
+    /// directory included.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub(crate) fn node(&self, inode: u64) -> io::Result<&Node> {
+        match self.nodes.get(&inode) {
+            Some(node) => Ok(node),
+            None => ioerr!(NotFound, "entry was removed")
+        }
+    }
+
+    fn node_mut(&mut self, inode: u64) -> io::Result<&mut Node> {
+        match self.nodes.get_mut(&inode) {
+            Some(node) => Ok(node),
+            None => ioerr!(NotFound, "entry was removed")
+        }
+    }
+
+    fn children(&self, dir: u64) -> io::Result<&BTreeMap<String, u64>> {
+        match &self.node(dir)?.data {
+            Data::Dir(children) => Ok(children),
+            Data::File(_) => ioerr!(InvalidInput, "not a directory")
+        }
+    }
+
+    fn children_mut(&mut self, dir: u64) -> io::Result<&mut BTreeMap<String, u64>> {
+        match &mut self.node_mut(dir)?.data {
+            Data::Dir(children) => Ok(children),
+            Data::File(_) => ioerr!(InvalidInput, "not a directory")
+        }
+    }
+
+    fn data_mut(&mut self, inode: u64) -> io::Result<&mut Vec<u8>> {
+        match &mut self.node_mut(inode)?.data {
+            Data::File(data) => Ok(data),
+            Data::Dir(_) => ioerr!(Other, "not a regular file")
+        }
+    }
+
+    /// Returns the metadata of the node `inode`, which is named `name`.
+    pub(crate) fn metadata(&self, inode: u64, name: &str) -> io::Result<Metadata> {
+        let node = self.node(inode)?;
+        Ok(Metadata {
+            inode,
+            size: match &node.data {
+                Data::File(data) => data.len() as u64,
+                Data::Dir(_) => 0,
+            },
+            created: node.created,
+            accessed: node.accessed,
+            modified: node.modified,
+            dot_file: name.starts_with('.'),
+        })
+    }
+
+    /// Returns the inode number of the entry named `name` in the directory
+    /// `dir`.
+    pub(crate) fn lookup(&self, dir: u64, name: &str) -> io::Result<u64> {
+        match self.children(dir)?.get(name) {
+            Some(&inode) => Ok(inode),
+            None => ioerr!(NotFound, "Entry not found in directory")
+        }
+    }
+
+    /// Returns the names and inode numbers of the entries in the directory
+    /// `dir`, sorted by name.
+    pub(crate) fn list(&self, dir: u64) -> io::Result<Vec<(String, u64)>> {
+        Ok(self.children(dir)?.iter().map(|(name, &inode)| (name.clone(), inode)).collect())
+    }
+
+    /// Returns the inode number of the directory holding `inode`.
+    pub(crate) fn parent(&self, inode: u64) -> io::Result<u64> {
+        Ok(self.node(inode)?.parent)
+    }
+
+    /// Creates a node holding `data` named `name` in the directory `dir` and
+    /// returns its inode number.
+    pub(crate) fn insert(&mut self, dir: u64, name: &str, data: Data) -> io::Result<u64> {
+        check_name(name)?;
+        if self.children(dir)?.contains_key(name) {
+            return ioerr!(AlreadyExists, "entry already exists");
+        }
+
+        let (inode, now) = (self.next_inode, self.now());
+        self.next_inode += 1;
+        self.nodes.insert(inode, Node { parent: dir, data, created: now, accessed: now, modified: now });
+        self.children_mut(dir)?.insert(String::from(name), inode);
+        self.node_mut(dir)?.modified = now;
+        Ok(inode)
+    }
+
+    /// Removes the entry named `name` from the directory `dir`. Directories
+    /// must be empty.
+    pub(crate) fn remove(&mut self, dir: u64, name: &str) -> io::Result<()> {
+        let inode = self.lookup(dir, name)?;
+        let freed = match &self.node(inode)?.data {
+            Data::File(data) => data.len() as u64,
+            Data::Dir(children) if children.is_empty() => 0,
+            Data::Dir(_) => return ioerr!(Other, "directory not empty")
+        };
+
+        self.children_mut(dir)?.remove(name);
+        self.nodes.remove(&inode);
+        self.used -= freed;
+        let now = self.now();
+        self.node_mut(dir)?.modified = now;
+        Ok(())
+    }
+
+    /// Moves the entry named `name` in the directory `dir` into the directory
+    /// `to`, naming it `new_name`.
+    pub(crate) fn rename(&mut self, dir: u64, name: &str, to: u64, new_name: &str) -> io::Result<()> {
+        check_name(new_name)?;
+        let inode = self.lookup(dir, name)?;
+        if self.children(to)?.contains_key(new_name) {
+            return ioerr!(AlreadyExists, "entry already exists");
+        }
+
+        let mut ancestor = to;
+        loop {
+            if ancestor == inode {
+                return ioerr!(InvalidInput, "cannot move a directory into itself");
+            }
+            if ancestor == ROOT_INODE {
+                break;
+            }
+            ancestor = self.parent(ancestor)?;
+        }
+
+        self.children_mut(dir)?.remove(name);
+        self.children_mut(to)?.insert(String::from(new_name), inode);
+        let now = self.now();
+        self.node_mut(inode)?.parent = to;
+        self.node_mut(dir)?.modified = now;
+        self.node_mut(to)?.modified = now;
+        Ok(())
+    }
+
+    /// Returns the size in bytes of the file `inode`.
+    pub(crate) fn size(&self, inode: u64) -> io::Result<u64> {
+        match &self.node(inode)?.data {
+            Data::File(data) => Ok(data.len() as u64),
+            Data::Dir(_) => ioerr!(Other, "not a regular file")
+        }
+    }
+
+    /// Reads the data of the file `inode` from byte `offset` into `buf`.
+    pub(crate) fn read(&mut self, inode: u64, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.data_mut(inode)?;
+        let start = min(offset, data.len() as u64) as usize;
+        let n = min(buf.len(), data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+
+        let now = self.now();
+        self.node_mut(inode)?.accessed = now;
+        Ok(n)
+    }
+
+    /// Writes `buf` to the file `inode` at byte `offset`, filling any gap
+    /// past the end of the file with zeroes.
+    pub(crate) fn write(&mut self, inode: u64, offset: u64, buf: &[u8]) -> io::Result<usize> {
+        let end = offset + buf.len() as u64;
+        if end > self.size(inode)? {
+            self.truncate(inode, end)?;
+        }
+
+        let data = self.data_mut(inode)?;
+        data[offset as usize..end as usize].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    /// Truncates or extends the file `inode` to `size` bytes. Extended space
+    /// is filled with zeroes.
+    pub(crate) fn truncate(&mut self, inode: u64, size: u64) -> io::Result<()> {
+        let old_size = self.size(inode)?;
+        if size > old_size && size - old_size > self.capacity - self.used {
+            return ioerr!(Other, "no space left on volume");
+        }
+
+        self.data_mut(inode)?.resize(size as usize, 0);
+        self.used = self.used + size - old_size;
+        let now = self.now();
+        self.node_mut(inode)?.modified = now;
+        Ok(())
+    }
+
+    /// Returns the inode number and name of the entry at `path`, which must
+    /// be absolute.
+    fn walk(&self, path: &Path) -> io::Result<(u64, String)> {
+        if !path.is_absolute() {
+            return ioerr!(InvalidInput, "path is not absolute");
+        }
+
+        let mut names = vec![String::from("root")];
+        let mut inode = ROOT_INODE;
+        for comp in path.components() {
+            match comp {
+                Component::Normal(name) => {
+                    let name = match name.to_str() {
+                        Some(name) => name,
+                        None => return ioerr!(InvalidInput, "name contained invalid utf8")
+                    };
+                    inode = self.lookup(inode, name)?;
+                    names.push(String::from(name));
+                }
+                Component::ParentDir => {
+                    inode = self.parent(inode)?;
+                    if names.len() > 1 {
+                        names.pop();
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok((inode, names.pop().unwrap_or_default()))
+    }
+}
+
+/// Checks that `name` can name an entry: it is not empty, `.` or `..`, and
+/// holds no path separator.
+fn check_name(name: &str) -> io::Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return ioerr!(InvalidInput, "invalid file name");
+    }
+    Ok(())
+}
+
+/// Returns the entry of `tmpfs` numbered `inode`, which is named `name`.
+pub(crate) fn entry<HANDLE: TmpFsHandle>(tmpfs: &HANDLE, inode: u64, name: &str) -> io::Result<Entry<HANDLE>> {
+    let (metadata, is_dir) = tmpfs.lock(|tmpfs: &mut TmpFs<HANDLE>| -> io::Result<_> {
+        let is_dir = match tmpfs.node(inode)?.data {
+            Data::Dir(_) => true,
+            Data::File(_) => false,
+        };
+        Ok((tmpfs.metadata(inode, name)?, is_dir))
+    })?;
+
+    let (tmpfs, name) = (tmpfs.clone(), String::from(name));
+    Ok(match is_dir {
+        true => Entry::Dir(Dir { tmpfs, name, metadata, inode }),
+        false => Entry::File(File { tmpfs, name, metadata, inode, current_offset: 0 }),
+    })
+}
+
+impl<'a, HANDLE: TmpFsHandle> FileSystem for &'a TmpFsVolume<HANDLE> {
+    type File = File<HANDLE>;
+    type Dir = Dir<HANDLE>;
+    type Entry = Entry<HANDLE>;
+
+    fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
+        let path = path.as_ref();
+        let (inode, name) = self.lock(|tmpfs: &mut TmpFs<HANDLE>| tmpfs.walk(path))?;
+        entry(&self.0, inode, &name)
+    }
+
+    fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self::File> {
+        let (parent, name) = split_parent(path.as_ref())?;
+        self.open_dir(parent)?.create_file(name)
+    }
+
+    fn create_dir<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Dir> {
+        let (parent, name) = split_parent(path.as_ref())?;
+        self.open_dir(parent)?.create_dir(name)
+    }
+
+    fn remove<P: AsRef<Path>>(self, path: P) -> io::Result<()> {
+        let (parent, name) = split_parent(path.as_ref())?;
+        self.open_dir(parent)?.remove(name)
+    }
+
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, from: P, to: Q) -> io::Result<()> {
+        let (from_parent, from_name) = split_parent(from.as_ref())?;
+        let (to_parent, to_name) = split_parent(to.as_ref())?;
+        let to_dir = self.open_dir(to_parent)?;
+        self.open_dir(from_parent)?.rename(from_name, &to_dir, to_name)
+    }
+}
@@ -20,6 +20,25 @@ pub trait BlockDevice: Send {
     /// Returns an error if seeking or reading from `self` fails.
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize>;
 
+    /// Reads consecutive sectors starting at sector `n` into `buf`, as many
+    /// as fit in it completely.
+    ///
+    /// Devices that can transfer several sectors with a single command should
+    /// override this; by default, the sectors are read one at a time. The
+    /// number of bytes read is returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if seeking or reading from `self` fails.
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let sector_size = self.sector_size() as usize;
+        let mut read = 0;
+        for (i, chunk) in buf.chunks_exact_mut(sector_size).enumerate() {
+            read += self.read_sector(n + i as u64, chunk)?;
+        }
+        Ok(read)
+    }
+
     /// Append sector number `n` into `vec`.
     ///
     /// `self.sector_size()` bytes are appended to `vec`. The number of bytes
@@ -63,6 +82,10 @@ impl<'a, T: BlockDevice> BlockDevice for &'a mut T {
         (*self).read_sector(n, buf)
     }
 
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        (*self).read_sectors(n, buf)
+    }
+
     fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
         (*self).write_sector(n, buf)
     }
@@ -80,6 +103,14 @@ macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
             Ok(to_read)
         }
 
+        fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+            let sector_size = self.sector_size();
+            let to_read = buf.len() - buf.len() % sector_size as usize;
+            self.seek(io::SeekFrom::Start(n * sector_size))?;
+            self.read_exact(&mut buf[..to_read])?;
+            Ok(to_read)
+        }
+
         fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
             let sector_size = self.sector_size();
             let to_write = ::core::cmp::min(sector_size as usize, buf.len());
@@ -93,5 +124,5 @@ macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
 impl_for_read_write_seek!(<'a> shim::io::Cursor<&'a mut [u8]>);
 impl_for_read_write_seek!(shim::io::Cursor<Vec<u8>>);
 impl_for_read_write_seek!(shim::io::Cursor<Box<[u8]>>);
-#[cfg(test)]
+#[cfg(not(feature = "no_std"))]
 impl_for_read_write_seek!(::std::fs::File);
@@ -66,6 +66,11 @@ pub trait Entry: Sized {
 }
 
 /// Trait implemented by file systems.
+///
+/// FAT32 volumes implement it for references to their `VFatHandle`. Every
+/// other file system implements it for references to a wrapper around its
+/// handle instead, since a blanket implementation for its handle type would
+/// overlap with the one for `VFatHandle`s.
 pub trait FileSystem: Sized {
     /// The type of files in this file system.
     type File: File;
@@ -113,4 +118,66 @@ pub trait FileSystem: Sized {
             .into_dir()
             .ok_or(io::Error::new(io::ErrorKind::Other, "not a directory"))
     }
+
+    /// Creates a new, empty file at `path`. `path` must be absolute.
+    ///
+    /// # Errors
+    ///
+    /// If the parent of `path` is not an existing directory, an error kind
+    /// of `NotFound` is returned.
+    ///
+    /// If an entry already exists at `path`, an error kind of `AlreadyExists`
+    /// is returned.
+    ///
+    /// File systems that cannot be written to return an error kind of
+    /// `PermissionDenied`, which is the default.
+    fn create_file<P: AsRef<Path>>(self, _path: P) -> io::Result<Self::File> {
+        Err(io::Error::new(io::ErrorKind::PermissionDenied, "read-only file system"))
+    }
+
+    /// Creates a new, empty directory at `path`. `path` must be absolute.
+    ///
+    /// # Errors
+    ///
+    /// The same as for `create_file()`.
+    fn create_dir<P: AsRef<Path>>(self, _path: P) -> io::Result<Self::Dir> {
+        Err(io::Error::new(io::ErrorKind::PermissionDenied, "read-only file system"))
+    }
+
+    /// Removes the file or empty directory at `path`. `path` must be
+    /// absolute.
+    ///
+    /// # Errors
+    ///
+    /// If there is no entry at `path`, an error kind of `NotFound` is
+    /// returned.
+    ///
+    /// If `path` refers to a directory that is not empty, an error kind of
+    /// `Other` is returned.
+    ///
+    /// File systems that cannot be written to return an error kind of
+    /// `PermissionDenied`, which is the default.
+    fn remove<P: AsRef<Path>>(self, _path: P) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::PermissionDenied, "read-only file system"))
+    }
+
+    /// Moves the entry at `from` to `to`. Both paths must be absolute. The
+    /// entry's contents are not copied.
+    ///
+    /// # Errors
+    ///
+    /// If there is no entry at `from`, or the parent of `to` is not an
+    /// existing directory, an error kind of `NotFound` is returned.
+    ///
+    /// If an entry already exists at `to`, an error kind of `AlreadyExists` is
+    /// returned.
+    ///
+    /// If `from` is a directory and `to` lies inside of it, an error kind of
+    /// `InvalidInput` is returned.
+    ///
+    /// File systems that cannot be written to return an error kind of
+    /// `PermissionDenied`, which is the default.
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, _from: P, _to: Q) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::PermissionDenied, "read-only file system"))
+    }
 }
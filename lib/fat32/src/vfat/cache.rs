@@ -1,4 +1,5 @@
 use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use alloc::vec::Vec;
 use core::fmt;
 use hashbrown::HashMap;
@@ -6,11 +7,35 @@ use shim::io;
 use shim::ioerr;
 
 use crate::traits::BlockDevice;
+use crate::vfat::journal::Journal;
 
 #[derive(Debug)]
 struct CacheEntry {
     data: Vec<u8>,
     dirty: bool,
+    /// Whether the sector holds file system metadata, which is journaled.
+    metadata: bool,
+    /// Value of the cache's clock the last time this entry was accessed.
+    last_used: u64,
+}
+
+/// The number of sectors a `CachedPartition` holds unless configured
+/// otherwise: 512KiB worth of 512 byte sectors.
+pub const DEFAULT_CACHE_CAPACITY: usize = 1024;
+
+/// Counters describing how effective a `CachedPartition` has been.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Accesses served from the cache.
+    pub hits: u64,
+    /// Accesses that had to read the sector from the device.
+    pub misses: u64,
+    /// Sectors dropped from the cache to make room for others.
+    pub evictions: u64,
+    /// Dirty sectors written back to the device, on eviction or flush.
+    pub writebacks: u64,
+    /// Sectors read ahead of their first access by `prefetch()`.
+    pub prefetched: u64,
 }
 
 pub struct Partition {
@@ -25,6 +50,15 @@ pub struct Partition {
 pub struct CachedPartition {
     device: Box<dyn BlockDevice>,
     cache: HashMap<u64, CacheEntry>,
+    /// The cached sectors by the `last_used` value of their entries, oldest
+    /// first.
+    recency: BTreeMap<u64, u64>,
+    /// The number of cached sectors that are dirty and hold metadata.
+    dirty_metadata: usize,
+    capacity: usize,
+    clock: u64,
+    stats: CacheStats,
+    journal: Option<Journal>,
     pub partition: Partition,
 }
 
@@ -42,22 +76,85 @@ impl CachedPartition {
     /// `partition.sector_size` must be an integer multiple of
     /// `device.sector_size()`.
     ///
+    /// At most `DEFAULT_CACHE_CAPACITY` sectors are cached at once; see
+    /// `with_capacity()`.
+    ///
     /// # Panics
     ///
     /// Panics if the partition's sector size is < the device's sector size.
     pub fn new<T>(device: T, partition: Partition) -> CachedPartition
+    where
+        T: BlockDevice + 'static,
+    {
+        CachedPartition::with_capacity(device, partition, DEFAULT_CACHE_CAPACITY)
+    }
+
+    /// Like `new()`, but caches at most `capacity` sectors. When the cache is
+    /// full, the least recently used sector is evicted, and written back to
+    /// the device first if it is dirty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the partition's sector size is < the device's sector size or
+    /// if `capacity` is 0.
+    pub fn with_capacity<T>(device: T, partition: Partition, capacity: usize) -> CachedPartition
     where
         T: BlockDevice + 'static,
     {
         assert!(partition.sector_size >= device.sector_size());
+        assert!(capacity > 0);
 
         CachedPartition {
             device: Box::new(device),
             cache: HashMap::new(),
+            recency: BTreeMap::new(),
+            dirty_metadata: 0,
+            capacity: capacity,
+            clock: 0,
+            stats: CacheStats::default(),
+            journal: None,
             partition: partition,
         }
     }
 
+    /// Returns the maximum number of sectors held in the cache.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Changes the maximum number of sectors held in the cache, evicting the
+    /// least recently used sectors if it currently holds more. Dirty metadata
+    /// sectors of a journaled volume stay cached until the next `flush()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing back an evicted dirty sector fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn set_capacity(&mut self, capacity: usize) -> io::Result<()> {
+        assert!(capacity > 0);
+        self.capacity = capacity;
+        while self.cache.len() > self.capacity && self.evict_lru()? {}
+        Ok(())
+    }
+
+    /// Returns the number of sectors currently cached.
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Returns the cache's hit, miss, eviction and write-back counters.
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Resets all of the cache's counters to zero.
+    pub fn reset_stats(&mut self) {
+        self.stats = CacheStats::default();
+    }
+
     /// Returns the number of physical sectors that corresponds to
     /// one logical sector.
     fn factor(&self) -> u64 {
@@ -67,7 +164,7 @@ impl CachedPartition {
     /// Maps a user's request for a sector `virt` to the physical sector.
     /// Returns `None` if the virtual sector number is out of range.
     fn virtual_to_physical(&self, virt: u64) -> Option<u64> {
-        let new_virt = virt - self.partition.start;
+        let new_virt = virt.checked_sub(self.partition.start)?;
         if new_virt >= self.partition.num_sectors {
             return None;
         }
@@ -89,17 +186,50 @@ impl CachedPartition {
     ///
     /// Returns an error if there is an error reading the sector from the disk.
     pub fn get_mut(&mut self, sector: u64) -> io::Result<&mut [u8]> {
+        self.reserve_journal(sector, false)?;
         self.insert_if_not_exists(sector)?;
 
         let entry = match self.cache.get_mut(&sector) {
             Some(e) => e,
             None => return ioerr!(NotFound, "sector not found in cache in get_mut (this should never happen)")
         };
+        if entry.metadata && !entry.dirty {
+            self.dirty_metadata += 1;
+        }
         entry.dirty = true;
 
         Ok(&mut entry.data)
     }
 
+    /// Like `get_mut()`, but marks the sector as holding metadata. While a
+    /// journal is set, dirty metadata sectors are only written in place once
+    /// they have been committed to the journal.
+    ///
+    /// If the journal has no room left for another sector, the cache is
+    /// flushed first, committing the metadata changes made so far as a
+    /// transaction of their own.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is an error reading the sector from the disk
+    /// or flushing the cache.
+    pub fn get_mut_metadata(&mut self, sector: u64) -> io::Result<&mut [u8]> {
+        self.reserve_journal(sector, true)?;
+        self.insert_if_not_exists(sector)?;
+
+        let entry = match self.cache.get_mut(&sector) {
+            Some(e) => e,
+            None => return ioerr!(NotFound, "sector not found in cache in get_mut_metadata (this should never happen)")
+        };
+        if !(entry.metadata && entry.dirty) {
+            self.dirty_metadata += 1;
+        }
+        entry.dirty = true;
+        entry.metadata = true;
+
+        Ok(&mut entry.data)
+    }
+
     /// Returns a reference to the cached sector `sector`. If the sector is not
     /// already cached, the sector is first read from the disk.
     ///
@@ -117,26 +247,307 @@ impl CachedPartition {
         Ok(&entry.data)
     }
 
+    /// Writes every dirty cached sector back to the underlying device and
+    /// marks it clean.
+    ///
+    /// While a journal is set, sectors not holding metadata are written
+    /// first. Dirty metadata sectors are then committed to the journal as a
+    /// single transaction, which is written in place before the journal is
+    /// cleared.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing a sector to the disk fails. Sectors that
+    /// were not written remain dirty.
+    ///
+    /// Returns an error of kind `Other` without writing any metadata in place
+    /// if the dirty metadata sectors do not fit in the journal.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let journaling = self.journal.is_some();
+        for sector in self.dirty_sectors(|entry| !(journaling && entry.metadata)) {
+            self.write_back(sector)?;
+        }
+
+        let mut journal = match self.journal.take() {
+            Some(journal) => journal,
+            None => return Ok(())
+        };
+        let result = self.checkpoint(&mut journal);
+        self.journal = Some(journal);
+        result?;
+
+        // dirty metadata may have kept the cache past its capacity
+        while self.cache.len() > self.capacity && self.evict_lru()? {}
+        Ok(())
+    }
+
+    /// Flushes the cache if writing to `sector`, as metadata if `metadata` is
+    /// `true`, would add a sector to a transaction the journal has no room
+    /// left for. Dirty metadata is otherwise never evicted, so without this
+    /// it could outgrow both the journal and the cache.
+    fn reserve_journal(&mut self, sector: u64, metadata: bool) -> io::Result<()> {
+        let capacity = match &self.journal {
+            Some(journal) => journal.capacity(),
+            None => return Ok(())
+        };
+        let (dirty, cached_metadata) = self.cache.get(&sector)
+            .map_or((false, false), |entry| (entry.dirty, entry.metadata));
+
+        let adds_sector = (metadata || cached_metadata) && !(dirty && cached_metadata);
+        match adds_sector && self.dirty_metadata >= capacity {
+            true => self.flush(),
+            false => Ok(())
+        }
+    }
+
+    /// Returns the sorted numbers of the dirty sectors for which `filter`
+    /// returns `true`.
+    fn dirty_sectors(&self, filter: impl Fn(&CacheEntry) -> bool) -> Vec<u64> {
+        let mut dirty: Vec<u64> = self.cache.iter()
+            .filter(|(_, entry)| entry.dirty && filter(entry))
+            .map(|(&sector, _)| sector)
+            .collect();
+        dirty.sort();
+        dirty
+    }
+
+    /// Writes the dirty metadata sectors back through `journal`.
+    fn checkpoint(&mut self, journal: &mut Journal) -> io::Result<()> {
+        let dirty = self.dirty_sectors(|entry| entry.metadata);
+        if dirty.is_empty() {
+            return Ok(());
+        } else if dirty.len() > journal.capacity() {
+            return ioerr!(Other, "metadata changes do not fit in the journal");
+        }
+
+        journal.commit(self, &dirty)?;
+        for &sector in dirty.iter() {
+            self.write_back(sector)?;
+        }
+        journal.clear(self)
+    }
+
+    /// Sets the journal dirty metadata sectors are committed to before they
+    /// are written in place, or turns journaling off if `journal` is `None`.
+    pub(crate) fn set_journal(&mut self, journal: Option<Journal>) {
+        self.journal = journal;
+    }
+
+    /// Returns the journal set with `set_journal()`.
+    pub(crate) fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    /// Returns the cached contents of `sector`, if it is cached.
+    pub(crate) fn cached(&self, sector: u64) -> Option<&[u8]> {
+        self.cache.get(&sector).map(|entry| &entry.data[..])
+    }
+
+    /// Reads `sector` from the device into `buf`, bypassing the cache. A
+    /// cached copy of the sector is dropped, unless it is dirty.
+    pub(crate) fn read_through(&mut self, sector: u64, buf: &mut [u8]) -> io::Result<()> {
+        let physical_sector = match self.virtual_to_physical(sector) {
+            Some(s) => s,
+            None => return ioerr!(NotFound, "virtual sector number out of range")
+        };
+        if self.cache.get(&sector).map_or(false, |entry| entry.dirty) {
+            buf.copy_from_slice(&self.cache[&sector].data);
+            return Ok(());
+        }
+        self.remove(sector);
+
+        let physical_sector_size = self.device.sector_size() as usize;
+        for (i, chunk) in buf.chunks_mut(physical_sector_size).enumerate() {
+            self.device.read_sector(physical_sector + i as u64, chunk)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `buf` to `sector` of the device, bypassing the cache. A cached
+    /// copy of the sector is dropped.
+    pub(crate) fn write_through(&mut self, sector: u64, buf: &[u8]) -> io::Result<()> {
+        let physical_sector = match self.virtual_to_physical(sector) {
+            Some(s) => s,
+            None => return ioerr!(NotFound, "virtual sector number out of range")
+        };
+        self.remove(sector);
+
+        let physical_sector_size = self.device.sector_size() as usize;
+        for (i, chunk) in buf.chunks(physical_sector_size).enumerate() {
+            self.device.write_sector(physical_sector + i as u64, chunk)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the cached sector `sector` to the device if it is dirty and
+    /// marks it clean.
+    fn write_back(&mut self, sector: u64) -> io::Result<()> {
+        let physical_sector = match self.virtual_to_physical(sector) {
+            Some(s) => s,
+            None => return ioerr!(NotFound, "virtual sector number out of range")
+        };
+        let physical_sector_size = self.device.sector_size();
+        let factor = self.factor();
+        let entry = match self.cache.get_mut(&sector) {
+            Some(e) if e.dirty => e,
+            _ => return Ok(())
+        };
+
+        for i in 0..factor {
+            let start_idx = (i * physical_sector_size) as usize;
+            let end_idx = start_idx + physical_sector_size as usize;
+            self.device.write_sector(physical_sector + i, &entry.data[start_idx..end_idx])?;
+        }
+        entry.dirty = false;
+        if entry.metadata {
+            self.dirty_metadata -= 1;
+        }
+        self.stats.writebacks += 1;
+
+        Ok(())
+    }
+
+    /// Removes the least recently used sector from the cache, writing it back
+    /// first if it is dirty. While a journal is set, dirty metadata sectors
+    /// are skipped: they may only reach the disk as part of a transaction
+    /// committed by `flush()`. If every cached sector is dirty metadata,
+    /// nothing is evicted and the cache grows past its capacity.
+    ///
+    /// Returns whether a sector was evicted.
+    fn evict_lru(&mut self) -> io::Result<bool> {
+        let journaling = self.journal.is_some();
+        let cache = &self.cache;
+        let victim = self.recency.values()
+            .find(|sector| !(journaling && cache[sector].dirty && cache[sector].metadata))
+            .cloned();
+        let victim = match victim {
+            Some(sector) => sector,
+            None => return Ok(false)
+        };
+
+        self.write_back(victim)?;
+        self.remove(victim);
+        self.stats.evictions += 1;
+        Ok(true)
+    }
+
+    /// Caches `data` as the contents of `sector`, used at the current time of
+    /// the clock.
+    fn insert(&mut self, sector: u64, data: Vec<u8>) {
+        self.remove(sector);
+        self.cache.insert(sector, CacheEntry { dirty: false, metadata: false, data, last_used: self.clock });
+        self.recency.insert(self.clock, sector);
+    }
+
+    /// Drops the cached copy of `sector`, if any, without writing it back.
+    fn remove(&mut self, sector: u64) {
+        if let Some(entry) = self.cache.remove(&sector) {
+            self.recency.remove(&entry.last_used);
+            if entry.dirty && entry.metadata {
+                self.dirty_metadata -= 1;
+            }
+        }
+    }
+
+    /// Reads the `count` sectors starting at sector `sector` into the cache,
+    /// unless they are cached already, so that later accesses to them are
+    /// hits. Runs of uncached sectors are read with a single call to
+    /// `BlockDevice::read_sectors()`. Sectors past the end of the partition
+    /// are ignored and at most half of the cache's capacity is filled.
+    ///
+    /// Prefetched sectors count as used at the time of the prefetch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading from the device or writing back an
+    /// evicted dirty sector fails.
+    pub fn prefetch(&mut self, sector: u64, count: u64) -> io::Result<()> {
+        let end = match sector.checked_sub(self.partition.start) {
+            Some(offset) if offset < self.partition.num_sectors => {
+                sector + core::cmp::min(count, self.partition.num_sectors - offset)
+            },
+            _ => return Ok(())
+        };
+        let end = core::cmp::min(end, sector + (self.capacity as u64 / 2));
+
+        let mut run_start = sector;
+        while run_start < end {
+            if self.cache.contains_key(&run_start) {
+                run_start += 1;
+                continue;
+            }
+
+            let mut run_end = run_start + 1;
+            while run_end < end && !self.cache.contains_key(&run_end) {
+                run_end += 1;
+            }
+            self.read_run(run_start, run_end)?;
+            run_start = run_end;
+        }
+
+        Ok(())
+    }
+
+    /// Reads the uncached sectors `start..end` from the device with a single
+    /// multi-sector read and inserts them into the cache.
+    fn read_run(&mut self, start: u64, end: u64) -> io::Result<()> {
+        let physical_sector = match self.virtual_to_physical(start) {
+            Some(s) => s,
+            None => return ioerr!(NotFound, "virtual sector number out of range")
+        };
+        let sector_size = self.partition.sector_size as usize;
+        let mut data = vec![0u8; (end - start) as usize * sector_size];
+        self.device.read_sectors(physical_sector, &mut data)?;
+
+        for (i, chunk) in data.chunks(sector_size).enumerate() {
+            if self.cache.len() >= self.capacity {
+                self.evict_lru()?;
+            }
+            self.clock += 1;
+            self.insert(start + i as u64, chunk.to_vec());
+            self.stats.prefetched += 1;
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if any cached sector has been modified but not yet
+    /// flushed to the device.
+    pub fn is_dirty(&self) -> bool {
+        self.cache.values().any(|entry| entry.dirty)
+    }
+
     /// Reads a logical sector from device and inserts it into the cache if 
     /// the sector is not already in the cache.
     fn insert_if_not_exists(&mut self, sector: u64) -> io::Result<()> {
         match self.virtual_to_physical(sector) {
             Some(physical_sector) => {
-                if !self.cache.contains_key(&sector) {
-                    // create a buf to hold virtual sector
-                    let mut buf_vec: Vec<u8> = Vec::new();
-
-                    // read the physical sectors that map to the requested virtual sector
-                    for i in 0..self.factor() {
-                        let phys_start_idx = (i * self.device.sector_size()) as usize;
-                        let phys_end_idx = phys_start_idx + self.device.sector_size() as usize;
-                        buf_vec.resize(buf_vec.len() + self.device.sector_size() as usize, 0);
-                        self.device.read_sector(physical_sector + i, &mut buf_vec[phys_start_idx..phys_end_idx])?;
-                    }
-
-                    // insert virtual sector in to cache
-                    self.cache.insert(sector, CacheEntry { dirty: false, data: buf_vec });
-                }             
+                self.clock += 1;
+                if let Some(entry) = self.cache.get_mut(&sector) {
+                    self.recency.remove(&entry.last_used);
+                    self.recency.insert(self.clock, sector);
+                    entry.last_used = self.clock;
+                    self.stats.hits += 1;
+                    return Ok(());
+                }
+
+                self.stats.misses += 1;
+                if self.cache.len() >= self.capacity {
+                    self.evict_lru()?;
+                }
+
+                // create a buf to hold virtual sector
+                let mut buf_vec: Vec<u8> = Vec::new();
+
+                // read the physical sectors that map to the requested virtual sector
+                for i in 0..self.factor() {
+                    let phys_start_idx = (i * self.device.sector_size()) as usize;
+                    let phys_end_idx = phys_start_idx + self.device.sector_size() as usize;
+                    buf_vec.resize(buf_vec.len() + self.device.sector_size() as usize, 0);
+                    self.device.read_sector(physical_sector + i, &mut buf_vec[phys_start_idx..phys_end_idx])?;
+                }
+
+                // insert virtual sector in to cache
+                self.insert(sector, buf_vec);
                 return Ok(());
             },
             None => return ioerr!(NotFound, "virtual sector number out of range")
@@ -186,7 +597,10 @@ impl fmt::Debug for CachedPartition {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("CachedPartition")
             .field("device", &"<block device>")
-            .field("cache", &self.cache)
+            .field("capacity", &self.capacity)
+            .field("cached", &self.cache.len())
+            .field("stats", &self.stats)
+            .field("journaling", &self.journal.is_some())
             .finish()
     }
 }
@@ -1,5 +1,6 @@
 use alloc::string::String;
 use alloc::vec::Vec;
+use core::mem;
 
 use shim::const_assert_size;
 use shim::ffi::OsStr;
@@ -9,14 +10,29 @@ use shim::ioerr;
 use crate::traits;
 use crate::util::VecExt;
 use crate::vfat::{Attributes, Date, Metadata, Time, Timestamp};
-use crate::vfat::{Cluster, Entry, File, VFatHandle, VFat};
+use crate::vfat::{Cluster, Entry, ExtentMap, File, VFatHandle, VFat};
+use crate::vfat::name;
 
 #[derive(Debug)]
 pub struct Dir<HANDLE: VFatHandle> {
     pub vfat: HANDLE,
     pub start_cluster: Cluster,
     pub name: String,
-    pub metadata: Metadata
+    pub metadata: Metadata,
+    /// Where this directory's entry lives in its parent. `None` for the root.
+    pub location: Option<EntryLocation>
+}
+
+/// The position of an entry's on-disk records inside its parent directory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EntryLocation {
+    /// First cluster of the parent directory.
+    pub dir_cluster: Cluster,
+    /// Index of the first 32-byte record belonging to the entry. This is the
+    /// first LFN record if the entry has a long name.
+    pub first_index: usize,
+    /// Index of the regular (8.3) record of the entry.
+    pub index: usize,
 }
 
 #[repr(C, packed)]
@@ -67,9 +83,119 @@ const_assert_size!(VFatUnknownDirEntry, 32);
 
 #[derive(Copy, Clone)]
 pub union VFatDirEntry {
-    unknown: VFatUnknownDirEntry,
-    regular: VFatRegularDirEntry,
-    long_filename: VFatLfnDirEntry,
+    pub(crate) unknown: VFatUnknownDirEntry,
+    pub(crate) regular: VFatRegularDirEntry,
+    pub(crate) long_filename: VFatLfnDirEntry,
+}
+
+const_assert_size!(VFatDirEntry, 32);
+
+/// Attribute bit marking an entry as a subdirectory.
+pub(crate) const ATTR_DIRECTORY: u8 = 0x10;
+/// Attribute bit marking an entry as modified since the last backup.
+pub(crate) const ATTR_ARCHIVE: u8 = 0x20;
+/// Attribute value marking a record as part of a long file name.
+pub(crate) const ATTR_LFN: u8 = 0x0F;
+/// The maximum number of 32-byte records in a directory.
+const MAX_DIR_ENTRIES: usize = 65536;
+/// First byte of a record that has been deleted and may be reused.
+pub(crate) const DELETED_MARKER: u8 = 0xE5;
+
+impl VFatRegularDirEntry {
+    /// Returns a new entry for `short_name`, stamped with `now`. The name is
+    /// the 8 byte base name followed by the 3 byte extension, both space
+    /// padded.
+    pub fn new(short_name: [u8; 11], attributes: Attributes, cluster: Cluster, now: Timestamp) -> VFatRegularDirEntry {
+        let mut filename = [0u8; 8];
+        let mut extension = [0u8; 3];
+        filename.copy_from_slice(&short_name[..8]);
+        extension.copy_from_slice(&short_name[8..]);
+        VFatRegularDirEntry {
+            filename,
+            extension,
+            attributes,
+            _r0: 0,
+            created_time_tenths: 0,
+            created_time: now.time,
+            created_date: now.date,
+            accessed_date: now.date,
+            cluster_high: (cluster.0 >> 16) as u16,
+            modified_time: now.time,
+            modified_date: now.date,
+            cluster_low: cluster.0 as u16,
+            size: 0,
+        }
+    }
+
+    /// The first cluster of the entry's data.
+    pub fn cluster(&self) -> Cluster {
+        Cluster((self.cluster_high as u32) << 16 | self.cluster_low as u32)
+    }
+
+    /// Points the entry at the chain beginning at `cluster`.
+    pub fn set_cluster(&mut self, cluster: Cluster) {
+        self.cluster_high = (cluster.0 >> 16) as u16;
+        self.cluster_low = cluster.0 as u16;
+    }
+
+    /// Builds the in-memory metadata for this entry, which is named `name`.
+    pub fn metadata(&self, name: String) -> Metadata {
+        Metadata {
+            filename: name,
+            extension: String::from(core::str::from_utf8(&self.extension).unwrap_or_default().trim_end()),
+            attributes: self.attributes,
+            created_timestamp: Timestamp { date: self.created_date, time: self.created_time },
+            accessed_date: Timestamp { date: self.accessed_date, time: Time::default() },
+            modified_timestamp: Timestamp { date: self.modified_date, time: self.modified_time },
+            size: self.size,
+            start_cluster: self.cluster()
+        }
+    }
+
+    /// Reads the regular record at `location`.
+    pub fn read<HANDLE: VFatHandle>(vfat: &mut VFat<HANDLE>, location: EntryLocation) -> io::Result<VFatRegularDirEntry> {
+        let raw = vfat.read_dir_entry(location.dir_cluster, location.index)?;
+        Ok(unsafe { VFatDirEntry::from_bytes(raw).regular })
+    }
+
+    /// Writes `self` to the regular record at `location`.
+    pub fn write<HANDLE: VFatHandle>(&self, vfat: &mut VFat<HANDLE>, location: EntryLocation) -> io::Result<()> {
+        vfat.write_dir_entry(location.dir_cluster, location.index, &VFatDirEntry { regular: *self }.to_bytes())
+    }
+}
+
+impl VFatDirEntry {
+    pub(crate) fn from_bytes(raw: [u8; 32]) -> VFatDirEntry {
+        unsafe { mem::transmute::<[u8; 32], VFatDirEntry>(raw) }
+    }
+
+    pub(crate) fn to_bytes(&self) -> [u8; 32] {
+        unsafe { mem::transmute::<VFatDirEntry, [u8; 32]>(*self) }
+    }
+}
+
+impl VFatLfnDirEntry {
+    /// Returns the LFN record holding the `seq_num`th (1-based) chunk of a
+    /// long name whose short name has checksum `checksum`. `last` marks the
+    /// final chunk, which is stored first on disk.
+    fn new(seq_num: u8, last: bool, chars: &[u16; name::LFN_CHARS_PER_ENTRY], checksum: u8) -> VFatLfnDirEntry {
+        let mut name_chars_1 = [0u16; 5];
+        let mut name_chars_2 = [0u16; 6];
+        let mut name_chars_3 = [0u16; 2];
+        name_chars_1.copy_from_slice(&chars[..5]);
+        name_chars_2.copy_from_slice(&chars[5..11]);
+        name_chars_3.copy_from_slice(&chars[11..]);
+        VFatLfnDirEntry {
+            seq_num: seq_num | if last { 0x40 } else { 0 },
+            name_chars_1,
+            attributes: ATTR_LFN,
+            vfat_type: 0,
+            name_checksum: checksum,
+            name_chars_2,
+            _r0: 0,
+            name_chars_3,
+        }
+    }
 }
 
 impl<HANDLE: VFatHandle> Dir<HANDLE> {
@@ -102,6 +228,405 @@ impl<HANDLE: VFatHandle> Dir<HANDLE> {
 
         ioerr!(NotFound, "Entry not found in directory")
     }
+
+    /// Creates an empty file named `name` in `self` and returns it.
+    ///
+    /// Names that are not upper-case 8.3 names are stored as long file names
+    /// alongside a generated `NAME~N.EXT` short name.
+    ///
+    /// # Errors
+    ///
+    /// If an entry named `name` already exists, an error of `AlreadyExists`
+    /// is returned.
+    ///
+    /// If `name` is not a valid file name, an error of `InvalidInput` is
+    /// returned.
+    ///
+    /// If `self` has no free entry left, an error of `Other` is returned.
+    pub fn create_file<P: AsRef<OsStr>>(&self, name: P) -> io::Result<File<HANDLE>> {
+        let (name, regular, location) = self.create_entry(name.as_ref(), Attributes(ATTR_ARCHIVE), Cluster(0))?;
+        let bytes_per_cluster = self.vfat.lock(|vfat: &mut VFat<HANDLE>| vfat.bytes_per_cluster() as u32);
+
+        Ok(File {
+            vfat: self.vfat.clone(),
+            start_cluster: Cluster(0),
+            current_cluster: None,
+            name: name.clone(),
+            metadata: regular.metadata(name),
+            current_offset: 0,
+            size: 0,
+            bytes_per_cluster: bytes_per_cluster,
+            location: location,
+            extents: ExtentMap::new(),
+            readahead: Default::default(),
+            entry_dirty: false
+        })
+    }
+
+    /// Writes a new entry named `name` into `self`, preceded by LFN records
+    /// if the name needs them, and returns the entry's name, its regular
+    /// record and where it was written.
+    fn create_entry(&self, name: &OsStr, attributes: Attributes, cluster: Cluster)
+        -> io::Result<(String, VFatRegularDirEntry, EntryLocation)>
+    {
+        let name = match name.to_str() {
+            Some(s) => s,
+            None => return ioerr!(InvalidInput, "name contained invalid utf8")
+        };
+        name::validate_long_name(name)?;
+        match self.find(name) {
+            Ok(_) => return ioerr!(AlreadyExists, "entry already exists"),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e)
+        };
+
+        let existing = self.short_names()?;
+        let (short_name, lfn_chunks) = match name::exact_short_name(name) {
+            Some(short_name) if !existing.contains(&short_name) => (short_name, Vec::new()),
+            _ => (self.generate_short_name(name, &existing)?, name::lfn_chunks(name))
+        };
+
+        let count = lfn_chunks.len() + 1;
+        let first_index = self.find_free_entries(count)?;
+        let location = EntryLocation {
+            dir_cluster: self.start_cluster,
+            first_index: first_index,
+            index: first_index + count - 1
+        };
+
+        let regular = self.vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<VFatRegularDirEntry> {
+            // LFN records are stored in reverse order, directly before the
+            // regular record they belong to
+            let checksum = name::lfn_checksum(&short_name);
+            for (i, chunk) in lfn_chunks.iter().enumerate().rev() {
+                let lfn = VFatLfnDirEntry::new((i + 1) as u8, i + 1 == lfn_chunks.len(), chunk, checksum);
+                let index = first_index + lfn_chunks.len() - 1 - i;
+                vfat.write_dir_entry(self.start_cluster, index, &VFatDirEntry { long_filename: lfn }.to_bytes())?;
+            }
+
+            let regular = VFatRegularDirEntry::new(short_name, attributes, cluster, vfat.now());
+            regular.write(vfat, location)?;
+            Ok(regular)
+        })?;
+
+        Ok((String::from(name), regular, location))
+    }
+
+    /// Picks a short name for the long name `name` that doesn't collide with
+    /// any name in `existing`, following the Windows `NAME~N.EXT` scheme.
+    fn generate_short_name(&self, name: &str, existing: &[[u8; 11]]) -> io::Result<[u8; 11]> {
+        let (basis, lossy) = name::basis_name(name);
+        if !lossy && !existing.contains(&basis) {
+            return Ok(basis);
+        }
+
+        for n in 1..1000000 {
+            let candidate = name::with_numeric_tail(&basis, n);
+            if !existing.contains(&candidate) {
+                return Ok(candidate);
+            }
+        }
+
+        ioerr!(AlreadyExists, "no unique short name available")
+    }
+
+    /// Returns the raw 8.3 names of all entries in `self`.
+    fn short_names(&self) -> io::Result<Vec<[u8; 11]>> {
+        let mut raw_data: Vec<u8> = Vec::new();
+        self.vfat.lock(|vfat: &mut VFat<HANDLE>| vfat.read_dir(self.start_cluster, &mut raw_data))?;
+
+        let mut names = Vec::new();
+        for record in raw_data.chunks(32) {
+            match (record[0], record[11]) {
+                (0x00, _) => break,
+                (DELETED_MARKER, _) | (_, ATTR_LFN) => continue,
+                _ => {
+                    let mut short_name = [0u8; 11];
+                    short_name.copy_from_slice(&record[..11]);
+                    names.push(short_name);
+                }
+            }
+        }
+        Ok(names)
+    }
+
+    /// Removes the file named `name` from `self` and frees its clusters.
+    ///
+    /// # Errors
+    ///
+    /// If no entry with name `name` exists in `self`, an error of `NotFound`
+    /// is returned.
+    ///
+    /// If the entry is a directory, an error of `Other` is returned.
+    ///
+    /// If the entry is the journal of a journaled volume, an error of
+    /// `PermissionDenied` is returned.
+    pub fn unlink<P: AsRef<OsStr>>(&self, name: P) -> io::Result<()> {
+        let file = match self.find(name)? {
+            Entry::File(f) => f,
+            Entry::Dir(_) => return ioerr!(Other, "entry is a directory")
+        };
+
+        self.vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<()> {
+            vfat.protect_journal(file.start_cluster)?;
+            if file.start_cluster.0 >= 2 {
+                vfat.free_chain(file.start_cluster)?;
+            }
+            delete_records(vfat, file.location)
+        })
+    }
+
+    /// Creates an empty directory named `name` in `self` and returns it. The
+    /// new directory contains only the `.` and `..` entries.
+    ///
+    /// # Errors
+    ///
+    /// Fails for the same reasons as `create_file()`, and with an error of
+    /// `Other` if there is no free cluster to hold the directory.
+    pub fn create_dir<P: AsRef<OsStr>>(&self, name: P) -> io::Result<Dir<HANDLE>> {
+        let cluster = self.vfat.lock(|vfat: &mut VFat<HANDLE>| vfat.alloc_cluster(None))?;
+        let (name, regular, location) = match self.create_entry(name.as_ref(), Attributes(ATTR_DIRECTORY), cluster) {
+            Ok(created) => created,
+            Err(e) => {
+                self.vfat.lock(|vfat: &mut VFat<HANDLE>| vfat.free_chain(cluster))?;
+                return Err(e);
+            }
+        };
+
+        self.vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<()> {
+            // `..` refers to the root directory as cluster 0
+            let parent_cluster = match self.start_cluster == vfat.rootdir_cluster {
+                true => Cluster(0),
+                false => self.start_cluster
+            };
+            let now = vfat.now();
+            let dot = VFatRegularDirEntry::new(*b".          ", Attributes(ATTR_DIRECTORY), cluster, now);
+            let dotdot = VFatRegularDirEntry::new(*b"..         ", Attributes(ATTR_DIRECTORY), parent_cluster, now);
+            vfat.write_dir_entry(cluster, 0, &VFatDirEntry { regular: dot }.to_bytes())?;
+            vfat.write_dir_entry(cluster, 1, &VFatDirEntry { regular: dotdot }.to_bytes())
+        })?;
+
+        Ok(Dir {
+            vfat: self.vfat.clone(),
+            start_cluster: cluster,
+            name: name.clone(),
+            metadata: regular.metadata(name),
+            location: Some(location)
+        })
+    }
+
+    /// Removes the file or empty directory named `name` from `self` and frees
+    /// its clusters.
+    ///
+    /// # Errors
+    ///
+    /// If no entry with name `name` exists in `self`, an error of `NotFound`
+    /// is returned.
+    ///
+    /// If `name` is `.` or `..`, an error of `InvalidInput` is returned.
+    ///
+    /// If the entry is a directory that is not empty, an error of `Other` is
+    /// returned.
+    ///
+    /// If the entry is the journal of a journaled volume, an error of
+    /// `PermissionDenied` is returned.
+    pub fn remove<P: AsRef<OsStr>>(&self, name: P) -> io::Result<()> {
+        use traits::Dir;
+        use traits::Entry as _;
+
+        let name = name.as_ref();
+        if name == "." || name == ".." {
+            return ioerr!(InvalidInput, "cannot remove '.' or '..'");
+        }
+
+        let (start_cluster, location) = match self.find(name)? {
+            Entry::File(f) => (f.start_cluster, f.location),
+            Entry::Dir(d) => {
+                if d.entries()?.any(|e| e.name() != "." && e.name() != "..") {
+                    return ioerr!(Other, "directory not empty");
+                }
+                match d.location {
+                    Some(location) => (d.start_cluster, location),
+                    None => return ioerr!(InvalidInput, "cannot remove the root directory")
+                }
+            }
+        };
+
+        self.vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<()> {
+            vfat.protect_journal(start_cluster)?;
+            if start_cluster.0 >= 2 {
+                vfat.free_chain(start_cluster)?;
+            }
+            delete_records(vfat, location)
+        })
+    }
+
+    /// Moves the entry named `name` in `self` into `to`, naming it `new_name`.
+    /// Only directory records are rewritten; the entry's data stays where it
+    /// is. The entry keeps its attributes, timestamps and size.
+    ///
+    /// # Errors
+    ///
+    /// If no entry with name `name` exists in `self`, an error of `NotFound`
+    /// is returned.
+    ///
+    /// If an entry named `new_name` already exists in `to`, an error of
+    /// `AlreadyExists` is returned.
+    ///
+    /// If either name is `.` or `..`, or a directory would be moved into
+    /// itself or one of its subdirectories, an error of `InvalidInput` is
+    /// returned.
+    ///
+    /// If the entry is the journal of a journaled volume, an error of
+    /// `PermissionDenied` is returned.
+    pub fn rename<P: AsRef<OsStr>, Q: AsRef<OsStr>>(&self, name: P, to: &Dir<HANDLE>, new_name: Q) -> io::Result<()> {
+        let (name, new_name) = (name.as_ref(), new_name.as_ref());
+        if name == "." || name == ".." || new_name == "." || new_name == ".." {
+            return ioerr!(InvalidInput, "cannot rename '.' or '..'");
+        }
+
+        let (start_cluster, location, is_dir) = match self.find(name)? {
+            Entry::File(f) => (f.start_cluster, f.location, false),
+            Entry::Dir(d) => match d.location {
+                Some(location) => (d.start_cluster, location, true),
+                None => return ioerr!(InvalidInput, "cannot rename the root directory")
+            }
+        };
+        if is_dir && to.is_within(start_cluster)? {
+            return ioerr!(InvalidInput, "cannot move a directory into itself");
+        }
+        self.vfat.lock(|vfat: &mut VFat<HANDLE>| vfat.protect_journal(start_cluster))?;
+
+        let old = self.vfat.lock(|vfat: &mut VFat<HANDLE>| VFatRegularDirEntry::read(vfat, location))?;
+        let old_records = self.vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<Vec<[u8; 32]>> {
+            (location.first_index..=location.index)
+                .map(|index| vfat.read_dir_entry(location.dir_cluster, index))
+                .collect()
+        })?;
+
+        // a rename that only changes case would collide with the entry itself,
+        // so its old records are released before the new ones are written
+        let same_entry = self.start_cluster == to.start_cluster
+            && name.to_str().map(|n| new_name.to_str().map_or(false, |m| n.eq_ignore_ascii_case(m))).unwrap_or(false);
+        if same_entry {
+            self.vfat.lock(|vfat: &mut VFat<HANDLE>| delete_records(vfat, location))?;
+        }
+
+        let created = to.create_entry(new_name, old.attributes, start_cluster);
+        let (_, mut regular, new_location) = match created {
+            Ok(created) => created,
+            Err(e) => {
+                if same_entry {
+                    self.vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<()> {
+                        for (i, raw) in old_records.iter().enumerate() {
+                            vfat.write_dir_entry(location.dir_cluster, location.first_index + i, raw)?;
+                        }
+                        Ok(())
+                    })?;
+                }
+                return Err(e);
+            }
+        };
+
+        self.vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<()> {
+            let (filename, extension) = (regular.filename, regular.extension);
+            regular = old;
+            regular.filename = filename;
+            regular.extension = extension;
+            regular.write(vfat, new_location)?;
+            if !same_entry {
+                delete_records(vfat, location)?;
+            }
+
+            if is_dir && self.start_cluster != to.start_cluster {
+                let parent_cluster = match to.start_cluster == vfat.rootdir_cluster {
+                    true => Cluster(0),
+                    false => to.start_cluster
+                };
+                let dotdot_location = EntryLocation { dir_cluster: start_cluster, first_index: 1, index: 1 };
+                let mut dotdot = VFatRegularDirEntry::read(vfat, dotdot_location)?;
+                dotdot.set_cluster(parent_cluster);
+                dotdot.write(vfat, dotdot_location)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Returns `true` if `self` is the directory starting at `cluster` or one
+    /// of its descendants.
+    fn is_within(&self, cluster: Cluster) -> io::Result<bool> {
+        self.vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<bool> {
+            let mut curr_cluster = self.start_cluster;
+            loop {
+                if curr_cluster == cluster {
+                    return Ok(true);
+                }
+                if curr_cluster == vfat.rootdir_cluster {
+                    return Ok(false);
+                }
+
+                let dotdot_location = EntryLocation { dir_cluster: curr_cluster, first_index: 1, index: 1 };
+                curr_cluster = match VFatRegularDirEntry::read(vfat, dotdot_location)?.cluster() {
+                    Cluster(0) => vfat.rootdir_cluster,
+                    parent => parent
+                };
+            }
+        })
+    }
+
+    /// Returns the index of the first of `count` consecutive unused records in
+    /// `self`, growing the directory's cluster chain if there is no such run.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `Other` if the directory would exceed the maximum
+    /// of 65536 records or the volume is out of free clusters, and if the
+    /// fixed root directory of a FAT12 or FAT16 volume is full.
+    fn find_free_entries(&self, count: usize) -> io::Result<usize> {
+        let mut raw_data: Vec<u8> = Vec::new();
+        self.vfat.lock(|vfat: &mut VFat<HANDLE>| vfat.read_dir(self.start_cluster, &mut raw_data))?;
+
+        let mut run = 0;
+        for (index, record) in raw_data.chunks(32).enumerate() {
+            run = if record[0] == 0x00 || record[0] == DELETED_MARKER { run + 1 } else { 0 };
+            if run == count {
+                return Ok(index + 1 - count);
+            }
+        }
+
+        // not enough room: extend the directory past its trailing free run
+        let first_index = raw_data.len() / 32 - run;
+        if first_index + count > MAX_DIR_ENTRIES {
+            return ioerr!(Other, "directory is full");
+        }
+
+        self.vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<()> {
+            if vfat.is_fixed_root(self.start_cluster) {
+                return ioerr!(Other, "root directory is full");
+            }
+
+            let entries_per_cluster = vfat.bytes_per_cluster() / 32;
+            let mut last = vfat.last_cluster(self.start_cluster)?;
+            let mut available = run;
+            while available < count {
+                last = vfat.alloc_cluster(Some(last))?;
+                available += entries_per_cluster;
+            }
+            Ok(())
+        })?;
+
+        Ok(first_index)
+    }
+}
+
+/// Marks every record belonging to the entry at `location` as deleted.
+fn delete_records<HANDLE: VFatHandle>(vfat: &mut VFat<HANDLE>, location: EntryLocation) -> io::Result<()> {
+    for index in location.first_index..=location.index {
+        let mut raw = vfat.read_dir_entry(location.dir_cluster, index)?;
+        raw[0] = DELETED_MARKER;
+        vfat.write_dir_entry(location.dir_cluster, index, &raw)?;
+    }
+    Ok(())
 }
 
 impl<HANDLE: VFatHandle> traits::Dir for Dir<HANDLE> {
@@ -114,16 +639,18 @@ impl<HANDLE: VFatHandle> traits::Dir for Dir<HANDLE> {
     /// Returns an interator over the entries in this directory.
     fn entries(&self) -> io::Result<Self::Iter> {
         let mut raw_data: Vec<u8> = Vec::new();
-        let bytes_per_cluster = self.vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<u32> {
-            vfat.read_chain(self.start_cluster, &mut raw_data)?;
-            Ok(vfat.bytes_per_sector as u32 * vfat.sectors_per_cluster as u32)
+        let (bytes_per_cluster, root_cluster) = self.vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<(u32, Cluster)> {
+            vfat.read_dir(self.start_cluster, &mut raw_data)?;
+            Ok((vfat.bytes_per_sector as u32 * vfat.sectors_per_cluster as u32, vfat.rootdir_cluster))
         })?;
 
         Ok(DirIterator {
             data: unsafe { raw_data.cast() },
             curr_idx: 0,
             vfat: self.vfat.clone(),
-            bytes_per_cluster:  bytes_per_cluster
+            bytes_per_cluster:  bytes_per_cluster,
+            dir_cluster: self.start_cluster,
+            root_cluster: root_cluster
         })
 
     }
@@ -133,7 +660,9 @@ pub struct DirIterator<HANDLE: VFatHandle>{
     data: Vec<VFatDirEntry>,
     curr_idx: usize,
     vfat: HANDLE,
-    bytes_per_cluster: u32
+    bytes_per_cluster: u32,
+    dir_cluster: Cluster,
+    root_cluster: Cluster
 }
 
 impl<HANDLE: VFatHandle> Iterator for DirIterator<HANDLE> {
@@ -144,6 +673,7 @@ impl<HANDLE: VFatHandle> Iterator for DirIterator<HANDLE> {
         
         let mut lfn = [0u16; 260];
         let mut has_lfn = false;
+        let mut first_index = self.curr_idx;
         while self.curr_idx < self.data.len() {
             let curr_entry = &self.data[self.curr_idx];
             let as_unknown = unsafe { curr_entry.unknown };
@@ -151,12 +681,19 @@ impl<HANDLE: VFatHandle> Iterator for DirIterator<HANDLE> {
             // check the entry's status
             match as_unknown.status {
                 0x00 => return None, // end of directory
-                0xE5 => { self.curr_idx += 1; continue; }, // deleted/unused                
+                DELETED_MARKER => {
+                    // deleted/unused
+                    self.curr_idx += 1;
+                    first_index = self.curr_idx;
+                    has_lfn = false;
+                    lfn = [0u16; 260];
+                    continue;
+                },
                 _ => ()
             };
 
             self.curr_idx += 1;
-            if as_unknown.attributes.0 == 0x0F {
+            if as_unknown.attributes.0 == ATTR_LFN {
                 // LFN entry
                 let lfn_entry = unsafe { curr_entry.long_filename };
                 let mut name_chars = [0u16; 13];
@@ -207,24 +744,26 @@ impl<HANDLE: VFatHandle> Iterator for DirIterator<HANDLE> {
                     }
                 };
 
-                let metadata = Metadata {
-                    filename: name.clone(),
-                    extension: String::from(core::str::from_utf8(&regular_entry.extension).unwrap().trim_end()),
-                    attributes: regular_entry.attributes,
-                    created_timestamp: Timestamp { date: regular_entry.created_date, time: regular_entry.created_time},
-                    accessed_date: Timestamp { date: regular_entry.accessed_date, time: Time::default() },
-                    modified_timestamp: Timestamp { date: regular_entry.modified_date, time: regular_entry.modified_time },
-                    size: regular_entry.size,
-                    start_cluster: Cluster((regular_entry.cluster_high as u32) << 16 | regular_entry.cluster_low as u32)
+                let metadata = regular_entry.metadata(name.clone());
+                let location = EntryLocation {
+                    dir_cluster: self.dir_cluster,
+                    first_index: if has_lfn { first_index } else { self.curr_idx - 1 },
+                    index: self.curr_idx - 1
                 };
 
-                if (regular_entry.attributes.0 & 0x10) != 0 {
+                if (regular_entry.attributes.0 & ATTR_DIRECTORY) != 0 {
+                    // a `..` entry in a top-level directory points at cluster 0
+                    let start_cluster = match (&metadata).start_cluster.0 {
+                        0 => self.root_cluster,
+                        _ => (&metadata).start_cluster
+                    };
                     return Some(
                         Entry::Dir(Dir {
                             vfat: self.vfat.clone(),
-                            start_cluster: (&metadata).start_cluster,
+                            start_cluster: start_cluster,
                             metadata: metadata,
-                            name: name
+                            name: name,
+                            location: Some(location)
                         })
                     );
                 } else {
@@ -232,12 +771,19 @@ impl<HANDLE: VFatHandle> Iterator for DirIterator<HANDLE> {
                         Entry::File(File {
                             vfat: self.vfat.clone(),
                             start_cluster: (&metadata).start_cluster.clone(),
-                            current_cluster: Some((&metadata).start_cluster.clone()),
+                            current_cluster: match (&metadata).start_cluster.0 {
+                                0 => None,
+                                _ => Some((&metadata).start_cluster.clone())
+                            },
                             name: name,
                             metadata: metadata,
                             current_offset: 0,
                             size: regular_entry.size,
-                            bytes_per_cluster: self.bytes_per_cluster
+                            bytes_per_cluster: self.bytes_per_cluster,
+                            location: location,
+                            extents: ExtentMap::new(),
+                            readahead: Default::default(),
+                            entry_dirty: false
                         })
                     );
                 }
@@ -1,5 +1,6 @@
 use core::fmt;
 use core::mem;
+use core::slice;
 use core::str;
 use shim::const_assert_size;
 
@@ -42,8 +43,38 @@ pub struct BiosParameterBlock {
 const_assert_size!(BiosParameterBlock, 512);
 
 impl BiosParameterBlock {
-    /// Reads the FAT32 extended BIOS parameter block from sector `sector` of
-    /// device `device`.
+    /// Returns a FAT32 EBPB with its jump instruction, OEM ID, geometry and
+    /// signatures filled in. Every field that describes a particular volume is
+    /// zero and must be set before the EBPB is written out.
+    pub fn blank() -> BiosParameterBlock {
+        let mut ebpb = unsafe { mem::transmute::<[u8; 512], BiosParameterBlock>([0u8; 512]) };
+        ebpb._ebxx90 = [0xEB, 0x58, 0x90];
+        ebpb.oem_id = u64::from_le_bytes(*b"MSWIN4.1");
+        ebpb.fat_id = 0xF8;
+        ebpb.sectors_per_track = 32;
+        ebpb.num_heads = 64;
+        ebpb.drive_num = 0x80;
+        ebpb.ebpb_signature = 0x29;
+        ebpb.volume_label = *b"NO NAME    ";
+        ebpb.system_id = *b"FAT32   ";
+        ebpb.boot_signature = 0xAA55;
+        ebpb
+    }
+
+    /// Returns the on-disk representation of the EBPB.
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self as *const BiosParameterBlock as *const u8, 512) }
+    }
+
+    /// Returns `true` if the boot sector has the FAT32 layout, i.e. a 32-bit
+    /// FAT size, a root directory cluster and an FSInfo sector.
+    pub fn is_fat32_layout(&self) -> bool {
+        self._sectors_per_fat == 0
+    }
+
+    /// Reads the extended BIOS parameter block from sector `sector` of device
+    /// `device`. The fields of FAT12 and FAT16 boot sectors are moved to where
+    /// the FAT32 layout keeps them, with the FAT32-only fields zeroed.
     ///
     /// # Errors
     ///
@@ -59,7 +90,29 @@ impl BiosParameterBlock {
 
         // transmute the buffer into an EBPB
         let mut ebpb = unsafe { mem::transmute::<[u8; 512], BiosParameterBlock>(ebpb_buf) };
-        
+
+        // FAT12 and FAT16 boot sectors have a 16-bit FAT size and their
+        // extended fields directly follow the BPB; move them to where the
+        // FAT32 layout keeps them
+        if !ebpb.is_fat32_layout() {
+            let mut volume_label = [0u8; 11];
+            let mut system_id = [0u8; 8];
+            volume_label.copy_from_slice(&ebpb_buf[43..54]);
+            system_id.copy_from_slice(&ebpb_buf[54..62]);
+
+            ebpb.sectors_per_fat = ebpb._sectors_per_fat as u32;
+            ebpb.flags = 0;
+            ebpb.fat_version = 0;
+            ebpb.root_cluster = 0;
+            ebpb.fs_info_sector = 0;
+            ebpb.backup_boot_sector = 0;
+            ebpb.drive_num = ebpb_buf[36];
+            ebpb.ebpb_signature = ebpb_buf[38];
+            ebpb.volume_id = u32::from_le_bytes([ebpb_buf[39], ebpb_buf[40], ebpb_buf[41], ebpb_buf[42]]);
+            ebpb.volume_label = volume_label;
+            ebpb.system_id = system_id;
+        }
+
         // validate signatures
         if !(ebpb.ebpb_signature == 0x28 || ebpb.ebpb_signature == 0x29)
              || ebpb.boot_signature != 0xAA55 {
@@ -1,10 +1,13 @@
 use shim::io;
 
+use crate::gpt;
 use crate::mbr;
+use crate::partition;
 
 #[derive(Debug)]
 pub enum Error {
     Mbr(mbr::Error),
+    Gpt(gpt::Error),
     Io(io::Error),
     BadSignature,
     NotFound,
@@ -21,3 +24,12 @@ impl From<io::Error> for Error {
         Error::Io(error)
     }
 }
+
+impl From<partition::Error> for Error {
+    fn from(error: partition::Error) -> Error {
+        match error {
+            partition::Error::Mbr(error) => Error::Mbr(error),
+            partition::Error::Gpt(error) => Error::Gpt(error),
+        }
+    }
+}
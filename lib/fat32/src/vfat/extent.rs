@@ -0,0 +1,168 @@
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+
+use shim::io;
+use shim::ioerr;
+
+use crate::vfat::{Cluster, VFat, VFatHandle};
+
+/// A run of clusters of a chain that are contiguous on disk.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Extent {
+    /// The position of the run's first cluster within the chain.
+    pub index: u32,
+    /// The run's first cluster.
+    pub start: Cluster,
+    /// The number of clusters in the run.
+    pub len: u32,
+}
+
+impl Extent {
+    /// The last cluster of the run.
+    fn last(&self) -> Cluster {
+        Cluster(self.start.0 + self.len - 1)
+    }
+}
+
+/// A cache of a cluster chain as runs of contiguous clusters, so that the
+/// cluster at any position of the chain can be found without walking the FAT.
+///
+/// The map is filled lazily, only as far into the chain as lookups have
+/// reached. It is dropped whenever a chain on the volume is shortened or
+/// relinked, as counted by `VFat::chain_generation()`; chains growing at
+/// their end leave it valid.
+#[derive(Debug, Default, Clone)]
+pub struct ExtentMap {
+    start: Cluster,
+    generation: u64,
+    extents: Vec<Extent>,
+}
+
+impl ExtentMap {
+    /// Returns an empty map.
+    pub fn new() -> ExtentMap {
+        ExtentMap::default()
+    }
+
+    /// Returns the extents found so far, in chain order.
+    pub fn extents(&self) -> &[Extent] {
+        &self.extents
+    }
+
+    /// Forgets every extent found so far.
+    pub fn invalidate(&mut self) {
+        self.extents.clear();
+    }
+
+    /// Returns the cluster at position `index` of the chain beginning at
+    /// `start`, or `None` if the chain is shorter than that. An empty chain,
+    /// i.e. a `start` of `Cluster(0)`, has no clusters.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidData` if the chain is broken or loops.
+    pub fn cluster<HANDLE: VFatHandle>(&mut self, vfat: &mut VFat<HANDLE>, start: Cluster, index: u32)
+        -> io::Result<Option<Cluster>>
+    {
+        if start.0 < 2 {
+            return Ok(None);
+        }
+        if self.start != start || self.generation != vfat.chain_generation() {
+            self.extents.clear();
+            self.start = start;
+            self.generation = vfat.chain_generation();
+        }
+        if self.extents.is_empty() {
+            self.extents.push(Extent { index: 0, start, len: 1 });
+        }
+
+        if !self.extend_to(vfat, index)? {
+            return Ok(None);
+        }
+
+        match self.find(index) {
+            Some(extent) => Ok(Some(Cluster(extent.start.0 + (index - extent.index)))),
+            None => ioerr!(InvalidData, "extent map has a gap")
+        }
+    }
+
+    /// Returns the cluster at position `index` of the chain beginning at
+    /// `start` and the number of clusters, at most `max_len`, that follow it
+    /// contiguously on disk, itself included. Returns `None` if the chain is
+    /// shorter than `index + 1` clusters.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidData` if the chain is broken or loops.
+    pub fn run<HANDLE: VFatHandle>(&mut self, vfat: &mut VFat<HANDLE>, start: Cluster, index: u32, max_len: u32)
+        -> io::Result<Option<(Cluster, u32)>>
+    {
+        let cluster = match self.cluster(vfat, start, index)? {
+            Some(cluster) => cluster,
+            None => return Ok(None)
+        };
+
+        // map the chain up to the end of the run, if it gets that far
+        self.cluster(vfat, start, index.saturating_add(max_len.saturating_sub(1)))?;
+        let contiguous = self.find(index).map_or(1, |extent| extent.index + extent.len - index);
+        Ok(Some((cluster, core::cmp::min(contiguous, max_len))))
+    }
+
+    /// Returns the extent covering position `index` of the chain, if it has
+    /// been mapped.
+    fn find(&self, index: u32) -> Option<&Extent> {
+        let position = self.extents.binary_search_by(|extent| {
+            if index < extent.index {
+                Ordering::Greater
+            } else if index >= extent.index + extent.len {
+                Ordering::Less
+            } else {
+                Ordering::Equal
+            }
+        });
+        position.ok().map(|i| &self.extents[i])
+    }
+
+    /// Returns the last cluster of the chain beginning at `start`, which must
+    /// not be empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidData` if the chain is broken or loops.
+    pub fn last_cluster<HANDLE: VFatHandle>(&mut self, vfat: &mut VFat<HANDLE>, start: Cluster)
+        -> io::Result<Cluster>
+    {
+        self.cluster(vfat, start, core::u32::MAX)?;
+        match self.extents.last() {
+            Some(extent) => Ok(extent.last()),
+            None => ioerr!(InvalidInput, "chain is empty")
+        }
+    }
+
+    /// Follows the FAT past the extents found so far until they cover
+    /// position `index`. Returns `false` if the chain ends before that.
+    fn extend_to<HANDLE: VFatHandle>(&mut self, vfat: &mut VFat<HANDLE>, index: u32) -> io::Result<bool> {
+        loop {
+            let last = match self.extents.last() {
+                Some(last) => *last,
+                None => return Ok(false)
+            };
+            if index < last.index + last.len {
+                return Ok(true);
+            }
+            if last.index + last.len > vfat.total_clusters {
+                return ioerr!(InvalidData, "cluster chain loops");
+            }
+
+            match vfat.next_cluster(last.last())? {
+                None => return Ok(false),
+                Some(next) if next.0 == last.last().0 + 1 => {
+                    self.extents.last_mut().unwrap().len += 1;
+                },
+                Some(next) => {
+                    self.extents.push(Extent { index: last.index + last.len, start: next, len: 1 });
+                }
+            }
+        }
+    }
+}
@@ -17,6 +17,72 @@ pub enum Status {
     Eoc(u32),
 }
 
+/// The width of the entries of a file allocation table.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FatType {
+    /// 12-bit entries, packed two to every three bytes.
+    Fat12,
+    /// 16-bit entries.
+    Fat16,
+    /// 32-bit entries of which the upper four bits are reserved.
+    Fat32,
+}
+
+impl FatType {
+    /// Determines the FAT type of a volume with `num_clusters` data clusters.
+    ///
+    /// The specification decides on the cluster count alone. A boot sector
+    /// with the FAT32 layout, which has no 16-bit FAT size, is taken as FAT32
+    /// regardless, as Linux does: small FAT32 volumes are common in images
+    /// made for testing.
+    pub fn detect(fat32_layout: bool, num_clusters: u32) -> FatType {
+        match (fat32_layout, num_clusters) {
+            (true, _) => FatType::Fat32,
+            (false, 0..=4084) => FatType::Fat12,
+            (false, _) => FatType::Fat16,
+        }
+    }
+
+    /// The bits of an entry that hold its value.
+    pub fn mask(self) -> u32 {
+        match self {
+            FatType::Fat12 => 0xFFF,
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFFFFFF,
+        }
+    }
+
+    /// The byte offset of `cluster`'s entry from the start of the FAT.
+    pub(crate) fn entry_offset(self, cluster: Cluster) -> usize {
+        let n = cluster.0 as usize;
+        match self {
+            FatType::Fat12 => n + n / 2,
+            FatType::Fat16 => n * 2,
+            FatType::Fat32 => n * 4,
+        }
+    }
+
+    /// The number of bytes that hold an entry. A FAT12 entry shares one of
+    /// its two bytes with a neighbour.
+    pub(crate) fn entry_bytes(self) -> usize {
+        match self {
+            FatType::Fat12 | FatType::Fat16 => 2,
+            FatType::Fat32 => 4,
+        }
+    }
+
+    /// Extends the raw value `raw` of a FAT12 or FAT16 entry to the FAT32
+    /// value with the same meaning, so that `FatEntry::status()` applies to
+    /// every FAT type.
+    pub(crate) fn widen(self, raw: u32) -> FatEntry {
+        let mask = self.mask();
+        match raw & mask {
+            special if special >= mask - 0xF => FatEntry(special | (FatType::Fat32.mask() & !mask)),
+            value => FatEntry(value)
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 #[repr(C, packed)]
 pub struct FatEntry(pub u32);
@@ -5,8 +5,14 @@ use shim::ioerr;
 
 
 use crate::traits;
-use crate::vfat::{Cluster, Metadata, VFatHandle, VFat, Status, FatEntry};
-use core::cmp::min;
+use crate::vfat::{Cluster, EntryLocation, ExtentMap, Metadata, VFatHandle, VFat};
+use crate::vfat::dir::VFatRegularDirEntry;
+use core::cmp::{max, min};
+
+/// The size of the first read-ahead of a sequential read, in bytes.
+const READAHEAD_MIN: u32 = 16 * 1024;
+/// The size read-ahead grows to over a long sequential read, in bytes.
+const READAHEAD_MAX: u32 = 128 * 1024;
 
 #[derive(Debug)]
 pub struct File<HANDLE: VFatHandle> {
@@ -17,14 +23,188 @@ pub struct File<HANDLE: VFatHandle> {
     pub name: String,
     pub size: u32,
     pub current_cluster: Option<Cluster>,
-    pub bytes_per_cluster: u32
+    pub bytes_per_cluster: u32,
+    pub location: EntryLocation,
+    /// The file's cluster chain, mapped as far as the file has been accessed.
+    pub extents: ExtentMap,
+    pub(crate) readahead: ReadAhead,
+    /// Whether writes changed `size` or `start_cluster` since the directory
+    /// entry was last written, which `flush()` and `sync()` do.
+    pub(crate) entry_dirty: bool
+}
+
+/// The read pattern of a file, tracked to read ahead of sequential reads.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ReadAhead {
+    /// Where a read continuing the previous one starts.
+    next_offset: u32,
+    /// The number of clusters read ahead of a read, `0` until reads are
+    /// sequential.
+    window: u32,
+    /// The position within the chain of the first cluster past those read
+    /// ahead.
+    end: u32,
+}
+
+impl<HANDLE: VFatHandle> File<HANDLE> {
+    /// Truncates or extends the file to `size` bytes. Clusters past the new
+    /// end of the file are freed; extended space is filled with zeroes.
+    ///
+    /// The current offset is clamped to the new size.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `PermissionDenied` if the file is the journal of a
+    /// journaled volume.
+    pub fn truncate(&mut self, size: u64) -> io::Result<()> {
+        use io::{Seek, Write};
+        use traits::File;
+
+        let start_cluster = self.start_cluster;
+        self.vfat.lock(|vfat: &mut VFat<HANDLE>| vfat.protect_journal(start_cluster))?;
+
+        if size > self.size() {
+            let zeroes = [0u8; 512];
+            self.seek(SeekFrom::End(0))?;
+            while self.size() < size {
+                let n = min(zeroes.len() as u64, size - self.size()) as usize;
+                self.write_all(&zeroes[..n])?;
+            }
+            self.update_entry()?;
+        } else if size < self.size() {
+            let keep = ((size + self.bytes_per_cluster as u64 - 1) / self.bytes_per_cluster as u64) as usize;
+            self.vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<()> {
+                match keep {
+                    0 => vfat.free_chain(start_cluster),
+                    _ => vfat.truncate_chain(start_cluster, keep)
+                }
+            })?;
+
+            if keep == 0 {
+                self.start_cluster = Cluster(0);
+                self.metadata.start_cluster = Cluster(0);
+            }
+            self.size = size as u32;
+            self.metadata.size = size as u32;
+            self.update_entry()?;
+        }
+
+        let offset = min(self.current_offset as u64, size);
+        self.seek(SeekFrom::Start(offset))?;
+        Ok(())
+    }
+
+    /// Writes the file's size, first cluster and modification time back to
+    /// its directory entry.
+    fn update_entry(&mut self) -> io::Result<()> {
+        let location = self.location;
+        let (size, start_cluster) = (self.size, self.start_cluster);
+        let now = self.vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<_> {
+            let now = vfat.now();
+            let mut regular = VFatRegularDirEntry::read(vfat, location)?;
+            regular.size = size;
+            regular.set_cluster(start_cluster);
+            regular.modified_date = now.date;
+            regular.modified_time = now.time;
+            regular.accessed_date = now.date;
+            regular.write(vfat, location)?;
+            Ok(now)
+        })?;
+
+        self.metadata.modified_timestamp = now;
+        self.metadata.accessed_date.date = now.date;
+        self.entry_dirty = false;
+        Ok(())
+    }
+
+    /// Reads ahead of a read of `len` bytes at the current offset if it
+    /// continues the previous read. The clusters following the read are
+    /// prefetched, contiguous ones with a single device read, whenever a read
+    /// gets within half a window of the end of those prefetched so far. The
+    /// window starts at `READAHEAD_MIN` bytes and doubles up to
+    /// `READAHEAD_MAX` as the sequential reads continue.
+    fn read_ahead(&mut self, len: usize) -> io::Result<()> {
+        use traits::File;
+
+        let offset = self.current_offset;
+        let sequential = offset == self.readahead.next_offset;
+        self.readahead.next_offset = offset + len as u32;
+        if !sequential {
+            self.readahead.window = 0;
+            self.readahead.end = 0;
+            return Ok(());
+        }
+
+        let bytes_per_cluster = self.bytes_per_cluster;
+        let last = (offset + len as u32 - 1) / bytes_per_cluster;
+        if last + self.readahead.window / 2 < self.readahead.end {
+            return Ok(());
+        }
+
+        let min_window = max(READAHEAD_MIN / bytes_per_cluster, 1);
+        let max_window = max(READAHEAD_MAX / bytes_per_cluster, 1);
+        let window = min(max(self.readahead.window * 2, min_window), max_window);
+        let num_clusters = ((self.size() + bytes_per_cluster as u64 - 1) / bytes_per_cluster as u64) as u32;
+        let from = max(offset / bytes_per_cluster, self.readahead.end);
+        let to = min(last + 1 + window, num_clusters);
+
+        let (start_cluster, extents) = (self.start_cluster, &mut self.extents);
+        self.vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<()> {
+            let mut index = from;
+            while index < to {
+                match extents.run(vfat, start_cluster, index, to - index)? {
+                    Some((cluster, len)) => {
+                        vfat.prefetch_clusters(cluster, len)?;
+                        index += len;
+                    },
+                    None => break
+                }
+            }
+            Ok(())
+        })?;
+
+        self.readahead.window = window;
+        self.readahead.end = to;
+        Ok(())
+    }
+
+    /// Returns the cluster that the byte at the current offset should be
+    /// written to, appending a new cluster if the offset is at the end of the
+    /// chain.
+    fn cluster_for_write(&mut self) -> io::Result<Cluster> {
+        if let Some(cluster) = self.current_cluster {
+            return Ok(cluster);
+        }
+
+        let start_cluster = self.start_cluster;
+        let extents = &mut self.extents;
+        let cluster = self.vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<Cluster> {
+            match start_cluster.0 {
+                0 => vfat.alloc_cluster(None),
+                _ => {
+                    let last = extents.last_cluster(vfat, start_cluster)?;
+                    vfat.alloc_cluster(Some(last))
+                }
+            }
+        })?;
+
+        if start_cluster.0 == 0 {
+            self.start_cluster = cluster;
+            self.metadata.start_cluster = cluster;
+        }
+        Ok(cluster)
+    }
 }
 
-// FIXME: Implement `traits::File` (and its supertraits) for `File`.
 impl<HANDLE: VFatHandle> traits::File for File<HANDLE> {
-    /// Writes any buffered data to disk.
+    /// Writes the directory entry, if writes changed it, and any buffered
+    /// data to disk.
     fn sync(&mut self) -> io::Result<()> {
-        unimplemented!()
+        use io::Write;
+        self.flush()?;
+        self.vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<()> {
+            vfat.flush()
+        })
     }
 
     /// Returns the size of the file in bytes.
@@ -34,11 +214,58 @@ impl<HANDLE: VFatHandle> traits::File for File<HANDLE> {
 }
 
 impl<HANDLE: VFatHandle> io::Write for File<HANDLE>  {
-    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
-        panic!("Dummy")
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.current_offset as u64 + buf.len() as u64 > u32::max_value() as u64 {
+            return ioerr!(InvalidInput, "write would exceed the maximum FAT32 file size");
+        }
+
+        let mut cluster = self.cluster_for_write()?;
+        let mut buf_offset = 0;
+        loop {
+            let cluster_offset = (self.current_offset % self.bytes_per_cluster) as usize;
+            let written = self.vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<usize> {
+                vfat.write_cluster(cluster, cluster_offset, &buf[buf_offset..])
+            })?;
+            buf_offset += written;
+            self.current_offset += written as u32;
+
+            if self.current_offset % self.bytes_per_cluster != 0 {
+                self.current_cluster = Some(cluster);
+                break;
+            }
+
+            // we filled this cluster: move on to the next one, growing the
+            // chain only if there is more data to write
+            let next = self.vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<Option<Cluster>> {
+                match vfat.next_cluster(cluster)? {
+                    Some(next) => Ok(Some(next)),
+                    None if buf_offset < buf.len() => Ok(Some(vfat.alloc_cluster(Some(cluster))?)),
+                    None => Ok(None)
+                }
+            })?;
+            self.current_cluster = next;
+            match next {
+                Some(next) if buf_offset < buf.len() => cluster = next,
+                _ => break
+            }
+        }
+
+        self.size = max(self.size, self.current_offset);
+        self.metadata.size = self.size;
+        self.entry_dirty = true;
+        Ok(buf_offset)
     }
+
+    /// Writes the directory entry, if writes changed it. Like the data, it
+    /// is only cached until `sync()`.
     fn flush(&mut self) -> io::Result<()> {
-        panic!("Dummy")
+        match self.entry_dirty {
+            true => self.update_entry(),
+            false => Ok(())
+        }
     }
 }
 
@@ -50,29 +277,32 @@ impl<HANDLE:VFatHandle> io::Read for File<HANDLE> {
         use traits::File;
 
         let read_size = min(buf.len(), (self.size() - self.current_offset as u64) as usize);
+        if read_size == 0 {
+            return Ok(0);
+        }
+        self.read_ahead(read_size)?;
+
         let mut cluster_offset = (self.current_offset % self.bytes_per_cluster) as usize;
+        let mut cluster_index = self.current_offset / self.bytes_per_cluster;
         let mut remaining = read_size;
         let mut current_cluster = self.current_cluster;
         let mut buf_offset = 0;
 
         while remaining > 0 {
+            let cluster = match current_cluster {
+                Some(cluster) => cluster,
+                None => return ioerr!(InvalidData, "cluster chain is shorter than the file")
+            };
             let cluster_read_size = self.vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<usize> {
-                vfat.read_cluster(current_cluster.unwrap(), cluster_offset, &mut buf[buf_offset..read_size])
+                vfat.read_cluster(cluster, cluster_offset, &mut buf[buf_offset..read_size])
             })?;
 
             if cluster_read_size == self.bytes_per_cluster as usize - cluster_offset {
-                let entry = self.vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<FatEntry> {
-                    Ok((*vfat.fat_entry(current_cluster.unwrap())?).clone())
+                cluster_index += 1;
+                let (start_cluster, extents) = (self.start_cluster, &mut self.extents);
+                current_cluster = self.vfat.lock(|vfat: &mut VFat<HANDLE>| {
+                    extents.cluster(vfat, start_cluster, cluster_index)
                 })?;
-
-                match entry.status() {
-                    Status::Eoc(_) => current_cluster = None,
-                    Status::Data(next) => {
-                        current_cluster = Some(next);
-                    },
-                    _ => return ioerr!(InvalidData, "invalid cluster chain")
-
-                }
             }
             buf_offset += cluster_read_size;
             remaining -= cluster_read_size;
@@ -111,19 +341,13 @@ impl<HANDLE: VFatHandle> io::Seek for File<HANDLE> {
         if new_offset as u64 > self.size() || new_offset < 0 {
             return ioerr!(InvalidInput, "invalid seek");
         } else {
-            let mut curr_cluster = self.start_cluster;
-            for _i in 0..(new_offset as u32 / self.bytes_per_cluster) {
-                let entry = self.vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<FatEntry> {
-                    Ok((*vfat.fat_entry(curr_cluster)?).clone())
-                })?;
-
-                match entry.status() {
-                    Status::Data(next) => curr_cluster = next,
-                    _ => ()
-                };
-            }
-
-            self.current_cluster = Some(curr_cluster);
+            // an empty file has no chain; a seek to the end of a file that
+            // fills its last cluster lands past the end of the chain
+            let index = new_offset as u32 / self.bytes_per_cluster;
+            let (start_cluster, extents) = (self.start_cluster, &mut self.extents);
+            self.current_cluster = self.vfat.lock(|vfat: &mut VFat<HANDLE>| {
+                extents.cluster(vfat, start_cluster, index)
+            })?;
             self.current_offset = new_offset as u32;
             return Ok(self.current_offset as u64);
         }
@@ -0,0 +1,76 @@
+use core::fmt;
+use core::mem;
+use core::slice;
+use shim::const_assert_size;
+
+use crate::traits::BlockDevice;
+use crate::vfat::Error;
+
+/// Value of `free_clusters` and `next_free` when they are unknown.
+pub const FS_INFO_UNKNOWN: u32 = 0xFFFFFFFF;
+
+/// The FAT32 FSInfo structure, which caches the number of free clusters and
+/// a hint for where to look for the next one.
+#[repr(C, packed)]
+pub struct FsInfo {
+    lead_signature: u32,
+    _reserved0: [u8; 480],
+    struct_signature: u32,
+    /// Number of free clusters on the volume or `FS_INFO_UNKNOWN`.
+    pub free_clusters: u32,
+    /// Cluster to start looking for a free cluster at or `FS_INFO_UNKNOWN`.
+    pub next_free: u32,
+    _reserved1: [u8; 12],
+    trail_signature: u32,
+}
+
+const_assert_size!(FsInfo, 512);
+
+impl FsInfo {
+    /// Returns an FSInfo structure holding `free_clusters` and `next_free`.
+    pub fn new(free_clusters: u32, next_free: u32) -> FsInfo {
+        let mut fs_info = unsafe { mem::transmute::<[u8; 512], FsInfo>([0u8; 512]) };
+        fs_info.lead_signature = 0x41615252;
+        fs_info.struct_signature = 0x61417272;
+        fs_info.free_clusters = free_clusters;
+        fs_info.next_free = next_free;
+        fs_info.trail_signature = 0xAA550000;
+        fs_info
+    }
+
+    /// Reads the FSInfo structure from sector `sector` of device `device`.
+    ///
+    /// # Errors
+    ///
+    /// If any of the three FSInfo signatures is invalid, returns an error of
+    /// `BadSignature`.
+    pub fn from<T: BlockDevice>(mut device: T, sector: u64) -> Result<FsInfo, Error> {
+        let mut sector_buf = vec![0u8; device.sector_size() as usize];
+        device.read_sector(sector, &mut sector_buf)?;
+
+        let mut fs_info_buf = [0u8; 512];
+        fs_info_buf.copy_from_slice(&sector_buf[..512]);
+        let fs_info = unsafe { mem::transmute::<[u8; 512], FsInfo>(fs_info_buf) };
+        if fs_info.lead_signature != 0x41615252
+            || fs_info.struct_signature != 0x61417272
+            || fs_info.trail_signature != 0xAA550000 {
+            return Err(Error::BadSignature);
+        }
+
+        Ok(fs_info)
+    }
+
+    /// Returns the on-disk representation of the structure.
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self as *const FsInfo as *const u8, 512) }
+    }
+}
+
+impl fmt::Debug for FsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FsInfo")
+            .field("free_clusters", &{ self.free_clusters })
+            .field("next_free", &{ self.next_free })
+            .finish()
+    }
+}
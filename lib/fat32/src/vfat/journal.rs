@@ -0,0 +1,344 @@
+//! An optional write-ahead journal for the metadata of a FAT volume.
+//!
+//! While journaling, sectors of the FATs, of directories and of the FSInfo
+//! structure are not written in place until a copy of them has been committed
+//! to the journal, a hidden system file in the root directory that cannot be
+//! removed, renamed or truncated while journaling is on. A mount that
+//! finds a committed transaction in the journal replays it first, so that an
+//! interrupted flush leaves the metadata as it was either before or after it,
+//! never in between. File contents are written before the metadata that
+//! refers to them and are not journaled. A flush whose metadata does not fit
+//! in the journal fails without writing any of it in place.
+//!
+//! The journal file starts with the header: an 8 byte magic, the
+//! transaction's sequence number, the number of sectors it holds, a CRC32 of
+//! the header fields, target sector numbers and sector contents, and then the
+//! target sector numbers, which continue into as many sectors as a full
+//! transaction needs. Copies of the sectors follow the header in order. A
+//! transaction is committed once the header's first sector is written, which
+//! happens last, and retired by rewriting that sector with a count of zero.
+
+use alloc::vec::Vec;
+
+use shim::io;
+use shim::ioerr;
+
+use crate::gpt::crc32;
+use crate::traits::FileSystem;
+use crate::vfat::dir::{VFatDirEntry, VFatRegularDirEntry, ATTR_ARCHIVE, ATTR_LFN, DELETED_MARKER};
+use crate::vfat::{Attributes, CachedPartition, Cluster, VFat, VFatHandle};
+
+/// The name of the journal file in the root directory.
+pub const JOURNAL_NAME: &str = "JOURNAL.SYS";
+
+/// The smallest journal, in sectors: the header and a single sector copy.
+pub const MIN_JOURNAL_SECTORS: u32 = 2;
+
+/// The journal file's name as stored in its directory entry.
+const JOURNAL_SHORT_NAME: [u8; 11] = *b"JOURNAL SYS";
+
+/// Identifies a journal header.
+const JOURNAL_MAGIC: [u8; 8] = *b"FATJRNL1";
+
+/// The size of the fixed fields of the header, before the target sectors.
+const HEADER_SIZE: usize = 32;
+
+/// Attribute bits of the journal file's entry: hidden and system.
+const ATTR_HIDDEN_SYSTEM: u8 = 0x02 | 0x04;
+
+/// The location of a volume's journal and the state of its transactions.
+#[derive(Debug, Clone)]
+pub(crate) struct Journal {
+    /// The sectors of the journal file, the header first.
+    sectors: Vec<u64>,
+    /// The number of sectors the header takes.
+    header_sectors: usize,
+    /// The number of sectors a single transaction holds.
+    capacity: usize,
+    /// The sequence number of the last transaction committed.
+    sequence: u64,
+}
+
+impl Journal {
+    /// Returns a journal stored in `sectors`, which start with the header, on
+    /// a volume with `sector_size` byte sectors. Transactions hold as many
+    /// sectors as fit next to a header listing them.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidInput` if there are fewer than `MIN_JOURNAL_SECTORS`
+    /// sectors.
+    pub(crate) fn new(sectors: Vec<u64>, sector_size: usize) -> io::Result<Journal> {
+        if sectors.len() < MIN_JOURNAL_SECTORS as usize {
+            return ioerr!(InvalidInput, "journal is too small");
+        }
+
+        let mut capacity = sectors.len() - 1;
+        while header_sectors(capacity, sector_size) + capacity > sectors.len() {
+            capacity -= 1;
+        }
+        let header_sectors = header_sectors(capacity, sector_size);
+        Ok(Journal { sectors, header_sectors, capacity, sequence: 0 })
+    }
+
+    /// The number of sectors a single transaction holds.
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The first sector of the journal file.
+    pub(crate) fn first_sector(&self) -> u64 {
+        self.sectors[0]
+    }
+
+    /// Writes the contents of `targets`, which must be cached in `device`, to
+    /// the journal and commits them. At most `capacity()` sectors fit in a
+    /// transaction. Once this returns, the sectors may be written in place.
+    pub(crate) fn commit(&mut self, device: &mut CachedPartition, targets: &[u64]) -> io::Result<()> {
+        if targets.len() > self.capacity {
+            return ioerr!(InvalidInput, "transaction does not fit in the journal");
+        }
+
+        let mut contents = Vec::with_capacity(targets.len());
+        for &target in targets {
+            match device.cached(target) {
+                Some(data) => contents.push(data.to_vec()),
+                None => return ioerr!(NotFound, "journaled sector is not cached")
+            }
+        }
+
+        for (i, data) in contents.iter().enumerate() {
+            device.write_through(self.sectors[self.header_sectors + i], data)?;
+        }
+
+        // the first sector, holding the count, commits the transaction
+        self.sequence += 1;
+        let sector_size = device.partition.sector_size as usize;
+        let header = self.header(self.sequence, targets, &contents, sector_size as u64);
+        for (i, data) in header.chunks(sector_size).enumerate().rev() {
+            device.write_through(self.sectors[i], data)?;
+        }
+        Ok(())
+    }
+
+    /// Marks the journal as holding no transaction, once every sector of the
+    /// last one has been written in place.
+    pub(crate) fn clear(&mut self, device: &mut CachedPartition) -> io::Result<()> {
+        let sector_size = device.partition.sector_size as usize;
+        let header = self.header(self.sequence, &[], &[], sector_size as u64);
+        device.write_through(self.sectors[0], &header[..sector_size])
+    }
+
+    /// Writes the sectors of the transaction committed to the journal, if
+    /// there is one, in place and returns how many there were. A header that
+    /// is not a valid journal header, such as that of a new journal file, or
+    /// whose checksum does not match, as after a crash while committing, is
+    /// taken as an empty journal.
+    ///
+    /// The journal is not cleared.
+    pub(crate) fn replay(&mut self, device: &mut CachedPartition) -> io::Result<usize> {
+        let sector_size = device.partition.sector_size as usize;
+        let mut header = vec![0u8; self.header_sectors * sector_size];
+        device.read_through(self.sectors[0], &mut header[..sector_size])?;
+        if header[..8] != JOURNAL_MAGIC {
+            return Ok(0);
+        }
+
+        let sequence = read_u64(&header[8..16]);
+        let count = read_u32(&header[16..20]) as usize;
+        self.sequence = sequence;
+        if count == 0 || count > self.capacity {
+            return Ok(0);
+        }
+        for (i, data) in header.chunks_mut(sector_size).enumerate().skip(1) {
+            device.read_through(self.sectors[i], data)?;
+        }
+
+        let targets: Vec<u64> = (0..count)
+            .map(|i| read_u64(&header[HEADER_SIZE + i * 8..HEADER_SIZE + i * 8 + 8]))
+            .collect();
+        let mut contents = Vec::with_capacity(count);
+        for i in 0..count {
+            let mut data = vec![0u8; sector_size];
+            device.read_through(self.sectors[self.header_sectors + i], &mut data)?;
+            contents.push(data);
+        }
+
+        if self.header(sequence, &targets, &contents, sector_size as u64) != header {
+            return Ok(0);
+        }
+
+        for (&target, data) in targets.iter().zip(contents.iter()) {
+            device.write_through(target, data)?;
+        }
+        Ok(count)
+    }
+
+    /// Returns the header sectors of a transaction of `targets` holding
+    /// `contents`.
+    fn header(&self, sequence: u64, targets: &[u64], contents: &[Vec<u8>], sector_size: u64) -> Vec<u8> {
+        let mut header = vec![0u8; self.header_sectors * sector_size as usize];
+        header[..8].copy_from_slice(&JOURNAL_MAGIC);
+        header[8..16].copy_from_slice(&sequence.to_le_bytes());
+        header[16..20].copy_from_slice(&(targets.len() as u32).to_le_bytes());
+        for (i, target) in targets.iter().enumerate() {
+            header[HEADER_SIZE + i * 8..HEADER_SIZE + i * 8 + 8].copy_from_slice(&target.to_le_bytes());
+        }
+
+        let mut checked = Vec::with_capacity(12 + targets.len() * 8 + contents.len() * sector_size as usize);
+        checked.extend_from_slice(&header[8..20]);
+        checked.extend_from_slice(&header[HEADER_SIZE..HEADER_SIZE + targets.len() * 8]);
+        for data in contents {
+            checked.extend_from_slice(data);
+        }
+        header[20..24].copy_from_slice(&crc32(&checked).to_le_bytes());
+        header
+    }
+}
+
+/// Returns the number of sectors of a header listing `count` target sectors.
+fn header_sectors(count: usize, sector_size: usize) -> usize {
+    (HEADER_SIZE + count * 8 + sector_size - 1) / sector_size
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    let mut raw = [0u8; 4];
+    raw.copy_from_slice(bytes);
+    u32::from_le_bytes(raw)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut raw = [0u8; 8];
+    raw.copy_from_slice(bytes);
+    u64::from_le_bytes(raw)
+}
+
+impl<HANDLE: VFatHandle> VFat<HANDLE> {
+    /// Looks for the journal file in the root directory. If there is one,
+    /// replays the transaction it holds and turns journaling on.
+    pub(crate) fn load_journal(&mut self) -> io::Result<()> {
+        let (start, size) = match self.find_journal()? {
+            Some(found) => found,
+            None => return Ok(())
+        };
+
+        let bytes_per_sector = self.bytes_per_sector as u64;
+        let num_sectors = (size as u64 / bytes_per_sector) as usize;
+        if num_sectors < MIN_JOURNAL_SECTORS as usize {
+            return Ok(());
+        }
+
+        let mut sectors = Vec::with_capacity(num_sectors);
+        let mut cluster = start;
+        for _ in 0..self.total_clusters {
+            let first = self.data_start_sector + (cluster.0 as u64 - 2) * self.sectors_per_cluster as u64;
+            let n = core::cmp::min(self.sectors_per_cluster as usize, num_sectors - sectors.len());
+            sectors.extend((0..n as u64).map(|i| first + i));
+            if sectors.len() == num_sectors {
+                break;
+            }
+            cluster = match self.next_cluster(cluster)? {
+                Some(next) => next,
+                None => return ioerr!(InvalidData, "journal file is shorter than its size")
+            };
+        }
+
+        let mut journal = Journal::new(sectors, bytes_per_sector as usize)?;
+        if journal.replay(&mut self.device)? > 0 {
+            journal.clear(&mut self.device)?;
+        }
+        self.device.set_journal(Some(journal));
+        Ok(())
+    }
+
+    /// Returns the first cluster and size of the journal file, if the root
+    /// directory has one.
+    fn find_journal(&mut self) -> io::Result<Option<(Cluster, u32)>> {
+        let mut buf = Vec::new();
+        let root = self.rootdir_cluster;
+        self.read_dir(root, &mut buf)?;
+        for chunk in buf.chunks_exact(32) {
+            let mut raw = [0u8; 32];
+            raw.copy_from_slice(chunk);
+            let unknown = unsafe { VFatDirEntry::from_bytes(raw).unknown };
+            match (unknown.status, unknown.attributes.0) {
+                (0, _) => break,
+                (DELETED_MARKER, _) | (_, ATTR_LFN) => continue,
+                _ if raw[..11] == JOURNAL_SHORT_NAME => {
+                    let regular = unsafe { VFatDirEntry::from_bytes(raw).regular };
+                    return Ok(Some((regular.cluster(), regular.size)));
+                },
+                _ => continue,
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns `true` if metadata changes are journaled.
+    pub fn is_journaling(&self) -> bool {
+        self.device.journal().is_some()
+    }
+
+    /// Fails with `PermissionDenied` if the entry starting at `cluster` is the
+    /// journal file while journaling is on. The journal may not be removed,
+    /// renamed or truncated then, as its sectors are written behind the file's
+    /// back.
+    pub(crate) fn protect_journal(&self, cluster: Cluster) -> io::Result<()> {
+        let journal = match self.device.journal() {
+            Some(journal) if cluster.0 >= 2 => journal,
+            _ => return Ok(())
+        };
+        let first = self.data_start_sector + (cluster.0 as u64 - 2) * self.sectors_per_cluster as u64;
+        if first == journal.first_sector() {
+            return ioerr!(PermissionDenied, "the journal is in use");
+        }
+        Ok(())
+    }
+}
+
+/// Turns journaling on for `vfat`, creating a journal of `num_sectors`
+/// sectors in the root directory if it has none. Every pending change is
+/// written back first. Journaling stays on for later mounts of the volume
+/// for as long as the journal file exists.
+///
+/// # Errors
+///
+/// Returns `InvalidInput` if `num_sectors` is less than
+/// `MIN_JOURNAL_SECTORS`. Fails like `Dir::create_file()` and `File::write()`
+/// if the journal file can't be created.
+pub fn enable_journal<HANDLE: VFatHandle>(vfat: &HANDLE, num_sectors: u32) -> io::Result<()> {
+    use shim::io::Write;
+
+    if num_sectors < MIN_JOURNAL_SECTORS {
+        return ioerr!(InvalidInput, "journal is too small");
+    }
+    if vfat.lock(|vfat: &mut VFat<HANDLE>| vfat.is_journaling()) {
+        return Ok(());
+    }
+
+    if vfat.lock(|vfat: &mut VFat<HANDLE>| vfat.find_journal())?.is_none() {
+        let mut file = vfat.open_dir("/")?.create_file(JOURNAL_NAME)?;
+        let bytes_per_sector = vfat.lock(|vfat: &mut VFat<HANDLE>| vfat.bytes_per_sector as usize);
+        let zeroes = vec![0u8; bytes_per_sector];
+        for _ in 0..num_sectors {
+            file.write_all(&zeroes)?;
+        }
+        file.flush()?;
+
+        let location = file.location;
+        vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<()> {
+            let mut regular = VFatRegularDirEntry::read(vfat, location)?;
+            regular.attributes = Attributes(ATTR_ARCHIVE | ATTR_HIDDEN_SYSTEM);
+            regular.write(vfat, location)
+        })?;
+    }
+
+    vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<()> {
+        vfat.flush()?;
+        vfat.load_journal()?;
+        if !vfat.is_journaling() {
+            return ioerr!(Other, "journal file is too small");
+        }
+        Ok(())
+    })
+}
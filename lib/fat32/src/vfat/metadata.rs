@@ -40,6 +40,23 @@ pub struct Metadata {
     pub start_cluster: Cluster
 }
 
+impl Timestamp {
+    /// The earliest representable FAT timestamp: 01/01/1980 00:00:00. Used as
+    /// the modification time of new entries when no time source is set.
+    pub const EPOCH: Timestamp = Timestamp { date: Date((1 << 5) | 1), time: Time(0) };
+
+    /// Packs a calendar date and time into the on-disk FAT representation.
+    /// `year` is clamped to [1980, 2107]; seconds are stored with a two
+    /// second granularity.
+    pub fn new(year: usize, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Timestamp {
+        let year = (core::cmp::max(1980, core::cmp::min(year, 2107)) - 1980) as u16;
+        Timestamp {
+            date: Date(year << 9 | ((month as u16 & 0xF) << 5) | (day as u16 & 0x1F)),
+            time: Time(((hour as u16 & 0x1F) << 11) | ((minute as u16 & 0x3F) << 5) | ((second as u16 / 2) & 0x1F)),
+        }
+    }
+}
+
 impl traits::Timestamp for Timestamp {
     fn year(&self) -> usize {
         ((self.date.0 >> 9) & 0x7F) as usize + 1980
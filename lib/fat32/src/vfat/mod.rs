@@ -4,18 +4,27 @@ pub(crate) mod dir;
 pub(crate) mod ebpb;
 pub(crate) mod entry;
 pub(crate) mod error;
+pub(crate) mod extent;
 pub(crate) mod fat;
 pub(crate) mod file;
+pub(crate) mod fsinfo;
+pub(crate) mod journal;
 pub(crate) mod metadata;
+pub(crate) mod name;
 pub(crate) mod vfat;
 
-pub use self::dir::Dir;
+pub use self::cache::{CacheStats, DEFAULT_CACHE_CAPACITY};
+pub use self::dir::{Dir, EntryLocation};
 pub use self::ebpb::BiosParameterBlock;
 pub use self::entry::Entry;
 pub use self::error::Error;
+pub use self::extent::{Extent, ExtentMap};
+pub use self::fat::FatType;
 pub use self::file::File;
+pub use self::fsinfo::{FsInfo, FS_INFO_UNKNOWN};
+pub use self::journal::{enable_journal, JOURNAL_NAME, MIN_JOURNAL_SECTORS};
 pub use self::metadata::{Attributes, Date, Metadata, Time, Timestamp};
-pub use self::vfat::{VFat, VFatHandle};
+pub use self::vfat::{StatFs, VFat, VFatHandle};
 
 pub(crate) use self::cache::{CachedPartition, Partition};
 pub(crate) use self::cluster::Cluster;
@@ -0,0 +1,164 @@
+use alloc::vec::Vec;
+
+use shim::io;
+use shim::ioerr;
+
+/// Characters other than ASCII alphanumerics that may appear in a short name.
+const SHORT_NAME_SPECIAL: &[u8] = b"$%'-_@~`!(){}^#&";
+
+/// Characters that may not appear in a long file name.
+const LONG_NAME_FORBIDDEN: &str = "\"*/:<>?\\|";
+
+/// The maximum length of a long file name in UTF-16 code units.
+pub const MAX_LONG_NAME_LEN: usize = 255;
+
+/// The number of UTF-16 code units stored in a single LFN record.
+pub const LFN_CHARS_PER_ENTRY: usize = 13;
+
+fn is_short_name_char(c: u8) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || SHORT_NAME_SPECIAL.contains(&c)
+}
+
+/// Checks that `name` may be used as a long file name.
+///
+/// # Errors
+///
+/// Returns `InvalidInput` if `name` is empty, `.` or `..`, longer than 255
+/// UTF-16 code units, ends in a space or period, or contains a control
+/// character or one of `"*/:<>?\|`.
+pub fn validate_long_name(name: &str) -> io::Result<()> {
+    if name.is_empty() || name == "." || name == ".." {
+        return ioerr!(InvalidInput, "invalid file name");
+    }
+    if name.encode_utf16().count() > MAX_LONG_NAME_LEN {
+        return ioerr!(InvalidInput, "file name is too long");
+    }
+    if name.ends_with(' ') || name.ends_with('.') {
+        return ioerr!(InvalidInput, "file name may not end in a space or period");
+    }
+    if name.chars().any(|c| c.is_control() || LONG_NAME_FORBIDDEN.contains(c)) {
+        return ioerr!(InvalidInput, "file name contains invalid characters");
+    }
+
+    Ok(())
+}
+
+/// Returns the space padded 8.3 name that represents `name` exactly, if
+/// there is one. Only upper-case names that fit the format qualify; any other
+/// name needs an LFN entry to preserve it.
+pub fn exact_short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, ext) = match name.rfind('.') {
+        Some(0) => return None,
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (name, ""),
+    };
+
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 {
+        return None;
+    }
+
+    let mut raw = [b' '; 11];
+    raw[..base.len()].copy_from_slice(base.as_bytes());
+    raw[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+    if base.bytes().chain(ext.bytes()).all(is_short_name_char) {
+        Some(raw)
+    } else {
+        None
+    }
+}
+
+/// Derives the Windows "basis" short name of `name`. Characters that can't
+/// appear in a short name are replaced with `_`, spaces and periods other
+/// than the extension separator are dropped and both parts are truncated.
+///
+/// The second value is `true` if information was lost along the way, in which
+/// case a numeric tail must be added to the basis name before it is used.
+pub fn basis_name(name: &str) -> ([u8; 11], bool) {
+    let trimmed = name.trim_start_matches('.');
+    let mut lossy = trimmed.len() != name.len();
+    let (base, ext) = match trimmed.rfind('.') {
+        Some(i) => (&trimmed[..i], &trimmed[i + 1..]),
+        None => (trimmed, ""),
+    };
+
+    fn convert(part: &str, max: usize, lossy: &mut bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        for c in part.chars() {
+            if c == ' ' || c == '.' {
+                *lossy = true;
+                continue;
+            }
+
+            let c = c.to_ascii_uppercase();
+            let byte = if c.is_ascii() && is_short_name_char(c as u8) {
+                c as u8
+            } else {
+                *lossy = true;
+                b'_'
+            };
+
+            if out.len() == max {
+                *lossy = true;
+                break;
+            }
+            out.push(byte);
+        }
+        out
+    }
+
+    let mut base = convert(base, 8, &mut lossy);
+    let ext = convert(ext, 3, &mut lossy);
+    if base.is_empty() {
+        base.push(b'_');
+        lossy = true;
+    }
+
+    let mut raw = [b' '; 11];
+    raw[..base.len()].copy_from_slice(&base);
+    raw[8..8 + ext.len()].copy_from_slice(&ext);
+    (raw, lossy)
+}
+
+/// Returns `basis` with the numeric tail `~n` appended to its base name,
+/// truncating the base name as needed to keep it within 8 characters.
+pub fn with_numeric_tail(basis: &[u8; 11], n: u32) -> [u8; 11] {
+    let tail = format!("~{}", n);
+    let base_len = basis[..8].iter().position(|&c| c == b' ').unwrap_or(8);
+    let keep = core::cmp::min(base_len, 8 - tail.len());
+
+    let mut raw = *basis;
+    raw[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+    for c in raw[keep + tail.len()..8].iter_mut() {
+        *c = b' ';
+    }
+    raw
+}
+
+/// Computes the checksum of a short name that is stored in each of the LFN
+/// entries belonging to it.
+pub fn lfn_checksum(short_name: &[u8; 11]) -> u8 {
+    short_name.iter().fold(0u8, |sum, &c| {
+        ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(c)
+    })
+}
+
+/// Splits `name` into the 13 character chunks stored in LFN entries. The
+/// name is terminated by a NUL if there is room and padded with `0xFFFF`.
+pub fn lfn_chunks(name: &str) -> Vec<[u16; LFN_CHARS_PER_ENTRY]> {
+    let mut chars: Vec<u16> = name.encode_utf16().collect();
+    if chars.len() % LFN_CHARS_PER_ENTRY != 0 {
+        chars.push(0);
+    }
+    while chars.len() % LFN_CHARS_PER_ENTRY != 0 {
+        chars.push(0xFFFF);
+    }
+
+    chars
+        .chunks(LFN_CHARS_PER_ENTRY)
+        .map(|chunk| {
+            let mut out = [0u16; LFN_CHARS_PER_ENTRY];
+            out.copy_from_slice(chunk);
+            out
+        })
+        .collect()
+}
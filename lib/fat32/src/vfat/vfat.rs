@@ -1,20 +1,19 @@
 use core::fmt::Debug;
 use core::marker::PhantomData;
-use core::mem::size_of;
 
 use alloc::vec::Vec;
 
 use shim::io;
 use shim::ioerr;
+use shim::ffi::OsStr;
 use shim::path::Path;
 use shim::path::Component;
 use core::cmp::min;
 
-use crate::mbr::MasterBootRecord;
+use crate::partition::{self, PartitionInfo};
 use crate::traits::{BlockDevice, FileSystem};
-use crate::util::SliceExt;
-use crate::vfat::{BiosParameterBlock, CachedPartition, Partition};
-use crate::vfat::{Cluster, Dir, Error, FatEntry, Status, Metadata};
+use crate::vfat::{BiosParameterBlock, CachedPartition, FsInfo, Partition, FS_INFO_UNKNOWN};
+use crate::vfat::{Cluster, Dir, Error, FatEntry, FatType, Status, Metadata, Timestamp};
 
 /// A generic trait that handles a critical section as a closure
 pub trait VFatHandle: Clone + Debug + Send + Sync {
@@ -29,46 +28,188 @@ pub struct VFat<HANDLE: VFatHandle> {
     pub bytes_per_sector: u16,
     pub sectors_per_cluster: u8,
     pub sectors_per_fat: u32,
+    pub num_fats: u8,
     pub fat_start_sector: u64,
     pub data_start_sector: u64,
+    /// The first cluster of the root directory. `Cluster(0)` on FAT12 and
+    /// FAT16 volumes, where the root directory has a fixed region instead.
     pub rootdir_cluster: Cluster,
+    pub total_clusters: u32,
+    pub fat_type: FatType,
+    /// The first sector of the fixed root directory region of a FAT12 or
+    /// FAT16 volume.
+    pub root_dir_start_sector: u64,
+    /// The number of records in the fixed root directory region, `0` on FAT32
+    /// volumes.
+    pub root_dir_entries: usize,
+    /// The sector holding the FSInfo structure, if the volume has a valid one.
+    fs_info_sector: Option<u64>,
+    /// The number of free clusters, if known.
+    free_clusters: Option<u32>,
+    /// The cluster to start searching at for the next allocation.
+    next_free: u32,
+    /// Whether `free_clusters` or `next_free` changed since the FSInfo
+    /// structure was last written.
+    fs_info_dirty: bool,
+    /// Counts changes to the FAT that shorten or relink an existing chain.
+    /// Extent maps built under an older generation are stale.
+    chain_generation: u64,
+    time_source: fn() -> Timestamp,
+}
+
+/// File system usage statistics, as returned by `VFat::statfs()`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct StatFs {
+    /// The size of a cluster in bytes.
+    pub bytes_per_cluster: u64,
+    /// The number of data clusters on the volume.
+    pub total_clusters: u32,
+    /// The number of clusters not allocated to any file or directory.
+    pub free_clusters: u32,
+}
+
+impl StatFs {
+    /// The number of clusters in use.
+    pub fn used_clusters(&self) -> u32 {
+        self.total_clusters - self.free_clusters
+    }
+
+    /// The size of the data region in bytes.
+    pub fn total_bytes(&self) -> u64 {
+        self.total_clusters as u64 * self.bytes_per_cluster
+    }
+
+    /// The number of bytes available for new data.
+    pub fn free_bytes(&self) -> u64 {
+        self.free_clusters as u64 * self.bytes_per_cluster
+    }
+
+    /// The number of bytes in use.
+    pub fn used_bytes(&self) -> u64 {
+        self.used_clusters() as u64 * self.bytes_per_cluster
+    }
 }
 
 impl<HANDLE: VFatHandle> VFat<HANDLE> {
+    /// Mounts the first FAT file system on `device`. Partitions that may
+    /// hold a FAT file system are tried in partition table order, which may
+    /// be an MBR, with extended partitions, or a GPT.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if there are no candidate partitions, or the error
+    /// encountered while reading the first candidate if none of them holds a
+    /// FAT file system.
     pub fn from<T>(mut device: T) -> Result<HANDLE, Error>
     where
         T: BlockDevice + 'static,
     {
-        let mbr = MasterBootRecord::from(&mut device)?;
-        let part = match mbr.partition_table.iter().find(|&partition| partition.partition_type == 0xB || partition.partition_type == 0xC) {
-            Some(v) => v,
-            None => return Err(Error::NotFound)
-        };
-        let ebpb = BiosParameterBlock::from(&mut device, part.relative_sector.into())?;
+        let partitions = partition::partitions(&mut device)?;
+        let mut error = Error::NotFound;
+        for part in partitions.iter().filter(|part| part.may_be_fat()) {
+            match BiosParameterBlock::from(&mut device, part.start) {
+                Ok(ebpb) => return VFat::mount(device, part, ebpb),
+                Err(e) => if let Error::NotFound = error {
+                    error = e;
+                }
+            }
+        }
+
+        Err(error)
+    }
+
+    /// Mounts the FAT file system in `partition` of `device`, as returned by
+    /// `partition::partitions()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadSignature` if the partition doesn't hold a FAT file system.
+    pub fn from_partition<T>(mut device: T, partition: &PartitionInfo) -> Result<HANDLE, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        let ebpb = BiosParameterBlock::from(&mut device, partition.start)?;
+        VFat::mount(device, partition, ebpb)
+    }
+
+    fn mount<T>(device: T, part: &PartitionInfo, ebpb: BiosParameterBlock) -> Result<HANDLE, Error>
+    where
+        T: BlockDevice + 'static,
+    {
         // we want to consume the device here
         let cached_partition = CachedPartition::new(device, Partition { 
-            start: part.relative_sector as u64, 
-            num_sectors: part.total_sectors as u64, 
+            start: part.start, 
+            num_sectors: part.num_sectors, 
             sector_size: ebpb.bytes_per_sector as u64,
 
         });
+        let bytes_per_sector = ebpb.bytes_per_sector as u64;
         let fat_start_sector = cached_partition.partition.start + ebpb.num_reserved_sectors as u64;
-        let data_start_sector = fat_start_sector + (ebpb.num_fats as u32 * ebpb.sectors_per_fat) as u64;
-        let vfat = VFat::<HANDLE> {
+        let root_dir_start_sector = fat_start_sector + ebpb.num_fats as u64 * ebpb.sectors_per_fat as u64;
+        let root_dir_sectors = (ebpb.max_dirs as u64 * 32 + bytes_per_sector - 1) / bytes_per_sector;
+        let data_start_sector = root_dir_start_sector + root_dir_sectors;
+        let data_sectors = (ebpb.total_logical_sectors as u64)
+            .saturating_sub(data_start_sector - cached_partition.partition.start);
+        let total_clusters = (data_sectors / ebpb.sectors_per_cluster as u64) as u32;
+
+        let fat_type = FatType::detect(ebpb.is_fat32_layout(), total_clusters);
+        let (rootdir_cluster, root_dir_entries) = match fat_type {
+            FatType::Fat32 => (Cluster::from(ebpb.root_cluster), 0),
+            _ if ebpb.max_dirs == 0 => return Err(Error::BadSignature),
+            _ => (Cluster(0), ebpb.max_dirs as usize),
+        };
+
+        let fs_info_sector = match (fat_type, ebpb.fs_info_sector) {
+            (FatType::Fat32, 0) | (FatType::Fat32, 0xFFFF) => None,
+            (FatType::Fat32, n) => Some(cached_partition.partition.start + n as u64),
+            _ => None,
+        };
+
+        let mut vfat = VFat::<HANDLE> {
             phantom: PhantomData,
             device: cached_partition,
             bytes_per_sector: ebpb.bytes_per_sector,
             sectors_per_cluster: ebpb.sectors_per_cluster,
             sectors_per_fat: ebpb.sectors_per_fat,
+            num_fats: ebpb.num_fats,
             fat_start_sector: fat_start_sector, 
             data_start_sector: data_start_sector,
-            rootdir_cluster: Cluster::from(ebpb.root_cluster)
+            rootdir_cluster: rootdir_cluster,
+            total_clusters: total_clusters,
+            fat_type: fat_type,
+            root_dir_start_sector: root_dir_start_sector,
+            root_dir_entries: root_dir_entries,
+            fs_info_sector: None,
+            free_clusters: None,
+            next_free: 2,
+            fs_info_dirty: false,
+            chain_generation: 0,
+            time_source: || Timestamp::EPOCH,
         };
 
+        // the journal may hold the latest FSInfo sector, so it goes first
+        vfat.load_journal()?;
+        vfat.load_fs_info(fs_info_sector);
+
         //println!("root cluster vfat {:#?}", &vfat.rootdir_cluster);
         Ok(HANDLE::new(vfat))
     }
 
+    /// Reads the FSInfo structure at `sector`, if the volume has one, and
+    /// takes the free cluster count and next free cluster hint from it. A
+    /// missing or corrupt FSInfo structure only costs a FAT scan.
+    fn load_fs_info(&mut self, sector: Option<u64>) {
+        let fs_info = match sector.and_then(|sector| FsInfo::from(&mut self.device, sector).ok()) {
+            Some(fs_info) => fs_info,
+            None => return
+        };
+
+        let total_clusters = self.total_clusters;
+        self.fs_info_sector = sector;
+        self.free_clusters = Some(fs_info.free_clusters).filter(|&n| n <= total_clusters);
+        self.next_free = Some(fs_info.next_free).filter(|&n| n >= 2 && n < total_clusters + 2).unwrap_or(2);
+    }
+
     //
     //  * A method to read from an offset of a cluster into a buffer.
     pub fn read_cluster(&mut self, cluster: Cluster, offset: usize, buf: &mut [u8]) -> io::Result<usize> {
@@ -82,7 +223,7 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
                     let end_idx = start_idx + self.bytes_per_sector as usize;
                     let curr_sector = cluster_data_start_sector + i as u64;
                     cluster_data.resize(cluster_data.len() + self.bytes_per_sector as usize, 0);
-                    cluster_data[start_idx..end_idx].clone_from_slice(self.device.get_mut(curr_sector)?);
+                    cluster_data[start_idx..end_idx].clone_from_slice(self.device.get(curr_sector)?);
                 }
 
                 let num_bytes_read = min(buf.len(), (self.bytes_per_sector * self.sectors_per_cluster as u16) as usize - offset);
@@ -134,24 +275,391 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
         Ok(num_bytes_read)
     }
 
+    /// Reads every record of the directory starting at `dir` into `buf`: the
+    /// clusters of its chain or, for the root directory of a FAT12 or FAT16
+    /// volume, its fixed region.
+    pub fn read_dir(&mut self, dir: Cluster, buf: &mut Vec<u8>) -> io::Result<usize> {
+        if !self.is_fixed_root(dir) {
+            return self.read_chain(dir, buf);
+        }
+
+        let bytes_per_sector = self.bytes_per_sector as usize;
+        let num_bytes = self.root_dir_entries * 32;
+        let start = buf.len();
+        buf.resize(start + num_bytes, 0);
+        for (i, chunk) in buf[start..].chunks_mut(bytes_per_sector).enumerate() {
+            let sector = self.device.get(self.root_dir_start_sector + i as u64)?;
+            chunk.copy_from_slice(&sector[..chunk.len()]);
+        }
+        Ok(num_bytes)
+    }
+
+    /// Returns `true` if `dir` refers to the fixed root directory region of a
+    /// FAT12 or FAT16 volume, which can't grow.
+    pub fn is_fixed_root(&self, dir: Cluster) -> bool {
+        self.root_dir_entries != 0 && dir == self.rootdir_cluster
+    }
+
+    /// Returns the number of bytes in a single cluster.
+    pub fn bytes_per_cluster(&self) -> usize {
+        self.bytes_per_sector as usize * self.sectors_per_cluster as usize
+    }
+
+    /// Returns the first sector of the data region backing `cluster`.
+    fn cluster_start_sector(&self, cluster: Cluster) -> u64 {
+        self.data_start_sector + ((cluster.0 - 2) as u64 * self.sectors_per_cluster as u64)
+    }
+
     //
-    //  * A method to return a reference to a `FatEntry` for a cluster where the
-    //    reference points directly into a cached sector.
+    //  * A method to write from a buffer into an offset of a cluster. At most
+    //    the remainder of the cluster past `offset` is written.
     //
-    pub fn fat_entry(&mut self, cluster: Cluster) -> io::Result<&FatEntry> {
-        let (sector, offset) = self.get_cluster_addr(cluster);
-        let sector_data = unsafe { self.device.get(sector)?.cast::<FatEntry>() };
-        Ok(&sector_data[offset / size_of::<FatEntry>()])
+    pub fn write_cluster(&mut self, cluster: Cluster, offset: usize, buf: &[u8]) -> io::Result<usize> {
+        match self.fat_entry(cluster)?.status() {
+            Status::Data(_) | Status::Eoc(_) => (),
+            _ => return ioerr!(InvalidData, "attempted to write non-data cluster")
+        };
+
+        let bytes_per_sector = self.bytes_per_sector as usize;
+        let num_bytes = min(buf.len(), self.bytes_per_cluster() - offset);
+        let start_sector = self.cluster_start_sector(cluster);
+        let mut written = 0;
+        while written < num_bytes {
+            let pos = offset + written;
+            let sector_offset = pos % bytes_per_sector;
+            let n = min(num_bytes - written, bytes_per_sector - sector_offset);
+            let sector = self.device.get_mut(start_sector + (pos / bytes_per_sector) as u64)?;
+            sector[sector_offset..sector_offset + n].copy_from_slice(&buf[written..written + n]);
+            written += n;
+        }
+
+        Ok(num_bytes)
+    }
+
+    /// Reads the `count` clusters starting at `cluster`, which must be
+    /// contiguous on disk, into the sector cache ahead of their use. See
+    /// `CachedPartition::prefetch()`.
+    pub fn prefetch_clusters(&mut self, cluster: Cluster, count: u32) -> io::Result<()> {
+        let start_sector = self.cluster_start_sector(cluster);
+        self.device.prefetch(start_sector, count as u64 * self.sectors_per_cluster as u64)
+    }
+
+    /// Fills the data region of `cluster` with zeroes.
+    fn zero_cluster(&mut self, cluster: Cluster) -> io::Result<()> {
+        let start_sector = self.cluster_start_sector(cluster);
+        for i in 0..self.sectors_per_cluster as u64 {
+            for byte in self.device.get_mut(start_sector + i)?.iter_mut() {
+                *byte = 0;
+            }
+        }
+        Ok(())
     }
-        
-    // converts cluster to a sector and an offset within that sector
-    // inside the FAT
-    fn get_cluster_addr(&self, cluster: Cluster) -> (u64, usize) {
-        //let entries_per_sector = self.bytes_per_sector / 32;
-        let sector = self.fat_start_sector + (cluster.0 * size_of::<FatEntry>() as u32 / self.bytes_per_sector as u32) as u64;
-        let offset = cluster.0 * size_of::<FatEntry>() as u32 % self.bytes_per_sector as u32; 
 
-        (sector, offset as usize)
+    /// Returns the cluster following `cluster` in its chain, or `None` if
+    /// `cluster` is the last cluster of the chain.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidData` if `cluster` is not part of a valid chain.
+    pub fn next_cluster(&mut self, cluster: Cluster) -> io::Result<Option<Cluster>> {
+        match self.fat_entry(cluster)?.status() {
+            Status::Data(next) => Ok(Some(next)),
+            Status::Eoc(_) => Ok(None),
+            _ => ioerr!(InvalidData, "invalid cluster chain")
+        }
+    }
+
+    /// Returns the last cluster of the chain beginning at `start`.
+    pub fn last_cluster(&mut self, start: Cluster) -> io::Result<Cluster> {
+        let mut curr_cluster = start;
+        while let Some(next) = self.next_cluster(curr_cluster)? {
+            curr_cluster = next;
+        }
+        Ok(curr_cluster)
+    }
+
+    /// Finds a free cluster, marks it as the end of a chain and zeroes its
+    /// contents. If `prev` is `Some`, the new cluster is appended after it.
+    ///
+    /// The search starts at the FSInfo next-free hint and wraps around.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `Other` if the volume has no free clusters left.
+    pub fn alloc_cluster(&mut self, prev: Option<Cluster>) -> io::Result<Cluster> {
+        let mut free = None;
+        if self.free_clusters != Some(0) {
+            for i in 0..self.total_clusters {
+                let raw = 2 + (self.next_free - 2 + i) % self.total_clusters;
+                if self.fat_entry(Cluster(raw))?.status() == Status::Free {
+                    free = Some(Cluster(raw));
+                    break;
+                }
+            }
+        }
+
+        let cluster = match free {
+            Some(c) => c,
+            None => return ioerr!(Other, "no free clusters left on volume")
+        };
+
+        self.set_fat_entry(cluster, 0x0FFFFFFF)?;
+        self.zero_cluster(cluster)?;
+        if let Some(prev) = prev {
+            self.set_fat_entry(prev, cluster.0)?;
+        }
+        self.next_free = if cluster.0 + 1 < self.total_clusters + 2 { cluster.0 + 1 } else { 2 };
+        self.fs_info_dirty = true;
+
+        Ok(cluster)
+    }
+
+    /// Marks every cluster in the chain beginning at `start` as free.
+    pub fn free_chain(&mut self, start: Cluster) -> io::Result<()> {
+        let mut curr_cluster = Some(start);
+        while let Some(cluster) = curr_cluster {
+            curr_cluster = self.next_cluster(cluster)?;
+            self.set_fat_entry(cluster, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Shortens the chain beginning at `start` to its first `keep` clusters,
+    /// freeing the rest. `keep` must be at least 1; use `free_chain()` to
+    /// release a chain entirely.
+    pub fn truncate_chain(&mut self, start: Cluster, keep: usize) -> io::Result<()> {
+        let mut last = start;
+        for _ in 1..keep {
+            last = match self.next_cluster(last)? {
+                Some(next) => next,
+                None => return Ok(())
+            };
+        }
+
+        if let Some(rest) = self.next_cluster(last)? {
+            self.set_fat_entry(last, 0x0FFFFFFF)?;
+            self.free_chain(rest)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the sector and the byte offset within it of the `index`th
+    /// 32-byte entry of the directory whose chain begins at `dir`.
+    fn dir_entry_addr(&mut self, dir: Cluster, index: usize) -> io::Result<(u64, usize)> {
+        let bytes_per_sector = self.bytes_per_sector as usize;
+        if self.is_fixed_root(dir) {
+            if index >= self.root_dir_entries {
+                return ioerr!(NotFound, "directory entry index out of range");
+            }
+            let byte_offset = index * 32;
+            return Ok((self.root_dir_start_sector + (byte_offset / bytes_per_sector) as u64,
+                       byte_offset % bytes_per_sector));
+        }
+
+        let entries_per_cluster = self.bytes_per_cluster() / 32;
+        let mut cluster = dir;
+        for _ in 0..index / entries_per_cluster {
+            cluster = match self.next_cluster(cluster)? {
+                Some(next) => next,
+                None => return ioerr!(NotFound, "directory entry index out of range")
+            };
+        }
+
+        let byte_offset = (index % entries_per_cluster) * 32;
+        let sector = self.cluster_start_sector(cluster) + (byte_offset / bytes_per_sector) as u64;
+        Ok((sector, byte_offset % bytes_per_sector))
+    }
+
+    /// Reads the raw `index`th entry of the directory starting at `dir`.
+    pub fn read_dir_entry(&mut self, dir: Cluster, index: usize) -> io::Result<[u8; 32]> {
+        let (sector, offset) = self.dir_entry_addr(dir, index)?;
+        let mut raw = [0u8; 32];
+        raw.copy_from_slice(&self.device.get(sector)?[offset..offset + 32]);
+        Ok(raw)
+    }
+
+    /// Overwrites the raw `index`th entry of the directory starting at `dir`.
+    pub fn write_dir_entry(&mut self, dir: Cluster, index: usize, raw: &[u8; 32]) -> io::Result<()> {
+        let (sector, offset) = self.dir_entry_addr(dir, index)?;
+        self.device.get_mut_metadata(sector)?[offset..offset + 32].copy_from_slice(raw);
+        Ok(())
+    }
+
+    /// Writes all modified sectors back to the underlying block device,
+    /// updating the FSInfo structure first if it is out of date.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let (true, Some(sector)) = (self.fs_info_dirty, self.fs_info_sector) {
+            let fs_info = FsInfo::new(self.free_clusters.unwrap_or(FS_INFO_UNKNOWN), self.next_free);
+            self.device.get_mut_metadata(sector)?[..512].copy_from_slice(fs_info.as_bytes());
+            self.fs_info_dirty = false;
+        }
+
+        self.device.flush()
+    }
+
+    /// Returns the number of free clusters, counting them in the FAT if the
+    /// FSInfo structure didn't provide a trustworthy count.
+    pub fn free_clusters(&mut self) -> io::Result<u32> {
+        if let Some(free) = self.free_clusters {
+            return Ok(free);
+        }
+
+        let mut free = 0;
+        for raw in 2..self.total_clusters + 2 {
+            if self.fat_entry(Cluster(raw))?.status() == Status::Free {
+                free += 1;
+            }
+        }
+        self.set_free_clusters(free);
+        Ok(free)
+    }
+
+    /// Overrides the number of free clusters recorded for the volume.
+    pub(crate) fn set_free_clusters(&mut self, free: u32) {
+        self.free_clusters = Some(free);
+        self.fs_info_dirty = true;
+    }
+
+    /// Returns the free clusters count as currently recorded, without
+    /// counting them if it is unknown.
+    pub(crate) fn recorded_free_clusters(&self) -> Option<u32> {
+        self.free_clusters
+    }
+
+    /// Returns usage statistics for the volume.
+    pub fn statfs(&mut self) -> io::Result<StatFs> {
+        Ok(StatFs {
+            bytes_per_cluster: self.bytes_per_cluster() as u64,
+            total_clusters: self.total_clusters,
+            free_clusters: self.free_clusters()?,
+        })
+    }
+
+    /// Sets the function used to timestamp created and modified entries. The
+    /// Pi has no real-time clock, so by default `Timestamp::EPOCH` is used.
+    pub fn set_time_source(&mut self, time_source: fn() -> Timestamp) {
+        self.time_source = time_source;
+    }
+
+    /// Returns the current time according to the configured time source.
+    pub fn now(&self) -> Timestamp {
+        (self.time_source)()
+    }
+
+    //
+    //  * A method to return the `FatEntry` for a cluster. Entries of FAT12 and
+    //    FAT16 volumes are widened to the FAT32 value with the same meaning.
+    //
+    pub fn fat_entry(&mut self, cluster: Cluster) -> io::Result<FatEntry> {
+        let raw = self.raw_fat_entry(0, cluster)?;
+        Ok(self.fat_type.widen(raw))
+    }
+
+    //
+    //  * A method to overwrite the FAT entry for `cluster` in every FAT copy.
+    //    `value` is truncated to the width of the volume's entries, the
+    //    reserved upper four bits of an existing FAT32 entry are preserved and
+    //    the free cluster count follows the change.
+    //
+    pub(crate) fn set_fat_entry(&mut self, cluster: Cluster, value: u32) -> io::Result<()> {
+        let mask = self.fat_type.mask();
+        let old_status = self.fat_entry(cluster)?.status();
+        let was_free = old_status == Status::Free;
+        let is_free = value & mask == 0;
+
+        // allocating a cluster or appending to a chain leaves every existing
+        // chain as it was; anything else may change one
+        let is_append = match (old_status, self.fat_type.widen(value).status()) {
+            (Status::Free, _) | (Status::Eoc(_), Status::Data(_)) => true,
+            _ => false
+        };
+        if !is_append {
+            self.chain_generation += 1;
+        }
+        if let (Some(free), true) = (self.free_clusters, was_free != is_free) {
+            self.free_clusters = Some(if is_free { free + 1 } else { free.saturating_sub(1) });
+            self.fs_info_dirty = true;
+        }
+
+        for copy in 0..self.num_fats {
+            let old = self.raw_fat_entry(copy, cluster)?;
+            self.set_raw_fat_entry(copy, cluster, (old & !mask) | (value & mask))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of times an existing cluster chain was shortened or
+    /// relinked since the volume was mounted. See `ExtentMap`.
+    pub fn chain_generation(&self) -> u64 {
+        self.chain_generation
+    }
+
+    /// Returns the raw value of `cluster`'s entry in FAT copy `copy`.
+    pub(crate) fn raw_fat_entry(&mut self, copy: u8, cluster: Cluster) -> io::Result<u32> {
+        let mut bytes = [0u8; 4];
+        let num_bytes = self.fat_type.entry_bytes();
+        self.read_fat_bytes(copy, self.fat_type.entry_offset(cluster), &mut bytes[..num_bytes])?;
+        let value = u32::from_le_bytes(bytes);
+        Ok(match self.fat_type {
+            // odd entries live in the upper 12 bits of their two bytes
+            FatType::Fat12 if cluster.0 % 2 == 1 => value >> 4,
+            FatType::Fat12 => value & 0xFFF,
+            _ => value,
+        })
+    }
+
+    /// Overwrites `cluster`'s entry in FAT copy `copy` with the raw value
+    /// `value`, leaving the bits the entry shares with its neighbour alone.
+    pub(crate) fn set_raw_fat_entry(&mut self, copy: u8, cluster: Cluster, value: u32) -> io::Result<()> {
+        let offset = self.fat_type.entry_offset(cluster);
+        let num_bytes = self.fat_type.entry_bytes();
+        let mut bytes = [0u8; 4];
+        let value = match self.fat_type {
+            FatType::Fat12 => {
+                self.read_fat_bytes(copy, offset, &mut bytes[..num_bytes])?;
+                let shared = u32::from_le_bytes(bytes);
+                match cluster.0 % 2 {
+                    1 => (shared & 0x000F) | ((value & 0xFFF) << 4),
+                    _ => (shared & 0xF000) | (value & 0xFFF),
+                }
+            },
+            _ => value,
+        };
+
+        self.write_fat_bytes(copy, offset, &value.to_le_bytes()[..num_bytes])
+    }
+
+    /// Reads `buf.len()` bytes at byte `offset` of FAT copy `copy`. FAT12
+    /// entries may straddle two sectors.
+    fn read_fat_bytes(&mut self, copy: u8, offset: usize, buf: &mut [u8]) -> io::Result<()> {
+        let bytes_per_sector = self.bytes_per_sector as usize;
+        let fat_start = self.fat_start_sector + copy as u64 * self.sectors_per_fat as u64;
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done;
+            let sector_offset = pos % bytes_per_sector;
+            let n = min(buf.len() - done, bytes_per_sector - sector_offset);
+            let sector = self.device.get(fat_start + (pos / bytes_per_sector) as u64)?;
+            buf[done..done + n].copy_from_slice(&sector[sector_offset..sector_offset + n]);
+            done += n;
+        }
+        Ok(())
+    }
+
+    /// Writes `buf` at byte `offset` of FAT copy `copy`.
+    fn write_fat_bytes(&mut self, copy: u8, offset: usize, buf: &[u8]) -> io::Result<()> {
+        let bytes_per_sector = self.bytes_per_sector as usize;
+        let fat_start = self.fat_start_sector + copy as u64 * self.sectors_per_fat as u64;
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done;
+            let sector_offset = pos % bytes_per_sector;
+            let n = min(buf.len() - done, bytes_per_sector - sector_offset);
+            let sector = self.device.get_mut_metadata(fat_start + (pos / bytes_per_sector) as u64)?;
+            sector[sector_offset..sector_offset + n].copy_from_slice(&buf[done..done + n]);
+            done += n;
+        }
+        Ok(())
     }
 }
 
@@ -176,7 +684,8 @@ impl<'a, HANDLE: VFatHandle> FileSystem for &'a HANDLE {
                         start_cluster: self.lock(|vfat: &mut VFat<HANDLE>| -> Cluster {
                             vfat.rootdir_cluster
                         }),
-                        name: "root".to_string()
+                        name: "root".to_string(),
+                        location: None
                     }));
                 }
                 Component::Normal(name) => {
@@ -210,4 +719,38 @@ impl<'a, HANDLE: VFatHandle> FileSystem for &'a HANDLE {
 
         Ok(entry)
     }
+
+    fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self::File> {
+        let (parent, name) = split_parent(path.as_ref())?;
+        self.open_dir(parent)?.create_file(name)
+    }
+
+    fn create_dir<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Dir> {
+        let (parent, name) = split_parent(path.as_ref())?;
+        self.open_dir(parent)?.create_dir(name)
+    }
+
+    fn remove<P: AsRef<Path>>(self, path: P) -> io::Result<()> {
+        let (parent, name) = split_parent(path.as_ref())?;
+        self.open_dir(parent)?.remove(name)
+    }
+
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, from: P, to: Q) -> io::Result<()> {
+        let (from_parent, from_name) = split_parent(from.as_ref())?;
+        let (to_parent, to_name) = split_parent(to.as_ref())?;
+        let to_dir = self.open_dir(to_parent)?;
+        self.open_dir(from_parent)?.rename(from_name, &to_dir, to_name)
+    }
+}
+
+/// Splits `path` into its parent directory and final component.
+///
+/// # Errors
+///
+/// Returns `InvalidInput` if `path` has no parent or does not end in a name.
+pub(crate) fn split_parent(path: &Path) -> io::Result<(&Path, &OsStr)> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => Ok((parent, name)),
+        _ => ioerr!(InvalidInput, "path does not name an entry in a directory")
+    }
 }
@@ -79,3 +79,49 @@ pub const NR_TIME: usize = 2;
 pub const NR_EXIT: usize = 3;
 pub const NR_WRITE: usize = 4;
 pub const NR_GETPID: usize = 5;
+pub const NR_FORK: usize = 6;
+pub const NR_EXEC: usize = 7;
+pub const NR_WAIT: usize = 8;
+pub const NR_OPEN: usize = 9;
+pub const NR_READ: usize = 10;
+pub const NR_CLOSE: usize = 11;
+pub const NR_LSEEK: usize = 12;
+pub const NR_FSTAT: usize = 13;
+
+/// The process ID `NR_WAIT` takes to wait for any child.
+pub const WAIT_ANY: u64 = core::u64::MAX;
+
+/// `NR_OPEN` flag: open for reading only.
+pub const O_RDONLY: u64 = 0;
+/// `NR_OPEN` flag: open for writing only.
+pub const O_WRONLY: u64 = 1;
+/// `NR_OPEN` flag: open for reading and writing.
+pub const O_RDWR: u64 = 2;
+/// The bits of the `NR_OPEN` flags holding one of the three above.
+pub const O_ACCMODE: u64 = 3;
+/// `NR_OPEN` flag: create the file if it does not exist.
+pub const O_CREAT: u64 = 0o100;
+
+/// `NR_LSEEK` whence: the offset is from the start of the file.
+pub const SEEK_SET: u64 = 0;
+/// `NR_LSEEK` whence: the offset is from the current offset.
+pub const SEEK_CUR: u64 = 1;
+/// `NR_LSEEK` whence: the offset is from the end of the file.
+pub const SEEK_END: u64 = 2;
+
+/// `Stat::kind` of the console.
+pub const S_IFCHR: u64 = 1;
+/// `Stat::kind` of a regular file.
+pub const S_IFREG: u64 = 2;
+
+/// What `NR_FSTAT` reports about an open descriptor.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Stat {
+    /// One of the `S_IF*` constants.
+    pub kind: u64,
+    /// The size of the file in bytes, or `0` for the console.
+    pub size: u64,
+    /// `1` if the file system marks the file read-only, `0` otherwise.
+    pub read_only: u64,
+}
@@ -56,26 +56,132 @@ pub fn time() -> Duration {
     Duration::from_secs(secs) + Duration::from_nanos(nanos)
 }
 
-pub fn exit() -> ! {
+pub fn exit(status: i32) -> ! {
     unsafe {
-        asm!("svc $0"
-            :: "i"(NR_EXIT)
-            :: "volatile");
+        asm!("mov x0, $0
+              svc $1"
+            :: "r"(status), "i"(NR_EXIT)
+            : "x0"
+            : "volatile");
     }
     loop {}
 }
 
-pub fn write(b: u8) {
+/// Opens the file at `path` with the `O_*` flags `flags` and returns its
+/// file descriptor.
+pub fn open(path: &str, flags: u64) -> OsResult<u64> {
+    let mut ecode: u64;
+    let mut fd: u64;
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(fd), "=r"(ecode)
+            : "r"(path.as_ptr()), "r"(path.len()), "r"(flags), "i"(NR_OPEN)
+            : "x0", "x1", "x2", "x7"
+            : "volatile");
+    }
+
+    err_or!(ecode, fd)
+}
+
+/// Reads from `fd` into `buf` and returns the number of bytes read, which is
+/// `0` at the end of the file.
+pub fn read(fd: u64, buf: &mut [u8]) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut len: u64;
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(len), "=r"(ecode)
+            : "r"(fd), "r"(buf.as_mut_ptr()), "r"(buf.len()), "i"(NR_READ)
+            : "x0", "x1", "x2", "x7"
+            : "volatile");
+    }
+
+    err_or!(ecode, len as usize)
+}
+
+/// Writes `buf` to `fd` and returns the number of bytes written.
+pub fn write(fd: u64, buf: &[u8]) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut len: u64;
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(len), "=r"(ecode)
+            : "r"(fd), "r"(buf.as_ptr()), "r"(buf.len()), "i"(NR_WRITE)
+            : "x0", "x1", "x2", "x7"
+            : "volatile");
+    }
+
+    err_or!(ecode, len as usize)
+}
+
+/// Closes `fd`, writing back what was written through it.
+pub fn close(fd: u64) -> OsResult<()> {
     let mut ecode: u64;
     unsafe {
         asm!("mov x0, $1
               svc $2
               mov $0, x7"
             : "=r"(ecode)
-            : "r"(b), "i"(NR_WRITE)
+            : "r"(fd), "i"(NR_CLOSE)
             : "x0", "x7"
             : "volatile");
     }
+
+    err_or!(ecode, ())
+}
+
+/// Moves the offset of `fd` to `offset` from the position `whence`, one of
+/// the `SEEK_*` constants, and returns the new offset.
+pub fn lseek(fd: u64, offset: i64, whence: u64) -> OsResult<u64> {
+    let mut ecode: u64;
+    let mut new_offset: u64;
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(new_offset), "=r"(ecode)
+            : "r"(fd), "r"(offset), "r"(whence), "i"(NR_LSEEK)
+            : "x0", "x1", "x2", "x7"
+            : "volatile");
+    }
+
+    err_or!(ecode, new_offset)
+}
+
+/// Returns what `fd` refers to.
+pub fn fstat(fd: u64) -> OsResult<Stat> {
+    let mut ecode: u64;
+    let mut stat = Stat::default();
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              svc $3
+              mov $0, x7"
+            : "=r"(ecode)
+            : "r"(fd), "r"(&mut stat as *mut Stat), "i"(NR_FSTAT)
+            : "x0", "x1", "x7"
+            : "volatile");
+    }
+
+    err_or!(ecode, stat)
 }
 
 pub fn getpid() -> u64 {
@@ -94,13 +200,82 @@ pub fn getpid() -> u64 {
     pid
 }
 
+pub fn fork() -> OsResult<u64> {
+    let mut ecode: u64;
+    let mut pid: u64;
+    unsafe {
+        asm!("svc $2
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(pid), "=r"(ecode)
+            : "i"(NR_FORK)
+            : "x0", "x7"
+            : "volatile");
+    }
+
+    err_or!(ecode, pid)
+}
+
+/// Replaces the program of the current process with the one at `path`,
+/// passing it `args`. Only returns if that fails.
+pub fn exec(path: &str, args: &[&str]) -> OsError {
+    let mut ecode: u64;
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              mov x2, $3
+              mov x3, $4
+              svc $5
+              mov $0, x7"
+            : "=r"(ecode)
+            : "r"(path.as_ptr()), "r"(path.len()), "r"(args.as_ptr()), "r"(args.len()), "i"(NR_EXEC)
+            : "x0", "x1", "x2", "x3", "x7"
+            : "volatile");
+    }
+
+    OsError::from(ecode)
+}
+
+fn do_wait(pid: u64) -> OsResult<(u64, i32)> {
+    let mut ecode: u64;
+    let mut child: u64;
+    let mut status: u64;
+    unsafe {
+        asm!("mov x0, $3
+              svc $4
+              mov $0, x0
+              mov $1, x1
+              mov $2, x7"
+            : "=r"(child), "=r"(status), "=r"(ecode)
+            : "r"(pid), "i"(NR_WAIT)
+            : "x0", "x1", "x7"
+            : "volatile");
+    }
+
+    err_or!(ecode, (child, status as i32))
+}
+
+/// Waits for any child to exit and returns its ID and exit status.
+pub fn wait() -> OsResult<(u64, i32)> {
+    do_wait(WAIT_ANY)
+}
+
+/// Waits for the child `pid` to exit and returns its exit status.
+pub fn waitpid(pid: u64) -> OsResult<i32> {
+    do_wait(pid).map(|(_, status)| status)
+}
+
 
 struct Console;
 
 impl fmt::Write for Console {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for b in s.bytes() {
-            write(b);
+        let mut buf = s.as_bytes();
+        while !buf.is_empty() {
+            match write(1, buf) {
+                Ok(n) => buf = &buf[n..],
+                Err(_) => return Err(fmt::Error),
+            }
         }
         Ok(())
     }
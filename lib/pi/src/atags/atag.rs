@@ -1,13 +1,14 @@
 use crate::atags::raw;
 use core::slice;
 
-pub use crate::atags::raw::{Core, Mem};
+pub use crate::atags::raw::{Core, Initrd2, Mem};
 
 /// An ATAG.
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Atag {
     Core(raw::Core),
     Mem(raw::Mem),
+    Initrd2(raw::Initrd2),
     Cmd(&'static str),
     Unknown(u32),
     None,
@@ -30,6 +31,14 @@ impl Atag {
         }
     }
 
+    /// Returns `Some` if this is an `Initrd2` ATAG. Otherwise returns `None`.
+    pub fn initrd2(self) -> Option<Initrd2> {
+        match self {
+            Atag::Initrd2(raw) => Some(raw),
+            _ => None
+        }
+    }
+
     /// Returns `Some` with the command line string if this is a `Cmd` ATAG.
     /// Otherwise returns `None`.
     pub fn cmd(self) -> Option<&'static str> {
@@ -51,6 +60,9 @@ impl From<&'static raw::Atag> for Atag {
                 (raw::Atag::MEM, &raw::Kind { mem }) => {
                     Atag::Mem(mem)
                 },
+                (raw::Atag::INITRD2, &raw::Kind { initrd2 }) => {
+                    Atag::Initrd2(initrd2)
+                },
                 (raw::Atag::CMDLINE, &raw::Kind { ref cmd }) => {
                     let cmdline = unsafe {
                         // find the size of the string by incrementing the u8 pointer
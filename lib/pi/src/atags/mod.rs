@@ -4,7 +4,7 @@ mod raw;
 pub use self::atag::*;
 
 /// The address at which the firmware loads the ATAGS.
-const ATAG_BASE: usize = 0x100;
+pub(crate) const ATAG_BASE: usize = 0x100;
 
 /// An iterator over the ATAGS on this system.
 pub struct Atags {
@@ -42,7 +42,7 @@ impl Iterator for Atags {
 mod test {
     use super::{raw, Atag, Atags};
 
-    const MEM: [u32; 23] = [
+    const MEM: [u32; 27] = [
         // CORE
         5,
         raw::Atag::CORE,
@@ -58,6 +58,11 @@ mod test {
         3,
         raw::Atag::RAMDISK,
         1010,
+        // INITRD2
+        4,
+        raw::Atag::INITRD2,
+        0x0800_0000,
+        4096,
         // CMDLINE
         4,
         raw::Atag::CMDLINE,
@@ -99,6 +104,14 @@ mod test {
 
         assert_eq!(atags.next(), Some(Atag::Unknown(raw::Atag::RAMDISK)));
 
+        assert_eq!(
+            atags.next(),
+            Some(Atag::Initrd2(raw::Initrd2 {
+                start: 0x0800_0000,
+                size: 4096,
+            }))
+        );
+
         assert_eq!(atags.next(), Some(Atag::Cmd("hello")));
 
         assert_eq!(atags.next(), Some(Atag::Unknown(raw::Atag::REVISION)));
@@ -40,6 +40,7 @@ impl Atag {
 pub union Kind {
     pub core: Core,
     pub mem: Mem,
+    pub initrd2: Initrd2,
     pub cmd: Cmd,
 }
 
@@ -60,6 +61,16 @@ pub struct Mem {
     pub start: u32,
 }
 
+/// An `INITRD2` ATAG: where the firmware loaded the initial ramdisk.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Initrd2 {
+    /// The physical address of the ramdisk.
+    pub start: u32,
+    /// The size of the ramdisk in bytes.
+    pub size: u32,
+}
+
 /// A `CMDLINE` ATAG.
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
@@ -0,0 +1,87 @@
+//! What the firmware tells the kernel about the system, from a device tree
+//! if it passed one and from ATAGs otherwise.
+
+use crate::atags::{Atags, ATAG_BASE};
+use crate::fdt::Fdt;
+
+/// The address of the device tree blob the firmware passed in `x0`, or `0`.
+static mut DTB_ADDRESS: usize = 0;
+
+/// Records `addr`, the value of `x0` when the kernel was entered, as where
+/// the firmware may have passed a device tree blob. `BootInfo::get()` also
+/// looks for one where the ATAGs would be.
+///
+/// # Safety
+///
+/// Must be called before `BootInfo::get()`, on a single core, with `addr`
+/// either `0` or readable for at least the 40 bytes of a device tree header.
+pub unsafe fn set_dtb_address(addr: usize) {
+    DTB_ADDRESS = addr;
+}
+
+/// The source of the boot information.
+#[derive(Debug, Copy, Clone)]
+pub enum BootInfo {
+    Fdt(Fdt<'static>),
+    Atags,
+}
+
+impl BootInfo {
+    /// Returns the device tree the firmware passed, if there is a valid one
+    /// at the address recorded by `set_dtb_address()` or at the ATAG address,
+    /// and `BootInfo::Atags` otherwise.
+    pub fn get() -> BootInfo {
+        let dtb_address = unsafe { DTB_ADDRESS };
+        for &addr in [dtb_address, ATAG_BASE].iter().filter(|&&addr| addr != 0) {
+            if let Ok(fdt) = unsafe { Fdt::from_addr(addr) } {
+                return BootInfo::Fdt(fdt);
+            }
+        }
+        BootInfo::Atags
+    }
+
+    /// Returns the `(start, size)` of the first range of physical memory.
+    pub fn memory(&self) -> Option<(usize, usize)> {
+        match self {
+            BootInfo::Fdt(fdt) => fdt.memory()
+                .find(|&(_, size)| size > 0)
+                .map(|(start, size)| (start as usize, size as usize)),
+            BootInfo::Atags => Atags::get()
+                .filter_map(|atag| atag.mem())
+                .last()
+                .map(|mem| (mem.start as usize, mem.size as usize)),
+        }
+    }
+
+    /// Returns the `(start, end)` addresses of the initial ramdisk, if the
+    /// firmware loaded one. The end address is exclusive.
+    pub fn initrd(&self) -> Option<(usize, usize)> {
+        match self {
+            BootInfo::Fdt(fdt) => fdt.initrd().map(|(start, end)| (start as usize, end as usize)),
+            BootInfo::Atags => Atags::get()
+                .filter_map(|atag| atag.initrd2())
+                .find(|initrd| initrd.size > 0)
+                .map(|initrd| (initrd.start as usize, initrd.start as usize + initrd.size as usize)),
+        }
+    }
+
+    /// Returns the `(start, end)` addresses of the device tree blob, if the
+    /// information comes from one. The end address is exclusive.
+    pub fn dtb(&self) -> Option<(usize, usize)> {
+        match self {
+            BootInfo::Fdt(fdt) => {
+                let start = fdt.as_bytes().as_ptr() as usize;
+                Some((start, start + fdt.as_bytes().len()))
+            }
+            BootInfo::Atags => None,
+        }
+    }
+
+    /// Returns the kernel command line.
+    pub fn bootargs(&self) -> Option<&'static str> {
+        match self {
+            BootInfo::Fdt(fdt) => fdt.bootargs(),
+            BootInfo::Atags => Atags::get().filter_map(|atag| atag.cmd()).next(),
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use core::convert::TryInto;
+use core::time::Duration;
+
+use crate::common::IO_BASE;
+use crate::timer;
+use volatile::prelude::*;
+use volatile::{ReadVolatile, Reserved, Volatile};
+
+/// The base address for the EMMC controller registers.
+const EMMC_REG_BASE: usize = IO_BASE + 0x300000;
+
+/// The size of a block written by `write_block`.
+pub const BLOCK_SIZE: usize = 512;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    __r0: Reserved<u32>,
+    BLKSIZECNT: Volatile<u32>,
+    ARG1: Volatile<u32>,
+    CMDTM: Volatile<u32>,
+    RESP: [ReadVolatile<u32>; 4],
+    DATA: Volatile<u32>,
+    STATUS: ReadVolatile<u32>,
+    __r1: [Reserved<u32>; 2],
+    INTERRUPT: Volatile<u32>,
+}
+
+// `CMDTM` values of the commands the driver sends.
+const CARD_DESELECT: u32 = 0x0700_0000;
+const SEND_REL_ADDR: u32 = 0x0302_0000;
+const SEND_CSD: u32 = 0x0901_0000;
+const CARD_SELECT: u32 = 0x0703_0000;
+const WRITE_SINGLE: u32 = 0x1822_0000;
+
+// `STATUS` bits.
+const SR_CMD_INHIBIT: u32 = 1 << 0;
+const SR_DAT_INHIBIT: u32 = 1 << 1;
+
+// `INTERRUPT` bits.
+const INT_CMD_DONE: u32 = 1 << 0;
+const INT_DATA_DONE: u32 = 1 << 1;
+const INT_WRITE_RDY: u32 = 1 << 4;
+const INT_CMD_TIMEOUT: u32 = 1 << 16;
+const INT_DATA_TIMEOUT: u32 = 1 << 20;
+const INT_ERROR_MASK: u32 = 0x017E_8000;
+
+// Card responses.
+const R6_ERRORS: u32 = 0xe000;
+
+/// An error talking to the SD card.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The controller or the card did not respond in time.
+    TimedOut,
+    /// The controller reported an error, or the card rejected a command.
+    Command,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// How an SD card in the transfer state addresses its blocks, as learned by
+/// `Emmc::card()`.
+#[derive(Debug, Copy, Clone)]
+pub struct Card {
+    /// Whether the card is addressed by block (SDHC/SDXC) instead of by byte.
+    high_capacity: bool,
+}
+
+/// The Raspberry Pi EMMC controller, driving the SD card slot.
+///
+/// The controller is initialized, and the card read, by `libsd`; this driver
+/// only adds the commands it lacks. Only one `Emmc` may be used at a time, and
+/// not while `libsd` is.
+pub struct Emmc {
+    registers: &'static mut Registers,
+}
+
+impl Emmc {
+    /// Returns a new instance of `Emmc`.
+    pub fn new() -> Emmc {
+        Emmc {
+            registers: unsafe { &mut *(EMMC_REG_BASE as *mut Registers) },
+        }
+    }
+
+    /// Reads the card-specific data register of the selected card to learn
+    /// how it is addressed. The card is deselected to do so and selected
+    /// again under a new relative card address.
+    pub fn card(&mut self) -> Result<Card> {
+        self.command(CARD_DESELECT, 0)?;
+        let r6 = self.command(SEND_REL_ADDR, 0)?;
+        if r6 & R6_ERRORS != 0 {
+            return Err(Error::Command);
+        }
+        let rca = r6 & 0xffff_0000;
+
+        // the controller drops the CRC byte, so CSD bits 127:126, the
+        // structure version, are bits 23:22 of the last response word
+        self.command(SEND_CSD, rca)?;
+        let csd_structure = (self.registers.RESP[3].read() >> 22) & 0b11;
+        self.command(CARD_SELECT, rca)?;
+
+        Ok(Card { high_capacity: csd_structure != 0 })
+    }
+
+    /// Writes the first `BLOCK_SIZE` bytes of `buf` to block `n` of `card`,
+    /// returning once the card has received them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buf.len() < BLOCK_SIZE`.
+    pub fn write_block(&mut self, card: Card, n: u32, buf: &[u8]) -> Result<()> {
+        let buf = &buf[..BLOCK_SIZE];
+        let arg = match card.high_capacity {
+            true => n,
+            false => n.checked_mul(BLOCK_SIZE as u32).ok_or(Error::Command)?,
+        };
+
+        self.wait_status(SR_DAT_INHIBIT)?;
+        self.registers.BLKSIZECNT.write(1 << 16 | BLOCK_SIZE as u32);
+        self.command(WRITE_SINGLE, arg)?;
+        self.wait_interrupt(INT_WRITE_RDY)?;
+        for word in buf.chunks(4) {
+            self.registers.DATA.write(u32::from_le_bytes(word.try_into().unwrap()));
+        }
+        self.wait_interrupt(INT_DATA_DONE)
+    }
+
+    /// Sends the command `code` with argument `arg` and returns the first word
+    /// of the card's response.
+    fn command(&mut self, code: u32, arg: u32) -> Result<u32> {
+        self.wait_status(SR_CMD_INHIBIT)?;
+        let pending = self.registers.INTERRUPT.read();
+        self.registers.INTERRUPT.write(pending);
+        self.registers.ARG1.write(arg);
+        self.registers.CMDTM.write(code);
+
+        self.wait_interrupt(INT_CMD_DONE)?;
+        Ok(self.registers.RESP[0].read())
+    }
+
+    /// Waits for the `STATUS` bits in `mask` to clear.
+    fn wait_status(&mut self, mask: u32) -> Result<()> {
+        self.poll(500_000, |regs| !regs.STATUS.has_mask(mask))?;
+        match self.registers.INTERRUPT.read() & INT_ERROR_MASK {
+            0 => Ok(()),
+            _ => Err(Error::TimedOut),
+        }
+    }
+
+    /// Waits for one of the `INTERRUPT` bits in `mask` or an error, and
+    /// acknowledges it.
+    fn wait_interrupt(&mut self, mask: u32) -> Result<()> {
+        let all = mask | INT_ERROR_MASK;
+        let polled = self.poll(1_000_000, |regs| regs.INTERRUPT.read() & all != 0);
+
+        let r = self.registers.INTERRUPT.read();
+        if polled.is_err() || r & (INT_CMD_TIMEOUT | INT_DATA_TIMEOUT) != 0 {
+            self.registers.INTERRUPT.write(r);
+            Err(Error::TimedOut)
+        } else if r & INT_ERROR_MASK != 0 {
+            self.registers.INTERRUPT.write(r);
+            Err(Error::Command)
+        } else {
+            self.registers.INTERRUPT.write(mask);
+            Ok(())
+        }
+    }
+
+    /// Checks `done` up to `tries` times, a microsecond apart. Stops early
+    /// once an error interrupt is pending.
+    fn poll<F: Fn(&Registers) -> bool>(&self, tries: usize, done: F) -> Result<()> {
+        for _ in 0..tries {
+            if done(&*self.registers) {
+                return Ok(());
+            } else if self.registers.INTERRUPT.read() & INT_ERROR_MASK != 0 {
+                break;
+            }
+            timer::spin_sleep(Duration::from_micros(1));
+        }
+        Err(Error::TimedOut)
+    }
+}
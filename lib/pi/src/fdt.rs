@@ -0,0 +1,604 @@
+//! A parser for flattened device trees (DTBs), which newer firmware passes
+//! to the kernel instead of ATAGs. The tree is read in place; nothing is
+//! allocated.
+
+use core::{slice, str};
+
+/// The magic number at the start of every device tree blob.
+pub const FDT_MAGIC: u32 = 0xd00d_feed;
+
+/// The version of the format `Fdt` understands, the first whose header
+/// gives the size of the structure block.
+const FDT_VERSION: u32 = 17;
+
+/// The size of the header in bytes.
+const HEADER_SIZE: usize = 40;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+/// Errors that `Fdt::new()` can return.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// The blob doesn't start with `FDT_MAGIC`.
+    BadMagic,
+    /// The blob is in a version of the format that isn't understood.
+    BadVersion(u32),
+    /// The blob is shorter than its header says, or a block lies outside of
+    /// it.
+    BadLayout,
+}
+
+/// Reads the big-endian `u32` at `offset` of `data`, if it is in bounds.
+fn be_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Reads the big-endian number of `cells` 32-bit cells at the start of
+/// `data`.
+fn be_cells(data: &[u8], cells: usize) -> Option<u64> {
+    (0..cells).try_fold(0u64, |value, i| Some(value << 32 | be_u32(data, i * 4)? as u64))
+}
+
+/// Returns the NUL-terminated string at `offset` of `data`.
+fn c_str(data: &[u8], offset: usize) -> Option<&str> {
+    let bytes = data.get(offset..)?;
+    let len = bytes.iter().position(|&b| b == 0)?;
+    str::from_utf8(&bytes[..len]).ok()
+}
+
+/// Rounds `offset` up to the next multiple of 4.
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// A token of the structure block.
+enum Token<'a> {
+    BeginNode(&'a str),
+    EndNode,
+    Prop(Property<'a>),
+    End,
+}
+
+/// A flattened device tree.
+#[derive(Debug, Copy, Clone)]
+pub struct Fdt<'a> {
+    /// The whole blob.
+    data: &'a [u8],
+    /// The structure block: the nodes and their properties.
+    structs: &'a [u8],
+    /// The strings block: the names of the properties.
+    strings: &'a [u8],
+}
+
+impl<'a> Fdt<'a> {
+    /// Parses the header of the device tree blob `data` and returns the
+    /// tree.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadMagic` if `data` isn't a device tree blob, `BadVersion` if
+    /// it isn't compatible with version 17 of the format, and `BadLayout` if
+    /// it is shorter than its header says or its blocks lie outside of it.
+    pub fn new(data: &'a [u8]) -> Result<Fdt<'a>, Error> {
+        let field = |index: usize| be_u32(data, index * 4).ok_or(Error::BadLayout);
+        if data.len() < HEADER_SIZE || field(0)? != FDT_MAGIC {
+            return Err(Error::BadMagic);
+        }
+
+        let (version, last_compatible) = (field(5)?, field(6)?);
+        if version < FDT_VERSION || last_compatible > FDT_VERSION {
+            return Err(Error::BadVersion(version));
+        }
+
+        let total_size = field(1)? as usize;
+        let block = |offset: u32, size: u32| {
+            let (offset, size) = (offset as usize, size as usize);
+            match offset.checked_add(size) {
+                Some(end) if offset >= HEADER_SIZE && end <= total_size => Ok(&data[offset..end]),
+                _ => Err(Error::BadLayout)
+            }
+        };
+        if total_size > data.len() {
+            return Err(Error::BadLayout);
+        }
+
+        Ok(Fdt {
+            data: &data[..total_size],
+            structs: block(field(2)?, field(9)?)?,
+            strings: block(field(3)?, field(8)?)?,
+        })
+    }
+
+    /// Parses the device tree blob at address `addr`, which must be mapped.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must be readable for the 40 bytes of a header and, if they
+    /// start with `FDT_MAGIC`, for the size the header gives. The memory must
+    /// not be written to while the tree is in use.
+    pub unsafe fn from_addr(addr: usize) -> Result<Fdt<'static>, Error> {
+        let header = slice::from_raw_parts(addr as *const u8, HEADER_SIZE);
+        if be_u32(header, 0) != Some(FDT_MAGIC) {
+            return Err(Error::BadMagic);
+        }
+        let total_size = be_u32(header, 4).ok_or(Error::BadLayout)? as usize;
+        Fdt::new(slice::from_raw_parts(addr as *const u8, total_size.max(HEADER_SIZE)))
+    }
+
+    /// Returns the blob, as long as its header says.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Reads the token at `offset` of the structure block, skipping `NOP`s,
+    /// and returns it along with the offset of the next one. Returns `None`
+    /// if the block is malformed.
+    fn token(&self, mut offset: usize) -> Option<(Token<'a>, usize)> {
+        loop {
+            let next = offset + 4;
+            match be_u32(self.structs, offset)? {
+                FDT_BEGIN_NODE => {
+                    let name = c_str(self.structs, next)?;
+                    return Some((Token::BeginNode(name), align4(next + name.len() + 1)));
+                }
+                FDT_END_NODE => return Some((Token::EndNode, next)),
+                FDT_PROP => {
+                    let len = be_u32(self.structs, next)? as usize;
+                    let name = c_str(self.strings, be_u32(self.structs, next + 4)? as usize)?;
+                    let value = self.structs.get(next + 8..(next + 8).checked_add(len)?)?;
+                    return Some((Token::Prop(Property { name, value }), align4(next + 8 + len)));
+                }
+                FDT_NOP => offset = next,
+                FDT_END => return Some((Token::End, next)),
+                _ => return None
+            }
+        }
+    }
+
+    /// Returns the root node of the tree.
+    pub fn root(&self) -> Option<Node<'a>> {
+        match self.token(0)? {
+            (Token::BeginNode(name), offset) => Some(Node { fdt: *self, name, depth: 0, offset }),
+            _ => None
+        }
+    }
+
+    /// Returns an iterator over every node of the tree, parents before their
+    /// children.
+    pub fn nodes(&self) -> Nodes<'a> {
+        Nodes { fdt: *self, offset: Some(0), depth: 0 }
+    }
+
+    /// Returns the node at `path`, such as `/soc/serial@7e201000`. Names
+    /// without a unit address, such as `/memory`, also match nodes that have
+    /// one.
+    pub fn find_node(&self, path: &str) -> Option<Node<'a>> {
+        path.split('/')
+            .filter(|name| !name.is_empty())
+            .try_fold(self.root()?, |node, name| node.children().find(|child| child.matches(name)))
+    }
+
+    /// Returns the value of property `name` of the root node, or `default` if
+    /// it has none.
+    fn root_cells(&self, name: &str, default: usize) -> usize {
+        self.root()
+            .and_then(|root| root.property(name))
+            .and_then(|property| property.as_u32())
+            .map_or(default, |cells| cells as usize)
+    }
+
+    /// Returns an iterator over the `(start, size)` ranges of physical memory
+    /// listed by the `reg` property of the `/memory` node.
+    pub fn memory(&self) -> Regions<'a> {
+        let reg = self.find_node("/memory")
+            .and_then(|memory| memory.property("reg"))
+            .map_or(&[][..], |reg| reg.value);
+        Regions {
+            data: reg,
+            address_cells: self.root_cells("#address-cells", 2),
+            size_cells: self.root_cells("#size-cells", 1),
+        }
+    }
+
+    /// Returns the kernel command line, from the `bootargs` property of the
+    /// `/chosen` node.
+    pub fn bootargs(&self) -> Option<&'a str> {
+        self.find_node("/chosen")?.property("bootargs")?.as_str()
+    }
+
+    /// Returns the `(start, end)` addresses of the initial ramdisk, from the
+    /// `linux,initrd-start` and `linux,initrd-end` properties of the
+    /// `/chosen` node. The end address is exclusive.
+    pub fn initrd(&self) -> Option<(u64, u64)> {
+        let chosen = self.find_node("/chosen")?;
+        let start = chosen.property("linux,initrd-start")?.as_u64()?;
+        let end = chosen.property("linux,initrd-end")?.as_u64()?;
+        match start < end {
+            true => Some((start, end)),
+            false => None
+        }
+    }
+}
+
+/// A node of a device tree.
+#[derive(Debug, Copy, Clone)]
+pub struct Node<'a> {
+    fdt: Fdt<'a>,
+    /// The node's name, with its unit address if it has one. The root node's
+    /// name is empty.
+    pub name: &'a str,
+    /// How deep in the tree the node is; the root node is at depth `0`.
+    pub depth: usize,
+    /// The offset of the token following the node's name.
+    offset: usize,
+}
+
+impl<'a> Node<'a> {
+    /// Returns `true` if the node is named `name`, or `name` omits the unit
+    /// address of the node's name.
+    fn matches(&self, name: &str) -> bool {
+        self.name == name || (!name.contains('@') && self.name.split('@').next() == Some(name))
+    }
+
+    /// Returns an iterator over the properties of the node.
+    pub fn properties(&self) -> Properties<'a> {
+        Properties { fdt: self.fdt, offset: Some(self.offset) }
+    }
+
+    /// Returns the property of the node named `name`.
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|property| property.name == name)
+    }
+
+    /// Returns an iterator over the children of the node.
+    pub fn children(&self) -> Children<'a> {
+        Children { fdt: self.fdt, offset: Some(self.offset), depth: self.depth }
+    }
+}
+
+/// A property of a device tree node.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Property<'a> {
+    pub name: &'a str,
+    pub value: &'a [u8],
+}
+
+impl<'a> Property<'a> {
+    /// Returns the value as a single 32-bit cell, if it is one.
+    pub fn as_u32(&self) -> Option<u32> {
+        match self.value.len() {
+            4 => be_u32(self.value, 0),
+            _ => None
+        }
+    }
+
+    /// Returns the value as one or two 32-bit cells, if it is either.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.value.len() {
+            4 | 8 => be_cells(self.value, self.value.len() / 4),
+            _ => None
+        }
+    }
+
+    /// Returns the value as a string, if it is a NUL-terminated UTF-8 one.
+    pub fn as_str(&self) -> Option<&'a str> {
+        match self.value.split_last() {
+            Some((0, string)) => str::from_utf8(string).ok(),
+            _ => None
+        }
+    }
+}
+
+/// An iterator over every node of a device tree, as returned by
+/// `Fdt::nodes()`.
+pub struct Nodes<'a> {
+    fdt: Fdt<'a>,
+    /// The offset of the next token, `None` once the end has been reached.
+    offset: Option<usize>,
+    /// The depth of the next node.
+    depth: usize,
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Node<'a>> {
+        while let Some(offset) = self.offset {
+            self.offset = None;
+            match self.fdt.token(offset)? {
+                (Token::BeginNode(name), next) => {
+                    let node = Node { fdt: self.fdt, name, depth: self.depth, offset: next };
+                    self.offset = Some(next);
+                    self.depth += 1;
+                    return Some(node);
+                }
+                (Token::EndNode, next) => {
+                    self.depth = self.depth.checked_sub(1)?;
+                    self.offset = Some(next);
+                }
+                (Token::Prop(_), next) => self.offset = Some(next),
+                (Token::End, _) => return None,
+            }
+        }
+        None
+    }
+}
+
+/// An iterator over the children of a node, as returned by
+/// `Node::children()`.
+pub struct Children<'a> {
+    fdt: Fdt<'a>,
+    /// The offset of the next token, `None` once the end of the node has
+    /// been reached.
+    offset: Option<usize>,
+    /// The depth of the parent node.
+    depth: usize,
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Node<'a>> {
+        // how many nodes are open below the parent
+        let mut open = 0usize;
+        let mut child = None;
+        while let Some(offset) = self.offset {
+            self.offset = None;
+            match self.fdt.token(offset)? {
+                (Token::BeginNode(name), next) => {
+                    if open == 0 {
+                        child = Some(Node { fdt: self.fdt, name, depth: self.depth + 1, offset: next });
+                    }
+                    open += 1;
+                    self.offset = Some(next);
+                }
+                (Token::EndNode, next) => {
+                    if open == 0 {
+                        return None;
+                    }
+                    open -= 1;
+                    self.offset = Some(next);
+                    if open == 0 {
+                        return child;
+                    }
+                }
+                (Token::Prop(_), next) => self.offset = Some(next),
+                (Token::End, _) => return None,
+            }
+        }
+        None
+    }
+}
+
+/// An iterator over the properties of a node, as returned by
+/// `Node::properties()`.
+pub struct Properties<'a> {
+    fdt: Fdt<'a>,
+    /// The offset of the next token, `None` once the last property has been
+    /// read.
+    offset: Option<usize>,
+}
+
+impl<'a> Iterator for Properties<'a> {
+    type Item = Property<'a>;
+
+    fn next(&mut self) -> Option<Property<'a>> {
+        let (token, next) = self.fdt.token(self.offset?)?;
+        match token {
+            Token::Prop(property) => {
+                self.offset = Some(next);
+                Some(property)
+            }
+            _ => {
+                self.offset = None;
+                None
+            }
+        }
+    }
+}
+
+/// An iterator over the `(start, size)` ranges of a `reg` property, as
+/// returned by `Fdt::memory()`.
+pub struct Regions<'a> {
+    data: &'a [u8],
+    address_cells: usize,
+    size_cells: usize,
+}
+
+impl<'a> Iterator for Regions<'a> {
+    type Item = (u64, u64);
+
+    fn next(&mut self) -> Option<(u64, u64)> {
+        let len = (self.address_cells + self.size_cells) * 4;
+        if len == 0 || self.data.len() < len {
+            return None;
+        }
+        let start = be_cells(self.data, self.address_cells)?;
+        let size = be_cells(&self.data[self.address_cells * 4..], self.size_cells)?;
+        self.data = &self.data[len..];
+        Some((start, size))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use self::std::vec::Vec;
+    use super::*;
+
+    /// Builds the structure and strings blocks of a device tree.
+    struct Builder {
+        structs: Vec<u8>,
+        strings: Vec<u8>,
+    }
+
+    impl Builder {
+        fn new() -> Builder {
+            Builder { structs: Vec::new(), strings: Vec::new() }
+        }
+
+        fn token(&mut self, token: u32) -> &mut Builder {
+            self.structs.extend_from_slice(&token.to_be_bytes());
+            self
+        }
+
+        fn pad(&mut self) {
+            while self.structs.len() % 4 != 0 {
+                self.structs.push(0);
+            }
+        }
+
+        fn begin(&mut self, name: &str) -> &mut Builder {
+            self.token(FDT_BEGIN_NODE);
+            self.structs.extend_from_slice(name.as_bytes());
+            self.structs.push(0);
+            self.pad();
+            self
+        }
+
+        fn end(&mut self) -> &mut Builder {
+            self.token(FDT_END_NODE)
+        }
+
+        fn prop(&mut self, name: &str, value: &[u8]) -> &mut Builder {
+            let name_offset = self.strings.len() as u32;
+            self.strings.extend_from_slice(name.as_bytes());
+            self.strings.push(0);
+            self.token(FDT_PROP).token(value.len() as u32).token(name_offset);
+            self.structs.extend_from_slice(value);
+            self.pad();
+            self
+        }
+
+        fn cells(&mut self, name: &str, cells: &[u32]) -> &mut Builder {
+            let value: Vec<u8> = cells.iter().flat_map(|cell| cell.to_be_bytes().to_vec()).collect();
+            self.prop(name, &value)
+        }
+
+        /// Returns the blob: the header, the structure block and then the
+        /// strings block.
+        fn finish(&mut self) -> Vec<u8> {
+            self.token(FDT_END);
+            let structs_offset = HEADER_SIZE as u32 + 16;
+            let strings_offset = structs_offset + self.structs.len() as u32;
+            let total_size = strings_offset + self.strings.len() as u32;
+            let header = [
+                FDT_MAGIC, total_size, structs_offset, strings_offset, HEADER_SIZE as u32,
+                17, 16, 0, self.strings.len() as u32, self.structs.len() as u32,
+            ];
+
+            let mut blob: Vec<u8> = header.iter().flat_map(|field| field.to_be_bytes().to_vec()).collect();
+            blob.extend_from_slice(&[0; 16]);
+            blob.extend_from_slice(&self.structs);
+            blob.extend_from_slice(&self.strings);
+            blob
+        }
+    }
+
+    fn pi_tree() -> Vec<u8> {
+        Builder::new()
+            .begin("")
+            .cells("#address-cells", &[1])
+            .cells("#size-cells", &[1])
+            .prop("model", b"Raspberry Pi 3 Model B\0")
+            .begin("chosen")
+            .prop("bootargs", b"console=ttyS0 root=/dev/ram0\0")
+            .cells("linux,initrd-start", &[0x0800_0000])
+            .cells("linux,initrd-end", &[0x0810_0000])
+            .end()
+            .begin("soc")
+            .token(FDT_NOP)
+            .begin("serial@7e201000")
+            .prop("status", b"okay\0")
+            .end()
+            .end()
+            .begin("memory@0")
+            .prop("device_type", b"memory\0")
+            .cells("reg", &[0, 0x3b40_0000, 0x4000_0000, 0x1000])
+            .end()
+            .end()
+            .finish()
+    }
+
+    #[test]
+    fn test_header() {
+        let blob = pi_tree();
+        assert_eq!(Fdt::new(&blob).unwrap().as_bytes().len(), blob.len());
+        assert_eq!(Fdt::new(&blob[..blob.len() - 1]).unwrap_err(), Error::BadLayout);
+        assert_eq!(Fdt::new(&blob[..39]).unwrap_err(), Error::BadMagic);
+
+        let mut bad = blob.clone();
+        bad[0] = 0;
+        assert_eq!(Fdt::new(&bad).unwrap_err(), Error::BadMagic);
+
+        let mut old = blob.clone();
+        old[23] = 16;
+        assert_eq!(Fdt::new(&old).unwrap_err(), Error::BadVersion(16));
+
+        let mut outside = blob.clone();
+        outside[35] += 1;
+        assert_eq!(Fdt::new(&outside).unwrap_err(), Error::BadLayout);
+    }
+
+    #[test]
+    fn test_nodes_and_properties() {
+        let blob = pi_tree();
+        let fdt = Fdt::new(&blob).unwrap();
+
+        let nodes: Vec<(&str, usize)> = fdt.nodes().map(|node| (node.name, node.depth)).collect();
+        assert_eq!(nodes, [("", 0), ("chosen", 1), ("soc", 1), ("serial@7e201000", 2), ("memory@0", 1)]);
+
+        let root = fdt.root().unwrap();
+        let children: Vec<&str> = root.children().map(|node| node.name).collect();
+        assert_eq!(children, ["chosen", "soc", "memory@0"]);
+        let names: Vec<&str> = root.properties().map(|property| property.name).collect();
+        assert_eq!(names, ["#address-cells", "#size-cells", "model"]);
+        assert_eq!(root.property("model").unwrap().as_str(), Some("Raspberry Pi 3 Model B"));
+        assert_eq!(root.property("#size-cells").unwrap().as_u32(), Some(1));
+        assert!(root.property("missing").is_none());
+
+        let serial = fdt.find_node("/soc/serial").unwrap();
+        assert_eq!((serial.name, serial.depth), ("serial@7e201000", 2));
+        assert_eq!(serial.property("status").unwrap().as_str(), Some("okay"));
+        assert_eq!(serial.children().count(), 0);
+        assert!(fdt.find_node("/soc/serial@7e201000").is_some());
+        assert!(fdt.find_node("/soc/serial@0").is_none());
+        assert!(fdt.find_node("/serial").is_none());
+        assert_eq!(fdt.find_node("/").unwrap().name, "");
+    }
+
+    #[test]
+    fn test_helpers() {
+        let blob = pi_tree();
+        let fdt = Fdt::new(&blob).unwrap();
+
+        let memory: Vec<(u64, u64)> = fdt.memory().collect();
+        assert_eq!(memory, [(0, 0x3b40_0000), (0x4000_0000, 0x1000)]);
+        assert_eq!(fdt.bootargs(), Some("console=ttyS0 root=/dev/ram0"));
+        assert_eq!(fdt.initrd(), Some((0x0800_0000, 0x0810_0000)));
+
+        // two-cell addresses, and 64-bit initrd addresses
+        let blob = Builder::new()
+            .begin("")
+            .begin("memory")
+            .cells("reg", &[0x1, 0x0, 0x2000])
+            .end()
+            .begin("chosen")
+            .cells("linux,initrd-start", &[0x1, 0x0])
+            .cells("linux,initrd-end", &[0x1, 0x10])
+            .end()
+            .end()
+            .finish();
+        let fdt = Fdt::new(&blob).unwrap();
+        let memory: Vec<(u64, u64)> = fdt.memory().collect();
+        assert_eq!(memory, [(0x1_0000_0000, 0x2000)]);
+        assert_eq!(fdt.initrd(), Some((0x1_0000_0000, 0x1_0000_0010)));
+        assert_eq!(fdt.bootargs(), None);
+    }
+}
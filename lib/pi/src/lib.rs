@@ -6,7 +6,10 @@
 #![no_std]
 
 pub mod atags;
+pub mod bootinfo;
 pub mod common;
+pub mod emmc;
+pub mod fdt;
 pub mod gpio;
 pub mod interrupt;
 pub mod timer;
@@ -1,32 +1,20 @@
 #!/bin/bash -e
 
 IMG=fs.img
-MNT=mnt
+ROOT=root
 
-PROGS=(sleep fib syscall_test)
+PROGS=(init sleep fib syscall_test)
 
 for d in ${PROGS[@]}; do
     (cd $d; make build)
 done
 
-dd if=/dev/zero of=$IMG bs=1MB count=128
-echo -e "n\np\n1\n\n\nt\nc\nw\n" | fdisk $IMG
-
-LO=$(sudo losetup --show -f -P $IMG)
-LOP1=${LO}p1
-
-if [ ! -e $LOP1 ]; then
-    echo "[!] can't find the partition in $IMG"
-    sudo losetup -d $LO
-fi
-
-sudo mkfs.vfat -F32 $LOP1
-
-mkdir -p $MNT
-sudo mount $LOP1 $MNT
-
-trap "sudo umount $MNT; rmdir $MNT; sudo losetup -d $LO" EXIT
+rm -rf $ROOT
+mkdir -p $ROOT
+trap "rm -rf $ROOT" EXIT
 
 for d in ${PROGS[@]}; do
-    sudo cp $d/build/$d.bin $MNT/$d
+    cp $d/build/$d.elf $ROOT/$d
 done
+
+(cd ../lib/fat32; cargo run --release --bin mkfs -- "$OLDPWD/$IMG" 128M "$OLDPWD/$ROOT")
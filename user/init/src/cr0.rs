@@ -0,0 +1,41 @@
+use core::mem::zeroed;
+use core::panic::PanicInfo;
+use core::ptr::write_volatile;
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    loop {}
+}
+
+unsafe fn zeros_bss() {
+    extern "C" {
+        static mut __bss_beg: u64;
+        static mut __bss_end: u64;
+    }
+
+    let mut iter: *mut u64 = &mut __bss_beg;
+    let end: *mut u64 = &mut __bss_end;
+
+    while iter < end {
+        write_volatile(iter, zeroed());
+        iter = iter.add(1);
+    }
+}
+
+static mut ARGS: &[&str] = &[];
+
+/// Returns the arguments the program was started with by `exec()`.
+#[allow(dead_code)]
+pub fn args() -> &'static [&'static str] {
+    unsafe { ARGS }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _start(argc: usize, argv: *const &'static str) -> ! {
+    zeros_bss();
+    if argc > 0 {
+        ARGS = core::slice::from_raw_parts(argv, argc);
+    }
+    crate::main();
+    kernel_api::syscall::exit(0);
+}
@@ -0,0 +1,43 @@
+#![feature(asm)]
+#![no_std]
+#![no_main]
+
+mod cr0;
+
+use kernel_api::println;
+use kernel_api::syscall::{exec, exit, fork, wait};
+
+/// The programs started at boot, with their arguments.
+const PROGRAMS: &[&[&str]] = &[
+    &["/fib"],
+    &["/fib"],
+    &["/fib"],
+    &["/fib"],
+    &["/syscall_test"],
+    &["/syscall_test"],
+    &["/syscall_test"],
+    &["/syscall_test"],
+];
+
+/// Starts `args[0]` in a new process, passing it `args`.
+fn spawn(args: &[&str]) {
+    match fork() {
+        Ok(0) => {
+            let e = exec(args[0], args);
+            println!("init: {}: {:?}", args[0], e);
+            exit(127);
+        }
+        Ok(_) => {}
+        Err(e) => println!("init: fork: {:?}", e),
+    }
+}
+
+fn main() {
+    for args in PROGRAMS.iter() {
+        spawn(args);
+    }
+
+    while let Ok((pid, status)) = wait() {
+        println!("init: process {} exited with status {}", pid, status);
+    }
+}
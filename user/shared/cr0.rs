@@ -22,9 +22,20 @@ unsafe fn zeros_bss() {
     }
 }
 
+static mut ARGS: &[&str] = &[];
+
+/// Returns the arguments the program was started with by `exec()`.
+#[allow(dead_code)]
+pub fn args() -> &'static [&'static str] {
+    unsafe { ARGS }
+}
+
 #[no_mangle]
-pub unsafe extern "C" fn _start() -> ! {
+pub unsafe extern "C" fn _start(argc: usize, argv: *const &'static str) -> ! {
     zeros_bss();
+    if argc > 0 {
+        ARGS = core::slice::from_raw_parts(argv, argc);
+    }
     crate::main();
-    kernel_api::syscall::exit();
+    kernel_api::syscall::exit(0);
 }